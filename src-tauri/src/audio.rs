@@ -1,93 +1,34 @@
 //! Audio Engine Module
 //! Handles bit-perfect audio playback using WASAPI (Windows) / CoreAudio (macOS)
 
+use crate::channel_mixer::DownmixSettings;
+use crate::convolution::{ConvolutionSettings, Convolver, ImpulseResponse};
+use crate::crossfeed::{Crossfeed, CrossfeedSettings};
+use crate::cue;
+use crate::database::Track;
+use crate::decoder::{DecoderCommand, DecoderHandle, SourceSpec, TrackSource, QUEUE_SECONDS};
+use crate::dither::{DitherMode, Ditherer};
+use crate::dsd::{self, DsdOutput, DsdSettings};
+use crate::equalizer::{EqSettings, Equalizer};
+use crate::events::{EventPublisher, EventSink, PlaybackEvent};
+use crate::queue::{PlayQueue, QueueSnapshot};
+use crate::render::{RenderOptions, RenderSummary, RenderWriter};
+use crate::resampler::ResamplerQuality;
+use crate::ring_buffer::{sample_ring, RingConsumer, RingProducer};
+use crate::sink::{OutputBackend, OutputSinkSettings, SinkFile, SinkStream, SINK_PERIOD_FRAMES};
+use crate::time_stretch::SpeedSettings;
+use crate::visualizer::{self, Visualizer, VisualizerSettings};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::StreamConfig;
 use parking_lot::RwLock;
-use rubato::{FftFixedIn, Resampler};
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::{MediaSource, MediaSourceStream};
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-/// A wrapper around a File that limits reads to a specified byte limit.
-/// This is used for progressive streaming where only part of the file is downloaded.
-/// Returns EOF when the limit is reached, preventing reads into undownloaded (zero) portions.
-struct LimitedFileReader {
-    file: File,
-    limit: u64,
-    current_pos: u64,
-}
-
-impl LimitedFileReader {
-    fn new(file: File, limit: u64) -> Self {
-        Self {
-            file,
-            limit,
-            current_pos: 0,
-        }
-    }
-}
-
-impl Read for LimitedFileReader {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if self.current_pos >= self.limit {
-            return Ok(0); // EOF
-        }
-
-        let remaining = self.limit - self.current_pos;
-        let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
-
-        let bytes_read = self.file.read(&mut buf[..to_read])?;
-        self.current_pos += bytes_read as u64;
-        Ok(bytes_read)
-    }
-}
-
-impl Seek for LimitedFileReader {
-    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-        let new_pos = match pos {
-            SeekFrom::Start(offset) => {
-                self.file.seek(SeekFrom::Start(offset))?;
-                offset
-            }
-            SeekFrom::End(offset) => {
-                // For End, use the limit as the "end" not the actual file size
-                let target = (self.limit as i64 + offset).max(0) as u64;
-                self.file.seek(SeekFrom::Start(target))?;
-                target
-            }
-            SeekFrom::Current(offset) => {
-                let new = (self.current_pos as i64 + offset).max(0) as u64;
-                self.file.seek(SeekFrom::Start(new))?;
-                new
-            }
-        };
-        self.current_pos = new_pos;
-        Ok(new_pos)
-    }
-}
-
-impl MediaSource for LimitedFileReader {
-    fn is_seekable(&self) -> bool {
-        true
-    }
-
-    fn byte_len(&self) -> Option<u64> {
-        // Return the limit as the length, not the actual file size
-        Some(self.limit)
-    }
-}
-
 #[derive(Error, Debug)]
 pub enum AudioError {
     #[error("Failed to initialize audio host")]
@@ -104,6 +45,10 @@ pub enum AudioError {
     FileNotFound(String),
     #[error("Unsupported format")]
     UnsupportedFormat,
+    #[error("Audio device not found: {0}")]
+    DeviceNotFound(String),
+    #[error("Failed to write audio: {0}")]
+    Write(String),
 }
 
 #[derive(Clone, Debug, serde::Serialize)]
@@ -119,9 +64,77 @@ pub struct PlaybackState {
     pub shuffle: bool,
     pub repeat_mode: RepeatMode,
     pub track_finished: bool, // Set to true when playback reaches end of track
+    pub replaygain: ReplayGainSettings,
+    pub replaygain_gain: f32, // Linear normalization gain applied to the audible track
+    pub downmix: DownmixSettings,
+    pub crossfeed: CrossfeedSettings,
+    pub convolution: ConvolutionSettings,
+    pub resampler_quality: ResamplerQuality,
+    pub dsd: DsdSettings,
+    pub device: DeviceSettings,
+    pub buffer_size: Option<u32>, // Frames per callback, None for the device default
+    pub output_sink: OutputSinkSettings,
+    pub visualizer: VisualizerSettings,
+    pub speed: SpeedSettings,
+    pub output_device: Option<String>, // Device the stream plays on
+    pub output_sample_format: String,  // Sample format of the output stream ("i16", "i32", "f32")
+    pub bit_perfect: bool,             // Decoded samples reach the device unaltered
+    pub dsd_rate: Option<u32>,         // DSD sample rate of the audible track
+    pub dop: bool,                     // The stream carries DSD packed as DoP
+    #[serde(skip)]
+    output_integer_bits: u16, // Width of an integer output format, 0 for float
+    #[serde(skip)]
+    pub(crate) track_playthrough: u64, // Number of the audible track in its stream
+    #[serde(skip)]
+    source_channels: u16, // Channel count of the audible track's source
+    #[serde(skip)]
+    output_buffer: Option<u32>, // Fixed buffer size the stream was opened with
+    #[serde(skip)]
+    buffer_range: Option<(u32, u32)>, // Buffer sizes the device takes, if it says
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            current_track: None,
+            position: 0.0,
+            duration: 0.0,
+            volume: 1.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            shuffle: false,
+            repeat_mode: RepeatMode::Off,
+            track_finished: false,
+            replaygain: ReplayGainSettings::default(),
+            replaygain_gain: 1.0,
+            downmix: DownmixSettings::default(),
+            crossfeed: CrossfeedSettings::default(),
+            convolution: ConvolutionSettings::default(),
+            resampler_quality: ResamplerQuality::default(),
+            dsd: DsdSettings::default(),
+            device: DeviceSettings::default(),
+            buffer_size: None,
+            output_sink: OutputSinkSettings::default(),
+            visualizer: VisualizerSettings::default(),
+            speed: SpeedSettings::default(),
+            output_device: None,
+            output_sample_format: "f32".to_string(),
+            bit_perfect: false,
+            dsd_rate: None,
+            dop: false,
+            output_integer_bits: 0,
+            track_playthrough: 0,
+            source_channels: 0,
+            output_buffer: None,
+            buffer_range: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum RepeatMode {
     Off,
     One,
@@ -134,6 +147,216 @@ impl Default for RepeatMode {
     }
 }
 
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    Auto, // Album gain for in-order listening, track gain on shuffle
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    pub preamp_db: f32,         // Added to the tagged gain
+    pub fallback_gain_db: f32,  // Used for files without ReplayGain info
+    pub prevent_clipping: bool, // Lower the gain so that peak * gain never exceeds full scale
+}
+
+impl Default for ReplayGainSettings {
+    fn default() -> Self {
+        Self {
+            mode: ReplayGainMode::Off,
+            preamp_db: 0.0,
+            fallback_gain_db: 0.0,
+            prevent_clipping: true,
+        }
+    }
+}
+
+/// ReplayGain values of one track, gains in dB and peaks linear
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayGainInfo {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGainSettings {
+    /// Linear gain for a track with `info`
+    pub fn gain(&self, info: &ReplayGainInfo, shuffle: bool) -> f32 {
+        let mode = match self.mode {
+            ReplayGainMode::Auto if shuffle => ReplayGainMode::Track,
+            ReplayGainMode::Auto => ReplayGainMode::Album,
+            mode => mode,
+        };
+        let (gain, peak) = match mode {
+            ReplayGainMode::Off | ReplayGainMode::Auto => return 1.0,
+            ReplayGainMode::Track => (
+                info.track_gain.or(info.album_gain),
+                info.track_peak.or(info.album_peak),
+            ),
+            ReplayGainMode::Album => (
+                info.album_gain.or(info.track_gain),
+                info.album_peak.or(info.track_peak),
+            ),
+        };
+
+        let db = match gain {
+            Some(gain) => gain + self.preamp_db,
+            None => self.fallback_gain_db,
+        };
+        let mut linear = 10f32.powf(db / 20.0);
+
+        if self.prevent_clipping {
+            if let Some(peak) = peak.filter(|p| *p > 0.0) {
+                if linear * peak > 1.0 {
+                    linear = 1.0 / peak;
+                }
+            }
+        }
+        linear
+    }
+}
+
+/// Shape of the gain ramps used when crossfading between tracks
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossfadeCurve {
+    Linear,
+    EqualPower,
+    Logarithmic,
+}
+
+impl CrossfadeCurve {
+    /// Gains of the outgoing and incoming track at fade progress `t` (0..=1)
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            CrossfadeCurve::Linear => (1.0 - t, t),
+            CrossfadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            CrossfadeCurve::Logarithmic => {
+                ((1.0 + 9.0 * (1.0 - t)).log10(), (1.0 + 9.0 * t).log10())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct CrossfadeSettings {
+    pub duration: f64, // Seconds, 0 disables crossfading
+    pub curve: CrossfadeCurve,
+}
+
+impl Default for CrossfadeSettings {
+    fn default() -> Self {
+        Self {
+            duration: 0.0,
+            curve: CrossfadeCurve::EqualPower,
+        }
+    }
+}
+
+/// Output device choice
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DeviceSettings {
+    pub preferred: Option<String>, // Chosen by the user, None for the system default
+    pub return_to_preferred: bool, // Move back to the preferred device when it reconnects
+    pub profiles: HashMap<String, DeviceProfile>, // By cpal device name
+}
+
+impl Default for DeviceSettings {
+    fn default() -> Self {
+        Self {
+            preferred: None,
+            return_to_preferred: true,
+            profiles: HashMap::new(),
+        }
+    }
+}
+
+/// How the output stream keeps up with the device
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct AudioDiagnostics {
+    pub buffer_size: Option<u32>, // Frames per callback the stream asked for, None for the default
+    pub buffer_range: Option<(u32, u32)>, // Sizes the device takes, if it says
+    pub callback_frames: u32,     // Frames the device asked for in the latest callback
+    pub latency_ms: f64,          // From handing a frame to the device to hearing it
+    pub underruns: u64,           // Decoding fell behind and silence was played
+    pub xruns: u64,               // A callback came too late and the device ran dry
+    pub convolution_latency_ms: f64, // Added by the convolver, counting the IR's own delay
+    pub convolution_load: f32,    // Time the convolver takes relative to the audio's duration
+}
+
+/// Sample format a device profile asks for
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    I16,
+    I32,
+    F32,
+}
+
+impl OutputFormat {
+    fn sample_format(self) -> cpal::SampleFormat {
+        match self {
+            OutputFormat::I16 => cpal::SampleFormat::I16,
+            OutputFormat::I32 => cpal::SampleFormat::I32,
+            OutputFormat::F32 => cpal::SampleFormat::F32,
+        }
+    }
+}
+
+/// Output settings for one device, applied whenever playback goes to it
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub max_sample_rate: Option<u32>, // Highest rate to open the device at
+    pub sample_format: Option<OutputFormat>, // None picks the format by source bit depth
+    pub buffer_size: Option<u32>,     // Frames per callback, None for the device default
+    pub volume_limit: f32,            // Highest volume, 0-1
+    pub eq_preset: Option<i64>,       // EQ preset switched to along with the device
+    pub resample: bool, // Resample everything to one rate instead of switching the device rate
+    #[serde(skip)]
+    pub equalizer: Option<EqSettings>, // The EQ preset's settings, looked up when the profile is set
+}
+
+impl Default for DeviceProfile {
+    fn default() -> Self {
+        Self {
+            max_sample_rate: None,
+            sample_format: None,
+            buffer_size: None,
+            volume_limit: 1.0,
+            eq_preset: None,
+            resample: false,
+            equalizer: None,
+        }
+    }
+}
+
+impl DeviceProfile {
+    pub fn clamped(self) -> Self {
+        Self {
+            max_sample_rate: self.max_sample_rate.map(|r| r.clamp(8_000, 1_536_000)),
+            buffer_size: self.buffer_size.map(|b| b.clamp(16, 65_536)),
+            volume_limit: self.volume_limit.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+}
+
+impl CrossfadeSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.duration > 0.0
+    }
+}
+
 #[allow(dead_code)]
 pub enum AudioCommand {
     Play(String),
@@ -146,18 +369,333 @@ pub enum AudioCommand {
     Seek(f64),
     SetVolume(f32),
     SetDevice(String),
+    RefreshBufferSize,            // The buffer size setting changed
+    RefreshDeviceProfile(String), // The profile of a device changed
+    RefreshOutputSink,            // The output backend changed
+    PlayQueued,                   // Play the current track of the queue
+    QueueChanged,                 // Tracks, order or repeat mode of the queue changed
+    SetCrossfade(CrossfadeSettings),
+    RefreshGain,        // ReplayGain settings or shuffle changed
+    RefreshDownmix,     // Downmix settings changed
+    RefreshSpeed,       // Speed or pitch changed
+    RefreshEqualizer,   // EQ settings changed
+    RefreshCrossfeed,   // Crossfeed settings changed
+    RefreshConvolution, // Convolution settings or impulse response changed
+    SetDither(DitherMode),
     Shutdown,
 }
 
+/// Replacement DSP stages handed to the output callback. The callback sends the
+/// stage it replaced back the same way so it is freed off the real-time thread.
+enum DspUpdate {
+    Equalizer(Option<Box<Equalizer>>),
+    Crossfeed(Option<Box<Crossfeed>>),
+    Convolver(Option<Box<Convolver>>),
+}
+
+const DSP_UPDATE_CAPACITY: usize = 16;
+
+/// How often output devices are enumerated to notice them being plugged in or out
+const DEVICE_SCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// Longest wait for a moved stream to seek back to where it was
+const RESTART_SEEK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Previous restarts the current track once it has played this long
+const RESTART_SECONDS: f64 = 3.0;
+
+/// A track's place in the output stream
+#[derive(Clone, Debug)]
+pub struct TimelineEntry {
+    pub start_frame: u64,
+    /// Number of the track among those started in this stream; a seek keeps it
+    pub playthrough: u64,
+    /// Frames at its start mixed over the end of the track before
+    pub fade_frames: u64,
+    pub path: String,
+    pub duration: f64,
+    pub bit_depth: u16,
+    pub gain: f32,
+    /// Decoded at the output rate and channel count: no resampling or channel conversion
+    pub native: bool,
+    /// DSD rate of a DSF/DFF source
+    pub dsd_rate: Option<u32>,
+    /// Channel count of the source
+    pub channels: u16,
+}
+
+/// Tracks queued in the current output stream, in order. Gapless transitions put several
+/// tracks in one stream, so the audible track is the last one whose start has been played.
+#[derive(Default)]
+pub struct TrackTimeline {
+    entries: Vec<TimelineEntry>,
+    /// Next track that needs a new stream; started by the audio thread once this one played out
+    pub pending_handoff: Option<String>,
+}
+
+impl TrackTimeline {
+    /// Start over with a single track beginning at `entry.start_frame`
+    pub fn reset(&mut self, entry: TimelineEntry) {
+        self.entries = vec![entry];
+        self.pending_handoff = None;
+    }
+
+    pub fn push(&mut self, entry: TimelineEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.pending_handoff = None;
+    }
+
+    /// Record the ReplayGain now applied to `path`
+    pub fn set_gain(&mut self, path: &str, gain: f32) {
+        for entry in self.entries.iter_mut().filter(|e| e.path == path) {
+            entry.gain = gain;
+        }
+    }
+
+    /// Update the duration of the most recently queued track (known once fully decoded)
+    pub fn set_latest_duration(&mut self, duration: f64) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.duration = duration;
+        }
+    }
+
+    /// The track audible after `frames_played` output frames
+    pub fn current(&self, frames_played: u64) -> Option<&TimelineEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| e.start_frame <= frames_played)
+            .or(self.entries.first())
+    }
+
+    /// Forget tracks that have been played past
+    fn prune(&mut self, frames_played: u64) {
+        let audible = self
+            .entries
+            .iter()
+            .rposition(|e| e.start_frame <= frames_played)
+            .unwrap_or(0);
+        self.entries.drain(..audible);
+    }
+
+    /// Output frame up to which the audio after `frames_played` is one track's decoded
+    /// samples as they are, or 0 if the audible track is not `untouched` or fades in
+    fn untouched_until(
+        &self,
+        frames_played: u64,
+        untouched: impl Fn(&TimelineEntry) -> bool,
+    ) -> u64 {
+        let Some(index) = self
+            .entries
+            .iter()
+            .rposition(|e| e.start_frame <= frames_played)
+        else {
+            return 0;
+        };
+        let entry = &self.entries[index];
+        if frames_played < entry.start_frame + entry.fade_frames || !untouched(entry) {
+            return 0;
+        }
+        self.entries
+            .get(index + 1)
+            .map_or(u64::MAX, |next| next.start_frame)
+    }
+}
+
+/// Playback values shared with the real-time output callback.
+/// Everything here is atomic so the callback never blocks on a lock held elsewhere.
+pub struct OutputShared {
+    playing: AtomicBool,
+    volume: AtomicU32, // f32 bits
+    frames_played: AtomicU64,
+    end_of_stream: AtomicBool, // Set by the decoder once every queued source is decoded
+    finished: AtomicBool,
+    flush_requested: AtomicU64,
+    flush_completed: AtomicU64,
+    flush_position: AtomicU64,
+    dither: AtomicU8,           // DitherMode
+    untouched_until: AtomicU64, // Frames played before which Auto dither has nothing to do
+    device_lost: AtomicBool,    // The stream reported its device gone
+    latency_frames: AtomicU64,  // Output latency reported by the latest callback
+    callback_frames: AtomicU32, // Size of the latest callback
+    underruns: AtomicU64,
+    xruns: AtomicU64,
+    tap_enabled: AtomicBool, // The callback copies its output to the visualizer
+    speed: AtomicU64,        // f64 bits: source frames per output frame of the ring's audio
+    dsp_latency_frames: AtomicU64, // Delay of the DSP stages in the callback
+    convolution_load: AtomicU32, // f32 bits: convolver time per second of audio
+}
+
+impl OutputShared {
+    fn new() -> Self {
+        Self {
+            playing: AtomicBool::new(false),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            frames_played: AtomicU64::new(0),
+            end_of_stream: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            flush_requested: AtomicU64::new(0),
+            flush_completed: AtomicU64::new(0),
+            flush_position: AtomicU64::new(0),
+            dither: AtomicU8::new(DitherMode::default().to_u8()),
+            untouched_until: AtomicU64::new(0),
+            device_lost: AtomicBool::new(false),
+            latency_frames: AtomicU64::new(0),
+            callback_frames: AtomicU32::new(0),
+            underruns: AtomicU64::new(0),
+            xruns: AtomicU64::new(0),
+            tap_enabled: AtomicBool::new(false),
+            speed: AtomicU64::new(1.0f64.to_bits()),
+            dsp_latency_frames: AtomicU64::new(0),
+            convolution_load: AtomicU32::new(0),
+        }
+    }
+
+    /// Prepare for a new stream. Only called while no output callback is running.
+    fn reset(&self) {
+        self.frames_played.store(0, Ordering::Release);
+        self.set_untouched_until(0);
+        self.end_of_stream.store(false, Ordering::Release);
+        self.finished.store(false, Ordering::Release);
+        let epoch = self.flush_requested.load(Ordering::Acquire);
+        self.flush_completed.store(epoch, Ordering::Release);
+        self.latency_frames.store(0, Ordering::Relaxed);
+        self.callback_frames.store(0, Ordering::Relaxed);
+        self.set_speed(1.0);
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::Acquire)
+    }
+
+    fn set_playing(&self, playing: bool) {
+        self.playing.store(playing, Ordering::Release);
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn dither(&self) -> DitherMode {
+        DitherMode::from_u8(self.dither.load(Ordering::Relaxed))
+    }
+
+    fn set_dither(&self, mode: DitherMode) {
+        self.dither.store(mode.to_u8(), Ordering::Relaxed);
+    }
+
+    fn untouched_until(&self) -> u64 {
+        self.untouched_until.load(Ordering::Acquire)
+    }
+
+    /// Set by the audio thread from the timeline. The decoder lowers it for each track it
+    /// queues and clears it on a seek, so it never covers audio it does not describe.
+    pub fn set_untouched_until(&self, frame: u64) {
+        self.untouched_until.store(frame, Ordering::Release);
+    }
+
+    pub fn limit_untouched(&self, frame: u64) {
+        self.untouched_until.fetch_min(frame, Ordering::AcqRel);
+    }
+
+    pub fn speed(&self) -> f64 {
+        f64::from_bits(self.speed.load(Ordering::Acquire))
+    }
+
+    /// Set by the decoder before the stream starts and before each flush; the callback
+    /// picks it up with the flush, so audio already in the ring keeps its speed
+    pub fn set_speed(&self, speed: f64) {
+        self.speed.store(speed.to_bits(), Ordering::Release);
+    }
+
+    fn tap_enabled(&self) -> bool {
+        self.tap_enabled.load(Ordering::Relaxed)
+    }
+
+    fn set_device_lost(&self) {
+        self.device_lost.store(true, Ordering::Release);
+    }
+
+    fn take_device_lost(&self) -> bool {
+        self.device_lost.swap(false, Ordering::AcqRel)
+    }
+
+    /// Output frames played since the start of the stream (or the last seek target)
+    pub fn frames_played(&self) -> u64 {
+        self.frames_played.load(Ordering::Acquire)
+    }
+
+    /// Output frames heard so far: the DSP stages hold some back, and while playing the
+    /// last frames handed to the device are still on their way to the speakers
+    pub fn audible_frames(&self) -> u64 {
+        let frames = self.frames_played();
+        if self.is_finished() {
+            return frames;
+        }
+        let mut latency = self.dsp_latency_frames.load(Ordering::Relaxed);
+        if self.is_playing() {
+            latency += self.latency_frames.load(Ordering::Relaxed);
+        }
+        frames.saturating_sub((latency as f64 * self.speed()) as u64)
+    }
+
+    pub fn set_end_of_stream(&self, end: bool) {
+        self.end_of_stream.store(end, Ordering::Release);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// More audio is coming: clear the finished flag and keep playing
+    fn rearm(&self) {
+        self.end_of_stream.store(false, Ordering::Release);
+        self.finished.store(false, Ordering::Release);
+        self.playing.store(true, Ordering::Release);
+    }
+
+    /// Ask the callback to drop all buffered audio and restart counting at `frame`.
+    /// Returns the epoch to pass to `flush_done`.
+    pub fn request_flush(&self, frame: u64) -> u64 {
+        self.flush_position.store(frame, Ordering::Release);
+        self.finished.store(false, Ordering::Release);
+        self.flush_requested.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Number of flushes requested so far
+    fn flush_epoch(&self) -> u64 {
+        self.flush_requested.load(Ordering::Acquire)
+    }
+
+    pub fn flush_done(&self, epoch: u64) -> bool {
+        self.flush_completed.load(Ordering::Acquire) >= epoch
+    }
+}
+
+/// Told the id of each queue track playback moves on to by itself, to count it as played
+pub type PlayRecorder = Arc<dyn Fn(i64) + Send + Sync>;
+
 /// Thread-safe audio engine that delegates actual playback to a dedicated thread
 /// This is necessary because cpal::Stream is not Send/Sync
 #[allow(dead_code)]
 pub struct AudioEngine {
     state: Arc<RwLock<PlaybackState>>,
     command_tx: mpsc::Sender<AudioCommand>,
-    sample_buffer: Arc<RwLock<Vec<f32>>>,
-    buffer_position: Arc<RwLock<usize>>,
+    output: Arc<OutputShared>,
+    timeline: Arc<RwLock<TrackTimeline>>,
     device_list: Arc<RwLock<Vec<String>>>,
+    queue: Arc<RwLock<PlayQueue>>,
+    equalizer: Arc<RwLock<EqSettings>>,
+    impulse: Arc<RwLock<Option<Arc<ImpulseResponse>>>>, // Loaded for the convolution settings
+    visualizer: Visualizer,
+    crossfade: CrossfadeSettings,
 }
 
 // Explicitly implement Send and Sync for AudioEngine since it only contains thread-safe types
@@ -165,42 +703,44 @@ unsafe impl Send for AudioEngine {}
 unsafe impl Sync for AudioEngine {}
 
 impl AudioEngine {
-    pub fn new() -> Result<Self, AudioError> {
-        let state = Arc::new(RwLock::new(PlaybackState {
-            is_playing: false,
-            current_track: None,
-            position: 0.0,
-            duration: 0.0,
-            volume: 1.0,
-            sample_rate: 44100,
-            bit_depth: 16,
-            channels: 2,
-            shuffle: false,
-            repeat_mode: RepeatMode::Off,
-            track_finished: false,
-        }));
+    pub fn new(events: EventSink, plays: PlayRecorder) -> Result<Self, AudioError> {
+        let state = Arc::new(RwLock::new(PlaybackState::default()));
 
-        let sample_buffer = Arc::new(RwLock::new(Vec::new()));
-        let buffer_position = Arc::new(RwLock::new(0));
+        let output = Arc::new(OutputShared::new());
+        let timeline = Arc::new(RwLock::new(TrackTimeline::default()));
         let device_list = Arc::new(RwLock::new(Vec::new()));
+        let queue = Arc::new(RwLock::new(PlayQueue::new()));
+        let equalizer = Arc::new(RwLock::new(EqSettings::default()));
+        let impulse = Arc::new(RwLock::new(None));
+        let visualizer = Visualizer::spawn(Arc::clone(&events));
 
         // Create channel for commands
         let (command_tx, command_rx) = mpsc::channel::<AudioCommand>();
 
         // Clone Arcs for the audio thread
         let state_clone = Arc::clone(&state);
-        let sample_buffer_clone = Arc::clone(&sample_buffer);
-        let buffer_position_clone = Arc::clone(&buffer_position);
+        let output_clone = Arc::clone(&output);
+        let timeline_clone = Arc::clone(&timeline);
         let device_list_clone = Arc::clone(&device_list);
+        let queue_clone = Arc::clone(&queue);
+        let equalizer_clone = Arc::clone(&equalizer);
+        let impulse_clone = Arc::clone(&impulse);
+        let visualizer_clone = visualizer.clone();
 
         // Spawn dedicated audio thread (owns the non-Send Stream)
         thread::spawn(move || {
             AudioThread::new(
                 state_clone,
-                sample_buffer_clone,
-                buffer_position_clone,
+                output_clone,
+                timeline_clone,
                 device_list_clone,
+                queue_clone,
+                equalizer_clone,
+                impulse_clone,
+                visualizer_clone,
                 command_rx,
+                events,
+                plays,
             )
             .run();
         });
@@ -208,9 +748,14 @@ impl AudioEngine {
         Ok(Self {
             state,
             command_tx,
-            sample_buffer,
-            buffer_position,
+            output,
+            timeline,
             device_list,
+            queue,
+            equalizer,
+            impulse,
+            visualizer,
+            crossfade: CrossfadeSettings::default(),
         })
     }
 
@@ -218,7 +763,12 @@ impl AudioEngine {
         self.device_list.read().clone()
     }
 
+    /// Play on `device_name` from now on, moving the running stream over
     pub fn set_device(&mut self, device_name: &str) -> Result<(), AudioError> {
+        if !self.device_list.read().iter().any(|d| d == device_name) {
+            return Err(AudioError::DeviceNotFound(device_name.to_string()));
+        }
+        self.state.write().device.preferred = Some(device_name.to_string());
         self.command_tx
             .send(AudioCommand::SetDevice(device_name.to_string()))
             .map_err(|_| AudioError::HostInit)?;
@@ -244,7 +794,7 @@ impl AudioEngine {
         Ok(())
     }
 
-    /// Append audio samples from a file to the current buffer (for gapless playback)
+    /// Decode a file after the current one without a gap (for gapless chunk transitions)
     pub fn append_samples(&mut self, file_path: &str) -> Result<(), AudioError> {
         self.command_tx
             .send(AudioCommand::AppendSamples(file_path.to_string()))
@@ -252,8 +802,8 @@ impl AudioEngine {
         Ok(())
     }
 
-    /// Append samples from a specific byte range of a file.
-    /// Used for progressive streaming to append newly downloaded chunks.
+    /// Continue decoding a progressively downloaded file that has grown on disk.
+    /// The file may now be read up to `offset + limit` bytes.
     pub fn append_from_offset(
         &mut self,
         file_path: &str,
@@ -270,72 +820,585 @@ impl AudioEngine {
         Ok(())
     }
 
-    pub fn pause(&mut self) {
-        let _ = self.command_tx.send(AudioCommand::Pause);
+    pub fn get_queue(&self) -> QueueSnapshot {
+        self.queue.read().snapshot()
     }
 
-    pub fn resume(&mut self) {
-        let _ = self.command_tx.send(AudioCommand::Resume);
+    /// Replace the queue and play it from `start`. Returns the track that starts.
+    pub fn play_queue(&mut self, tracks: Vec<Track>, start: usize) -> Option<Track> {
+        self.queue.write().set(tracks, start);
+        let track = self.play_queued();
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
+        track
     }
 
-    pub fn stop(&mut self) {
-        let _ = self.command_tx.send(AudioCommand::Stop);
+    pub fn add_to_queue(&mut self, tracks: Vec<Track>) {
+        self.queue.write().add(tracks);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
     }
 
-    pub fn seek(&mut self, position: f64) {
-        let _ = self.command_tx.send(AudioCommand::Seek(position));
+    pub fn insert_next_in_queue(&mut self, tracks: Vec<Track>) {
+        self.queue.write().insert_next(tracks);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        let _ = self.command_tx.send(AudioCommand::SetVolume(volume));
+    pub fn remove_from_queue(&mut self, index: usize) -> bool {
+        let removed = self.queue.write().remove(index);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
+        removed
     }
 
-    pub fn get_state(&self) -> PlaybackState {
-        self.state.read().clone()
+    pub fn move_in_queue(&mut self, from: usize, to: usize) -> bool {
+        let moved = self.queue.write().move_track(from, to);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
+        moved
     }
 
-    pub fn set_shuffle(&mut self, enabled: bool) {
-        self.state.write().shuffle = enabled;
+    pub fn clear_queue(&mut self) {
+        self.queue.write().clear();
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
     }
 
-    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
-        self.state.write().repeat_mode = mode;
+    /// Play the track at `index` in the queue's play order
+    pub fn play_queue_index(&mut self, index: usize) -> Option<Track> {
+        self.queue.write().jump(index)?;
+        self.play_queued()
     }
-}
-
-/// Internal audio thread that owns the non-Send cpal::Stream
-#[allow(dead_code)]
-struct AudioThread {
-    host: cpal::Host,
-    device: Option<cpal::Device>,
-    stream: Option<cpal::Stream>,
-    state: Arc<RwLock<PlaybackState>>,
-    sample_buffer: Arc<RwLock<Vec<f32>>>,
-    buffer_position: Arc<RwLock<usize>>,
-    device_list: Arc<RwLock<Vec<String>>>,
-    command_rx: mpsc::Receiver<AudioCommand>,
-    output_sample_rate: Option<u32>, // The sample rate the stream is outputting at
-    output_channels: Option<u16>,    // The channel count the stream is outputting
-}
 
-impl AudioThread {
-    fn new(
-        state: Arc<RwLock<PlaybackState>>,
-        sample_buffer: Arc<RwLock<Vec<f32>>>,
-        buffer_position: Arc<RwLock<usize>>,
-        device_list: Arc<RwLock<Vec<String>>>,
-        command_rx: mpsc::Receiver<AudioCommand>,
-    ) -> Self {
-        // Initialize audio host on this thread
-        #[cfg(target_os = "windows")]
-        let host =
-            cpal::host_from_id(cpal::HostId::Wasapi).unwrap_or_else(|_| cpal::default_host());
+    /// Skip to the following track in the queue, stopping at its end
+    pub fn next_track(&mut self) -> Option<Track> {
+        if self.queue.write().skip().is_none() {
+            self.stop();
+            return None;
+        }
+        self.play_queued()
+    }
+
+    /// Restart the current track, or go back to the one played before it near its start
+    pub fn previous_track(&mut self) -> Option<Track> {
+        if self.get_state().position > RESTART_SECONDS || self.queue.write().previous().is_none() {
+            self.seek(0.0);
+            return None;
+        }
+        self.play_queued()
+    }
+
+    fn play_queued(&mut self) -> Option<Track> {
+        let track = self.queue.read().current().cloned()?;
+        let _ = self.command_tx.send(AudioCommand::PlayQueued);
+        Some(track)
+    }
+
+    pub fn get_crossfade(&self) -> CrossfadeSettings {
+        self.crossfade
+    }
+
+    /// Overlap the end of each track with the start of the next one
+    pub fn set_crossfade(&mut self, settings: CrossfadeSettings) -> Result<(), AudioError> {
+        let settings = CrossfadeSettings {
+            duration: settings.duration.clamp(0.0, 12.0),
+            ..settings
+        };
+        self.command_tx
+            .send(AudioCommand::SetCrossfade(settings))
+            .map_err(|_| AudioError::HostInit)?;
+        self.crossfade = settings;
+        Ok(())
+    }
+
+    pub fn get_equalizer(&self) -> EqSettings {
+        self.equalizer.read().clone()
+    }
+
+    /// Change the parametric EQ. Takes effect on the audio being played right away.
+    pub fn set_equalizer(&mut self, settings: EqSettings) -> Result<(), AudioError> {
+        *self.equalizer.write() = settings.clamped();
+        self.command_tx
+            .send(AudioCommand::RefreshEqualizer)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_dither(&self) -> DitherMode {
+        self.output.dither()
+    }
+
+    pub fn set_dither(&mut self, mode: DitherMode) {
+        let _ = self.command_tx.send(AudioCommand::SetDither(mode));
+    }
+
+    pub fn pause(&mut self) {
+        let _ = self.command_tx.send(AudioCommand::Pause);
+    }
+
+    pub fn resume(&mut self) {
+        let _ = self.command_tx.send(AudioCommand::Resume);
+    }
+
+    pub fn stop(&mut self) {
+        let _ = self.command_tx.send(AudioCommand::Stop);
+    }
+
+    pub fn seek(&mut self, position: f64) {
+        let _ = self.command_tx.send(AudioCommand::Seek(position));
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        let _ = self.command_tx.send(AudioCommand::SetVolume(volume));
+    }
+
+    pub fn get_state(&self) -> PlaybackState {
+        snapshot(
+            &self.state,
+            &self.output,
+            &self.timeline,
+            &self.equalizer.read(),
+        )
+    }
+
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        self.state.write().shuffle = enabled;
+        self.queue.write().set_shuffle(enabled);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
+        // Auto ReplayGain follows shuffle
+        let _ = self.command_tx.send(AudioCommand::RefreshGain);
+    }
+
+    pub fn get_replaygain(&self) -> ReplayGainSettings {
+        self.state.read().replaygain
+    }
+
+    /// Change loudness normalization. Applies to audio decoded from now on.
+    pub fn set_replaygain(&mut self, settings: ReplayGainSettings) -> Result<(), AudioError> {
+        self.state.write().replaygain = ReplayGainSettings {
+            preamp_db: settings.preamp_db.clamp(-15.0, 15.0),
+            fallback_gain_db: settings.fallback_gain_db.clamp(-15.0, 15.0),
+            ..settings
+        };
+        self.command_tx
+            .send(AudioCommand::RefreshGain)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_downmix(&self) -> DownmixSettings {
+        self.state.read().downmix
+    }
+
+    /// Change how channel layouts are mixed. Applies to audio decoded from now on.
+    pub fn set_downmix(&mut self, settings: DownmixSettings) -> Result<(), AudioError> {
+        self.state.write().downmix = settings;
+        self.command_tx
+            .send(AudioCommand::RefreshDownmix)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_speed(&self) -> SpeedSettings {
+        self.state.read().speed
+    }
+
+    /// Change tempo and pitch. The audio already buffered is dropped so it applies at once.
+    pub fn set_speed(&mut self, settings: SpeedSettings) -> Result<(), AudioError> {
+        self.state.write().speed = settings.clamped();
+        self.command_tx
+            .send(AudioCommand::RefreshSpeed)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_crossfeed(&self) -> CrossfeedSettings {
+        self.state.read().crossfeed
+    }
+
+    /// Change the headphone crossfeed. Applies to what is heard now.
+    pub fn set_crossfeed(&mut self, settings: CrossfeedSettings) -> Result<(), AudioError> {
+        self.state.write().crossfeed = settings.clamped();
+        self.command_tx
+            .send(AudioCommand::RefreshCrossfeed)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_convolution(&self) -> ConvolutionSettings {
+        self.state.read().convolution.clone()
+    }
+
+    /// Change the convolution filter. A new impulse response is loaded here, so a file that
+    /// cannot be used is reported to the caller and the previous settings stay.
+    pub fn set_convolution(&mut self, settings: ConvolutionSettings) -> Result<(), AudioError> {
+        let loaded = self.impulse.read().as_ref().map(|ir| ir.path.clone());
+        if settings.ir_path != loaded {
+            let impulse = match &settings.ir_path {
+                Some(path) => {
+                    let ir = ImpulseResponse::load(path)?;
+                    println!(
+                        "[Audio] Impulse response: {} channel(s), {} frames at {}Hz",
+                        ir.channels(),
+                        ir.frames(),
+                        ir.sample_rate
+                    );
+                    Some(Arc::new(ir))
+                }
+                None => None,
+            };
+            *self.impulse.write() = impulse;
+        }
+        self.state.write().convolution = settings;
+        self.command_tx
+            .send(AudioCommand::RefreshConvolution)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    /// The settings an offline render of a track uses, taken as they are now
+    pub fn render_context(&self) -> RenderContext {
+        RenderContext {
+            state: self.state.read().clone(),
+            equalizer: self.equalizer.read().clone(),
+            impulse: self.impulse.read().clone(),
+            dither: self.output.dither(),
+        }
+    }
+
+    pub fn get_resampler_quality(&self) -> ResamplerQuality {
+        self.state.read().resampler_quality
+    }
+
+    /// Takes effect from the next track or device change
+    pub fn set_resampler_quality(&mut self, quality: ResamplerQuality) {
+        self.state.write().resampler_quality = quality;
+    }
+
+    pub fn get_device_settings(&self) -> DeviceSettings {
+        self.state.read().device.clone()
+    }
+
+    pub fn set_return_to_preferred_device(&mut self, enabled: bool) {
+        self.state.write().device.return_to_preferred = enabled;
+    }
+
+    pub fn get_device_profiles(&self) -> HashMap<String, DeviceProfile> {
+        self.state.read().device.profiles.clone()
+    }
+
+    /// Set the profile of `device_name`, or remove it with None.
+    /// Applies right away when playback is on that device.
+    pub fn set_device_profile(&mut self, device_name: &str, profile: Option<DeviceProfile>) {
+        let profiles = &mut self.state.write().device.profiles;
+        match profile {
+            Some(profile) => profiles.insert(device_name.to_string(), profile.clamped()),
+            None => profiles.remove(device_name),
+        };
+        let _ = self
+            .command_tx
+            .send(AudioCommand::RefreshDeviceProfile(device_name.to_string()));
+    }
+
+    pub fn get_buffer_size(&self) -> Option<u32> {
+        self.state.read().buffer_size
+    }
+
+    /// Frames per callback for every device without a buffer size in its profile.
+    /// Reopens the running stream.
+    pub fn set_buffer_size(&mut self, frames: Option<u32>) {
+        self.state.write().buffer_size = frames.map(|f| f.clamp(16, 65_536));
+        let _ = self.command_tx.send(AudioCommand::RefreshBufferSize);
+    }
+
+    pub fn get_output_sink(&self) -> OutputSinkSettings {
+        self.state.read().output_sink.clone()
+    }
+
+    /// Play on the device or on a sink. A running stream moves over, resuming where it was.
+    pub fn set_output_sink(&mut self, settings: OutputSinkSettings) -> Result<(), AudioError> {
+        let settings = settings.clamped();
+        if settings.backend == OutputBackend::File && settings.path.is_none() {
+            return Err(AudioError::Write("No file to write to".to_string()));
+        }
+        self.state.write().output_sink = settings;
+        self.command_tx
+            .send(AudioCommand::RefreshOutputSink)
+            .map_err(|_| AudioError::HostInit)?;
+        Ok(())
+    }
+
+    pub fn get_diagnostics(&self) -> AudioDiagnostics {
+        let state = self.state.read();
+        let latency_frames = self.output.latency_frames.load(Ordering::Relaxed);
+        AudioDiagnostics {
+            buffer_size: state.output_buffer,
+            buffer_range: state.buffer_range,
+            callback_frames: self.output.callback_frames.load(Ordering::Relaxed),
+            latency_ms: if state.sample_rate > 0 {
+                latency_frames as f64 * 1000.0 / state.sample_rate as f64
+            } else {
+                0.0
+            },
+            underruns: self.output.underruns.load(Ordering::Relaxed),
+            xruns: self.output.xruns.load(Ordering::Relaxed),
+            convolution_latency_ms: if state.sample_rate > 0 {
+                self.output.dsp_latency_frames.load(Ordering::Relaxed) as f64 * 1000.0
+                    / state.sample_rate as f64
+            } else {
+                0.0
+            },
+            convolution_load: f32::from_bits(self.output.convolution_load.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn get_visualizer(&self) -> VisualizerSettings {
+        self.state.read().visualizer
+    }
+
+    /// Start or stop "visualizer-frame" events, or change how they are computed
+    pub fn set_visualizer(&mut self, settings: VisualizerSettings) {
+        let settings = settings.clamped();
+        self.state.write().visualizer = settings;
+        self.output
+            .tap_enabled
+            .store(settings.enabled, Ordering::Relaxed);
+        self.visualizer.set_settings(settings);
+    }
+
+    pub fn get_dsd(&self) -> DsdSettings {
+        self.state.read().dsd
+    }
+
+    /// Takes effect from the next track
+    pub fn set_dsd(&mut self, settings: DsdSettings) {
+        self.state.write().dsd = DsdSettings {
+            pcm_rate: settings.pcm_rate.clamp(44_100, 705_600),
+            ..settings
+        };
+    }
+
+    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
+        self.state.write().repeat_mode = mode.clone();
+        self.queue.write().set_repeat_mode(mode);
+        let _ = self.command_tx.send(AudioCommand::QueueChanged);
+    }
+}
+
+/// DSP settings of an offline render, copied from the engine so the render runs unlocked
+pub struct RenderContext {
+    pub state: PlaybackState,
+    pub equalizer: EqSettings,
+    pub impulse: Option<Arc<ImpulseResponse>>,
+    pub dither: DitherMode,
+}
+
+/// Play `file_path` through a decoder and output renderer set up as for playback, into
+/// `output_path` instead of a device. Runs as fast as decoding allows, and the same file
+/// and settings always give the same result. The volume is left at full.
+pub fn render_offline(
+    file_path: &str,
+    output_path: &str,
+    options: &RenderOptions,
+    mut context: RenderContext,
+) -> Result<RenderSummary, AudioError> {
+    options.validate()?;
+    let source = TrackSource::open(file_path, None, DsdOutput::Pcm(context.state.dsd.pcm_rate))?;
+    let spec = source.spec();
+    let sample_rate = options.sample_rate.unwrap_or(spec.sample_rate);
+    let channels = options.channels.unwrap_or(spec.channels);
+    println!(
+        "[Audio] Rendering {} at {}Hz/{}ch/{}-bit to {}",
+        file_path, sample_rate, channels, options.bit_depth, output_path
+    );
+    let mut writer = RenderWriter::create(output_path, options, sample_rate, channels)?;
+
+    context.state.output_integer_bits = options.dither_bits().unwrap_or(0) as u16;
+    let state = Arc::new(RwLock::new(context.state));
+    let output = Arc::new(OutputShared::new());
+    output.set_dither(context.dither);
+    output.set_playing(true);
+    let (producer, consumer) =
+        sample_ring(sample_rate as usize * channels as usize * QUEUE_SECONDS);
+    let timeline = Arc::new(RwLock::new(TrackTimeline::default()));
+    let decoder = DecoderHandle::spawn(
+        file_path,
+        None,
+        source,
+        sample_rate,
+        channels,
+        producer,
+        Arc::clone(&output),
+        Arc::clone(&timeline),
+        Arc::clone(&state),
+    );
+    // A render is one track from its start, never seeked
+    output.set_untouched_until(
+        timeline
+            .read()
+            .untouched_until(0, |entry| is_untouched(entry, &state.read())),
+    );
+
+    // Nothing swaps DSP stages or reads the visualizer tap during a render
+    let (_dsp_tx, dsp_updates) = mpsc::sync_channel(1);
+    let (dsp_retired, _dsp_retired) = mpsc::sync_channel(1);
+    let (tap, _) = visualizer::tap(sample_rate, channels);
+    let mut renderer = OutputRenderer::new(
+        consumer,
+        Arc::clone(&output),
+        sample_rate,
+        channels,
+        false,
+        tap,
+        dsp_updates,
+        dsp_retired,
+    );
+    renderer.install_dsp(
+        &state.read(),
+        &context.equalizer,
+        context.impulse.as_deref(),
+        spec.channels,
+    );
+
+    let mut chunk = vec![0.0; channels as usize * RENDER_CHUNK_FRAMES];
+    while !output.is_finished() {
+        // Wait for a whole chunk, so the renderer never sees the decoder fall behind
+        while renderer.consumer.available() < chunk.len()
+            && !output.end_of_stream.load(Ordering::Acquire)
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let tail = renderer.convolution_tail;
+        let written = renderer.render_chunk(&mut chunk, options.dither_bits());
+        // After the last of the decoded audio, only the convolver's tail is kept
+        let mut samples = written;
+        if let Some(convolver) = renderer
+            .convolver
+            .as_ref()
+            .filter(|_| written < chunk.len())
+        {
+            let tail = if written > 0 { convolver.tail() } else { tail };
+            samples = (written + tail * channels as usize).min(chunk.len());
+        }
+        writer.write(&chunk[..samples])?;
+    }
+    drop(decoder);
+
+    let summary = writer.finish()?;
+    println!(
+        "[Audio] Rendered {} frames, peak {:.1}dBFS",
+        summary.frames, summary.peak_db
+    );
+    Ok(summary)
+}
+
+/// The playback state with the live values of the running stream filled in
+fn snapshot(
+    state: &RwLock<PlaybackState>,
+    output: &OutputShared,
+    timeline: &RwLock<TrackTimeline>,
+    equalizer: &EqSettings,
+) -> PlaybackState {
+    let mut state = state.read().clone();
+    // Live values come straight from the output callback's atomics
+    state.is_playing = output.is_playing();
+    state.volume = output.volume();
+    let frames_played = output.audible_frames();
+    let timeline = timeline.read();
+    // A pending handoff means the next track is about to start, not that playback ended
+    state.track_finished = output.is_finished() && timeline.pending_handoff.is_none();
+    if let Some(entry) = timeline.current(frames_played) {
+        state.current_track = Some(entry.path.clone());
+        state.duration = entry.duration;
+        state.bit_depth = entry.bit_depth;
+        state.replaygain_gain = entry.gain;
+        state.dsd_rate = entry.dsd_rate;
+        state.track_playthrough = entry.playthrough;
+        state.source_channels = entry.channels;
+        if state.sample_rate > 0 {
+            let frames = frames_played.saturating_sub(entry.start_frame);
+            state.position = frames as f64 / state.sample_rate as f64;
+        }
+        // DoP bypasses the output DSP; PCM is only exact if none of it runs
+        let processed = state.volume != 1.0
+            || equalizer.is_active()
+            || crossfeed_applies(&state.crossfeed, state.channels, entry.channels)
+            || state.convolution.is_active()
+            || (state.output_integer_bits == 16
+                && matches!(output.dither(), DitherMode::Tpdf | DitherMode::NoiseShaped));
+        state.bit_perfect = is_untouched(entry, &state) && (entry.dsd_rate.is_some() || !processed);
+    }
+    state
+}
+
+/// The decoder hands `entry`'s samples on as they are: no gain, resampling, channel
+/// conversion or speed change, in an output format that holds them. Samples are carried
+/// as f32, which holds up to 24 significant bits exactly. DoP delivers DSD bits as they
+/// are, converted DSD never is.
+fn is_untouched(entry: &TimelineEntry, state: &PlaybackState) -> bool {
+    if entry.dsd_rate.is_some() {
+        return state.dop;
+    }
+    entry.native
+        && !state.speed.is_active()
+        && entry.bit_depth <= 24
+        && state.output_integer_bits >= entry.bit_depth
+        && entry.gain == 1.0
+}
+
+/// Internal audio thread that owns the non-Send cpal::Stream
+#[allow(dead_code)]
+struct AudioThread {
+    host: cpal::Host,
+    device: Option<cpal::Device>, // None plays on the system default
+    stream: Option<OutputStream>,
+    sink_file: Option<SinkFile>, // Kept open between streams of the file sink
+    decoder: Option<DecoderHandle>,
+    state: Arc<RwLock<PlaybackState>>,
+    output: Arc<OutputShared>,
+    timeline: Arc<RwLock<TrackTimeline>>,
+    device_list: Arc<RwLock<Vec<String>>>,
+    queue: Arc<RwLock<PlayQueue>>,
+    command_rx: mpsc::Receiver<AudioCommand>,
+    output_sample_rate: Option<u32>, // The sample rate the stream is outputting at
+    output_channels: Option<u16>,    // The channel count the stream is outputting
+    crossfade: CrossfadeSettings,
+    equalizer: Arc<RwLock<EqSettings>>,
+    impulse: Arc<RwLock<Option<Arc<ImpulseResponse>>>>,
+    visualizer: Visualizer,
+    volume: f32,       // Volume asked for, before the device's volume limit
+    volume_limit: f32, // From the profile of the device in use
+    dsp_tx: Option<mpsc::SyncSender<DspUpdate>>, // DSP stages for the running stream's callback
+    dsp_retired: Option<mpsc::Receiver<DspUpdate>>,
+    dsp_dirty: bool, // An update did not fit into the channel and has to be resent
+    source_channels: u16, // Channel count of the audible source, which decides on crossfeed
+    events: EventPublisher,
+    plays: PlayRecorder,
+    streams_started: u64, // Counts play_internal calls, telling apart playthroughs of one file
+    queue_playing: bool,  // The stream plays the queue, so it advances when a track ends
+    byte_limit: Option<Arc<AtomicU64>>, // Shared with the decoder of a progressive stream
+    devices_scanned: Instant,
+    next_queued: Option<String>, // Upcoming queue track last handed to the decoder
+}
+
+impl AudioThread {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        state: Arc<RwLock<PlaybackState>>,
+        output: Arc<OutputShared>,
+        timeline: Arc<RwLock<TrackTimeline>>,
+        device_list: Arc<RwLock<Vec<String>>>,
+        queue: Arc<RwLock<PlayQueue>>,
+        equalizer: Arc<RwLock<EqSettings>>,
+        impulse: Arc<RwLock<Option<Arc<ImpulseResponse>>>>,
+        visualizer: Visualizer,
+        command_rx: mpsc::Receiver<AudioCommand>,
+        events: EventSink,
+        plays: PlayRecorder,
+    ) -> Self {
+        // Initialize audio host on this thread
+        #[cfg(target_os = "windows")]
+        let host =
+            cpal::host_from_id(cpal::HostId::Wasapi).unwrap_or_else(|_| cpal::default_host());
 
         #[cfg(not(target_os = "windows"))]
         let host = cpal::default_host();
 
-        let device = host.default_output_device();
-
         // Populate device list
         if let Ok(devices) = host.output_devices() {
             let names: Vec<String> = devices.filter_map(|d| d.name().ok()).collect();
@@ -344,232 +1407,556 @@ impl AudioThread {
 
         Self {
             host,
-            device,
+            device: None,
             stream: None,
+            sink_file: None,
+            decoder: None,
             state,
-            sample_buffer,
-            buffer_position,
+            output,
+            timeline,
             device_list,
+            queue,
             command_rx,
             output_sample_rate: None,
             output_channels: None,
+            crossfade: CrossfadeSettings::default(),
+            equalizer,
+            impulse,
+            visualizer,
+            volume: 1.0,
+            volume_limit: 1.0,
+            dsp_tx: None,
+            dsp_retired: None,
+            dsp_dirty: false,
+            source_channels: 0,
+            events: EventPublisher::new(events),
+            plays,
+            streams_started: 0,
+            queue_playing: false,
+            next_queued: None,
+            byte_limit: None,
+            devices_scanned: Instant::now(),
         }
     }
 
     fn run(mut self) {
         loop {
-            match self.command_rx.recv() {
+            // Wake up regularly to start a handed-off track once the current one has played out
+            match self.command_rx.recv_timeout(Duration::from_millis(20)) {
                 Ok(AudioCommand::Play(path)) => {
+                    self.queue_playing = false;
                     if let Err(e) = self.play_internal(&path, None) {
-                        log::error!("Playback error: {}", e);
+                        self.report_error("Playback error", e);
                     }
                 }
                 Ok(AudioCommand::PlayWithLimit(path, limit)) => {
+                    self.queue_playing = false;
                     if let Err(e) = self.play_internal(&path, Some(limit)) {
-                        log::error!("Playback with limit error: {}", e);
+                        self.report_error("Playback with limit error", e);
                     }
                 }
                 Ok(AudioCommand::AppendSamples(path)) => {
                     if let Err(e) = self.append_samples_internal(&path) {
-                        log::error!("Append samples error: {}", e);
+                        self.report_error("Append samples error", e);
                     }
                 }
                 Ok(AudioCommand::AppendFromOffset(path, offset, limit)) => {
                     if let Err(e) = self.append_from_offset_internal(&path, offset, limit) {
-                        log::error!("Append from offset error: {}", e);
+                        self.report_error("Append from offset error", e);
                     }
                 }
                 Ok(AudioCommand::Pause) => {
-                    self.state.write().is_playing = false;
+                    self.output.set_playing(false);
                 }
                 Ok(AudioCommand::Resume) => {
-                    self.state.write().is_playing = true;
+                    self.output.set_playing(true);
                 }
                 Ok(AudioCommand::Stop) => {
+                    self.queue_playing = false;
                     self.stop_internal();
                 }
                 Ok(AudioCommand::Seek(position)) => {
                     self.seek_internal(position);
                 }
                 Ok(AudioCommand::SetVolume(volume)) => {
-                    self.state.write().volume = volume.clamp(0.0, 1.0);
+                    self.volume = volume.clamp(0.0, 1.0);
+                    self.output.set_volume(self.volume.min(self.volume_limit));
+                }
+                Ok(AudioCommand::SetDevice(_)) => {
+                    self.set_device_internal();
+                }
+                Ok(AudioCommand::RefreshBufferSize) => {
+                    self.restart_stream();
+                }
+                Ok(AudioCommand::RefreshDeviceProfile(name)) => {
+                    self.refresh_device_profile(&name);
+                }
+                Ok(AudioCommand::RefreshOutputSink) => {
+                    self.refresh_output_sink();
+                }
+                Ok(AudioCommand::PlayQueued) => {
+                    self.play_queued();
+                }
+                Ok(AudioCommand::QueueChanged) => {
+                    self.events
+                        .emit(PlaybackEvent::QueueChanged(self.queue.read().snapshot()));
+                    self.queue_next();
+                }
+                Ok(AudioCommand::SetCrossfade(settings)) => {
+                    self.crossfade = settings;
+                }
+                Ok(AudioCommand::RefreshGain) => {
+                    if let Some(decoder) = &self.decoder {
+                        decoder.send(DecoderCommand::RefreshGain);
+                    }
+                }
+                Ok(AudioCommand::RefreshDownmix) => {
+                    if let Some(decoder) = &self.decoder {
+                        decoder.send(DecoderCommand::RefreshDownmix);
+                    }
+                }
+                Ok(AudioCommand::RefreshSpeed) => {
+                    if let Some(decoder) = &self.decoder {
+                        decoder.send(DecoderCommand::RefreshSpeed);
+                    }
+                }
+                Ok(AudioCommand::RefreshEqualizer) => {
+                    self.push_equalizer();
+                }
+                Ok(AudioCommand::RefreshCrossfeed) => {
+                    self.push_crossfeed();
                 }
-                Ok(AudioCommand::SetDevice(name)) => {
-                    self.set_device_internal(&name);
+                Ok(AudioCommand::RefreshConvolution) => {
+                    self.push_convolver();
                 }
-                Ok(AudioCommand::Shutdown) | Err(_) => {
+                Ok(AudioCommand::SetDither(mode)) => {
+                    self.output.set_dither(mode);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.check_track_end();
+                    self.check_devices();
+                    self.collect_dsp();
+                }
+                Ok(AudioCommand::Shutdown) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.stop_internal();
+                    self.close_sink_file();
                     break;
                 }
             }
+            self.publish_events();
         }
     }
 
-    fn set_device_internal(&mut self, device_name: &str) {
-        if let Ok(devices) = self.host.output_devices() {
-            self.device = devices
-                .filter(|d| d.name().map(|n| n == device_name).unwrap_or(false))
-                .next();
+    /// Tell the frontend what changed since the last pass through the loop
+    fn publish_events(&mut self) {
+        let state = snapshot(
+            &self.state,
+            &self.output,
+            &self.timeline,
+            &self.equalizer.read(),
+        );
+        // Crossfeed only suits stereo sources, so it follows the audible track
+        if self.stream.is_some() && state.source_channels != self.source_channels {
+            self.source_channels = state.source_channels;
+            self.push_crossfeed();
+        }
+        self.publish_untouched(&state);
+        let moved_on = self.events.update(&state, self.streams_started);
+        // Only a switch into the track handed to the decoder moves the queue on
+        if moved_on && self.queue_playing && state.current_track == self.next_queued {
+            self.advance_queue();
+            self.queue_next();
         }
     }
 
-    fn stop_internal(&mut self) {
-        self.stream = None;
-        let mut state = self.state.write();
-        state.is_playing = false;
-        state.position = 0.0;
-        state.current_track = None;
+    /// Tell the output callback how far Auto dither can stay off. Stored under the timeline
+    /// lock, so a seek or a newly queued track always has the last word.
+    fn publish_untouched(&self, state: &PlaybackState) {
+        let timeline = self.timeline.read();
+        // Until the callback takes up a seek, the frames played are still the old audio's
+        if !self.output.flush_done(self.output.flush_epoch()) {
+            return;
+        }
+        let until = timeline.untouched_until(self.output.frames_played(), |entry| {
+            is_untouched(entry, state)
+        });
+        self.output.set_untouched_until(until);
     }
 
-    fn seek_internal(&mut self, position: f64) {
-        let state = self.state.read();
-        let sample_rate = state.sample_rate;
-        let channels = state.channels;
-        drop(state);
+    fn report_error(&self, context: &str, error: AudioError) {
+        log::error!("{}: {}", context, error);
+        self.events.emit(PlaybackEvent::Error {
+            message: error.to_string(),
+        });
+    }
 
-        let sample_position = (position * sample_rate as f64 * channels as f64) as usize;
-        *self.buffer_position.write() = sample_position;
-        self.state.write().position = position;
+    fn set_device_internal(&mut self) {
+        self.device = self.find_device(self.state.read().device.preferred.as_deref());
+        if self.stream.is_some() {
+            self.restart_stream();
+        } else if let Some(name) = self.device.as_ref().and_then(|d| d.name().ok()) {
+            // Nothing plays yet, the rest of the profile applies when the stream opens
+            self.apply_device_profile(&name);
+        }
+        self.emit_device_changed();
     }
 
-    fn play_internal(
-        &mut self,
-        file_path: &str,
-        byte_limit: Option<u64>,
-    ) -> Result<(), AudioError> {
-        // Stop any current playback
-        self.stop_internal();
+    /// Switch to the EQ and volume limit of the profile of `device_name`. Everything else in
+    /// the profile is picked up by the config selection when the stream opens.
+    fn apply_device_profile(&mut self, device_name: &str) {
+        let profile = self
+            .state
+            .read()
+            .device
+            .profiles
+            .get(device_name)
+            .cloned()
+            .unwrap_or_default();
+        self.volume_limit = profile.volume_limit;
+        self.output.set_volume(self.volume.min(self.volume_limit));
+        if let Some(equalizer) = profile.equalizer {
+            println!("[Audio] Applying the EQ preset of {}", device_name);
+            *self.equalizer.write() = equalizer;
+            self.push_equalizer();
+        }
+    }
 
-        let path = Path::new(file_path);
-        if !path.exists() {
-            return Err(AudioError::FileNotFound(file_path.to_string()));
+    /// Reopen the stream when the profile of the device it plays on changed
+    fn refresh_device_profile(&mut self, device_name: &str) {
+        if self.state.read().output_device.as_deref() != Some(device_name) {
+            return;
         }
+        self.apply_device_profile(device_name);
+        self.restart_stream();
+    }
 
-        // Open the media source - use LimitedFileReader if byte_limit is specified
-        let file =
-            std::fs::File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
+    /// The connected output device called `name`
+    fn find_device(&self, name: Option<&str>) -> Option<cpal::Device> {
+        let name = name?;
+        self.host
+            .output_devices()
+            .ok()?
+            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+    }
 
-        let mss = if let Some(limit) = byte_limit {
-            println!("[Audio] Playing with byte limit: {} bytes", limit);
-            let limited_reader = LimitedFileReader::new(file, limit);
-            MediaSourceStream::new(Box::new(limited_reader), Default::default())
-        } else {
-            MediaSourceStream::new(Box::new(file), Default::default())
+    /// Re-enumerate devices now and then. Playback falls back to the default device when
+    /// its device goes away, and returns to the preferred one when that reconnects.
+    fn check_devices(&mut self) {
+        let lost = self.output.take_device_lost();
+        if !lost && self.devices_scanned.elapsed() < DEVICE_SCAN_INTERVAL {
+            return;
+        }
+        self.devices_scanned = Instant::now();
+        let Ok(devices) = self.host.output_devices() else {
+            return;
         };
-
-        // Create a hint to help the format registry
-        let mut hint = Hint::new();
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            hint.with_extension(ext);
+        let names: Vec<String> = devices.filter_map(|d| d.name().ok()).collect();
+        let mut changed = names != *self.device_list.read();
+        if changed {
+            *self.device_list.write() = names.clone();
         }
 
-        // Probe the media source
-        let format_opts = FormatOptions::default();
-        let metadata_opts = MetadataOptions::default();
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &format_opts, &metadata_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
+        // A sink plays on whatever devices come and go
+        if self.state.read().output_sink.is_virtual() {
+            if changed {
+                self.emit_device_changed();
+            }
+            return;
+        }
 
-        let mut format = probed.format;
+        let settings = self.state.read().device.clone();
+        let connected = |name: &str| names.iter().any(|n| n == name);
+        // The chosen device is gone, or the default one the stream plays on
+        let device_gone = self
+            .device
+            .as_ref()
+            .is_some_and(|d| d.name().map_or(true, |n| !connected(&n)));
+        let output_gone = self.stream.is_some()
+            && self
+                .state
+                .read()
+                .output_device
+                .as_deref()
+                .is_some_and(|n| !connected(n));
+        if lost || device_gone || output_gone {
+            println!("[Audio] Output device disconnected, falling back to the default device");
+            self.device = None;
+            self.restart_stream();
+            changed = true;
+        } else if self.device.is_none() && settings.return_to_preferred {
+            if let Some(preferred) = settings.preferred.filter(|p| connected(p)) {
+                println!(
+                    "[Audio] {} reconnected, moving playback back to it",
+                    preferred
+                );
+                self.device = self.find_device(Some(&preferred));
+                self.restart_stream();
+                changed = true;
+            }
+        }
+        if changed {
+            self.emit_device_changed();
+        }
+    }
 
-        // Find the first audio track
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or(AudioError::UnsupportedFormat)?;
+    fn emit_device_changed(&self) {
+        let preferred = self.state.read().device.preferred.clone();
+        let sink = self.state.read().output_sink.clone();
+        let device = match &self.device {
+            _ if sink.is_virtual() => Some(sink.label()),
+            Some(device) => device.name().ok(),
+            None => self
+                .host
+                .default_output_device()
+                .and_then(|d| d.name().ok()),
+        };
+        self.events.emit(PlaybackEvent::DeviceChanged {
+            fallback: preferred.is_some() && self.device.is_none() && !sink.is_virtual(),
+            device,
+            devices: self.device_list.read().clone(),
+        });
+    }
 
-        let track_id = track.id;
+    /// Move the running stream to the output backend that was switched to
+    fn refresh_output_sink(&mut self) {
+        // After the last track there is nothing to move, but the old sink has to let go of
+        // its file
+        let ended = self.output.is_finished() && self.timeline.read().pending_handoff.is_none();
+        if ended && matches!(self.stream, Some(OutputStream::Sink(_))) {
+            self.stop_internal();
+        }
+        self.restart_stream();
+        if !matches!(self.stream, Some(OutputStream::Sink(_))) {
+            self.close_sink_file();
+        }
+        self.emit_device_changed();
+    }
 
-        // Get audio parameters
-        let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
-        let channels = track
-            .codec_params
-            .channels
-            .map(|c| c.count() as u16)
-            .unwrap_or(2);
-        let bit_depth = track.codec_params.bits_per_sample.unwrap_or(16) as u16;
+    /// Complete the file sink's file, which no stream writes to any more
+    fn close_sink_file(&mut self) {
+        if let Some(file) = self.sink_file.take() {
+            file.finish();
+        }
+    }
 
-        log::info!(
-            "Decoded audio: {}Hz, {} channels, {}-bit, codec: {:?}",
-            sample_rate,
-            channels,
-            bit_depth,
-            track.codec_params.codec
+    /// Move the running stream to the current device, resuming where it was
+    fn restart_stream(&mut self) {
+        if self.stream.is_none() {
+            return;
+        }
+        let state = snapshot(
+            &self.state,
+            &self.output,
+            &self.timeline,
+            &self.equalizer.read(),
         );
+        let Some(path) = state.current_track.filter(|_| !state.track_finished) else {
+            return;
+        };
+        let limit = self
+            .byte_limit
+            .as_ref()
+            .map(|limit| limit.load(Ordering::Acquire));
+        if let Err(e) = self.play_internal(&path, limit) {
+            self.report_error("Playback error", e);
+            return;
+        }
+        // The same playthrough goes on, only in a new stream
+        self.events.stream_restarted(self.streams_started);
+        if state.position > 0.0 {
+            // Stay silent until the callback dropped what was decoded from the start
+            self.output.set_playing(false);
+            let epoch = self.output.flush_epoch();
+            self.seek_internal(state.position);
+            let deadline = Instant::now() + RESTART_SEEK_TIMEOUT;
+            while !(self.output.flush_epoch() > epoch && self.output.flush_done(epoch + 1))
+                && Instant::now() < deadline
+            {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        self.output.set_playing(state.is_playing);
+        self.next_queued = None;
+        self.queue_next();
+    }
 
-        // Calculate duration
-        let _duration = track
-            .codec_params
-            .n_frames
-            .map(|frames| frames as f64 / sample_rate as f64)
-            .unwrap_or(0.0);
+    fn stop_internal(&mut self) {
+        if let Some(OutputStream::Sink(sink)) = self.stream.take() {
+            self.sink_file = sink.stop();
+        }
+        self.decoder = None;
+        self.dsp_tx = None;
+        self.dsp_retired = None;
+        self.dsp_dirty = false;
+        self.output.set_playing(false);
+        self.timeline.write().clear();
+        let mut state = self.state.write();
+        state.position = 0.0;
+        state.current_track = None;
+    }
 
-        // Create decoder
-        let dec_opts = DecoderOptions::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
+    fn play_queued(&mut self) {
+        let Some(path) = self.queue.read().current().map(|t| t.file_path.clone()) else {
+            return;
+        };
+        self.emit_queue_position();
+        self.queue_playing = true;
+        self.next_queued = None;
+        if let Err(e) = self.play_internal(&path, None) {
+            self.queue_playing = false;
+            self.report_error("Playback error", e);
+            return;
+        }
+        self.queue_next();
+    }
 
-        // Decode all samples into buffer (for simplicity - production would stream)
-        let mut samples: Vec<f32> = Vec::new();
+    /// The queue moved on to its upcoming track by itself
+    fn advance_queue(&mut self) {
+        let played = self.queue.write().advance().map(|track| track.id);
+        if let Some(id) = played {
+            (self.plays)(id);
+        }
+        self.next_queued = None;
+        self.emit_queue_position();
+    }
 
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(symphonia::core::errors::Error::IoError(_)) => break,
-                Err(e) => {
-                    log::warn!("Error reading packet: {}", e);
-                    break;
-                }
-            };
+    fn emit_queue_position(&self) {
+        self.events.emit(PlaybackEvent::QueuePosition {
+            position: self.queue.read().position(),
+        });
+    }
 
-            if packet.track_id() != track_id {
-                continue;
+    /// Hand the queue's upcoming track to the decoder, so it follows the current one
+    /// without a gap, or start it now if the current track already ended
+    fn queue_next(&mut self) {
+        if !self.queue_playing {
+            return;
+        }
+        let next = self.queue.read().upcoming().map(|t| t.file_path.clone());
+        if next == self.next_queued {
+            return;
+        }
+        self.next_queued = next.clone();
+        match &self.decoder {
+            Some(decoder) if !self.output.is_finished() => {
+                // The decoder mixes the overlap, resampling the incoming track if its rate differs,
+                // and skips the fade when both tracks belong to the same album
+                let crossfade = Some(self.crossfade).filter(|c| c.is_enabled());
+                decoder.send(DecoderCommand::SetNext(next, crossfade));
             }
+            _ => {
+                if next.is_some() && self.state.read().current_track.is_some() {
+                    self.events.finish_track();
+                    self.advance_queue();
+                    self.play_queued();
+                }
+            }
+        }
+    }
 
-            match decoder.decode(&packet) {
-                Ok(decoded) => {
-                    // Convert to f32 samples
-                    match decoded {
-                        AudioBufferRef::F32(buf) => {
-                            for frame in 0..buf.frames() {
-                                for ch in 0..buf.spec().channels.count() {
-                                    samples.push(buf.chan(ch)[frame]);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                            for frame in 0..buf.frames() {
-                                for ch in 0..buf.spec().channels.count() {
-                                    samples.push(buf.chan(ch)[frame] as f32 / 32768.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S24(buf) => {
-                            for frame in 0..buf.frames() {
-                                for ch in 0..buf.spec().channels.count() {
-                                    let sample = buf.chan(ch)[frame].0;
-                                    samples.push(sample as f32 / 8388608.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S32(buf) => {
-                            for frame in 0..buf.frames() {
-                                for ch in 0..buf.spec().channels.count() {
-                                    samples.push(buf.chan(ch)[frame] as f32 / 2147483648.0);
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+    /// Start the handed-off track once the current one has played out, and drop
+    /// tracks that are no longer audible from the timeline
+    fn check_track_end(&mut self) {
+        if self.decoder.is_none() {
+            return;
+        }
+        if self.output.is_finished() {
+            let handoff = self.timeline.write().pending_handoff.take();
+            if let Some(path) = handoff {
+                println!("[Audio] Track ended, continuing with {}", path);
+                self.events.finish_track();
+                if self.queue_playing {
+                    self.advance_queue();
                 }
-                Err(e) => {
-                    log::warn!("Decode error: {}", e);
+                if let Err(e) = self.play_internal(&path, None) {
+                    self.report_error("Playback error", e);
                 }
+                self.queue_next();
             }
+        } else {
+            self.timeline.write().prune(self.output.audible_frames());
+        }
+    }
+
+    /// Hand an equalizer designed for the running stream's format to the output callback
+    fn push_equalizer(&mut self) {
+        let (Some(tx), Some(rate), Some(channels)) =
+            (&self.dsp_tx, self.output_sample_rate, self.output_channels)
+        else {
+            return;
+        };
+        let equalizer = Equalizer::new(&self.equalizer.read(), rate, channels).map(Box::new);
+        if let Err(mpsc::TrySendError::Full(_)) = tx.try_send(DspUpdate::Equalizer(equalizer)) {
+            self.dsp_dirty = true;
+        }
+    }
+
+    fn push_crossfeed(&mut self) {
+        let (Some(tx), Some(rate), Some(channels)) =
+            (&self.dsp_tx, self.output_sample_rate, self.output_channels)
+        else {
+            return;
+        };
+        let crossfeed = crossfeed_for(
+            &self.state.read().crossfeed,
+            rate,
+            channels,
+            self.source_channels,
+        );
+        if let Err(mpsc::TrySendError::Full(_)) = tx.try_send(DspUpdate::Crossfeed(crossfeed)) {
+            self.dsp_dirty = true;
+        }
+    }
+
+    /// Hand a convolver for the running stream's format to the output callback. It starts
+    /// from silence, so the audio inside the previous one is dropped.
+    fn push_convolver(&mut self) {
+        let (Some(tx), Some(rate), Some(channels)) =
+            (&self.dsp_tx, self.output_sample_rate, self.output_channels)
+        else {
+            return;
+        };
+        let convolver = convolver_for(
+            &self.state.read().convolution,
+            self.impulse.read().as_deref(),
+            rate,
+            channels,
+        );
+        if let Err(mpsc::TrySendError::Full(_)) = tx.try_send(DspUpdate::Convolver(convolver)) {
+            self.dsp_dirty = true;
+        }
+    }
+
+    /// Free DSP stages the callback replaced and resend updates that did not fit
+    fn collect_dsp(&mut self) {
+        if let Some(retired) = &self.dsp_retired {
+            while retired.try_recv().is_ok() {}
+        }
+        if self.dsp_dirty {
+            self.dsp_dirty = false;
+            self.push_equalizer();
+            self.push_crossfeed();
+            self.push_convolver();
         }
+    }
+
+    fn seek_internal(&mut self, position: f64) {
+        // The decoder seeks the demuxer and the callback resets the position once it has flushed
+        if let Some(decoder) = &self.decoder {
+            decoder.send(DecoderCommand::Seek(position));
+        }
+    }
 
-        // Create output stream first to determine output sample rate
+    /// Pick the device and the config to open it with for `source`. A device that cannot
+    /// take DoP gets the source reopened with DSD converted to PCM.
+    fn open_device(
+        &mut self,
+        file_path: &str,
+        byte_limit: &Option<Arc<AtomicU64>>,
+        pcm_rate: u32,
+        source: &mut TrackSource,
+        spec: &mut SourceSpec,
+    ) -> Result<DeviceOutput, AudioError> {
         let device = self
             .device
             .as_ref()
@@ -578,19 +1965,71 @@ impl AudioThread {
             .ok_or(AudioError::NoDevice)?;
 
         // Log device name
-        if let Ok(name) = device.name() {
+        let device_name = device.name().ok();
+        if let Some(name) = &device_name {
             log::info!("[Audio] Device: {}", name);
             println!("[Audio] Device: {}", name);
         }
+        let device_changed = device_name != self.state.read().output_device;
+        if let Some(name) = device_name.as_deref().filter(|_| device_changed) {
+            self.apply_device_profile(name);
+        }
+        let profile = device_name
+            .as_ref()
+            .and_then(|name| self.state.read().device.profiles.get(name).cloned())
+            .unwrap_or_default();
+        let max_rate = profile.max_sample_rate.unwrap_or(u32::MAX);
+
+        let supported_configs: Vec<_> = device
+            .supported_output_configs()
+            .map_err(|e| AudioError::DeviceConfig(e.to_string()))?
+            .collect();
+
+        // DoP needs the exact rate and channel count in a format that keeps 24 bits untouched
+        let takes_dop = !profile.resample
+            && spec.sample_rate <= max_rate
+            && supported_configs.iter().any(|c| {
+                c.channels() == spec.channels
+                    && c.sample_format() == cpal::SampleFormat::I32
+                    && c.min_sample_rate().0 <= spec.sample_rate
+                    && c.max_sample_rate().0 >= spec.sample_rate
+            });
+        if spec.dop && !takes_dop {
+            println!(
+                "[Audio] Device does not take DoP at {}Hz/{}ch - converting DSD to PCM",
+                spec.sample_rate, spec.channels
+            );
+            *source = TrackSource::open(file_path, byte_limit.clone(), DsdOutput::Pcm(pcm_rate))?;
+            *spec = source.spec();
+        }
+        let sample_rate = spec.sample_rate;
+        let channels = spec.channels;
+        let bit_depth = spec.bit_depth;
+
+        // A resampling profile keeps the device at one rate: its limit, or else the default rate
+        let rate = if profile.resample {
+            match profile.max_sample_rate {
+                Some(rate) => rate,
+                None => {
+                    device
+                        .default_output_config()
+                        .map_err(|e| AudioError::DeviceConfig(e.to_string()))?
+                        .sample_rate()
+                        .0
+                }
+            }
+        } else {
+            sample_rate
+        };
+        // DoP carries DSD bits in I32 samples, whatever format the profile asks for
+        let format = profile
+            .sample_format
+            .filter(|_| !spec.dop)
+            .map(OutputFormat::sample_format);
 
         // Find the best supported configuration - prioritize EXACT match first, then highest quality
         // ONLY resample when absolutely necessary
-        let config = {
-            let supported_configs: Vec<_> = device
-                .supported_output_configs()
-                .map_err(|e| AudioError::DeviceConfig(e.to_string()))?
-                .collect();
-
+        let (mut config, sample_format, supported_buffer) = {
             // Log ALL supported configurations for debugging
             println!("=== Device Supported Configurations ===");
             for (i, cfg) in supported_configs.iter().enumerate() {
@@ -610,415 +2049,357 @@ impl AudioThread {
             );
 
             // First, try to find exact match for file's sample rate and channels
-            let exact_match = supported_configs.iter().find(|c| {
-                c.channels() == channels
-                    && c.min_sample_rate().0 <= sample_rate
-                    && c.max_sample_rate().0 >= sample_rate
-            });
-
-            if let Some(_config_range) = exact_match {
-                // Use the file's exact sample rate - NO RESAMPLING NEEDED
-                println!(
-                    "[Audio] ✓ EXACT MATCH: Device supports {}Hz/{}ch - NO resampling!",
-                    sample_rate, channels
-                );
-                StreamConfig {
-                    channels,
-                    sample_rate: cpal::SampleRate(sample_rate),
-                    buffer_size: cpal::BufferSize::Default,
-                }
-            } else {
-                // Try with 2 channels if file has different channel count
-                let stereo_match = supported_configs.iter().find(|c| {
-                    c.channels() == 2
-                        && c.min_sample_rate().0 <= sample_rate
-                        && c.max_sample_rate().0 >= sample_rate
-                });
+            let exact_match = best_format(
+                supported_configs.iter().filter(|c| {
+                    c.channels() == channels
+                        && rate <= max_rate
+                        && c.min_sample_rate().0 <= rate
+                        && c.max_sample_rate().0 >= rate
+                }),
+                bit_depth,
+                format,
+            );
 
-                if let Some(_config_range) = stereo_match {
+            if let Some(config_range) = exact_match {
+                // Use the file's exact sample rate (or the profile's fixed one) as it is
+                if rate == sample_rate {
                     println!(
-                        "[Audio] ✓ Sample rate match with stereo: {}Hz/2ch",
-                        sample_rate
+                        "[Audio] ✓ EXACT MATCH: Device supports {}Hz/{}ch - NO resampling!",
+                        rate, channels
                     );
+                } else {
+                    println!(
+                        "[Audio] Profile resamples to a fixed rate: {}Hz -> {}Hz/{}ch",
+                        sample_rate, rate, channels
+                    );
+                }
+                (
                     StreamConfig {
-                        channels: 2,
-                        sample_rate: cpal::SampleRate(sample_rate),
+                        channels,
+                        sample_rate: cpal::SampleRate(rate),
                         buffer_size: cpal::BufferSize::Default,
-                    }
+                    },
+                    config_range.sample_format(),
+                    *config_range.buffer_size(),
+                )
+            } else {
+                // Try with 2 channels if file has different channel count
+                let stereo_match = best_format(
+                    supported_configs.iter().filter(|c| {
+                        c.channels() == 2
+                            && rate <= max_rate
+                            && c.min_sample_rate().0 <= rate
+                            && c.max_sample_rate().0 >= rate
+                    }),
+                    bit_depth,
+                    format,
+                );
+
+                if let Some(config_range) = stereo_match {
+                    println!("[Audio] ✓ Sample rate match with stereo: {}Hz/2ch", rate);
+                    (
+                        StreamConfig {
+                            channels: 2,
+                            sample_rate: cpal::SampleRate(rate),
+                            buffer_size: cpal::BufferSize::Default,
+                        },
+                        config_range.sample_format(),
+                        *config_range.buffer_size(),
+                    )
                 } else {
                     // No exact sample rate match - find the HIGHEST rate the device supports
-                    let best_config = supported_configs
+                    // within the profile's limit
+                    let usable = |c: &&cpal::SupportedStreamConfigRange| {
+                        (c.channels() == channels || c.channels() == 2)
+                            && c.min_sample_rate().0 <= max_rate
+                            && format_preference(c.sample_format(), bit_depth).is_some()
+                    };
+                    let highest_rate =
+                        |c: &cpal::SupportedStreamConfigRange| c.max_sample_rate().0.min(max_rate);
+                    let best_rate = supported_configs
                         .iter()
-                        .filter(|c| c.channels() == channels || c.channels() == 2)
-                        .max_by_key(|c| c.max_sample_rate().0);
+                        .filter(usable)
+                        .map(highest_rate)
+                        .max();
+                    let best_config = best_rate.and_then(|rate| {
+                        best_format(
+                            supported_configs
+                                .iter()
+                                .filter(usable)
+                                .filter(|c| highest_rate(c) == rate),
+                            bit_depth,
+                            format,
+                        )
+                    });
 
                     if let Some(config_range) = best_config {
-                        let best_rate = config_range.max_sample_rate().0;
+                        let best_rate = highest_rate(config_range);
                         let best_channels = config_range.channels();
                         println!(
                             "[Audio] ✗ RESAMPLING NEEDED: {}Hz -> {}Hz (device max: {}Hz/{}ch)",
                             sample_rate, best_rate, best_rate, best_channels
-                        );
-                        StreamConfig {
-                            channels: best_channels,
-                            sample_rate: cpal::SampleRate(best_rate),
-                            buffer_size: cpal::BufferSize::Default,
-                        }
+                        );
+                        (
+                            StreamConfig {
+                                channels: best_channels,
+                                sample_rate: cpal::SampleRate(best_rate),
+                                buffer_size: cpal::BufferSize::Default,
+                            },
+                            config_range.sample_format(),
+                            *config_range.buffer_size(),
+                        )
                     } else {
                         // Last resort: use device default
                         println!("[Audio] No suitable config, using device default");
                         let default_config = device
                             .default_output_config()
                             .map_err(|e| AudioError::DeviceConfig(e.to_string()))?;
-                        StreamConfig {
-                            channels: default_config.channels(),
-                            sample_rate: default_config.sample_rate(),
-                            buffer_size: cpal::BufferSize::Default,
-                        }
+                        let format = Some(default_config.sample_format())
+                            .filter(|f| format_preference(*f, bit_depth).is_some())
+                            .unwrap_or(cpal::SampleFormat::F32);
+                        (
+                            StreamConfig {
+                                channels: default_config.channels(),
+                                sample_rate: default_config.sample_rate(),
+                                buffer_size: cpal::BufferSize::Default,
+                            },
+                            format,
+                            *default_config.buffer_size(),
+                        )
                     }
                 }
             }
         };
 
-        let output_sample_rate = config.sample_rate.0;
-        let output_channels = config.channels;
+        // The device's profile overrides the buffer size chosen for every device
+        let frames = profile.buffer_size.or(self.state.read().buffer_size);
+        config.buffer_size = buffer_size(&supported_buffer, frames);
+        if let cpal::BufferSize::Fixed(frames) = config.buffer_size {
+            println!("[Audio] Buffer size: {} frames", frames);
+        }
+
+        Ok(DeviceOutput {
+            device,
+            name: device_name,
+            config,
+            sample_format,
+            supported_buffer,
+        })
+    }
+
+    fn play_internal(
+        &mut self,
+        file_path: &str,
+        byte_limit: Option<u64>,
+    ) -> Result<(), AudioError> {
+        // Stop any current playback
+        self.stop_internal();
+
+        if let Some(limit) = byte_limit {
+            println!("[Audio] Playing with byte limit: {} bytes", limit);
+        }
+        let byte_limit = byte_limit.map(|limit| Arc::new(AtomicU64::new(limit)));
+        self.byte_limit = byte_limit.clone();
+
+        // Only probe the file here - decoding happens on the decoder thread
+        let dsd = self.state.read().dsd;
+        let dsd_output = if dsd.dop {
+            DsdOutput::Dop
+        } else {
+            DsdOutput::Pcm(dsd.pcm_rate)
+        };
+        let mut source = TrackSource::open(file_path, byte_limit.clone(), dsd_output)?;
+        self.streams_started += 1;
+        let mut spec = source.spec();
+
+        let sink = self.state.read().output_sink.clone();
+        let device = if sink.is_virtual() {
+            // Only a DAC makes sense of DoP, so a sink gets PCM
+            if spec.dop {
+                source =
+                    TrackSource::open(file_path, byte_limit.clone(), DsdOutput::Pcm(dsd.pcm_rate))?;
+                spec = source.spec();
+            }
+            let label = sink.label();
+            println!("[Audio] Output: {}", label);
+            if self.state.read().output_device.as_deref() != Some(label.as_str()) {
+                self.apply_device_profile(&label);
+            }
+            None
+        } else {
+            Some(self.open_device(file_path, &byte_limit, dsd.pcm_rate, &mut source, &mut spec)?)
+        };
+        let sample_rate = spec.sample_rate;
+        let channels = spec.channels;
+        let bit_depth = spec.bit_depth;
+        let (output_sample_rate, output_channels) = match &device {
+            Some(device) => (device.config.sample_rate.0, device.config.channels),
+            None => sink.format(sample_rate, channels),
+        };
+        let sample_format = match &device {
+            Some(device) => device.sample_format.to_string(),
+            None => sink.sample_format().to_string(),
+        };
+        let period_frames = self.state.read().buffer_size.unwrap_or(SINK_PERIOD_FRAMES);
 
         // Store the output format for use by append_samples
         self.output_sample_rate = Some(output_sample_rate);
         self.output_channels = Some(output_channels);
 
         println!(
-            "[Audio] Final: Source {}Hz/{}ch -> Output {}Hz/{}ch",
-            sample_rate, channels, output_sample_rate, output_channels
+            "[Audio] Final: Source {}Hz/{}ch/{}-bit -> Output {}Hz/{}ch/{}",
+            sample_rate, channels, bit_depth, output_sample_rate, output_channels, sample_format
         );
 
-        // Resample if sample rates differ
-        let final_samples = if sample_rate != output_sample_rate {
-            println!(
-                "[Audio] ⚡ RESAMPLING: {}Hz -> {}Hz",
-                sample_rate, output_sample_rate
-            );
-            resample_audio(&samples, channels as usize, sample_rate, output_sample_rate)?
-        } else {
+        if sample_rate == output_sample_rate {
             println!("[Audio] ✓ NO RESAMPLING NEEDED ({}Hz)", sample_rate);
-            samples
-        };
-
-        // Handle channel conversion if needed
-        let final_samples = if channels != output_channels {
-            println!(
-                "[Audio] Converting channels: {}ch -> {}ch",
-                channels, output_channels
-            );
-            convert_channels(&final_samples, channels as usize, output_channels as usize)
-        } else {
-            final_samples
-        };
-
-        // Calculate duration based on resampled audio
-        let resampled_duration =
-            final_samples.len() as f64 / (output_sample_rate as f64 * output_channels as f64);
+        }
 
-        // Store samples
-        *self.sample_buffer.write() = final_samples;
-        *self.buffer_position.write() = 0;
+        // Lock-free ring between decoder and output: memory stays flat whatever the track length
+        let (producer, consumer) =
+            sample_ring(output_sample_rate as usize * output_channels as usize * QUEUE_SECONDS);
+        self.output.reset();
 
         // Update state
         {
             let mut state = self.state.write();
             state.current_track = Some(file_path.to_string());
-            state.duration = resampled_duration;
+            state.duration = spec.duration().unwrap_or(0.0);
             state.position = 0.0;
             state.sample_rate = output_sample_rate;
             state.bit_depth = bit_depth;
             state.channels = output_channels;
-            state.is_playing = true;
-            state.track_finished = false;
-        }
-
-        let sample_buffer = Arc::clone(&self.sample_buffer);
-        let buffer_position = Arc::clone(&self.buffer_position);
-        let state = Arc::clone(&self.state);
-        let channel_count = output_channels as usize;
-        let sr = output_sample_rate;
-
-        let stream = device
-            .build_output_stream(
-                &config,
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                    let buffer = sample_buffer.read();
-                    let mut pos = buffer_position.write();
-                    let state_read = state.read();
-                    let volume = state_read.volume;
-                    let is_playing = state_read.is_playing;
-                    let was_playing = is_playing;
-                    drop(state_read);
-
-                    let mut finished_this_frame = false;
-
-                    for sample in data.iter_mut() {
-                        if is_playing && *pos < buffer.len() {
-                            *sample = buffer[*pos] * volume;
-                            *pos += 1;
-                        } else {
-                            *sample = 0.0;
-                            // Detect when we've reached the end of the buffer
-                            if was_playing && *pos >= buffer.len() && !buffer.is_empty() {
-                                finished_this_frame = true;
-                            }
-                        }
-                    }
-
-                    // Update position in state
-                    let current_pos = *pos as f64 / (sr as f64 * channel_count as f64);
-                    drop(pos);
-
-                    let mut state_write = state.write();
-                    state_write.position = current_pos;
-
-                    // Set track_finished flag when playback reaches end
-                    if finished_this_frame && !state_write.track_finished {
-                        state_write.track_finished = true;
-                        state_write.is_playing = false;
-                        log::info!("Track playback finished");
-                    }
-                },
-                |err| {
-                    log::error!("Audio stream error: {}", err);
-                },
-                None,
-            )
-            .map_err(|e: cpal::BuildStreamError| AudioError::StreamBuild(e.to_string()))?;
+            state.dop = spec.dop;
+            state.output_sample_format = sample_format;
+            match &device {
+                Some(device) => {
+                    state.output_device = device.name.clone();
+                    state.output_integer_bits = match device.sample_format {
+                        cpal::SampleFormat::I16 => 16,
+                        cpal::SampleFormat::I32 => 32,
+                        _ => 0,
+                    };
+                    state.output_buffer = match device.config.buffer_size {
+                        cpal::BufferSize::Fixed(frames) => Some(frames),
+                        cpal::BufferSize::Default => None,
+                    };
+                    state.buffer_range = match device.supported_buffer {
+                        cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+                        cpal::SupportedBufferSize::Unknown => None,
+                    };
+                }
+                None => {
+                    state.output_device = Some(sink.label());
+                    state.output_integer_bits = sink.integer_bits();
+                    state.output_buffer = Some(period_frames);
+                    state.buffer_range = None;
+                }
+            }
+        }
+        self.output.set_playing(true);
+
+        self.decoder = Some(DecoderHandle::spawn(
+            file_path,
+            byte_limit,
+            source,
+            output_sample_rate,
+            output_channels,
+            producer,
+            Arc::clone(&self.output),
+            Arc::clone(&self.timeline),
+            Arc::clone(&self.state),
+        ));
+
+        let (tap, visualizer_tap) = visualizer::tap(output_sample_rate, output_channels);
+        self.visualizer.attach(visualizer_tap);
+
+        // DSP runs in the callback at the output format, so settings apply to what is heard now
+        let (dsp_tx, dsp_updates) = mpsc::sync_channel(DSP_UPDATE_CAPACITY);
+        let (dsp_retired_tx, dsp_retired) = mpsc::sync_channel(DSP_UPDATE_CAPACITY);
+        self.dsp_tx = Some(dsp_tx);
+        self.dsp_retired = Some(dsp_retired);
+        self.source_channels = spec.channels;
+
+        let mut renderer = OutputRenderer::new(
+            consumer,
+            Arc::clone(&self.output),
+            output_sample_rate,
+            output_channels,
+            spec.dop,
+            tap,
+            dsp_updates,
+            dsp_retired_tx,
+        );
+        renderer.install_dsp(
+            &self.state.read(),
+            &self.equalizer.read(),
+            self.impulse.read().as_deref(),
+            spec.channels,
+        );
 
-        stream
-            .play()
-            .map_err(|e: cpal::PlayStreamError| AudioError::StreamBuild(e.to_string()))?;
+        let stream = match device {
+            Some(device) => OutputStream::Device(device.start(renderer, self.events.sink())?),
+            None => {
+                let dither_bits = sink.dither_bits();
+                OutputStream::Sink(SinkStream::start(
+                    &sink,
+                    self.sink_file.take(),
+                    output_sample_rate,
+                    output_channels,
+                    period_frames,
+                    self.events.sink(),
+                    move |data, late| {
+                        // What follows the end of playback or a pause is not written
+                        let playing = renderer.shared.is_playing();
+                        renderer.render_chunk(data, dither_bits);
+                        renderer.measure_period(data.len(), late);
+                        playing
+                    },
+                )?)
+            }
+        };
         self.stream = Some(stream);
 
         Ok(())
     }
 
-    /// Append samples from a file to the existing buffer (for gapless chunk transitions)
+    /// Queue a file to be decoded right after the current sources (for gapless chunk transitions)
     fn append_samples_internal(&mut self, file_path: &str) -> Result<(), AudioError> {
-        let path = Path::new(file_path);
-        if !path.exists() {
+        if !std::path::Path::new(cue::split_locator(file_path).0).exists() {
             return Err(AudioError::FileNotFound(file_path.to_string()));
         }
 
-        // Get the output format we need to resample to
-        let output_sample_rate = self.output_sample_rate.ok_or_else(|| {
-            AudioError::Decode(
-                "No output sample rate set - play_internal must be called first".to_string(),
-            )
-        })?;
-        let output_channels = self.output_channels.ok_or_else(|| {
-            AudioError::Decode(
-                "No output channels set - play_internal must be called first".to_string(),
-            )
+        let decoder = self.decoder.as_ref().ok_or_else(|| {
+            AudioError::Decode("No active decoder - play_internal must be called first".to_string())
         })?;
 
         log::info!("Appending samples from: {}", file_path);
 
-        // Open and decode the file
-        let file =
-            std::fs::File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
-
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-        let mut hint = Hint::new();
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            hint.with_extension(ext);
-        }
-
-        let format_opts = FormatOptions::default();
-        let metadata_opts = MetadataOptions::default();
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &format_opts, &metadata_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
-
-        let mut format = probed.format;
-
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or(AudioError::UnsupportedFormat)?;
-
-        let track_id = track.id;
-
-        // Extract source sample rate and channels from the file
-        let source_sample_rate = track.codec_params.sample_rate.unwrap_or(output_sample_rate);
-        let source_channels = track
-            .codec_params
-            .channels
-            .map(|c| c.count() as u16)
-            .unwrap_or(output_channels);
-
-        println!(
-            "[Audio] Chunk: {}Hz/{}ch -> Output: {}Hz/{}ch",
-            source_sample_rate, source_channels, output_sample_rate, output_channels
-        );
-
-        let dec_opts = DecoderOptions::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
-
-        // Decode all samples
-        let mut new_samples: Vec<f32> = Vec::new();
-
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(symphonia::core::errors::Error::IoError(_)) => break,
-                Err(e) => {
-                    log::warn!("Error reading packet: {}", e);
-                    break;
-                }
-            };
-
-            if packet.track_id() != track_id {
-                continue;
-            }
-
-            match decoder.decode(&packet) {
-                Ok(decoded) => match decoded {
-                    AudioBufferRef::F32(buf) => {
-                        for frame in 0..buf.frames() {
-                            for ch in 0..buf.spec().channels.count() {
-                                new_samples.push(buf.chan(ch)[frame]);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S16(buf) => {
-                        for frame in 0..buf.frames() {
-                            for ch in 0..buf.spec().channels.count() {
-                                new_samples.push(buf.chan(ch)[frame] as f32 / 32768.0);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S24(buf) => {
-                        for frame in 0..buf.frames() {
-                            for ch in 0..buf.spec().channels.count() {
-                                let sample = buf.chan(ch)[frame].0;
-                                new_samples.push(sample as f32 / 8388608.0);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S32(buf) => {
-                        for frame in 0..buf.frames() {
-                            for ch in 0..buf.spec().channels.count() {
-                                new_samples.push(buf.chan(ch)[frame] as f32 / 2147483648.0);
-                            }
-                        }
-                    }
-                    _ => {}
-                },
-                Err(e) => {
-                    log::warn!("Decode error: {}", e);
-                }
-            }
-        }
-
-        println!(
-            "[Audio] Chunk decoded: {} samples at {}Hz",
-            new_samples.len(),
-            source_sample_rate
-        );
-
-        // Resample if source sample rate differs from output sample rate
-        let resampled_samples = if source_sample_rate != output_sample_rate {
-            println!(
-                "[Audio] ⚡ RESAMPLING CHUNK: {}Hz -> {}Hz",
-                source_sample_rate, output_sample_rate
-            );
-            resample_audio(
-                &new_samples,
-                source_channels as usize,
-                source_sample_rate,
-                output_sample_rate,
-            )?
-        } else {
-            println!(
-                "[Audio] ✓ CHUNK NO RESAMPLE: {}Hz matches output",
-                source_sample_rate
-            );
-            new_samples
-        };
-
-        // Convert channels if needed
-        let final_samples = if source_channels != output_channels {
-            println!(
-                "[Audio] Converting chunk channels: {}ch -> {}ch",
-                source_channels, output_channels
-            );
-            convert_channels(
-                &resampled_samples,
-                source_channels as usize,
-                output_channels as usize,
-            )
-        } else {
-            resampled_samples
-        };
-
-        // Append to existing buffer
-        {
-            let mut buffer = self.sample_buffer.write();
-            let old_len = buffer.len();
-            buffer.extend(final_samples.iter());
-            println!(
-                "[Audio] Appended {} samples (buffer: {} -> {})",
-                final_samples.len(),
-                old_len,
-                buffer.len()
-            );
-        }
-
-        // Update duration in state
-        {
-            let buffer = self.sample_buffer.read();
-            let new_duration =
-                buffer.len() as f64 / (output_sample_rate as f64 * output_channels as f64);
-            drop(buffer);
-
-            let mut state = self.state.write();
-            state.duration = new_duration;
-            // Reset track_finished flag since we have more audio
-            state.track_finished = false;
-            state.is_playing = true;
-        }
+        // Reset track_finished flag since we have more audio
+        self.output.rearm();
+        decoder.send(DecoderCommand::Append(file_path.to_string()));
 
         Ok(())
     }
 
-    /// Append samples from a specific byte range of a file.
-    /// This is used for progressive streaming where we want to decode only newly downloaded data.
-    /// The file is read from `offset` up to `offset + limit` bytes.
+    /// Continue decoding a progressively downloaded file that has grown on disk.
+    /// The decoder may now read the file up to `offset + limit` bytes.
     fn append_from_offset_internal(
         &mut self,
         file_path: &str,
         offset: u64,
         limit: u64,
     ) -> Result<(), AudioError> {
-        let path = Path::new(file_path);
-        if !path.exists() {
+        if !std::path::Path::new(file_path).exists() {
             return Err(AudioError::FileNotFound(file_path.to_string()));
         }
 
-        // Get the output format we need to resample to
-        let output_sample_rate = self.output_sample_rate.ok_or_else(|| {
-            AudioError::Decode(
-                "No output sample rate set - play_internal must be called first".to_string(),
-            )
-        })?;
-        let output_channels = self.output_channels.ok_or_else(|| {
-            AudioError::Decode(
-                "No output channels set - play_internal must be called first".to_string(),
-            )
+        let decoder = self.decoder.as_ref().ok_or_else(|| {
+            AudioError::Decode("No active decoder - play_internal must be called first".to_string())
         })?;
 
         log::info!(
-            "Appending samples from offset {} (limit {} bytes): {}",
+            "Extending byte limit from offset {} (limit {} bytes): {}",
             offset,
             limit,
             file_path
@@ -1028,392 +2409,532 @@ impl AudioThread {
             offset, limit, file_path
         );
 
-        // Open the file and create a limited reader starting from offset
-        let mut file =
-            std::fs::File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
-
-        // Seek to the offset
-        file.seek(SeekFrom::Start(offset))
-            .map_err(|e| AudioError::Decode(format!("Seek failed: {}", e)))?;
+        self.output.rearm();
+        decoder.send(DecoderCommand::ExtendLimit(
+            file_path.to_string(),
+            offset + limit,
+        ));
 
-        // Create a limited reader that reads from current position up to `limit` bytes
-        // But we still need the FLAC header, so we need to create a reader that includes the header
-        // This is tricky - FLAC decoders need the header at the start of the stream
+        Ok(())
+    }
+}
 
-        // For FLAC progressive streaming, we need a different approach:
-        // Re-read the entire file from the start up to (offset + limit) and seek in the decoder
-        let file =
-            std::fs::File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
-        let total_limit = offset + limit;
-        let limited_reader = LimitedFileReader::new(file, total_limit);
+/// Whether crossfeed runs for a source of `source_channels` on an output of `channels`.
+/// Mono has nothing to cross and multichannel is not headphone stereo.
+fn crossfeed_applies(settings: &CrossfeedSettings, channels: u16, source_channels: u16) -> bool {
+    settings.enabled && channels == 2 && source_channels == 2
+}
 
-        let mss = MediaSourceStream::new(Box::new(limited_reader), Default::default());
+fn crossfeed_for(
+    settings: &CrossfeedSettings,
+    sample_rate: u32,
+    channels: u16,
+    source_channels: u16,
+) -> Option<Box<Crossfeed>> {
+    if !crossfeed_applies(settings, channels, source_channels) {
+        return None;
+    }
+    Crossfeed::new(settings, sample_rate, channels).map(Box::new)
+}
 
-        let mut hint = Hint::new();
-        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-            hint.with_extension(ext);
+/// A convolver for the output format, None when convolution is off or fails to set up
+fn convolver_for(
+    settings: &ConvolutionSettings,
+    impulse: Option<&ImpulseResponse>,
+    sample_rate: u32,
+    channels: u16,
+) -> Option<Box<Convolver>> {
+    let impulse = impulse.filter(|_| settings.is_active())?;
+    match Convolver::new(impulse, sample_rate, channels) {
+        Ok(convolver) => Some(Box::new(convolver)),
+        Err(e) => {
+            log::error!("Failed to set up convolution: {}", e);
+            None
         }
+    }
+}
 
-        let format_opts = FormatOptions::default();
-        let metadata_opts = MetadataOptions::default();
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &format_opts, &metadata_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
+/// Preference of an output sample format for a source of `bit_depth` bits, lower is better.
+/// Integer formats at least as wide as the source take the samples unaltered. cpal has no
+/// packed 24-bit format, so 24-bit audio goes out as I32. None for formats we cannot render.
+fn format_preference(format: cpal::SampleFormat, bit_depth: u16) -> Option<u8> {
+    match format {
+        cpal::SampleFormat::I16 if bit_depth <= 16 => Some(0),
+        cpal::SampleFormat::I32 if bit_depth <= 16 => Some(1),
+        cpal::SampleFormat::I32 => Some(0),
+        cpal::SampleFormat::F32 => Some(2),
+        cpal::SampleFormat::I16 => Some(3), // Loses the low bits of high resolution sources
+        _ => None,
+    }
+}
 
-        let mut format = probed.format;
+/// The config range with the most suitable sample format, `preferred` if there is one in it
+fn best_format<'a>(
+    configs: impl Iterator<Item = &'a cpal::SupportedStreamConfigRange>,
+    bit_depth: u16,
+    preferred: Option<cpal::SampleFormat>,
+) -> Option<&'a cpal::SupportedStreamConfigRange> {
+    configs
+        .filter_map(|c| format_preference(c.sample_format(), bit_depth).map(|p| (p, c)))
+        .min_by_key(|(preference, c)| (Some(c.sample_format()) != preferred, *preference))
+        .map(|(_, config)| config)
+}
 
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or(AudioError::UnsupportedFormat)?;
+/// A profile's buffer size in frames, kept within what the device supports
+fn buffer_size(supported: &cpal::SupportedBufferSize, frames: Option<u32>) -> cpal::BufferSize {
+    match (frames, supported) {
+        (Some(frames), cpal::SupportedBufferSize::Range { min, max }) => {
+            cpal::BufferSize::Fixed(frames.clamp(*min, *max))
+        }
+        (Some(frames), cpal::SupportedBufferSize::Unknown) => cpal::BufferSize::Fixed(frames),
+        (None, _) => cpal::BufferSize::Default,
+    }
+}
 
-        let track_id = track.id;
+/// A device and the config picked to open it with
+struct DeviceOutput {
+    device: cpal::Device,
+    name: Option<String>,
+    config: StreamConfig,
+    sample_format: cpal::SampleFormat,
+    supported_buffer: cpal::SupportedBufferSize,
+}
 
-        // Extract source sample rate and channels from the file
-        let source_sample_rate = track.codec_params.sample_rate.unwrap_or(output_sample_rate);
-        let source_channels = track
-            .codec_params
-            .channels
-            .map(|c| c.count() as u16)
-            .unwrap_or(output_channels);
+impl DeviceOutput {
+    fn start(
+        &self,
+        renderer: OutputRenderer,
+        events: EventSink,
+    ) -> Result<cpal::Stream, AudioError> {
+        let (device, config) = (&self.device, &self.config);
+        let stream = match self.sample_format {
+            cpal::SampleFormat::I16 => {
+                build_output_stream::<i16>(device, config, renderer, events)?
+            }
+            cpal::SampleFormat::I32 => {
+                build_output_stream::<i32>(device, config, renderer, events)?
+            }
+            _ => build_output_stream::<f32>(device, config, renderer, events)?,
+        };
+        stream
+            .play()
+            .map_err(|e: cpal::PlayStreamError| AudioError::StreamBuild(e.to_string()))?;
+        Ok(stream)
+    }
+}
 
-        // Calculate how many samples we already have (to skip)
-        let existing_samples = self.sample_buffer.read().len();
+/// Where the output renderer's audio goes
+enum OutputStream {
+    // Only held so the stream keeps playing; dropping it stops the device
+    Device(#[allow(dead_code)] cpal::Stream),
+    Sink(SinkStream),
+}
 
-        // Calculate samples per second for skipping
-        let samples_per_second = output_sample_rate as usize * output_channels as usize;
-        let existing_duration_samples = existing_samples;
+fn build_output_stream<T: OutputSample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut renderer: OutputRenderer,
+    events: EventSink,
+) -> Result<cpal::Stream, AudioError> {
+    let shared = Arc::clone(&renderer.shared);
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                renderer.render_into(data);
+                renderer.measure(&info.timestamp(), data.len());
+            },
+            move |err| {
+                log::error!("Audio stream error: {}", err);
+                // The audio thread moves playback to another device
+                if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+                    shared.set_device_lost();
+                }
+                events(PlaybackEvent::Error {
+                    message: err.to_string(),
+                });
+            },
+            None,
+        )
+        .map_err(|e: cpal::BuildStreamError| AudioError::StreamBuild(e.to_string()))
+}
 
-        // Convert to source sample count for seeking
-        let source_samples_per_second = source_sample_rate as usize * source_channels as usize;
-        let ratio = source_samples_per_second as f64 / samples_per_second as f64;
-        let source_samples_to_skip = (existing_duration_samples as f64 * ratio) as usize;
+/// Sample types the output stream can be built with. Integer sources were scaled by a
+/// power of two when decoded, so converting back is exact at unity gain.
+trait OutputSample: cpal::SizedSample + Send + 'static {
+    /// Width to dither to, for formats narrower than the f32 processing precision
+    const DITHER_BITS: Option<u32>;
 
-        println!(
-            "[Audio] Offset decode: skipping first {} source samples to append new audio",
-            source_samples_to_skip
-        );
+    fn from_f32(sample: f32) -> Self;
+}
 
-        let dec_opts = DecoderOptions::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|e| AudioError::Decode(e.to_string()))?;
+impl OutputSample for f32 {
+    const DITHER_BITS: Option<u32> = None;
 
-        // Decode all samples, but skip the ones we already have
-        let mut new_samples: Vec<f32> = Vec::new();
-        let mut samples_decoded = 0usize;
+    fn from_f32(sample: f32) -> Self {
+        sample
+    }
+}
 
-        loop {
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(symphonia::core::errors::Error::IoError(_)) => break,
-                Err(e) => {
-                    log::warn!("Error reading packet: {}", e);
-                    break;
-                }
-            };
+impl OutputSample for i16 {
+    const DITHER_BITS: Option<u32> = Some(16);
 
-            if packet.track_id() != track_id {
-                continue;
-            }
+    fn from_f32(sample: f32) -> Self {
+        (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+    }
+}
 
-            match decoder.decode(&packet) {
-                Ok(decoded) => {
-                    let frame_samples = match &decoded {
-                        AudioBufferRef::F32(buf) => buf.frames() * buf.spec().channels.count(),
-                        AudioBufferRef::S16(buf) => buf.frames() * buf.spec().channels.count(),
-                        AudioBufferRef::S24(buf) => buf.frames() * buf.spec().channels.count(),
-                        AudioBufferRef::S32(buf) => buf.frames() * buf.spec().channels.count(),
-                        _ => 0,
-                    };
+impl OutputSample for i32 {
+    const DITHER_BITS: Option<u32> = None;
 
-                    // Skip samples we already have
-                    if samples_decoded + frame_samples <= source_samples_to_skip {
-                        samples_decoded += frame_samples;
-                        continue;
-                    }
+    fn from_f32(sample: f32) -> Self {
+        (sample as f64 * 2147483648.0)
+            .round()
+            .clamp(-2147483648.0, 2147483647.0) as i32
+    }
+}
 
-                    // Calculate how many samples to skip in this frame
-                    let skip_in_frame = if samples_decoded < source_samples_to_skip {
-                        source_samples_to_skip - samples_decoded
-                    } else {
-                        0
-                    };
-                    samples_decoded += frame_samples;
-
-                    // Extract samples, skipping the first `skip_in_frame` samples
-                    match decoded {
-                        AudioBufferRef::F32(buf) => {
-                            let channels_count = buf.spec().channels.count();
-                            let mut sample_idx = 0;
-                            for frame in 0..buf.frames() {
-                                for ch in 0..channels_count {
-                                    if sample_idx >= skip_in_frame {
-                                        new_samples.push(buf.chan(ch)[frame]);
-                                    }
-                                    sample_idx += 1;
-                                }
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                            let channels_count = buf.spec().channels.count();
-                            let mut sample_idx = 0;
-                            for frame in 0..buf.frames() {
-                                for ch in 0..channels_count {
-                                    if sample_idx >= skip_in_frame {
-                                        new_samples.push(buf.chan(ch)[frame] as f32 / 32768.0);
-                                    }
-                                    sample_idx += 1;
-                                }
-                            }
-                        }
-                        AudioBufferRef::S24(buf) => {
-                            let channels_count = buf.spec().channels.count();
-                            let mut sample_idx = 0;
-                            for frame in 0..buf.frames() {
-                                for ch in 0..channels_count {
-                                    if sample_idx >= skip_in_frame {
-                                        let sample = buf.chan(ch)[frame].0;
-                                        new_samples.push(sample as f32 / 8388608.0);
-                                    }
-                                    sample_idx += 1;
-                                }
-                            }
-                        }
-                        AudioBufferRef::S32(buf) => {
-                            let channels_count = buf.spec().channels.count();
-                            let mut sample_idx = 0;
-                            for frame in 0..buf.frames() {
-                                for ch in 0..channels_count {
-                                    if sample_idx >= skip_in_frame {
-                                        new_samples.push(buf.chan(ch)[frame] as f32 / 2147483648.0);
-                                    }
-                                    sample_idx += 1;
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                Err(e) => {
-                    log::warn!("Decode error: {}", e);
-                }
-            }
-        }
+/// Frames rendered at a time before conversion to the device's sample format
+const RENDER_CHUNK_FRAMES: usize = 1024;
+
+/// Owned by the real-time output callback. Only touches the ring and atomics:
+/// it never blocks, allocates or takes a lock.
+struct OutputRenderer {
+    consumer: RingConsumer,
+    shared: Arc<OutputShared>,
+    channels: usize,
+    scratch: Vec<f32>, // Whole frames, allocated with the renderer
+    ditherer: Ditherer,
+    equalizer: Option<Box<Equalizer>>,
+    crossfeed: Option<Box<Crossfeed>>,
+    convolver: Option<Box<Convolver>>,
+    /// Frames the convolver still has to put out after the last of the ring's audio
+    convolution_tail: usize,
+    /// Time spent convolving and frames convolved since the load was last published
+    convolution_time: Duration,
+    convolution_frames: usize,
+    dsp_updates: mpsc::Receiver<DspUpdate>,
+    dsp_retired: mpsc::SyncSender<DspUpdate>,
+    /// Every frame is DoP: no DSP, and gaps are filled with DSD silence
+    dop: bool,
+    dop_marker: usize,
+    sample_rate: u32,
+    /// The ring has delivered audio since the stream started or was flushed, so running
+    /// dry now is an underrun rather than the decoder getting going
+    primed: bool,
+    /// When the audio of the previous callback finishes playing
+    playback_end: Option<cpal::StreamInstant>,
+    /// Copy of the output for the visualizer
+    tap: RingProducer,
+    /// Source frames each frame from the ring stands for, and the fraction of a frame
+    /// not yet counted in `frames_played`
+    speed: f64,
+    frame_fraction: f64,
+}
 
-        if new_samples.is_empty() {
-            println!("[Audio] No new samples decoded from offset");
-            return Ok(());
+impl OutputRenderer {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        consumer: RingConsumer,
+        shared: Arc<OutputShared>,
+        sample_rate: u32,
+        channels: u16,
+        dop: bool,
+        tap: RingProducer,
+        dsp_updates: mpsc::Receiver<DspUpdate>,
+        dsp_retired: mpsc::SyncSender<DspUpdate>,
+    ) -> Self {
+        let speed = shared.speed();
+        Self {
+            consumer,
+            shared,
+            channels: channels as usize,
+            scratch: vec![0.0; channels as usize * RENDER_CHUNK_FRAMES],
+            ditherer: Ditherer::new(sample_rate, channels),
+            equalizer: None,
+            crossfeed: None,
+            convolver: None,
+            convolution_tail: 0,
+            convolution_time: Duration::ZERO,
+            convolution_frames: 0,
+            dsp_updates,
+            dsp_retired,
+            dop,
+            dop_marker: 0,
+            sample_rate,
+            primed: false,
+            playback_end: None,
+            tap,
+            speed,
+            frame_fraction: 0.0,
         }
+    }
 
-        println!(
-            "[Audio] Offset decode: {} new samples at {}Hz",
-            new_samples.len(),
-            source_sample_rate
+    /// Set up the DSP stages for the settings, before the renderer runs
+    fn install_dsp(
+        &mut self,
+        state: &PlaybackState,
+        equalizer: &EqSettings,
+        impulse: Option<&ImpulseResponse>,
+        source_channels: u16,
+    ) {
+        let channels = self.channels as u16;
+        self.equalizer = Equalizer::new(equalizer, self.sample_rate, channels).map(Box::new);
+        self.crossfeed = crossfeed_for(
+            &state.crossfeed,
+            self.sample_rate,
+            channels,
+            source_channels,
         );
+        self.convolver = convolver_for(&state.convolution, impulse, self.sample_rate, channels)
+            .filter(|_| !self.dop);
+        self.shared.dsp_latency_frames.store(
+            self.convolver.as_ref().map_or(0, |c| c.latency() as u64),
+            Ordering::Relaxed,
+        );
+        self.shared.convolution_load.store(0, Ordering::Relaxed);
+    }
 
-        // Resample if source sample rate differs from output sample rate
-        let resampled_samples = if source_sample_rate != output_sample_rate {
-            println!(
-                "[Audio] ⚡ RESAMPLING CHUNK: {}Hz -> {}Hz",
-                source_sample_rate, output_sample_rate
-            );
-            resample_audio(
-                &new_samples,
-                source_channels as usize,
-                source_sample_rate,
-                output_sample_rate,
-            )?
-        } else {
-            new_samples
-        };
-
-        // Convert channels if needed
-        let final_samples = if source_channels != output_channels {
-            convert_channels(
-                &resampled_samples,
-                source_channels as usize,
-                output_channels as usize,
-            )
-        } else {
-            resampled_samples
-        };
-
-        // Append to existing buffer
-        {
-            let mut buffer = self.sample_buffer.write();
-            let old_len = buffer.len();
-            buffer.extend(final_samples.iter());
-            println!(
-                "[Audio] Appended {} samples (buffer: {} -> {})",
-                final_samples.len(),
-                old_len,
-                buffer.len()
-            );
+    /// Render into the device buffer through the f32 scratch buffer
+    fn render_into<T: OutputSample>(&mut self, data: &mut [T]) {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        for chunk in data.chunks_mut(scratch.len()) {
+            let rendered = &mut scratch[..chunk.len()];
+            self.render_chunk(rendered, T::DITHER_BITS);
+            for (out, sample) in chunk.iter_mut().zip(rendered.iter()) {
+                *out = T::from_f32(*sample);
+            }
         }
+        self.scratch = scratch;
+    }
 
-        // Update duration in state
-        {
-            let buffer = self.sample_buffer.read();
-            let new_duration =
-                buffer.len() as f64 / (output_sample_rate as f64 * output_channels as f64);
-            drop(buffer);
-
-            let mut state = self.state.write();
-            state.duration = new_duration;
-            state.track_finished = false;
-            state.is_playing = true;
+    /// Render audio ready to convert to an output format of `dither_bits`, returning how
+    /// many samples are audio rather than silence
+    fn render_chunk(&mut self, data: &mut [f32], dither_bits: Option<u32>) -> usize {
+        // Rendering the last of a stream stops playback, so look before
+        let playing = self.shared.is_playing();
+        let start = self.shared.frames_played();
+        let written = self.render(data);
+        // Whatever does not fit is dropped, the visualizer only needs the latest audio
+        if !self.dop && self.shared.tap_enabled() {
+            self.tap.push(data);
         }
-
-        Ok(())
-    }
-}
-/// Resample audio from one sample rate to another using high-quality sinc interpolation
-fn resample_audio(
-    samples: &[f32],
-    channels: usize,
-    from_rate: u32,
-    to_rate: u32,
-) -> Result<Vec<f32>, AudioError> {
-    if channels == 0 || samples.is_empty() {
-        return Ok(Vec::new());
+        if let Some(bits) = dither_bits.filter(|_| playing) {
+            self.dither(data, bits, start);
+        }
+        written
     }
 
-    let num_frames = samples.len() / channels;
-
-    // Deinterleave samples into separate channels
-    let mut channel_data: Vec<Vec<f32>> = vec![Vec::with_capacity(num_frames); channels];
-    for (i, sample) in samples.iter().enumerate() {
-        channel_data[i % channels].push(*sample);
+    /// Requantize for an output format narrower than the processing precision. `start` is
+    /// the frame position before the samples were rendered.
+    fn dither(&mut self, samples: &mut [f32], bits: u32, start: u64) {
+        let noise_shaped = match self.shared.dither() {
+            DitherMode::Off => return,
+            // Decoded samples that nothing altered are already exact at the output width,
+            // if all of the chunk lies before the end of the untouched audio
+            DitherMode::Auto
+                if self.shared.volume() == 1.0
+                    && self.equalizer.is_none()
+                    && self.crossfeed.is_none()
+                    && self.convolver.is_none()
+                    && start < self.shared.untouched_until()
+                    && self.shared.frames_played() <= self.shared.untouched_until() =>
+            {
+                return
+            }
+            DitherMode::Auto | DitherMode::Tpdf => false,
+            DitherMode::NoiseShaped => true,
+        };
+        self.ditherer.process(samples, bits, noise_shaped);
     }
 
-    // Create resampler
-    let mut resampler = FftFixedIn::<f32>::new(
-        from_rate as usize,
-        to_rate as usize,
-        1024, // chunk size
-        2,    // sub chunks
-        channels,
-    )
-    .map_err(|e| AudioError::Decode(format!("Failed to create resampler: {}", e)))?;
-
-    // Process in chunks
-    let chunk_size = resampler.input_frames_next();
-    let mut resampled_channels: Vec<Vec<f32>> = vec![Vec::new(); channels];
+    /// Fill `data`, returning how many samples are audio rather than silence
+    fn render(&mut self, data: &mut [f32]) -> usize {
+        let written = self.render_samples(data);
+        // The DAC drops out of DSD mode on anything that is not a DoP frame
+        if self.dop {
+            dsd::restamp_dop(data, self.channels, written, &mut self.dop_marker);
+        }
+        written
+    }
 
-    let mut pos = 0;
-    while pos < num_frames {
-        let end = (pos + chunk_size).min(num_frames);
-        let frames_in_chunk = end - pos;
+    /// Fill `data` from the ring, returning how many samples came from it
+    fn render_samples(&mut self, data: &mut [f32]) -> usize {
+        self.apply_dsp_updates();
+
+        // A seek asked us to drop everything buffered for the old position
+        let requested = self.shared.flush_requested.load(Ordering::Acquire);
+        if requested != self.shared.flush_completed.load(Ordering::Relaxed) {
+            self.consumer.clear();
+            let position = self.shared.flush_position.load(Ordering::Acquire);
+            self.shared.frames_played.store(position, Ordering::Release);
+            self.shared
+                .flush_completed
+                .store(requested, Ordering::Release);
+            if let Some(equalizer) = self.equalizer.as_mut() {
+                equalizer.reset();
+            }
+            if let Some(crossfeed) = self.crossfeed.as_mut() {
+                crossfeed.reset();
+            }
+            if let Some(convolver) = self.convolver.as_mut() {
+                convolver.reset();
+            }
+            self.convolution_tail = 0;
+            self.primed = false;
+            self.speed = self.shared.speed();
+            self.frame_fraction = 0.0;
+        }
 
-        // Prepare input chunk (pad with zeros if needed)
-        let input: Vec<Vec<f32>> = channel_data
-            .iter()
-            .map(|ch| {
-                let mut chunk: Vec<f32> = ch[pos..end].to_vec();
-                // Pad with zeros if this is the last chunk and it's smaller than chunk_size
-                while chunk.len() < chunk_size {
-                    chunk.push(0.0);
-                }
-                chunk
-            })
-            .collect();
+        if !self.shared.is_playing() {
+            data.fill(0.0);
+            return 0;
+        }
 
-        // Resample
-        match resampler.process(&input, None) {
-            Ok(output) => {
-                for (ch_idx, ch_data) in output.into_iter().enumerate() {
-                    resampled_channels[ch_idx].extend(ch_data);
-                }
+        let volume = if self.dop { 1.0 } else { self.shared.volume() };
+        let written = self.consumer.pop(data);
+        if let Some(equalizer) = self.equalizer.as_mut().filter(|_| !self.dop) {
+            equalizer.process(&mut data[..written]);
+        }
+        if let Some(crossfeed) = self.crossfeed.as_mut().filter(|_| !self.dop) {
+            crossfeed.process(&mut data[..written]);
+        }
+        data[written..].fill(0.0);
+        // The convolver's output lags its input, so it runs over the silence after the
+        // ring's audio too, and its tail plays out before the stream counts as finished
+        let mut tail_pending = false;
+        if let Some(convolver) = self.convolver.as_mut().filter(|_| !self.dop) {
+            let started = Instant::now();
+            convolver.process(data);
+            self.convolution_time += started.elapsed();
+            self.convolution_frames += data.len() / self.channels;
+            if written > 0 {
+                self.convolution_tail = convolver.tail();
             }
-            Err(e) => {
-                log::warn!("Resampling error at frame {}: {}", pos, e);
+            let silence = (data.len() - written) / self.channels;
+            self.convolution_tail = self.convolution_tail.saturating_sub(silence);
+            tail_pending = self.convolution_tail > 0;
+            self.publish_convolution_load();
+        }
+        // At full volume the samples pass through untouched
+        if volume != 1.0 {
+            for sample in data.iter_mut() {
+                *sample *= volume;
             }
         }
 
-        pos += frames_in_chunk;
-    }
+        if written < data.len() && !self.shared.end_of_stream.load(Ordering::Acquire) {
+            if self.primed {
+                self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            self.primed = false;
+        } else if written > 0 {
+            self.primed = true;
+        }
 
-    // Interleave resampled channels back together
-    let output_frames = resampled_channels.get(0).map(|c| c.len()).unwrap_or(0);
-    let mut result = Vec::with_capacity(output_frames * channels);
+        // Frames played count in source time, whatever the playback speed
+        let frames = (written / self.channels) as f64 * self.speed + self.frame_fraction;
+        self.frame_fraction = frames.fract();
+        self.shared
+            .frames_played
+            .fetch_add(frames as u64, Ordering::AcqRel);
+
+        // Detect when the decoder is done and everything has been played
+        if written < data.len()
+            && !tail_pending
+            && self.shared.end_of_stream.load(Ordering::Acquire)
+            && self.consumer.is_empty()
+            && !self.shared.finished.swap(true, Ordering::AcqRel)
+        {
+            self.shared.playing.store(false, Ordering::Release);
+        }
+        written
+    }
 
-    for frame in 0..output_frames {
-        for ch in 0..channels {
-            if frame < resampled_channels[ch].len() {
-                result.push(resampled_channels[ch][frame]);
-            } else {
-                result.push(0.0);
+    /// Record the output latency, and count an xrun when this callback's audio starts
+    /// playing later than the previous callback's ends
+    fn measure(&mut self, timestamp: &cpal::OutputStreamTimestamp, samples: usize) {
+        let frames = samples / self.channels;
+        let rate = self.sample_rate as f64;
+        if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+            let latency_frames = (latency.as_secs_f64() * rate) as u64;
+            self.shared
+                .latency_frames
+                .store(latency_frames, Ordering::Relaxed);
+        }
+        self.shared
+            .callback_frames
+            .store(frames as u32, Ordering::Relaxed);
+
+        let duration = Duration::from_secs_f64(frames as f64 / rate);
+        // Timestamps are estimates, so only a gap of more than a whole buffer counts
+        if let Some(gap) = self
+            .playback_end
+            .and_then(|end| timestamp.playback.duration_since(&end))
+        {
+            if gap > duration {
+                self.shared.xruns.fetch_add(1, Ordering::Relaxed);
             }
         }
+        self.playback_end = timestamp.playback.add(duration);
     }
 
-    log::info!(
-        "Resampled {} frames from {}Hz to {}Hz -> {} frames",
-        num_frames,
-        from_rate,
-        to_rate,
-        output_frames
-    );
+    /// The sink counterpart of `measure`: sinks add no latency, and a period rendered
+    /// late is what a device would have run dry on
+    fn measure_period(&mut self, samples: usize, late: bool) {
+        self.shared
+            .callback_frames
+            .store((samples / self.channels) as u32, Ordering::Relaxed);
+        if late {
+            self.shared.xruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
 
-    Ok(result)
-}
+    /// Share the convolver's share of real time about twice a second
+    fn publish_convolution_load(&mut self) {
+        if self.convolution_frames < self.sample_rate as usize / 2 {
+            return;
+        }
+        let audio = self.convolution_frames as f64 / self.sample_rate as f64;
+        let load = (self.convolution_time.as_secs_f64() / audio) as f32;
+        self.shared
+            .convolution_load
+            .store(load.to_bits(), Ordering::Relaxed);
+        self.convolution_time = Duration::ZERO;
+        self.convolution_frames = 0;
+    }
 
-/// Convert audio between different channel counts
-fn convert_channels(samples: &[f32], from_channels: usize, to_channels: usize) -> Vec<f32> {
-    if from_channels == to_channels || from_channels == 0 {
-        return samples.to_vec();
-    }
-
-    let num_frames = samples.len() / from_channels;
-    let mut result = Vec::with_capacity(num_frames * to_channels);
-
-    for frame in 0..num_frames {
-        let frame_start = frame * from_channels;
-
-        if to_channels < from_channels {
-            // Downmix: average channels
-            if to_channels == 1 && from_channels == 2 {
-                // Stereo to mono
-                let left = samples[frame_start];
-                let right = samples[frame_start + 1];
-                result.push((left + right) / 2.0);
-            } else if to_channels == 2 && from_channels > 2 {
-                // Multi-channel to stereo (simple downmix)
-                let left = samples[frame_start];
-                let right = if from_channels > 1 {
-                    samples[frame_start + 1]
-                } else {
-                    left
-                };
-                result.push(left);
-                result.push(right);
-            } else {
-                // Generic downmix: take first to_channels
-                for ch in 0..to_channels {
-                    result.push(samples[frame_start + ch]);
+    /// Swap in DSP stages built by the audio thread (bounded channels never allocate)
+    fn apply_dsp_updates(&mut self) {
+        while let Ok(update) = self.dsp_updates.try_recv() {
+            let retired = match update {
+                DspUpdate::Equalizer(mut equalizer) => {
+                    if let (Some(new), Some(old)) = (equalizer.as_mut(), self.equalizer.as_ref()) {
+                        new.carry_state(old);
+                    }
+                    DspUpdate::Equalizer(std::mem::replace(&mut self.equalizer, equalizer))
                 }
-            }
-        } else {
-            // Upmix: duplicate channels
-            if from_channels == 1 && to_channels == 2 {
-                // Mono to stereo
-                let mono = samples[frame_start];
-                result.push(mono);
-                result.push(mono);
-            } else {
-                // Generic upmix: copy existing, fill rest with zeros
-                for ch in 0..to_channels {
-                    if ch < from_channels {
-                        result.push(samples[frame_start + ch]);
-                    } else {
-                        result.push(0.0);
+                DspUpdate::Crossfeed(mut crossfeed) => {
+                    if let (Some(new), Some(old)) = (crossfeed.as_mut(), self.crossfeed.as_ref()) {
+                        new.carry_state(old);
                     }
+                    DspUpdate::Crossfeed(std::mem::replace(&mut self.crossfeed, crossfeed))
                 }
-            }
+                DspUpdate::Convolver(convolver) => {
+                    let convolver = convolver.filter(|_| !self.dop);
+                    let latency = convolver.as_ref().map_or(0, |c| c.latency());
+                    self.shared
+                        .dsp_latency_frames
+                        .store(latency as u64, Ordering::Relaxed);
+                    if convolver.is_none() {
+                        self.shared.convolution_load.store(0, Ordering::Relaxed);
+                    }
+                    self.convolution_tail = 0;
+                    DspUpdate::Convolver(std::mem::replace(&mut self.convolver, convolver))
+                }
+            };
+            // Only dropped here if the audio thread has fallen behind collecting
+            let _ = self.dsp_retired.try_send(retired);
         }
     }
-
-    result
 }
@@ -0,0 +1,301 @@
+//! Channel Mixer Module
+//! Matrix up/downmixing between the source's channel layout and the output's.
+//! Downmix gains follow ITU-R BS.775; speakers the output lacks are folded into their
+//! nearest neighbours, and speakers the source lacks are derived explicitly.
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::audio::Channels;
+
+/// -3 dB
+const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DownmixSettings {
+    pub include_lfe: bool, // BS.775 leaves the LFE channel out of downmixes
+    pub normalize: bool,   // Scale the matrix so no output can exceed full scale
+}
+
+impl Default for DownmixSettings {
+    fn default() -> Self {
+        Self {
+            include_lfe: false,
+            normalize: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    RearLeft,
+    RearRight,
+    FrontLeftCenter,
+    FrontRightCenter,
+    RearCenter,
+    SideLeft,
+    SideRight,
+    Other,
+}
+
+impl Speaker {
+    /// Ear-level position of a symphonia channel. Height and wide channels are folded
+    /// onto the speaker below or beside them.
+    fn from_channel(channel: Channels) -> Self {
+        match channel {
+            Channels::FRONT_LEFT
+            | Channels::FRONT_LEFT_WIDE
+            | Channels::FRONT_LEFT_HIGH
+            | Channels::TOP_FRONT_LEFT => Speaker::FrontLeft,
+            Channels::FRONT_RIGHT
+            | Channels::FRONT_RIGHT_WIDE
+            | Channels::FRONT_RIGHT_HIGH
+            | Channels::TOP_FRONT_RIGHT => Speaker::FrontRight,
+            Channels::FRONT_CENTRE
+            | Channels::FRONT_CENTRE_HIGH
+            | Channels::TOP_FRONT_CENTRE
+            | Channels::TOP_CENTRE => Speaker::Center,
+            Channels::LFE1 | Channels::LFE2 => Speaker::Lfe,
+            Channels::REAR_LEFT | Channels::REAR_LEFT_CENTRE | Channels::TOP_REAR_LEFT => {
+                Speaker::RearLeft
+            }
+            Channels::REAR_RIGHT | Channels::REAR_RIGHT_CENTRE | Channels::TOP_REAR_RIGHT => {
+                Speaker::RearRight
+            }
+            Channels::FRONT_LEFT_CENTRE => Speaker::FrontLeftCenter,
+            Channels::FRONT_RIGHT_CENTRE => Speaker::FrontRightCenter,
+            Channels::REAR_CENTRE | Channels::TOP_REAR_CENTRE => Speaker::RearCenter,
+            Channels::SIDE_LEFT => Speaker::SideLeft,
+            Channels::SIDE_RIGHT => Speaker::SideRight,
+            _ => Speaker::Other,
+        }
+    }
+}
+
+/// Usual speaker order for a channel count (WAVEFORMATEXTENSIBLE / FLAC defaults)
+fn default_layout(channels: usize) -> Vec<Speaker> {
+    use Speaker::*;
+    let mut layout = match channels {
+        1 => vec![Center],
+        2 => vec![FrontLeft, FrontRight],
+        3 => vec![FrontLeft, FrontRight, Center],
+        4 => vec![FrontLeft, FrontRight, RearLeft, RearRight],
+        5 => vec![FrontLeft, FrontRight, Center, RearLeft, RearRight],
+        6 => vec![FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight],
+        7 => vec![
+            FrontLeft, FrontRight, Center, Lfe, RearCenter, SideLeft, SideRight,
+        ],
+        _ => vec![
+            FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight, SideLeft, SideRight,
+        ],
+    };
+    layout.resize(channels, Other);
+    layout
+}
+
+/// Speakers of a source, in the order symphonia interleaves them
+fn source_layout(layout: Option<Channels>, channels: usize) -> Vec<Speaker> {
+    match layout {
+        // Symphonia reports mono as a lone front-left channel
+        Some(layout) if layout.count() == channels && channels > 1 => {
+            layout.iter().map(Speaker::from_channel).collect()
+        }
+        _ => default_layout(channels),
+    }
+}
+
+pub struct ChannelMixer {
+    inputs: usize,
+    outputs: usize,
+    /// Gain from input `i` to output `o` at `o * inputs + i`
+    matrix: Vec<f32>,
+    passthrough: bool,
+}
+
+impl ChannelMixer {
+    pub fn new(
+        layout: Option<Channels>,
+        inputs: u16,
+        outputs: u16,
+        settings: &DownmixSettings,
+    ) -> Self {
+        let inputs = inputs.max(1) as usize;
+        let outputs = outputs.max(1) as usize;
+        let source = source_layout(layout, inputs);
+        let target = default_layout(outputs);
+        let mut matrix = vec![0.0; outputs * inputs];
+
+        for (input, speaker) in source.iter().enumerate() {
+            let mut routes = Vec::new();
+            route(*speaker, 1.0, &target, settings, 0, &mut routes);
+            for (output, gain) in routes {
+                matrix[output * inputs + input] += gain;
+            }
+        }
+
+        // Upmix: speakers nothing was routed to get a defined signal instead of silence
+        for (output, speaker) in target.iter().enumerate() {
+            let row = &mut matrix[output * inputs..(output + 1) * inputs];
+            if row.iter().any(|g| *g != 0.0) {
+                continue;
+            }
+            for (input, gain) in fill(*speaker, &source) {
+                row[input] += gain;
+            }
+        }
+
+        if settings.normalize {
+            let loudest = matrix
+                .chunks(inputs)
+                .map(|row| row.iter().map(|g| g.abs()).sum::<f32>())
+                .fold(0.0, f32::max);
+            if loudest > 1.0 {
+                for gain in matrix.iter_mut() {
+                    *gain /= loudest;
+                }
+            }
+        }
+
+        let passthrough = inputs == outputs
+            && (0..outputs).all(|o| {
+                (0..inputs).all(|i| matrix[o * inputs + i] == if i == o { 1.0 } else { 0.0 })
+            });
+
+        Self {
+            inputs,
+            outputs,
+            matrix,
+            passthrough,
+        }
+    }
+
+    /// The output equals the input
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    pub fn process(&self, samples: &[f32], out: &mut Vec<f32>) {
+        if self.passthrough {
+            out.extend_from_slice(samples);
+            return;
+        }
+        out.reserve(samples.len() / self.inputs * self.outputs);
+        for frame in samples.chunks_exact(self.inputs) {
+            for row in self.matrix.chunks_exact(self.inputs) {
+                out.push(row.iter().zip(frame).map(|(g, s)| g * s).sum());
+            }
+        }
+    }
+}
+
+/// Send `speaker` at `gain` to the output speakers that reproduce it (BS.775 folding)
+fn route(
+    speaker: Speaker,
+    gain: f32,
+    target: &[Speaker],
+    settings: &DownmixSettings,
+    depth: usize,
+    routes: &mut Vec<(usize, f32)>,
+) {
+    use Speaker::*;
+    if let Some(output) = target.iter().position(|s| *s == speaker) {
+        routes.push((output, gain));
+        return;
+    }
+    if depth > 3 {
+        return;
+    }
+    let has = |s: Speaker| target.contains(&s);
+    let to = |s: Speaker, g: f32, routes: &mut Vec<(usize, f32)>| {
+        route(s, gain * g, target, settings, depth + 1, routes)
+    };
+
+    match speaker {
+        // A mono output has only the centre
+        FrontLeft | FrontRight => to(Center, H, routes),
+        Center => {
+            to(FrontLeft, H, routes);
+            to(FrontRight, H, routes);
+        }
+        Lfe => {
+            if settings.include_lfe {
+                to(FrontLeft, H, routes);
+                to(FrontRight, H, routes);
+            }
+        }
+        FrontLeftCenter => to(FrontLeft, 1.0, routes),
+        FrontRightCenter => to(FrontRight, 1.0, routes),
+        // Side and rear surrounds stand in for each other, otherwise go to the front
+        SideLeft if has(RearLeft) => to(RearLeft, 1.0, routes),
+        RearLeft if has(SideLeft) => to(SideLeft, 1.0, routes),
+        SideRight if has(RearRight) => to(RearRight, 1.0, routes),
+        RearRight if has(SideRight) => to(SideRight, 1.0, routes),
+        SideLeft | RearLeft => to(FrontLeft, H, routes),
+        SideRight | RearRight => to(FrontRight, H, routes),
+        RearCenter => {
+            to(RearLeft, H, routes);
+            to(RearRight, H, routes);
+        }
+        Other => {}
+    }
+}
+
+/// Input gains for an output speaker the source has no channel for
+fn fill(speaker: Speaker, source: &[Speaker]) -> Vec<(usize, f32)> {
+    use Speaker::*;
+    let find = |s: Speaker| source.iter().position(|x| *x == s);
+    let either = |a: Speaker, b: Speaker| find(a).or_else(|| find(b));
+    let mut gains = Vec::new();
+    let mut add = |input: Option<usize>, gain: f32| {
+        if let Some(input) = input {
+            gains.push((input, gain));
+        }
+    };
+
+    match speaker {
+        FrontLeft | FrontRight => add(find(Center), H),
+        // The part common to both fronts
+        Center => {
+            add(find(FrontLeft), 0.5);
+            add(find(FrontRight), 0.5);
+        }
+        // Fed full range; the LFE input of a receiver or subwoofer low-passes it
+        Lfe => match (find(FrontLeft), find(FrontRight)) {
+            (Some(left), Some(right)) => {
+                add(Some(left), 0.5);
+                add(Some(right), 0.5);
+            }
+            _ => add(find(Center), H),
+        },
+        // Surrounds repeat the same side of the source at -3 dB
+        SideLeft | RearLeft => {
+            add(either(RearLeft, SideLeft).or_else(|| find(FrontLeft)), H);
+            if !source
+                .iter()
+                .any(|s| matches!(s, FrontLeft | RearLeft | SideLeft))
+            {
+                add(find(Center), 0.5);
+            }
+        }
+        SideRight | RearRight => {
+            add(either(RearRight, SideRight).or_else(|| find(FrontRight)), H);
+            if !source
+                .iter()
+                .any(|s| matches!(s, FrontRight | RearRight | SideRight))
+            {
+                add(find(Center), 0.5);
+            }
+        }
+        RearCenter => {
+            add(either(RearLeft, SideLeft).or_else(|| find(FrontLeft)), 0.5);
+            add(
+                either(RearRight, SideRight).or_else(|| find(FrontRight)),
+                0.5,
+            );
+        }
+        FrontLeftCenter | FrontRightCenter | Other => {}
+    }
+    gains
+}
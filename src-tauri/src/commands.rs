@@ -1,16 +1,36 @@
 //! Tauri Commands Module
 //! Exposes backend functionality to the frontend
 
-use crate::audio::RepeatMode;
-use crate::database::{Album, Artist, LibraryFolder, Statistics, Track};
+use crate::audio::{
+    render_offline, AudioDiagnostics, CrossfadeCurve, CrossfadeSettings, DeviceProfile,
+    DeviceSettings, RepeatMode, ReplayGainMode, ReplayGainSettings,
+};
+use crate::channel_mixer::DownmixSettings;
+use crate::convolution::ConvolutionSettings;
+use crate::crossfeed::{CrossfeedPreset, CrossfeedSettings};
+use crate::cue;
+use crate::database::{
+    Album, Artist, Database, EqPreset, LibraryFolder, Statistics, Track, TrackLoudness,
+};
+use crate::dither::DitherMode;
+use crate::dsd::DsdSettings;
+use crate::equalizer::{self, EqBand, EqSettings};
+use crate::loudness::{self, AnalysisStatus};
+use crate::queue::QueueSnapshot;
+use crate::render::{RenderFormat, RenderOptions, RenderSummary};
+use crate::resampler::ResamplerQuality;
+use crate::sink::{OutputBackend, OutputSinkSettings, SinkContainer};
 use crate::stream_cache::{DownloadResult, NextChunkResult, ProgressiveStreamResult, STREAM_CACHE};
 use crate::streaming::{
     SpotifyAlbum, SpotifyCredentials, SpotifySearchResult, SpotifyTrack, StreamInfo, StreamSource,
     StreamingService, StreamingURLs,
 };
+use crate::time_stretch::{SpeedMode, SpeedSettings};
+use crate::visualizer::{VisualizerSettings, WindowFunction};
 use crate::AppState;
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 use tauri::{Emitter, State};
 
@@ -44,6 +64,15 @@ pub struct PlaybackStateResponse {
     pub track_finished: bool, // True when current track has finished playing
 }
 
+#[derive(Serialize)]
+pub struct ReplayGainResponse {
+    pub mode: String,
+    pub preamp_db: f32,
+    pub fallback_gain_db: f32,
+    pub prevent_clipping: bool,
+    pub current_gain_db: f32, // Gain applied to the track playing now
+}
+
 // Library Commands
 #[tauri::command]
 pub fn get_all_tracks(state: State<AppState>) -> Result<Vec<Track>, String> {
@@ -106,6 +135,11 @@ pub async fn scan_library(state: State<'_, AppState>) -> Result<i32, String> {
                 if db.track_exists(&track.file_hash).unwrap_or(false) {
                     continue;
                 }
+                // The tracks of a cue sheet replace the image they were cut from
+                if track.start_offset.is_some() {
+                    db.remove_track_by_path(cue::split_locator(&track.file_path).0)
+                        .ok();
+                }
 
                 if db.insert_track(&track).is_ok() {
                     total_added += 1;
@@ -115,6 +149,11 @@ pub async fn scan_library(state: State<'_, AppState>) -> Result<i32, String> {
         }
     }
 
+    // Measure loudness of the new tracks in the background
+    if total_added > 0 {
+        state.loudness_analyzer.wake();
+    }
+
     Ok(total_added)
 }
 
@@ -218,23 +257,526 @@ pub fn get_playback_state(state: State<AppState>) -> Result<PlaybackStateRespons
     })
 }
 
+// Queue Commands
 #[tauri::command]
-pub fn next_track(state: State<AppState>) -> Result<(), String> {
-    // This would be handled by the frontend queue management
-    // For now, just stop playback
+pub fn get_queue(state: State<AppState>) -> Result<QueueSnapshot, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_queue())
+}
+
+/// Replace the queue and start playing it at `start_index`
+#[tauri::command]
+pub fn play_queue(
+    state: State<AppState>,
+    tracks: Vec<Track>,
+    start_index: usize,
+) -> Result<(), String> {
+    let started = state.audio_engine.lock().play_queue(tracks, start_index);
+    record_queue_play(&state, started);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn add_to_queue(state: State<AppState>, tracks: Vec<Track>) -> Result<(), String> {
     let mut engine = state.audio_engine.lock();
-    engine.stop();
+    engine.add_to_queue(tracks);
+    Ok(())
+}
+
+/// Queue tracks to play right after the current one
+#[tauri::command]
+pub fn play_next(state: State<AppState>, tracks: Vec<Track>) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine.insert_next_in_queue(tracks);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn remove_from_queue(state: State<AppState>, index: usize) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    if !engine.remove_from_queue(index) {
+        return Err(format!("No track at queue position {}", index));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn move_in_queue(state: State<AppState>, from: usize, to: usize) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    if !engine.move_in_queue(from, to) {
+        return Err(format!("Cannot move queue position {} to {}", from, to));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn clear_queue(state: State<AppState>) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine.clear_queue();
+    Ok(())
+}
+
+/// Play the track at `index` in the queue
+#[tauri::command]
+pub fn play_queue_index(state: State<AppState>, index: usize) -> Result<(), String> {
+    let started = state.audio_engine.lock().play_queue_index(index);
+    if started.is_none() {
+        return Err(format!("No track at queue position {}", index));
+    }
+    record_queue_play(&state, started);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn next_track(state: State<AppState>) -> Result<(), String> {
+    let started = state.audio_engine.lock().next_track();
+    record_queue_play(&state, started);
     Ok(())
 }
 
 #[tauri::command]
 pub fn previous_track(state: State<AppState>) -> Result<(), String> {
-    // This would be handled by the frontend queue management
+    let started = state.audio_engine.lock().previous_track();
+    record_queue_play(&state, started);
+    Ok(())
+}
+
+/// Record a play for a queue track the user started
+fn record_queue_play(state: &State<AppState>, track: Option<Track>) {
+    if let Some(track) = track {
+        state.database.lock().record_play(track.id).ok();
+    }
+}
+
+#[tauri::command]
+pub fn get_crossfade(state: State<AppState>) -> Result<CrossfadeSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_crossfade())
+}
+
+/// Crossfade between consecutive tracks (duration in seconds, 0 turns it off)
+#[tauri::command]
+pub fn set_crossfade(state: State<AppState>, duration: f64, curve: String) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let curve = match curve.as_str() {
+        "linear" => CrossfadeCurve::Linear,
+        "logarithmic" => CrossfadeCurve::Logarithmic,
+        _ => CrossfadeCurve::EqualPower,
+    };
+    engine
+        .set_crossfade(CrossfadeSettings { duration, curve })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_replaygain(state: State<AppState>) -> Result<ReplayGainResponse, String> {
+    let engine = state.audio_engine.lock();
+    let settings = engine.get_replaygain();
+    let playback_state = engine.get_state();
+
+    Ok(ReplayGainResponse {
+        mode: match settings.mode {
+            ReplayGainMode::Off => "off".to_string(),
+            ReplayGainMode::Track => "track".to_string(),
+            ReplayGainMode::Album => "album".to_string(),
+            ReplayGainMode::Auto => "auto".to_string(),
+        },
+        preamp_db: settings.preamp_db,
+        fallback_gain_db: settings.fallback_gain_db,
+        prevent_clipping: settings.prevent_clipping,
+        current_gain_db: 20.0 * playback_state.replaygain_gain.max(1e-6).log10(),
+    })
+}
+
+#[tauri::command]
+pub fn set_replaygain(
+    state: State<AppState>,
+    mode: String,
+    preamp_db: f32,
+    fallback_gain_db: f32,
+) -> Result<(), String> {
     let mut engine = state.audio_engine.lock();
-    engine.seek(0.0);
+    let mode = match mode.as_str() {
+        "track" => ReplayGainMode::Track,
+        "album" => ReplayGainMode::Album,
+        "auto" => ReplayGainMode::Auto,
+        _ => ReplayGainMode::Off,
+    };
+    let settings = ReplayGainSettings {
+        mode,
+        preamp_db,
+        fallback_gain_db,
+        ..engine.get_replaygain()
+    };
+    engine.set_replaygain(settings).map_err(|e| e.to_string())
+}
+
+/// Limit ReplayGain so that the track's peak never exceeds full scale
+#[tauri::command]
+pub fn set_clipping_prevention(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let settings = ReplayGainSettings {
+        prevent_clipping: enabled,
+        ..engine.get_replaygain()
+    };
+    engine.set_replaygain(settings).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_dither(state: State<AppState>) -> Result<String, String> {
+    let engine = state.audio_engine.lock();
+    Ok(match engine.get_dither() {
+        DitherMode::Off => "off",
+        DitherMode::Auto => "auto",
+        DitherMode::Tpdf => "tpdf",
+        DitherMode::NoiseShaped => "noise_shaped",
+    }
+    .to_string())
+}
+
+/// Dither applied when converting to 16-bit output
+#[tauri::command]
+pub fn set_dither(state: State<AppState>, mode: String) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let mode = match mode.as_str() {
+        "off" => DitherMode::Off,
+        "tpdf" => DitherMode::Tpdf,
+        "noise_shaped" => DitherMode::NoiseShaped,
+        _ => DitherMode::Auto,
+    };
+    engine.set_dither(mode);
     Ok(())
 }
 
+#[tauri::command]
+pub fn get_downmix(state: State<AppState>) -> Result<DownmixSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_downmix())
+}
+
+/// How multichannel sources are mixed to the output's channel count
+#[tauri::command]
+pub fn set_downmix(
+    state: State<AppState>,
+    include_lfe: bool,
+    normalize: bool,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine
+        .set_downmix(DownmixSettings {
+            include_lfe,
+            normalize,
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_crossfeed(state: State<AppState>) -> Result<CrossfeedSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_crossfeed())
+}
+
+/// Headphone crossfeed for stereo sources. `cutoff` (Hz) and `level` (dB) are used by the
+/// "custom" preset.
+#[tauri::command]
+pub fn set_crossfeed(
+    state: State<AppState>,
+    enabled: bool,
+    preset: String,
+    cutoff: f32,
+    level: f32,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let preset = match preset.as_str() {
+        "chu_moy" => CrossfeedPreset::ChuMoy,
+        "jan_meier" => CrossfeedPreset::JanMeier,
+        "custom" => CrossfeedPreset::Custom,
+        _ => CrossfeedPreset::Default,
+    };
+    engine
+        .set_crossfeed(CrossfeedSettings {
+            enabled,
+            preset,
+            cutoff,
+            level,
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_convolution(state: State<AppState>) -> Result<ConvolutionSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_convolution())
+}
+
+/// Convolve the output with the mono or stereo impulse response at `ir_path` (room
+/// correction). Fails if the file cannot be read as one.
+#[tauri::command]
+pub fn set_convolution(
+    state: State<AppState>,
+    enabled: bool,
+    ir_path: Option<String>,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine
+        .set_convolution(ConvolutionSettings { enabled, ir_path })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_resampler_quality(state: State<AppState>) -> Result<String, String> {
+    let engine = state.audio_engine.lock();
+    Ok(match engine.get_resampler_quality() {
+        ResamplerQuality::Fast => "fast",
+        ResamplerQuality::HighQuality => "high_quality",
+        ResamplerQuality::MinimumPhase => "minimum_phase",
+    }
+    .to_string())
+}
+
+/// Filter used when a track's sample rate differs from the device's
+#[tauri::command]
+pub fn set_resampler_quality(state: State<AppState>, quality: String) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let quality = match quality.as_str() {
+        "high_quality" => ResamplerQuality::HighQuality,
+        "minimum_phase" => ResamplerQuality::MinimumPhase,
+        _ => ResamplerQuality::Fast,
+    };
+    engine.set_resampler_quality(quality);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_dsd(state: State<AppState>) -> Result<DsdSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_dsd())
+}
+
+/// How DSF/DFF files reach the device: DoP where the device takes it, else PCM near `pcm_rate`
+#[tauri::command]
+pub fn set_dsd(state: State<AppState>, dop: bool, pcm_rate: u32) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine.set_dsd(DsdSettings { dop, pcm_rate });
+    Ok(())
+}
+
+// Equalizer Commands
+#[tauri::command]
+pub fn get_equalizer(state: State<AppState>) -> Result<EqSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_equalizer())
+}
+
+#[tauri::command]
+pub fn set_equalizer(
+    state: State<AppState>,
+    enabled: bool,
+    preamp_db: f32,
+    bands: Vec<EqBand>,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine
+        .set_equalizer(EqSettings {
+            enabled,
+            preamp_db,
+            bands,
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_eq_presets(state: State<AppState>) -> Result<Vec<EqPreset>, String> {
+    let db = state.database.lock();
+    db.get_eq_presets().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn save_eq_preset(
+    state: State<AppState>,
+    name: String,
+    preamp_db: f32,
+    bands: Vec<EqBand>,
+) -> Result<i64, String> {
+    let db = state.database.lock();
+    db.save_eq_preset(&name, preamp_db, &bands)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn delete_eq_preset(state: State<AppState>, id: i64) -> Result<(), String> {
+    let db = state.database.lock();
+    db.delete_eq_preset(id).map_err(|e| e.to_string())
+}
+
+/// Load a preset into the equalizer and turn it on
+#[tauri::command]
+pub fn apply_eq_preset(state: State<AppState>, id: i64) -> Result<EqSettings, String> {
+    let preset = {
+        let db = state.database.lock();
+        db.get_eq_preset(id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("EQ preset {} not found", id))?
+    };
+    let settings = EqSettings {
+        enabled: true,
+        preamp_db: preset.preamp_db,
+        bands: preset.bands,
+    };
+    let mut engine = state.audio_engine.lock();
+    engine.set_equalizer(settings).map_err(|e| e.to_string())?;
+    Ok(engine.get_equalizer())
+}
+
+/// Import an AutoEQ `ParametricEQ.txt` as a preset and apply it
+#[tauri::command]
+pub fn import_autoeq(state: State<AppState>, file_path: String) -> Result<EqPreset, String> {
+    let path = Path::new(&file_path);
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let settings = equalizer::parse_autoeq(&text)?;
+
+    // "Sennheiser HD 650 ParametricEQ.txt" -> "Sennheiser HD 650"
+    let name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.trim_end_matches("ParametricEQ").trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "AutoEQ".to_string());
+
+    let id = {
+        let db = state.database.lock();
+        db.save_eq_preset(&name, settings.preamp_db, &settings.bands)
+            .map_err(|e| e.to_string())?
+    };
+
+    let mut engine = state.audio_engine.lock();
+    engine
+        .set_equalizer(settings.clone())
+        .map_err(|e| e.to_string())?;
+
+    Ok(EqPreset {
+        id,
+        name,
+        preamp_db: settings.preamp_db,
+        bands: settings.bands,
+    })
+}
+
+// Loudness Analysis Commands
+#[tauri::command]
+pub fn get_loudness_analysis_status(state: State<AppState>) -> Result<AnalysisStatus, String> {
+    Ok(state.loudness_analyzer.status())
+}
+
+#[tauri::command]
+pub fn set_loudness_analysis_enabled(state: State<AppState>, enabled: bool) -> Result<(), String> {
+    state.loudness_analyzer.set_enabled(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_track_loudness(
+    state: State<AppState>,
+    track_id: i64,
+) -> Result<Option<TrackLoudness>, String> {
+    let db = state.database.lock();
+    db.get_track_loudness(track_id).map_err(|e| e.to_string())
+}
+
+/// Write REPLAYGAIN tags from the loudness analysis into the given tracks' files.
+/// Files that already carry ReplayGain tags are left alone unless `overwrite` is set.
+/// Returns the number of files written.
+#[tauri::command]
+pub async fn write_replaygain_tags(
+    state: State<'_, AppState>,
+    track_ids: Vec<i64>,
+    overwrite: bool,
+) -> Result<usize, String> {
+    let mut written = 0;
+
+    for track_id in track_ids {
+        let (file, loudness) = {
+            let db = state.database.lock();
+            (
+                db.get_track_file(track_id).map_err(|e| e.to_string())?,
+                db.get_track_loudness(track_id).map_err(|e| e.to_string())?,
+            )
+        };
+        let (Some((path, tagged_gain)), Some(loudness)) = (file, loudness) else {
+            continue;
+        };
+        if tagged_gain.is_some() && !overwrite {
+            continue;
+        }
+        // The tracks of a cue sheet share one file, which can only hold one track gain
+        if cue::split_locator(&path).1.is_some() {
+            continue;
+        }
+        let info = loudness::replaygain_from(&loudness);
+        if info.track_gain.is_none() {
+            continue;
+        }
+
+        if let Err(e) = loudness::write_replaygain_tags(&path, &info) {
+            log::error!("Failed to write ReplayGain tags to {}: {}", path, e);
+            continue;
+        }
+
+        // The tags changed the start of the file, keep the stored hash in sync
+        let file_hash = {
+            let scanner = state.library_scanner.lock();
+            scanner
+                .compute_file_hash(Path::new(&path))
+                .unwrap_or_default()
+        };
+        let db = state.database.lock();
+        db.update_track_replaygain(
+            track_id,
+            &file_hash,
+            info.track_gain.map(|g| g as f64),
+            info.track_peak.map(|p| p as f64),
+            info.album_gain.map(|g| g as f64),
+            info.album_peak.map(|p| p as f64),
+        )
+        .map_err(|e| e.to_string())?;
+        written += 1;
+    }
+
+    Ok(written)
+}
+
+/// Render a library track through the current DSP settings into a WAV or FLAC file.
+/// `bit_depth` is 16, 24 or 32 (float WAV); the rate and channels default to the source's.
+#[tauri::command]
+pub async fn render_track(
+    state: State<'_, AppState>,
+    track_id: i64,
+    output_path: String,
+    format: String,
+    bit_depth: u16,
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+) -> Result<RenderSummary, String> {
+    let (path, _) = {
+        let db = state.database.lock();
+        db.get_track_file(track_id)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Track {} not found", track_id))?
+    };
+    let options = RenderOptions {
+        format: match format.as_str() {
+            "flac" => RenderFormat::Flac,
+            "raw" => RenderFormat::Raw,
+            _ => RenderFormat::Wav,
+        },
+        bit_depth,
+        sample_rate,
+        channels,
+    };
+    let context = state.audio_engine.lock().render_context();
+    render_offline(&path, &output_path, &options, context).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn set_shuffle(state: State<AppState>, enabled: bool) -> Result<(), String> {
     let mut engine = state.audio_engine.lock();
@@ -266,6 +808,191 @@ pub fn set_audio_device(state: State<AppState>, device_name: String) -> Result<(
     engine.set_device(&device_name).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn get_device_settings(state: State<AppState>) -> Result<DeviceSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_device_settings())
+}
+
+/// Whether playback moves back to the chosen device when it reconnects after a fallback
+#[tauri::command]
+pub fn set_device_settings(
+    state: State<AppState>,
+    return_to_preferred: bool,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine.set_return_to_preferred_device(return_to_preferred);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_buffer_size(state: State<AppState>) -> Result<Option<u32>, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_buffer_size())
+}
+
+/// Frames per callback, None for the device default. Device profiles override it.
+#[tauri::command]
+pub fn set_buffer_size(state: State<AppState>, frames: Option<u32>) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    engine.set_buffer_size(frames);
+    Ok(())
+}
+
+/// Buffer size, output latency and dropout counts of the running stream
+#[tauri::command]
+pub fn get_audio_diagnostics(state: State<AppState>) -> Result<AudioDiagnostics, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_diagnostics())
+}
+
+#[tauri::command]
+pub fn get_output_sink(state: State<AppState>) -> Result<OutputSinkSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_output_sink())
+}
+
+/// Play on the output device ("device"), discard the audio in real time ("null"), or write
+/// it to the file or FIFO at `path` ("file") as raw PCM or WAV
+#[tauri::command]
+pub fn set_output_sink(
+    state: State<AppState>,
+    backend: String,
+    path: Option<String>,
+    container: String,
+    bit_depth: u16,
+    sample_rate: Option<u32>,
+    channels: Option<u16>,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let settings = OutputSinkSettings {
+        backend: match backend.as_str() {
+            "null" => OutputBackend::Null,
+            "file" => OutputBackend::File,
+            _ => OutputBackend::Device,
+        },
+        path,
+        container: match container.as_str() {
+            "wav" => SinkContainer::Wav,
+            _ => SinkContainer::Raw,
+        },
+        bit_depth,
+        sample_rate,
+        channels,
+    };
+    engine.set_output_sink(settings).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_visualizer(state: State<AppState>) -> Result<VisualizerSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_visualizer())
+}
+
+/// Spectrum and level data of the output, sent as "visualizer-frame" events while enabled
+#[tauri::command]
+pub fn set_visualizer(
+    state: State<AppState>,
+    enabled: bool,
+    fft_size: usize,
+    window: String,
+    frame_rate: u32,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let window = match window.as_str() {
+        "rectangular" => WindowFunction::Rectangular,
+        "hamming" => WindowFunction::Hamming,
+        "blackman_harris" => WindowFunction::BlackmanHarris,
+        _ => WindowFunction::Hann,
+    };
+    engine.set_visualizer(VisualizerSettings {
+        enabled,
+        fft_size,
+        window,
+        frame_rate,
+    });
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_speed(state: State<AppState>) -> Result<SpeedSettings, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_speed())
+}
+
+/// Playback speed (0.5-2.0) and pitch shift in semitones. Mode "varispeed" lets the pitch
+/// follow the speed, anything else keeps it.
+#[tauri::command]
+pub fn set_speed(
+    state: State<AppState>,
+    speed: f64,
+    mode: String,
+    pitch: f64,
+) -> Result<(), String> {
+    let mut engine = state.audio_engine.lock();
+    let mode = match mode.as_str() {
+        "varispeed" => SpeedMode::Varispeed,
+        _ => SpeedMode::PreservePitch,
+    };
+    engine
+        .set_speed(SpeedSettings { speed, mode, pitch })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn get_device_profiles(
+    state: State<AppState>,
+) -> Result<HashMap<String, DeviceProfile>, String> {
+    let engine = state.audio_engine.lock();
+    Ok(engine.get_device_profiles())
+}
+
+/// Save the output settings of a device. They apply whenever playback goes to it.
+#[tauri::command]
+pub fn save_device_profile(
+    state: State<AppState>,
+    device_name: String,
+    profile: DeviceProfile,
+) -> Result<(), String> {
+    let profile = {
+        let db = state.database.lock();
+        db.save_device_profile(&device_name, &profile)
+            .map_err(|e| e.to_string())?;
+        with_eq_preset(&db, profile)
+    };
+    let mut engine = state.audio_engine.lock();
+    engine.set_device_profile(&device_name, Some(profile));
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_device_profile(state: State<AppState>, device_name: String) -> Result<(), String> {
+    {
+        let db = state.database.lock();
+        db.delete_device_profile(&device_name)
+            .map_err(|e| e.to_string())?;
+    }
+    let mut engine = state.audio_engine.lock();
+    engine.set_device_profile(&device_name, None);
+    Ok(())
+}
+
+/// Look up the settings of the profile's EQ preset, which the audio thread cannot read itself
+pub fn with_eq_preset(db: &Database, profile: DeviceProfile) -> DeviceProfile {
+    let equalizer = profile
+        .eq_preset
+        .and_then(|id| db.get_eq_preset(id).ok().flatten())
+        .map(|preset| EqSettings {
+            enabled: true,
+            preamp_db: preset.preamp_db,
+            bands: preset.bands,
+        });
+    DeviceProfile {
+        equalizer,
+        ..profile
+    }
+}
+
 // Artwork
 #[tauri::command]
 pub fn get_track_artwork(
@@ -273,7 +1000,8 @@ pub fn get_track_artwork(
     file_path: String,
 ) -> Result<Option<String>, String> {
     let scanner = state.library_scanner.lock();
-    let path = Path::new(&file_path);
+    // Tracks of a cue sheet show the artwork of their album image
+    let path = Path::new(cue::split_locator(&file_path).0);
 
     if let Some(artwork_data) = scanner.extract_artwork(path) {
         let base64 = BASE64.encode(&artwork_data);
@@ -410,7 +1138,10 @@ pub fn get_lyrics(state: State<AppState>, file_path: String) -> Result<Option<St
     }
     drop(db);
 
-    // Then check for .lrc file
+    // Then check for .lrc file. One beside an album image would be for the whole album.
+    if cue::split_locator(&file_path).1.is_some() {
+        return Ok(None);
+    }
     let scanner = state.library_scanner.lock();
     let path = Path::new(&file_path);
 
@@ -0,0 +1,306 @@
+//! Convolution Module
+//! FIR filtering with long impulse responses, such as room correction filters exported
+//! from REW or DRC. Uniformly partitioned overlap-save: the impulse response is cut into
+//! equal blocks, each transformed once, and every block of input is multiplied with all of
+//! them in the frequency domain. The latency is one block; longer impulse responses get
+//! longer blocks so the cost stays manageable.
+
+use crate::audio::AudioError;
+use crate::decoder::TrackSource;
+use crate::dsd::DsdOutput;
+use crate::resampler::{ResamplerQuality, StreamResampler};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Range of frames per partition, which is also the latency the convolver adds
+const MIN_PARTITION_FRAMES: usize = 512;
+const MAX_PARTITION_FRAMES: usize = 8192;
+/// Partitions aimed for: beyond this, partitions grow rather than multiply
+const TARGET_PARTITIONS: usize = 64;
+/// Longer impulse responses are cut off
+const MAX_IR_SECONDS: f64 = 10.0;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConvolutionSettings {
+    pub enabled: bool,
+    pub ir_path: Option<String>, // Mono or stereo WAV impulse response
+}
+
+impl ConvolutionSettings {
+    pub fn is_active(&self) -> bool {
+        self.enabled && self.ir_path.is_some()
+    }
+}
+
+/// An impulse response as read from its file
+pub struct ImpulseResponse {
+    pub path: String,
+    pub sample_rate: u32,
+    channels: Vec<Vec<f32>>,
+}
+
+impl ImpulseResponse {
+    pub fn load(path: &str) -> Result<Self, AudioError> {
+        // Any format the player reads works; DSD gets a PCM rate the filter design can use
+        let mut source = TrackSource::open(path, None, DsdOutput::Pcm(88200))?;
+        let spec = source.spec();
+        if spec.channels == 0 || spec.channels > 2 {
+            return Err(AudioError::Decode(format!(
+                "Impulse responses have to be mono or stereo, {} has {} channels",
+                path, spec.channels
+            )));
+        }
+        let limit = (spec.sample_rate as f64 * MAX_IR_SECONDS) as usize * spec.channels as usize;
+        let mut samples = Vec::new();
+        while samples.len() < limit && source.decode_next(&mut samples)? {}
+        if samples.len() > limit {
+            log::warn!("Impulse response {} cut to {}s", path, MAX_IR_SECONDS);
+            samples.truncate(limit);
+        }
+        if samples.is_empty() {
+            return Err(AudioError::Decode(format!(
+                "Impulse response {} is empty",
+                path
+            )));
+        }
+
+        let channels = spec.channels as usize;
+        Ok(Self {
+            path: path.to_string(),
+            sample_rate: spec.sample_rate,
+            channels: (0..channels)
+                .map(|ch| samples.iter().skip(ch).step_by(channels).copied().collect())
+                .collect(),
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    /// The impulse response at `sample_rate`. Resampling spreads it over more or fewer
+    /// samples, so it is scaled to keep its frequency response.
+    fn at_rate(&self, sample_rate: u32) -> Result<Vec<Vec<f32>>, AudioError> {
+        if sample_rate == self.sample_rate {
+            return Ok(self.channels.clone());
+        }
+        let channels = self.channels.len();
+        let interleaved: Vec<f32> = (0..self.frames())
+            .flat_map(|frame| self.channels.iter().map(move |ch| ch[frame]))
+            .collect();
+        let mut resampler = StreamResampler::new(
+            self.sample_rate,
+            sample_rate,
+            channels,
+            ResamplerQuality::HighQuality,
+        )?;
+        let mut resampled = Vec::new();
+        resampler.process(&interleaved, &mut resampled);
+        resampler.flush(&mut resampled);
+
+        let scale = self.sample_rate as f32 / sample_rate as f32;
+        Ok((0..channels)
+            .map(|ch| {
+                resampled
+                    .iter()
+                    .skip(ch)
+                    .step_by(channels)
+                    .map(|s| s * scale)
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+/// Convolves interleaved audio with an impulse response. Built on the audio thread and run
+/// in the output callback, so `process` never allocates.
+pub struct Convolver {
+    channels: usize,
+    block: usize, // Frames per partition
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    scratch: Vec<Complex<f32>>,
+    /// Spectra of the impulse response partitions of each output channel, back to back.
+    /// Only bins up to Nyquist are kept: the input is real, so the rest mirror them.
+    filters: Vec<Vec<Complex<f32>>>,
+    partitions: usize,
+    /// Spectra of the latest `partitions` input blocks of each channel, a ring indexed by
+    /// `newest`
+    history: Vec<Vec<Complex<f32>>>,
+    newest: usize,
+    /// Previous and current input block of each channel
+    input: Vec<Vec<f32>>,
+    /// Output of the last processed block of each channel
+    output: Vec<Vec<f32>>,
+    /// Frames of the current block filled so far
+    position: usize,
+    spectrum: Vec<Complex<f32>>,
+    /// Frames from a sample entering to its impulse response peak leaving
+    latency: usize,
+    ir_frames: usize,
+}
+
+impl Convolver {
+    /// Prepare `ir` for an output stream. A mono impulse response filters every channel; a
+    /// stereo one filters the first two, and further channels only get the same delay.
+    pub fn new(ir: &ImpulseResponse, sample_rate: u32, channels: u16) -> Result<Self, AudioError> {
+        let ir_channels = ir.at_rate(sample_rate)?;
+        let channels = channels.max(1) as usize;
+        let ir_frames = ir_channels[0].len();
+        let block = (ir_frames / TARGET_PARTITIONS)
+            .next_power_of_two()
+            .clamp(MIN_PARTITION_FRAMES, MAX_PARTITION_FRAMES);
+        let partitions = ir_frames.div_ceil(block).max(1);
+        let size = block * 2;
+        let bins = block + 1;
+
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(size);
+        let ifft = planner.plan_fft_inverse(size);
+        let mut scratch = vec![
+            Complex::default();
+            fft.get_inplace_scratch_len()
+                .max(ifft.get_inplace_scratch_len())
+        ];
+
+        let identity = [1.0f32];
+        let mut buffer = vec![Complex::default(); size];
+        let filters = (0..channels)
+            .map(|ch| {
+                let taps: &[f32] = match ir_channels.len() {
+                    1 => &ir_channels[0],
+                    _ if ch < 2 => &ir_channels[ch],
+                    _ => &identity,
+                };
+                let mut filter = vec![Complex::default(); partitions * bins];
+                for (p, spectrum) in filter.chunks_exact_mut(bins).enumerate() {
+                    buffer.fill(Complex::default());
+                    let start = (p * block).min(taps.len());
+                    let end = (start + block).min(taps.len());
+                    for (slot, tap) in buffer.iter_mut().zip(&taps[start..end]) {
+                        *slot = Complex::new(*tap, 0.0);
+                    }
+                    fft.process_with_scratch(&mut buffer, &mut scratch);
+                    spectrum.copy_from_slice(&buffer[..bins]);
+                }
+                filter
+            })
+            .collect();
+
+        // The filters' own delay: where the first channel's response peaks
+        let peak = ir_channels[0]
+            .iter()
+            .enumerate()
+            .fold((0, 0.0f32), |best, (i, s)| {
+                if s.abs() > best.1 {
+                    (i, s.abs())
+                } else {
+                    best
+                }
+            })
+            .0;
+
+        Ok(Self {
+            channels,
+            block,
+            fft,
+            ifft,
+            scratch,
+            filters,
+            partitions,
+            history: vec![vec![Complex::default(); partitions * bins]; channels],
+            newest: 0,
+            input: vec![vec![0.0; size]; channels],
+            output: vec![vec![0.0; block]; channels],
+            position: 0,
+            spectrum: vec![Complex::default(); size],
+            latency: block + peak,
+            ir_frames,
+        })
+    }
+
+    /// Frames the output lags the input by, counting the impulse response's own delay
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    /// Frames of output that follow the last input frame
+    pub fn tail(&self) -> usize {
+        self.block + self.ir_frames
+    }
+
+    /// Forget buffered audio (after a seek)
+    pub fn reset(&mut self) {
+        for channel in 0..self.channels {
+            self.history[channel].fill(Complex::default());
+            self.input[channel].fill(0.0);
+            self.output[channel].fill(0.0);
+        }
+        self.position = 0;
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                self.input[channel][self.block + self.position] = *sample;
+                *sample = self.output[channel][self.position];
+            }
+            self.position += 1;
+            if self.position == self.block {
+                self.position = 0;
+                self.process_block();
+            }
+        }
+    }
+
+    /// Filter the block that just filled up
+    fn process_block(&mut self) {
+        let block = self.block;
+        let (size, bins) = (block * 2, block + 1);
+        self.newest = (self.newest + 1) % self.partitions;
+        let scale = 1.0 / size as f32;
+
+        for channel in 0..self.channels {
+            // Transform the last two blocks and keep the spectrum in the history
+            for (slot, sample) in self.spectrum.iter_mut().zip(&self.input[channel]) {
+                *slot = Complex::new(*sample, 0.0);
+            }
+            self.fft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+            let history = &mut self.history[channel];
+            history[self.newest * bins..(self.newest + 1) * bins]
+                .copy_from_slice(&self.spectrum[..bins]);
+
+            // Partition p of the filter meets the input block from p blocks ago
+            let sum = &mut self.spectrum[..bins];
+            sum.fill(Complex::default());
+            let filter = &self.filters[channel];
+            for p in 0..self.partitions {
+                let block = (self.newest + self.partitions - p) % self.partitions;
+                let input = &history[block * bins..(block + 1) * bins];
+                let taps = &filter[p * bins..(p + 1) * bins];
+                for ((acc, x), h) in sum.iter_mut().zip(input).zip(taps) {
+                    *acc += x * h;
+                }
+            }
+            // Mirror the bins above Nyquist for a real result
+            for bin in 1..block {
+                self.spectrum[size - bin] = self.spectrum[bin].conj();
+            }
+            self.ifft
+                .process_with_scratch(&mut self.spectrum, &mut self.scratch);
+
+            // The second half is free of wrap-around from the circular convolution
+            for (out, value) in self.output[channel].iter_mut().zip(&self.spectrum[block..]) {
+                *out = value.re * scale;
+            }
+            self.input[channel].copy_within(block.., 0);
+        }
+    }
+}
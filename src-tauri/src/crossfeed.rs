@@ -0,0 +1,142 @@
+//! Crossfeed Module
+//! Headphone crossfeed after Bauer, as in bs2b: each ear gets a low-passed, slightly delayed
+//! copy of the other channel, and its own channel gets a matching treble boost so the
+//! overall tone stays flat. Takes the edge off hard-panned stereo on headphones.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossfeedPreset {
+    /// 700Hz, 4.5dB: close to a real speaker setup
+    #[default]
+    Default,
+    /// 700Hz, 6dB: Chu Moy's headphone amplifier
+    ChuMoy,
+    /// 650Hz, 9.5dB: Jan Meier's amplifiers, the strongest
+    JanMeier,
+    /// `cutoff` and `level` of the settings
+    Custom,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CrossfeedSettings {
+    pub enabled: bool,
+    pub preset: CrossfeedPreset,
+    pub cutoff: f32, // Hz, 300-2000, used by the custom preset
+    pub level: f32,  // dB the crossfed low end sits below the direct one, 1-15, custom preset
+}
+
+impl Default for CrossfeedSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            preset: CrossfeedPreset::Default,
+            cutoff: 700.0,
+            level: 4.5,
+        }
+    }
+}
+
+impl CrossfeedSettings {
+    pub fn clamped(self) -> Self {
+        Self {
+            cutoff: self.cutoff.clamp(300.0, 2000.0),
+            level: self.level.clamp(1.0, 15.0),
+            ..self
+        }
+    }
+
+    /// Cutoff frequency and feed level the filters are designed for
+    pub fn parameters(&self) -> (f32, f32) {
+        match self.preset {
+            CrossfeedPreset::Default => (700.0, 4.5),
+            CrossfeedPreset::ChuMoy => (700.0, 6.0),
+            CrossfeedPreset::JanMeier => (650.0, 9.5),
+            CrossfeedPreset::Custom => (self.cutoff, self.level),
+        }
+    }
+}
+
+/// Runs over interleaved stereo in the output callback, so `process` never allocates
+pub struct Crossfeed {
+    /// One-pole low-pass of the crossfed channel
+    lo_a0: f64,
+    lo_b1: f64,
+    /// First-order high-shelf of the direct channel
+    hi_a0: f64,
+    hi_a1: f64,
+    hi_b1: f64,
+    /// Brings the summed low end back to unity
+    gain: f64,
+    /// Filter memory per channel: low-pass output, high-shelf output, previous input
+    lo: [f64; 2],
+    hi: [f64; 2],
+    input: [f64; 2],
+}
+
+impl Crossfeed {
+    /// Design the filters for the output stream. None when disabled or the output is not
+    /// stereo; the caller also leaves it out for sources that are not stereo.
+    pub fn new(settings: &CrossfeedSettings, sample_rate: u32, channels: u16) -> Option<Self> {
+        if !settings.enabled || sample_rate == 0 || channels != 2 {
+            return None;
+        }
+        let (cutoff, level) = settings.parameters();
+        let (cutoff, level) = (cutoff as f64, level as f64);
+        let rate = sample_rate as f64;
+
+        // Gains of the low-pass and of the high-shelf's boost, in dB as bs2b derives them
+        let lo_db = -level * 5.0 / 6.0 - 3.0;
+        let hi_db = level / 6.0 - 3.0;
+        let lo_gain = 10f64.powf(lo_db / 20.0);
+        let hi_gain = 1.0 - 10f64.powf(hi_db / 20.0);
+        let hi_cutoff = cutoff * 2f64.powf((lo_db - 20.0 * hi_gain.log10()) / 12.0);
+
+        let x = (-2.0 * std::f64::consts::PI * cutoff / rate).exp();
+        let (lo_b1, lo_a0) = (x, lo_gain * (1.0 - x));
+        let x = (-2.0 * std::f64::consts::PI * hi_cutoff / rate).exp();
+        let (hi_b1, hi_a0, hi_a1) = (x, 1.0 - hi_gain * (1.0 - x), -x);
+
+        Some(Self {
+            lo_a0,
+            lo_b1,
+            hi_a0,
+            hi_a1,
+            hi_b1,
+            gain: 1.0 / (1.0 - hi_gain + lo_gain),
+            lo: [0.0; 2],
+            hi: [0.0; 2],
+            input: [0.0; 2],
+        })
+    }
+
+    /// Continue from the filter memory of the crossfeed this one replaces
+    pub fn carry_state(&mut self, previous: &Crossfeed) {
+        self.lo = previous.lo;
+        self.hi = previous.hi;
+        self.input = previous.input;
+    }
+
+    /// Forget the filter memory (after a seek)
+    pub fn reset(&mut self) {
+        self.lo = [0.0; 2];
+        self.hi = [0.0; 2];
+        self.input = [0.0; 2];
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(2) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let x = *sample as f64;
+                self.lo[channel] = self.lo_a0 * x + self.lo_b1 * self.lo[channel];
+                self.hi[channel] = self.hi_a0 * x
+                    + self.hi_a1 * self.input[channel]
+                    + self.hi_b1 * self.hi[channel];
+                self.input[channel] = x;
+            }
+            frame[0] = ((self.hi[0] + self.lo[1]) * self.gain) as f32;
+            frame[1] = ((self.hi[1] + self.lo[0]) * self.gain) as f32;
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! Cue Module
+//! CUE sheets: an album ripped to one image file, with a cue sheet beside it or embedded in
+//! a FLAC file, is split into virtual tracks. A virtual track's path is the image's path with
+//! the range of sample frames it covers appended ("Album.flac#cue=0-13329228"), so the
+//! decoder cuts it from the image like the encoder delay of any other file, and tracks that
+//! follow each other in the image play gaplessly.
+
+use metaflac::block::{Block, BlockType};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Separates the image's path from the range in a virtual track's path
+const LOCATOR_MARKER: &str = "#cue=";
+
+/// Index points in cue sheet text are in CD frames, 75 to the second
+const CD_FRAMES_PER_SECOND: u64 = 75;
+
+/// FLAC cue sheet track numbers of the lead-out, which only marks the end
+const LEAD_OUT_TRACKS: [u8; 2] = [170, 255];
+
+/// Sample frames of an image a virtual track covers, at the image's sample rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CueRange {
+    pub start: u64,
+    pub end: Option<u64>, // None runs to the end of the image
+}
+
+impl CueRange {
+    /// Frames in the range, given the frames in the image if it is open-ended
+    pub fn frames(&self, total: Option<u64>) -> Option<u64> {
+        self.end.or(total).map(|end| end.saturating_sub(self.start))
+    }
+}
+
+/// Path of the virtual track covering `range` of `image`
+pub fn locator(image: &str, range: CueRange) -> String {
+    let end = range.end.map(|end| end.to_string()).unwrap_or_default();
+    format!("{}{}{}-{}", image, LOCATOR_MARKER, range.start, end)
+}
+
+/// The image's path and the range of a virtual track's path; other paths come back as
+/// they are
+pub fn split_locator(path: &str) -> (&str, Option<CueRange>) {
+    let Some((image, range)) = path.rsplit_once(LOCATOR_MARKER) else {
+        return (path, None);
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return (path, None);
+    };
+    let end = match end {
+        "" => None,
+        end => match end.parse() {
+            Ok(end) => Some(end),
+            Err(_) => return (path, None),
+        },
+    };
+    match start.parse() {
+        Ok(start) => (image, Some(CueRange { start, end })),
+        Err(_) => (path, None),
+    }
+}
+
+/// Where a track starts in its file
+#[derive(Clone, Copy, Debug)]
+pub enum CuePosition {
+    /// mm:ss:ff of a cue sheet
+    CdFrames(u64),
+    /// Offset of a FLAC CUESHEET block
+    Samples(u64),
+}
+
+impl CuePosition {
+    /// Sample frame at `sample_rate`. Every common rate is a multiple of 75, so this is exact.
+    pub fn frame(self, sample_rate: u32) -> u64 {
+        match self {
+            CuePosition::CdFrames(frames) => frames * sample_rate as u64 / CD_FRAMES_PER_SECOND,
+            CuePosition::Samples(samples) => samples,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CueTrack {
+    pub number: u32,
+    pub file: Option<String>, // FILE the track is in, as written in the sheet
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: CuePosition, // INDEX 01; a pregap belongs to the track before
+    pub replaygain_gain: Option<f64>,
+    pub replaygain_peak: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub replaygain_gain: Option<f64>, // REPLAYGAIN_ALBUM_GAIN
+    pub replaygain_peak: Option<f64>,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueSheet {
+    /// Parse cue sheet text. Only audio tracks with an INDEX 01 are kept.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut sheet = CueSheet::default();
+        let mut file: Option<String> = None;
+        // The track being read, None before the first and inside data tracks
+        let mut track: Option<CueTrack> = None;
+        let mut start: Option<u64> = None; // Its INDEX 01, in CD frames
+        let mut in_track = false;
+
+        for line in text.lines() {
+            let words = split_words(line);
+            let Some(command) = words.first() else {
+                continue;
+            };
+            let arg = |i: usize| words.get(i).cloned();
+            match command.to_uppercase().as_str() {
+                "FILE" => {
+                    sheet.push(track.take(), start.take());
+                    in_track = false;
+                    file = arg(1);
+                }
+                "TRACK" => {
+                    sheet.push(track.take(), start.take());
+                    in_track = true;
+                    let audio = arg(2).is_some_and(|kind| kind.eq_ignore_ascii_case("AUDIO"));
+                    track = arg(1)
+                        .and_then(|n| n.parse().ok())
+                        .filter(|_| audio)
+                        .map(|number| CueTrack {
+                            number,
+                            file: file.clone(),
+                            title: None,
+                            performer: None,
+                            start: CuePosition::CdFrames(0),
+                            replaygain_gain: None,
+                            replaygain_peak: None,
+                        });
+                }
+                "TITLE" if in_track => {
+                    if let Some(track) = track.as_mut() {
+                        track.title = arg(1);
+                    }
+                }
+                "TITLE" => sheet.title = arg(1),
+                "PERFORMER" if in_track => {
+                    if let Some(track) = track.as_mut() {
+                        track.performer = arg(1);
+                    }
+                }
+                "PERFORMER" => sheet.performer = arg(1),
+                "INDEX" => {
+                    let index = arg(1).and_then(|n| n.parse::<u32>().ok());
+                    let position = arg(2).as_deref().and_then(parse_msf);
+                    if index == Some(1) && track.is_some() {
+                        start = position;
+                    }
+                }
+                "REM" => {
+                    let Some(key) = arg(1) else {
+                        continue;
+                    };
+                    // Values with spaces ("-6.52 dB") may be unquoted
+                    let value = words[2.min(words.len())..].join(" ");
+                    let gain = crate::library::parse_replaygain(&value);
+                    match key.to_uppercase().as_str() {
+                        "GENRE" => sheet.genre = Some(value),
+                        "DATE" => sheet.year = value.get(..4).and_then(|y| y.parse().ok()),
+                        "REPLAYGAIN_ALBUM_GAIN" => sheet.replaygain_gain = gain,
+                        "REPLAYGAIN_ALBUM_PEAK" => sheet.replaygain_peak = gain,
+                        "REPLAYGAIN_TRACK_GAIN" => {
+                            if let Some(track) = track.as_mut() {
+                                track.replaygain_gain = gain;
+                            }
+                        }
+                        "REPLAYGAIN_TRACK_PEAK" => {
+                            if let Some(track) = track.as_mut() {
+                                track.replaygain_peak = gain;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        sheet.push(track, start);
+        Some(sheet).filter(|sheet| !sheet.tracks.is_empty())
+    }
+
+    /// Keep a finished track if it got its INDEX 01
+    fn push(&mut self, track: Option<CueTrack>, start: Option<u64>) {
+        if let (Some(track), Some(start)) = (track, start) {
+            self.tracks.push(CueTrack {
+                start: CuePosition::CdFrames(start),
+                ..track
+            });
+        }
+    }
+
+    /// Read a .cue file, whatever its text encoding. Returns the sheet for each file it
+    /// splits, found among `audio_files`; files with a single track are played as they are.
+    pub fn read_file(cue_path: &Path, audio_files: &[PathBuf]) -> Vec<(PathBuf, CueSheet)> {
+        let Ok(bytes) = fs::read(cue_path) else {
+            return Vec::new();
+        };
+        let Some(sheet) = CueSheet::parse(&decode_text(&bytes)) else {
+            log::warn!("No tracks in cue sheet {}", cue_path.display());
+            return Vec::new();
+        };
+        let directory = cue_path.parent().unwrap_or(Path::new(""));
+
+        let mut names: Vec<Option<String>> = sheet.tracks.iter().map(|t| t.file.clone()).collect();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let tracks: Vec<CueTrack> = sheet
+                    .tracks
+                    .iter()
+                    .filter(|t| t.file == name)
+                    .cloned()
+                    .collect();
+                if tracks.len() < 2 {
+                    return None;
+                }
+                let image = find_image(directory, name.as_deref()?, audio_files)?;
+                Some((
+                    image,
+                    CueSheet {
+                        tracks,
+                        ..sheet.clone()
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// The cue sheet embedded in a FLAC file: a CUESHEET comment with the text of one, or
+    /// else a CUESHEET block, which only has the track offsets
+    pub fn read_embedded(path: &Path) -> Option<Self> {
+        let is_flac = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("flac"));
+        if !is_flac {
+            return None;
+        }
+        let tag = metaflac::Tag::read_from_path(path).ok()?;
+
+        if let Some(sheet) = tag
+            .get_vorbis("CUESHEET")
+            .and_then(|mut values| values.next())
+            .and_then(CueSheet::parse)
+            .filter(|sheet| sheet.tracks.len() >= 2)
+        {
+            // Every track of an embedded sheet is in the file it is embedded in
+            let tracks = sheet
+                .tracks
+                .into_iter()
+                .map(|track| CueTrack {
+                    file: None,
+                    ..track
+                })
+                .collect();
+            return Some(CueSheet { tracks, ..sheet });
+        }
+
+        let cuesheet = tag
+            .get_blocks(BlockType::CueSheet)
+            .find_map(|block| match block {
+                Block::CueSheet(cuesheet) => Some(cuesheet),
+                _ => None,
+            })?;
+        let tracks: Vec<CueTrack> = cuesheet
+            .tracks
+            .iter()
+            .filter(|t| t.is_audio && !LEAD_OUT_TRACKS.contains(&t.number))
+            .filter_map(|t| {
+                let index = t.indices.iter().find(|i| i.point_num == 1)?;
+                Some(CueTrack {
+                    number: t.number as u32,
+                    file: None,
+                    title: None,
+                    performer: None,
+                    start: CuePosition::Samples(t.offset + index.offset),
+                    replaygain_gain: None,
+                    replaygain_peak: None,
+                })
+            })
+            .collect();
+        Some(CueSheet {
+            tracks,
+            ..Default::default()
+        })
+        .filter(|sheet| sheet.tracks.len() >= 2)
+    }
+}
+
+/// Words of a cue sheet line, quoted ones with their spaces
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            words.push(quoted[..end].to_string());
+            rest = quoted.get(end + 1..).unwrap_or("").trim_start();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            words.push(rest[..end].to_string());
+            rest = rest[end..].trim_start();
+        }
+    }
+    words
+}
+
+/// "mm:ss:ff" in CD frames
+fn parse_msf(value: &str) -> Option<u64> {
+    let mut parts = value.split(':').map(|p| p.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minutes * 60 + seconds) * CD_FRAMES_PER_SECOND + frames)
+}
+
+/// Cue sheets from older rippers are often in a legacy code page rather than UTF-8
+fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        // Latin-1: every byte is the code point of the same value
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// The scanned audio file a cue sheet's FILE names. Rips are often converted after the
+/// sheet was written, so a file with the same name but another extension counts too.
+fn find_image(directory: &Path, name: &str, audio_files: &[PathBuf]) -> Option<PathBuf> {
+    let wanted = directory.join(name.replace('\\', "/"));
+    let stem = |path: &Path| path.file_stem().map(|s| s.to_string_lossy().to_lowercase());
+    let in_directory = |path: &&PathBuf| path.parent() == wanted.parent();
+    audio_files
+        .iter()
+        .find(|path| **path == wanted)
+        .or_else(|| {
+            audio_files.iter().filter(in_directory).find(|path| {
+                path.file_name().map(|n| n.to_string_lossy().to_lowercase())
+                    == wanted
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_lowercase())
+            })
+        })
+        .or_else(|| {
+            audio_files
+                .iter()
+                .filter(in_directory)
+                .find(|path| stem(path) == stem(&wanted))
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start of a track in CD frames
+    fn start(track: &CueTrack) -> u64 {
+        track.start.frame(CD_FRAMES_PER_SECOND as u32)
+    }
+
+    #[test]
+    fn pregap_belongs_to_the_track_before() {
+        let sheet = CueSheet::parse(
+            r#"FILE "Album.wav" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 00 03:10:00
+    INDEX 01 03:12:37
+"#,
+        )
+        .unwrap();
+        assert_eq!(sheet.tracks.len(), 2);
+        assert_eq!(start(&sheet.tracks[0]), 0);
+        assert_eq!(start(&sheet.tracks[1]), (3 * 60 + 12) * 75 + 37);
+        assert_eq!(
+            sheet.tracks[1].start.frame(44100),
+            (3 * 60 + 12) * 44100 + 21756
+        );
+    }
+
+    #[test]
+    fn data_tracks_are_skipped() {
+        let sheet = CueSheet::parse(
+            r#"TITLE "Enhanced CD"
+FILE "Album.bin" BINARY
+  TRACK 01 MODE1/2352
+    TITLE "Data"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "First"
+    INDEX 01 02:00:00
+  TRACK 03 AUDIO
+    TITLE "Second"
+    INDEX 01 05:00:00
+"#,
+        )
+        .unwrap();
+        let numbers: Vec<u32> = sheet.tracks.iter().map(|t| t.number).collect();
+        assert_eq!(numbers, vec![2, 3]);
+        assert_eq!(sheet.title.as_deref(), Some("Enhanced CD"));
+        assert_eq!(sheet.tracks[0].title.as_deref(), Some("First"));
+        assert_eq!(start(&sheet.tracks[0]), 2 * 60 * 75);
+    }
+
+    #[test]
+    fn tracks_keep_their_file() {
+        let sheet = CueSheet::parse(
+            r#"FILE "Disc 1.flac" WAVE
+  TRACK 01 AUDIO
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 01 04:00:00
+FILE "Disc 2.flac" WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+"#,
+        )
+        .unwrap();
+        let files: Vec<Option<&str>> = sheet.tracks.iter().map(|t| t.file.as_deref()).collect();
+        assert_eq!(
+            files,
+            vec![
+                Some("Disc 1.flac"),
+                Some("Disc 1.flac"),
+                Some("Disc 2.flac")
+            ]
+        );
+        assert_eq!(start(&sheet.tracks[2]), 0);
+    }
+
+    #[test]
+    fn unquoted_replaygain_values() {
+        let sheet = CueSheet::parse(
+            r#"REM GENRE Jazz
+REM DATE 1959
+REM REPLAYGAIN_ALBUM_GAIN -6.52 dB
+REM REPLAYGAIN_ALBUM_PEAK 0.988
+FILE "Album.flac" WAVE
+  TRACK 01 AUDIO
+    REM REPLAYGAIN_TRACK_GAIN "+1.25 dB"
+    REM REPLAYGAIN_TRACK_PEAK 0.5
+    INDEX 01 00:00:00
+"#,
+        )
+        .unwrap();
+        assert_eq!(sheet.genre.as_deref(), Some("Jazz"));
+        assert_eq!(sheet.year, Some(1959));
+        assert_eq!(sheet.replaygain_gain, Some(-6.52));
+        assert_eq!(sheet.replaygain_peak, Some(0.988));
+        assert_eq!(sheet.tracks[0].replaygain_gain, Some(1.25));
+        assert_eq!(sheet.tracks[0].replaygain_peak, Some(0.5));
+    }
+
+    #[test]
+    fn sheet_without_audio_tracks_is_none() {
+        assert!(CueSheet::parse("FILE \"Album.bin\" BINARY\n  TRACK 01 MODE1/2352\n").is_none());
+    }
+
+    #[test]
+    fn msf_is_in_cd_frames() {
+        assert_eq!(parse_msf("00:00:00"), Some(0));
+        assert_eq!(parse_msf("01:02:03"), Some((60 + 2) * 75 + 3));
+        assert_eq!(parse_msf("01:02"), None);
+        assert_eq!(parse_msf("aa:00:00"), None);
+    }
+
+    #[test]
+    fn locator_round_trip() {
+        let open = CueRange {
+            start: 13_329_228,
+            end: None,
+        };
+        let path = locator("/music/Album.flac", open);
+        assert_eq!(path, "/music/Album.flac#cue=13329228-");
+        assert_eq!(split_locator(&path), ("/music/Album.flac", Some(open)));
+
+        let closed = CueRange {
+            start: 0,
+            end: Some(13_329_228),
+        };
+        let path = locator("/music/Album.flac", closed);
+        assert_eq!(split_locator(&path), ("/music/Album.flac", Some(closed)));
+    }
+
+    #[test]
+    fn other_paths_are_not_split() {
+        assert_eq!(
+            split_locator("/music/Track.flac"),
+            ("/music/Track.flac", None)
+        );
+        assert_eq!(
+            split_locator("/music/Album.flac#cue=x-"),
+            ("/music/Album.flac#cue=x-", None)
+        );
+    }
+}
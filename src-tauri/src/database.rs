@@ -1,6 +1,8 @@
 //! Database Module
 //! SQLite-based storage for library metadata
 
+use crate::audio::DeviceProfile;
+use crate::equalizer::EqBand;
 use rusqlite::{params, Connection, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
@@ -29,6 +31,42 @@ pub struct Track {
     pub last_played: Option<String>,
     pub date_added: String,
     pub is_favorite: bool,
+    pub replaygain_track_gain: Option<f64>, // dB
+    pub replaygain_track_peak: Option<f64>, // Linear, 1.0 = full scale
+    pub replaygain_album_gain: Option<f64>,
+    pub replaygain_album_peak: Option<f64>,
+    pub start_offset: Option<i64>, // Frames into the file where a cue sheet track starts
+    pub end_offset: Option<i64>,   // None runs to the end of the file
+}
+
+/// EBU R128 analysis of a track. Loudness in LUFS, range in LU, peaks linear.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackLoudness {
+    pub track_id: i64,
+    pub integrated_lufs: Option<f64>,
+    pub loudness_range: Option<f64>,
+    pub true_peak: Option<f64>,
+    pub album_integrated_lufs: Option<f64>,
+    pub album_loudness_range: Option<f64>,
+    pub album_true_peak: Option<f64>,
+}
+
+/// Stored analysis data of one track of an album, for computing album loudness
+pub struct AlbumLoudnessInput {
+    pub track_id: i64,
+    pub file_path: String,
+    pub analyzed: bool,
+    pub true_peak: Option<f64>,
+    pub block_histogram: Option<Vec<u8>>,
+    pub short_term_histogram: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EqPreset {
+    pub id: i64,
+    pub name: String,
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,7 +143,13 @@ impl Database {
                 play_count INTEGER DEFAULT 0,
                 last_played TEXT,
                 date_added TEXT NOT NULL,
-                is_favorite INTEGER DEFAULT 0
+                is_favorite INTEGER DEFAULT 0,
+                replaygain_track_gain REAL,
+                replaygain_track_peak REAL,
+                replaygain_album_gain REAL,
+                replaygain_album_peak REAL,
+                start_offset INTEGER,
+                end_offset INTEGER
             );
 
             CREATE TABLE IF NOT EXISTS library_folders (
@@ -131,6 +175,35 @@ impl Database {
                 FOREIGN KEY (track_id) REFERENCES tracks(id)
             );
 
+            CREATE TABLE IF NOT EXISTS track_loudness (
+                track_id INTEGER PRIMARY KEY,
+                integrated_lufs REAL,
+                loudness_range REAL,
+                true_peak REAL,
+                block_histogram BLOB,
+                short_term_histogram BLOB,
+                album_integrated_lufs REAL,
+                album_loudness_range REAL,
+                album_true_peak REAL,
+                failed INTEGER DEFAULT 0,
+                analyzed_at TEXT NOT NULL,
+                FOREIGN KEY (track_id) REFERENCES tracks(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS eq_presets (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                preamp_db REAL NOT NULL,
+                bands TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS device_profiles (
+                device_name TEXT PRIMARY KEY,
+                profile TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            );
+
             CREATE INDEX IF NOT EXISTS idx_tracks_artist ON tracks(artist);
             CREATE INDEX IF NOT EXISTS idx_tracks_album ON tracks(album);
             CREATE INDEX IF NOT EXISTS idx_tracks_title ON tracks(title);
@@ -139,6 +212,33 @@ impl Database {
             CREATE INDEX IF NOT EXISTS idx_play_history_date ON play_history(played_at);
         "#,
         )?;
+        self.migrate()?;
+        Ok(())
+    }
+
+    /// Add columns introduced after the first release to existing databases.
+    /// New columns are always appended so `SELECT *` column indices stay valid.
+    fn migrate(&self) -> Result<()> {
+        let mut stmt = self.conn.prepare("PRAGMA table_info(tracks)")?;
+        let columns: Vec<String> = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<_>>()?;
+
+        for (name, definition) in [
+            ("replaygain_track_gain", "REAL"),
+            ("replaygain_track_peak", "REAL"),
+            ("replaygain_album_gain", "REAL"),
+            ("replaygain_album_peak", "REAL"),
+            ("start_offset", "INTEGER"),
+            ("end_offset", "INTEGER"),
+        ] {
+            if !columns.iter().any(|c| c == name) {
+                self.conn.execute(
+                    &format!("ALTER TABLE tracks ADD COLUMN {} {}", name, definition),
+                    [],
+                )?;
+            }
+        }
         Ok(())
     }
 
@@ -147,8 +247,11 @@ impl Database {
             r#"INSERT OR REPLACE INTO tracks 
                (file_path, file_hash, title, artist, album, album_artist, track_number, 
                 disc_number, year, genre, duration, sample_rate, bit_depth, channels, 
-                file_size, format, has_artwork, date_added, is_favorite)
-               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)"#,
+                file_size, format, has_artwork, date_added, is_favorite,
+                replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+                start_offset, end_offset)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                       ?20, ?21, ?22, ?23, ?24, ?25)"#,
             params![
                 track.file_path,
                 track.file_hash,
@@ -169,6 +272,12 @@ impl Database {
                 track.has_artwork as i32,
                 track.date_added,
                 track.is_favorite as i32,
+                track.replaygain_track_gain,
+                track.replaygain_track_peak,
+                track.replaygain_album_gain,
+                track.replaygain_album_peak,
+                track.start_offset,
+                track.end_offset,
             ],
         )?;
         Ok(self.conn.last_insert_rowid())
@@ -203,6 +312,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -295,6 +410,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -355,6 +476,7 @@ impl Database {
     pub fn clear_all_tracks(&self) -> Result<usize> {
         // Clear all tracks, play history, and favorites
         self.conn.execute("DELETE FROM play_history", [])?;
+        self.conn.execute("DELETE FROM track_loudness", [])?;
         self.conn.execute("DELETE FROM favorites", [])?;
         let count = self.conn.execute("DELETE FROM tracks", [])?;
         Ok(count)
@@ -430,6 +552,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -473,6 +601,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -546,6 +680,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -581,6 +721,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         });
 
@@ -591,6 +737,13 @@ impl Database {
         }
     }
 
+    /// Remove one track, such as an album image that was imported before its cue sheet
+    pub fn remove_track_by_path(&self, path: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM tracks WHERE file_path = ?1", params![path])?;
+        Ok(())
+    }
+
     pub fn track_exists(&self, file_hash: &str) -> Result<bool> {
         let mut stmt = self
             .conn
@@ -623,6 +776,276 @@ impl Database {
         }
     }
 
+    /// A track that has no loudness analysis yet, album by album
+    pub fn next_unanalyzed_track(&self) -> Result<Option<(i64, String)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT t.id, t.file_path FROM tracks t
+            LEFT JOIN track_loudness l ON l.track_id = t.id
+            WHERE l.track_id IS NULL
+            ORDER BY t.artist, t.album, t.disc_number, t.track_number
+            LIMIT 1
+        "#,
+        )?;
+
+        let mut rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.next().transpose()
+    }
+
+    /// (analyzed or failed, total) track counts
+    pub fn get_loudness_progress(&self) -> Result<(i64, i64)> {
+        self.conn.query_row(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM track_loudness l INNER JOIN tracks t ON t.id = l.track_id),
+                (SELECT COUNT(*) FROM tracks)
+        "#,
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    pub fn save_track_loudness(
+        &self,
+        loudness: &TrackLoudness,
+        block_histogram: &[u8],
+        short_term_histogram: &[u8],
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"INSERT OR REPLACE INTO track_loudness
+               (track_id, integrated_lufs, loudness_range, true_peak, block_histogram,
+                short_term_histogram, failed, analyzed_at)
+               VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, datetime('now'))"#,
+            params![
+                loudness.track_id,
+                loudness.integrated_lufs,
+                loudness.loudness_range,
+                loudness.true_peak,
+                block_histogram,
+                short_term_histogram,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn mark_loudness_failed(&self, track_id: i64) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO track_loudness (track_id, failed, analyzed_at) VALUES (?1, 1, datetime('now'))",
+            params![track_id],
+        )?;
+        Ok(())
+    }
+
+    /// Analysis state of every track on the same album as `track_id`
+    pub fn get_album_loudness_inputs(&self, track_id: i64) -> Result<Vec<AlbumLoudnessInput>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT t.id, t.file_path, l.track_id IS NOT NULL, l.true_peak,
+                   l.block_histogram, l.short_term_histogram
+            FROM tracks t
+            LEFT JOIN track_loudness l ON l.track_id = t.id
+            INNER JOIN tracks ref ON ref.id = ?1
+            WHERE t.album = ref.album AND t.artist = ref.artist
+        "#,
+        )?;
+
+        let inputs = stmt.query_map(params![track_id], |row| {
+            Ok(AlbumLoudnessInput {
+                track_id: row.get(0)?,
+                file_path: row.get(1)?,
+                analyzed: row.get(2)?,
+                true_peak: row.get(3)?,
+                block_histogram: row.get(4)?,
+                short_term_histogram: row.get(5)?,
+            })
+        })?;
+
+        inputs.collect()
+    }
+
+    pub fn save_album_loudness(
+        &self,
+        track_ids: &[i64],
+        integrated_lufs: Option<f64>,
+        loudness_range: Option<f64>,
+        true_peak: Option<f64>,
+    ) -> Result<()> {
+        for track_id in track_ids {
+            self.conn.execute(
+                r#"UPDATE track_loudness
+                   SET album_integrated_lufs = ?2, album_loudness_range = ?3, album_true_peak = ?4
+                   WHERE track_id = ?1"#,
+                params![track_id, integrated_lufs, loudness_range, true_peak],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn get_track_loudness(&self, track_id: i64) -> Result<Option<TrackLoudness>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT track_id, integrated_lufs, loudness_range, true_peak,
+                   album_integrated_lufs, album_loudness_range, album_true_peak
+            FROM track_loudness WHERE track_id = ?1 AND failed = 0
+        "#,
+        )?;
+
+        let mut rows = stmt.query_map(params![track_id], Self::row_to_loudness)?;
+        rows.next().transpose()
+    }
+
+    /// Every successful analysis with the analyzed file's path
+    pub fn get_all_track_loudness(&self) -> Result<Vec<(String, TrackLoudness)>> {
+        let mut stmt = self.conn.prepare(
+            r#"
+            SELECT l.track_id, l.integrated_lufs, l.loudness_range, l.true_peak,
+                   l.album_integrated_lufs, l.album_loudness_range, l.album_true_peak, t.file_path
+            FROM track_loudness l
+            INNER JOIN tracks t ON t.id = l.track_id
+            WHERE l.failed = 0
+        "#,
+        )?;
+
+        let results = stmt.query_map([], |row| Ok((row.get(7)?, Self::row_to_loudness(row)?)))?;
+        results.collect()
+    }
+
+    fn row_to_loudness(row: &rusqlite::Row) -> Result<TrackLoudness> {
+        Ok(TrackLoudness {
+            track_id: row.get(0)?,
+            integrated_lufs: row.get(1)?,
+            loudness_range: row.get(2)?,
+            true_peak: row.get(3)?,
+            album_integrated_lufs: row.get(4)?,
+            album_loudness_range: row.get(5)?,
+            album_true_peak: row.get(6)?,
+        })
+    }
+
+    /// Store ReplayGain values after they were written into the file's tags
+    pub fn update_track_replaygain(
+        &self,
+        track_id: i64,
+        file_hash: &str,
+        track_gain: Option<f64>,
+        track_peak: Option<f64>,
+        album_gain: Option<f64>,
+        album_peak: Option<f64>,
+    ) -> Result<()> {
+        self.conn.execute(
+            r#"UPDATE tracks
+               SET file_hash = ?2, replaygain_track_gain = ?3, replaygain_track_peak = ?4,
+                   replaygain_album_gain = ?5, replaygain_album_peak = ?6
+               WHERE id = ?1"#,
+            params![track_id, file_hash, track_gain, track_peak, album_gain, album_peak],
+        )?;
+        Ok(())
+    }
+
+    /// Path of a track and its tagged track gain, if any
+    pub fn get_track_file(&self, track_id: i64) -> Result<Option<(String, Option<f64>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT file_path, replaygain_track_gain FROM tracks WHERE id = ?1")?;
+        let mut rows = stmt.query_map(params![track_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.next().transpose()
+    }
+
+    /// Save an EQ preset, replacing the bands of an existing preset with the same name
+    pub fn save_eq_preset(&self, name: &str, preamp_db: f32, bands: &[EqBand]) -> Result<i64> {
+        let bands = serde_json::to_string(bands)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            r#"INSERT INTO eq_presets (name, preamp_db, bands, created_at)
+               VALUES (?1, ?2, ?3, datetime('now'))
+               ON CONFLICT(name) DO UPDATE SET preamp_db = excluded.preamp_db, bands = excluded.bands"#,
+            params![name, preamp_db, bands],
+        )?;
+        self.conn.query_row(
+            "SELECT id FROM eq_presets WHERE name = ?1",
+            params![name],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn get_eq_presets(&self) -> Result<Vec<EqPreset>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, preamp_db, bands FROM eq_presets ORDER BY name")?;
+        let presets = stmt.query_map([], Self::row_to_eq_preset)?;
+        presets.collect()
+    }
+
+    pub fn get_eq_preset(&self, id: i64) -> Result<Option<EqPreset>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, preamp_db, bands FROM eq_presets WHERE id = ?1")?;
+        let mut rows = stmt.query_map(params![id], Self::row_to_eq_preset)?;
+        rows.next().transpose()
+    }
+
+    pub fn delete_eq_preset(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM eq_presets WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    fn row_to_eq_preset(row: &rusqlite::Row) -> Result<EqPreset> {
+        let bands: String = row.get(3)?;
+        Ok(EqPreset {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            preamp_db: row.get(2)?,
+            bands: serde_json::from_str(&bands).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    3,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?,
+        })
+    }
+
+    pub fn save_device_profile(&self, device_name: &str, profile: &DeviceProfile) -> Result<()> {
+        let profile = serde_json::to_string(profile)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            r#"INSERT INTO device_profiles (device_name, profile, updated_at)
+               VALUES (?1, ?2, datetime('now'))
+               ON CONFLICT(device_name) DO UPDATE SET profile = excluded.profile, updated_at = excluded.updated_at"#,
+            params![device_name, profile],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_device_profiles(&self) -> Result<Vec<(String, DeviceProfile)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT device_name, profile FROM device_profiles")?;
+        let profiles = stmt.query_map([], |row| {
+            let profile: String = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                serde_json::from_str(&profile).map_err(|e| {
+                    rusqlite::Error::FromSqlConversionFailure(
+                        1,
+                        rusqlite::types::Type::Text,
+                        Box::new(e),
+                    )
+                })?,
+            ))
+        })?;
+        profiles.collect()
+    }
+
+    pub fn delete_device_profile(&self, device_name: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM device_profiles WHERE device_name = ?1",
+            params![device_name],
+        )?;
+        Ok(())
+    }
+
     pub fn get_hires_tracks(&self) -> Result<Vec<Track>> {
         let mut stmt = self.conn.prepare(
             "SELECT * FROM tracks WHERE bit_depth >= 24 ORDER BY artist, album, track_number",
@@ -652,6 +1075,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
@@ -687,6 +1116,12 @@ impl Database {
                 last_played: row.get(19)?,
                 date_added: row.get(20)?,
                 is_favorite: row.get::<_, i32>(21)? != 0,
+                replaygain_track_gain: row.get(22)?,
+                replaygain_track_peak: row.get(23)?,
+                replaygain_album_gain: row.get(24)?,
+                replaygain_album_peak: row.get(25)?,
+                start_offset: row.get(26)?,
+                end_offset: row.get(27)?,
             })
         })?;
 
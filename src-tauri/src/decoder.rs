@@ -0,0 +1,1738 @@
+//! Streaming Decoder Module
+//! Decodes audio packet by packet on a dedicated thread and feeds the lock-free output ring,
+//! so playback starts immediately and memory use does not depend on track length
+
+use crate::audio::{
+    AudioError, CrossfadeSettings, OutputShared, PlaybackState, ReplayGainInfo, TimelineEntry,
+    TrackTimeline,
+};
+use crate::channel_mixer::ChannelMixer;
+use crate::cue::{self, CueRange};
+use crate::dsd::{is_dsd_path, tag_text, DsdOutput, DsdReader};
+use crate::library::parse_replaygain;
+use crate::loudness;
+use crate::resampler::StreamResampler;
+use crate::ring_buffer::RingProducer;
+use crate::sample_format::{append_interleaved, source_bit_depth};
+use crate::time_stretch::SpeedProcessor;
+use id3::TagLike;
+use parking_lot::RwLock;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use symphonia::core::audio::Channels;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+
+/// Seconds of output audio the decoder is allowed to run ahead of the output callback
+pub const QUEUE_SECONDS: usize = 2;
+
+/// A wrapper around a File that limits reads to a specified byte limit.
+/// This is used for progressive streaming where only part of the file is downloaded.
+/// Returns EOF when the limit is reached, preventing reads into undownloaded (zero) portions.
+/// The limit is shared so it can grow while the file is being decoded.
+struct LimitedFileReader {
+    file: File,
+    limit: Arc<AtomicU64>,
+    current_pos: u64,
+}
+
+impl LimitedFileReader {
+    fn new(file: File, limit: Arc<AtomicU64>) -> Self {
+        Self {
+            file,
+            limit,
+            current_pos: 0,
+        }
+    }
+}
+
+impl Read for LimitedFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let limit = self.limit.load(Ordering::Acquire);
+        if self.current_pos >= limit {
+            return Ok(0); // EOF
+        }
+
+        let remaining = limit - self.current_pos;
+        let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
+
+        let bytes_read = self.file.read(&mut buf[..to_read])?;
+        self.current_pos += bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for LimitedFileReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                offset
+            }
+            SeekFrom::End(offset) => {
+                // For End, use the limit as the "end" not the actual file size
+                let limit = self.limit.load(Ordering::Acquire);
+                let target = (limit as i64 + offset).max(0) as u64;
+                self.file.seek(SeekFrom::Start(target))?;
+                target
+            }
+            SeekFrom::Current(offset) => {
+                let new = (self.current_pos as i64 + offset).max(0) as u64;
+                self.file.seek(SeekFrom::Start(new))?;
+                new
+            }
+        };
+        self.current_pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+impl MediaSource for LimitedFileReader {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        // Return the limit as the length, not the actual file size
+        Some(self.limit.load(Ordering::Acquire))
+    }
+}
+
+/// Format of a decoded source, as reported by its codec parameters
+#[derive(Clone, Copy, Debug)]
+pub struct SourceSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_depth: u16,
+    pub n_frames: Option<u64>,
+    /// Speaker positions, when the container declares them
+    pub layout: Option<Channels>,
+    /// DSD sample rate of a DSF/DFF source
+    pub dsd_rate: Option<u32>,
+    /// Frames are DoP: DSD bits that have to reach the DAC untouched
+    pub dop: bool,
+}
+
+impl SourceSpec {
+    /// Duration in seconds, if the container reports a frame count
+    pub fn duration(&self) -> Option<f64> {
+        self.n_frames
+            .map(|frames| frames as f64 / self.sample_rate as f64)
+    }
+}
+
+/// One opened file being decoded packet by packet
+pub struct TrackSource {
+    reader: SourceReader,
+    spec: SourceSpec,
+    /// Frames still to be discarded after an accurate seek landed before the target
+    skip_frames: u64,
+    /// Source frame index of the next frame handed out by `decode_next`
+    frame_position: u64,
+    /// Encoder delay the demuxer does not trim itself (iTunSMPB), in stream frames
+    frame_offset: u64,
+    /// Number of real frames after the encoder delay, when padding has to be cut by hand
+    frame_limit: Option<u64>,
+    /// Album artist and album from the file's tags, to tell tracks of one album apart
+    album: Option<String>,
+    replaygain: ReplayGainInfo,
+}
+
+enum SourceReader {
+    /// Demuxed and decoded by symphonia
+    Packets {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32,
+        time_base: Option<TimeBase>,
+    },
+    /// DSF/DFF, which symphonia cannot read
+    Dsd(Box<DsdReader>),
+}
+
+impl TrackSource {
+    /// Open and probe a file. If `byte_limit` is set, only that many bytes are ever read.
+    /// DSD files are decoded to `dsd`. A cue sheet track plays its range of the image.
+    pub fn open(
+        file_path: &str,
+        byte_limit: Option<Arc<AtomicU64>>,
+        dsd: DsdOutput,
+    ) -> Result<Self, AudioError> {
+        let (image, range) = cue::split_locator(file_path);
+        let path = Path::new(image);
+        if !path.exists() {
+            return Err(AudioError::FileNotFound(file_path.to_string()));
+        }
+        let mut source = if is_dsd_path(path) {
+            Self::open_dsd(image, dsd)?
+        } else {
+            Self::open_packets(image, byte_limit)?
+        };
+        if let Some(range) = range {
+            source.select_range(file_path, range)?;
+        }
+        Ok(source)
+    }
+
+    fn open_packets(
+        file_path: &str,
+        byte_limit: Option<Arc<AtomicU64>>,
+    ) -> Result<Self, AudioError> {
+        let path = Path::new(file_path);
+
+        let file = File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
+
+        let mss = if let Some(limit) = byte_limit {
+            println!(
+                "[Audio] Decoding with byte limit: {} bytes",
+                limit.load(Ordering::Acquire)
+            );
+            MediaSourceStream::new(
+                Box::new(LimitedFileReader::new(file, limit)),
+                Default::default(),
+            )
+        } else {
+            MediaSourceStream::new(Box::new(file), Default::default())
+        };
+
+        // Create a hint to help the format registry
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+
+        // Gapless mode makes the MP3 (LAME/Xing) and Ogg demuxers report encoder delay and
+        // padding, which their decoders then trim from the first and last packets
+        let format_opts = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let metadata_opts = MetadataOptions::default();
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, mss, &format_opts, &metadata_opts)
+            .map_err(|e| AudioError::Decode(e.to_string()))?;
+
+        // Tags may sit in front of the container (ID3) or inside it
+        let container_tags = probed.metadata.get().and_then(|m| m.current().cloned());
+        let mut format = probed.format;
+        let revisions: Vec<MetadataRevision> = container_tags
+            .into_iter()
+            .chain(format.metadata().current().cloned())
+            .collect();
+
+        // MP4/M4A files carry their gapless info in an iTunSMPB tag instead
+        let smpb = revisions.iter().find_map(find_itunsmpb);
+        let album = album_key(&revisions);
+        // Untagged files fall back to the library's own loudness analysis
+        let mut replaygain = replaygain_info(&revisions);
+        if replaygain.track_gain.is_none() && replaygain.album_gain.is_none() {
+            if let Some(analyzed) = loudness::analyzed_replaygain(file_path) {
+                replaygain = analyzed;
+            }
+        }
+
+        // Find the first audio track
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(AudioError::UnsupportedFormat)?;
+
+        let mut spec = SourceSpec {
+            sample_rate: track.codec_params.sample_rate.unwrap_or(44100),
+            channels: track
+                .codec_params
+                .channels
+                .map(|c| c.count() as u16)
+                .unwrap_or(2),
+            bit_depth: source_bit_depth(
+                track.codec_params.bits_per_sample,
+                track.codec_params.sample_format,
+            ),
+            n_frames: track.codec_params.n_frames,
+            layout: track.codec_params.channels,
+            dsd_rate: None,
+            dop: false,
+        };
+
+        if track.codec_params.delay.is_some() || track.codec_params.padding.is_some() {
+            log::info!(
+                "Gapless: encoder delay {} frames, padding {} frames",
+                track.codec_params.delay.unwrap_or(0),
+                track.codec_params.padding.unwrap_or(0)
+            );
+        }
+
+        let (frame_offset, frame_limit) = match smpb {
+            Some(smpb) => {
+                log::info!(
+                    "Gapless (iTunSMPB): delay {} frames, padding {} frames, {} frames total",
+                    smpb.delay,
+                    smpb.padding,
+                    smpb.total_frames
+                );
+                spec.n_frames = Some(smpb.total_frames);
+                (smpb.delay, Some(smpb.total_frames))
+            }
+            None => (0, None),
+        };
+
+        log::info!(
+            "Decoding audio: {}Hz, {} channels, {}-bit, codec: {:?}",
+            spec.sample_rate,
+            spec.channels,
+            spec.bit_depth,
+            track.codec_params.codec
+        );
+
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+
+        let dec_opts = DecoderOptions::default();
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &dec_opts)
+            .map_err(|e| AudioError::Decode(e.to_string()))?;
+
+        Ok(Self {
+            reader: SourceReader::Packets {
+                format,
+                decoder,
+                track_id,
+                time_base,
+            },
+            spec,
+            skip_frames: frame_offset,
+            frame_position: 0,
+            frame_offset,
+            frame_limit,
+            album,
+            replaygain,
+        })
+    }
+
+    fn open_dsd(file_path: &str, output: DsdOutput) -> Result<Self, AudioError> {
+        let reader = DsdReader::open(Path::new(file_path), output)?;
+        let info = reader.info();
+        let spec = SourceSpec {
+            sample_rate: reader.sample_rate(),
+            channels: info.channels,
+            // DoP words are 24-bit, the conversion produces f32
+            bit_depth: if reader.is_dop() { 24 } else { 32 },
+            n_frames: Some(reader.n_frames()),
+            layout: info.layout,
+            dsd_rate: Some(info.sample_rate),
+            dop: reader.is_dop(),
+        };
+
+        let tag = info.id3.as_ref();
+        let album = tag.and_then(|tag| {
+            let artist = tag.album_artist().or(tag.artist()).unwrap_or_default();
+            Some(album_key_of(artist, tag.album()?))
+        });
+        let mut replaygain = tag.map(id3_replaygain_info).unwrap_or_default();
+        if replaygain.track_gain.is_none() && replaygain.album_gain.is_none() {
+            if let Some(analyzed) = loudness::analyzed_replaygain(file_path) {
+                replaygain = analyzed;
+            }
+        }
+
+        log::info!(
+            "Decoding DSD: {}Hz, {} channels, {}",
+            info.sample_rate,
+            spec.channels,
+            match output {
+                DsdOutput::Dop => format!("DoP at {}Hz", spec.sample_rate),
+                DsdOutput::Pcm(_) => format!("PCM at {}Hz", spec.sample_rate),
+            }
+        );
+
+        Ok(Self {
+            reader: SourceReader::Dsd(Box::new(reader)),
+            spec,
+            skip_frames: 0,
+            frame_position: 0,
+            frame_offset: 0,
+            frame_limit: None,
+            album,
+            replaygain,
+        })
+    }
+
+    /// Play only `range` of the file, a cue sheet track of an album image. The range is in
+    /// frames at the rate the library stores for the image, the DSD rate for DSD.
+    fn select_range(&mut self, file_path: &str, range: CueRange) -> Result<(), AudioError> {
+        let scale = |frame: u64| match self.spec.dsd_rate {
+            Some(dsd_rate) => frame * self.spec.sample_rate as u64 / dsd_rate.max(1) as u64,
+            None => frame,
+        };
+        let start = scale(range.start);
+        let frames = CueRange {
+            start,
+            end: range.end.map(scale),
+        }
+        .frames(self.spec.n_frames);
+
+        self.frame_offset += start;
+        self.frame_limit = frames;
+        self.spec.n_frames = frames;
+
+        // The image's tags describe the whole album: its track gain is the album's gain
+        let replaygain = &mut self.replaygain;
+        if replaygain.album_gain.is_none() {
+            replaygain.album_gain = replaygain.track_gain;
+            replaygain.album_peak = replaygain.track_peak;
+        }
+        replaygain.track_gain = None;
+        replaygain.track_peak = None;
+        if let Some(analyzed) = loudness::analyzed_replaygain(file_path) {
+            replaygain.track_gain = analyzed.track_gain;
+            replaygain.track_peak = analyzed.track_peak;
+            if replaygain.album_gain.is_none() {
+                replaygain.album_gain = analyzed.album_gain;
+                replaygain.album_peak = analyzed.album_peak;
+            }
+        }
+
+        self.seek(0)
+    }
+
+    pub fn spec(&self) -> SourceSpec {
+        self.spec
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.album.as_deref()
+    }
+
+    pub fn replaygain(&self) -> ReplayGainInfo {
+        self.replaygain
+    }
+
+    /// Source frame index of the next decoded frame
+    pub fn frame_position(&self) -> u64 {
+        self.frame_position
+    }
+
+    /// Decode the next packet of the audio track and append its interleaved samples to `out`.
+    /// Returns `Ok(false)` once the end of the stream has been reached.
+    pub fn decode_next(&mut self, out: &mut Vec<f32>) -> Result<bool, AudioError> {
+        let (format, decoder, track_id) = match &mut self.reader {
+            SourceReader::Packets {
+                format,
+                decoder,
+                track_id,
+                ..
+            } => (format, decoder, *track_id),
+            SourceReader::Dsd(reader) => {
+                let start = out.len();
+                let frames = reader.read(out)?;
+                if frames == 0 {
+                    return Ok(false);
+                }
+                let channels = self.spec.channels as usize;
+                return Ok(self.take_frames(out, start, frames, channels));
+            }
+        };
+
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(symphonia::core::errors::Error::IoError(_)) => return Ok(false),
+                Err(e) => {
+                    log::warn!("Error reading packet: {}", e);
+                    return Ok(false);
+                }
+            };
+
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    log::warn!("Decode error: {}", e);
+                    continue;
+                }
+            };
+
+            let channels = decoded.spec().channels.count();
+            let start = out.len();
+            append_interleaved(&decoded, out);
+
+            // Drop frames that precede an accurate seek target or the encoder delay
+            let mut frames = ((out.len() - start).checked_div(channels).unwrap_or(0)) as u64;
+            if self.skip_frames > 0 {
+                let skip = self.skip_frames.min(frames);
+                out.drain(start..start + skip as usize * channels);
+                self.skip_frames -= skip;
+                frames -= skip;
+            }
+
+            return Ok(self.take_frames(out, start, frames, channels));
+        }
+    }
+
+    /// Account for `frames` frames appended to `out` from `start`, cutting the encoder
+    /// padding or the rest of a cue sheet image at the end of the track
+    fn take_frames(
+        &mut self,
+        out: &mut Vec<f32>,
+        start: usize,
+        frames: u64,
+        channels: usize,
+    ) -> bool {
+        if let Some(limit) = self.frame_limit {
+            let remaining = limit.saturating_sub(self.frame_position);
+            if frames >= remaining {
+                out.truncate(start + remaining as usize * channels);
+                self.frame_position += remaining;
+                return remaining > 0;
+            }
+        }
+        self.frame_position += frames;
+        true
+    }
+
+    /// Seek to a source frame using the demuxer's own seeking
+    pub fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+        let (format, decoder, track_id, time_base) = match &mut self.reader {
+            SourceReader::Packets {
+                format,
+                decoder,
+                track_id,
+                time_base,
+            } => (format, decoder, *track_id, *time_base),
+            SourceReader::Dsd(reader) => {
+                reader.seek(frame + self.frame_offset)?;
+                self.frame_position = frame;
+                return Ok(());
+            }
+        };
+
+        let stream_frame = frame + self.frame_offset;
+        let time = Time::from(stream_frame as f64 / self.spec.sample_rate as f64);
+        let seeked = format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time,
+                    track_id: Some(track_id),
+                },
+            )
+            .map_err(|e| AudioError::Decode(format!("Seek failed: {}", e)))?;
+
+        decoder.reset();
+
+        let rate = self.spec.sample_rate;
+        let required = ts_to_frames(time_base, rate, seeked.required_ts);
+        let actual = ts_to_frames(time_base, rate, seeked.actual_ts);
+        self.skip_frames = required.saturating_sub(actual);
+        if required < self.frame_offset {
+            // Landed inside the encoder delay: it still has to be skipped
+            self.skip_frames += self.frame_offset - required;
+        }
+        self.frame_position = required.saturating_sub(self.frame_offset);
+        Ok(())
+    }
+}
+
+fn ts_to_frames(time_base: Option<TimeBase>, sample_rate: u32, ts: u64) -> u64 {
+    match time_base {
+        Some(tb) => {
+            let time = tb.calc_time(ts);
+            let rate = sample_rate as u64;
+            time.seconds * rate + (time.frac * rate as f64).round() as u64
+        }
+        None => ts,
+    }
+}
+
+/// Encoder delay and padding from an iTunes iTunSMPB tag
+struct ItunSmpb {
+    delay: u64,
+    padding: u64,
+    total_frames: u64,
+}
+
+fn find_itunsmpb(revision: &MetadataRevision) -> Option<ItunSmpb> {
+    revision
+        .tags()
+        .iter()
+        .find(|tag| tag.key.ends_with("iTunSMPB"))
+        .and_then(|tag| parse_itunsmpb(&tag.value.to_string()))
+}
+
+fn album_key(revisions: &[MetadataRevision]) -> Option<String> {
+    let find = |key: StandardTagKey| {
+        revisions
+            .iter()
+            .flat_map(|r| r.tags())
+            .find(|tag| tag.std_key == Some(key))
+            .map(|tag| tag.value.to_string())
+    };
+    let album = find(StandardTagKey::Album)?;
+    let artist = find(StandardTagKey::AlbumArtist)
+        .or_else(|| find(StandardTagKey::Artist))
+        .unwrap_or_default();
+    Some(album_key_of(&artist, &album))
+}
+
+fn album_key_of(artist: &str, album: &str) -> String {
+    format!("{}\u{1f}{}", artist.to_lowercase(), album.to_lowercase())
+}
+
+fn replaygain_info(revisions: &[MetadataRevision]) -> ReplayGainInfo {
+    let find = |key: StandardTagKey| {
+        revisions
+            .iter()
+            .flat_map(|r| r.tags())
+            .find(|tag| tag.std_key == Some(key))
+            .and_then(|tag| parse_replaygain(&tag.value.to_string()))
+            .map(|v| v as f32)
+    };
+    ReplayGainInfo {
+        track_gain: find(StandardTagKey::ReplayGainTrackGain),
+        track_peak: find(StandardTagKey::ReplayGainTrackPeak),
+        album_gain: find(StandardTagKey::ReplayGainAlbumGain),
+        album_peak: find(StandardTagKey::ReplayGainAlbumPeak),
+    }
+}
+
+/// ReplayGain from the TXXX frames of an ID3v2 tag (DSF files)
+fn id3_replaygain_info(tag: &id3::Tag) -> ReplayGainInfo {
+    let find = |description: &str| {
+        tag_text(tag, description)
+            .and_then(parse_replaygain)
+            .map(|v| v as f32)
+    };
+    ReplayGainInfo {
+        track_gain: find("REPLAYGAIN_TRACK_GAIN"),
+        track_peak: find("REPLAYGAIN_TRACK_PEAK"),
+        album_gain: find("REPLAYGAIN_ALBUM_GAIN"),
+        album_peak: find("REPLAYGAIN_ALBUM_PEAK"),
+    }
+}
+
+/// The tag is a list of hex fields: reserved, delay, padding, total samples, ...
+fn parse_itunsmpb(value: &str) -> Option<ItunSmpb> {
+    let fields: Vec<u64> = value
+        .split_whitespace()
+        .map(|f| u64::from_str_radix(f, 16))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    if fields.len() < 4 || fields[3] == 0 {
+        return None;
+    }
+    Some(ItunSmpb {
+        delay: fields[1],
+        padding: fields[2],
+        total_frames: fields[3],
+    })
+}
+
+pub enum DecoderCommand {
+    Seek(f64),
+    Append(String), // Decode this file after the current sources (chunk transitions)
+    ExtendLimit(String, u64), // The file grew on disk: raise its byte limit to the new total
+    SetNext(Option<String>, Option<CrossfadeSettings>), // Track to continue with once the current one has been decoded
+    RefreshGain,    // Recompute ReplayGain from the current settings
+    RefreshDownmix, // Rebuild the channel mixers from the current settings
+    RefreshSpeed,   // Rebuild the speed processor and restart from the current position
+    Stop,
+}
+
+/// A file in the chain of sources that make up the current track
+struct ChainEntry {
+    path: String,
+    byte_limit: Option<Arc<AtomicU64>>,
+    /// Playback time at which this entry starts, in seconds
+    start_time: f64,
+    /// Known duration in seconds (from the container, or measured once fully decoded)
+    duration: Option<f64>,
+    /// Source frames decoded so far, used to resume after the byte limit grows
+    frames_decoded: u64,
+    /// Set when a byte-limited source reached its current limit
+    exhausted: bool,
+}
+
+impl ChainEntry {
+    fn new(path: String, byte_limit: Option<Arc<AtomicU64>>, duration: Option<f64>) -> Self {
+        Self {
+            path,
+            byte_limit,
+            start_time: 0.0,
+            duration,
+            frames_decoded: 0,
+            exhausted: false,
+        }
+    }
+}
+
+/// The track queued after the current one, opened ahead of time so the switch is immediate
+struct PreparedTrack {
+    path: String,
+    source: Option<TrackSource>,
+    crossfade: Option<CrossfadeSettings>,
+}
+
+/// A track that was switched away from, and the output frame where the switch happens
+struct PreviousTrack {
+    chain: Vec<ChainEntry>,
+    switch_frame: u64,
+    playthrough: u64,
+    crossfade: Option<CrossfadeSettings>,
+    spec: SourceSpec,
+    album: Option<String>,
+    replaygain: ReplayGainInfo,
+}
+
+/// The incoming track while it is being mixed over the end of the current one
+struct Crossfade {
+    settings: CrossfadeSettings,
+    /// Output frame where the overlap starts, and its length in frames
+    start_frame: u64,
+    length: u64,
+    path: String,
+    playthrough: u64,
+    spec: SourceSpec,
+    album: Option<String>,
+    replaygain: ReplayGainInfo,
+    gain: f32,
+    source: Option<TrackSource>,
+    resampler: Option<StreamResampler>,
+    mixer: Option<ChannelMixer>,
+    frames_decoded: u64,
+    /// Incoming audio converted to the output format, not yet mixed
+    buffer: Vec<f32>,
+    buffer_pos: usize,
+}
+
+impl Crossfade {
+    /// Decode the incoming track until `samples` output samples are buffered or it ends
+    fn fill(&mut self, samples: usize) {
+        while self.buffer.len() - self.buffer_pos < samples {
+            let Some(source) = self.source.as_mut() else {
+                break;
+            };
+            let mut decoded = Vec::new();
+            let more = source.decode_next(&mut decoded).unwrap_or(false);
+            self.frames_decoded = source.frame_position();
+
+            let mut converted = Vec::new();
+            match self.resampler.as_mut() {
+                Some(resampler) => {
+                    resampler.process(&decoded, &mut converted);
+                    if !more {
+                        resampler.flush(&mut converted);
+                    }
+                }
+                None => converted = decoded,
+            }
+            let start = self.buffer.len();
+            match &self.mixer {
+                Some(mixer) => mixer.process(&converted, &mut self.buffer),
+                None => self.buffer.extend_from_slice(&converted),
+            }
+            apply_gain(&mut self.buffer[start..], self.gain);
+
+            if !more {
+                self.source = None;
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        match self.buffer.get(self.buffer_pos) {
+            Some(sample) => {
+                self.buffer_pos += 1;
+                *sample
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Handle to the decoder thread of the current playback. Dropping it stops the thread.
+pub struct DecoderHandle {
+    command_tx: mpsc::Sender<DecoderCommand>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DecoderHandle {
+    /// Start decoding `source` (already opened from `path`) into the output ring
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn(
+        path: &str,
+        byte_limit: Option<Arc<AtomicU64>>,
+        source: TrackSource,
+        output_sample_rate: u32,
+        output_channels: u16,
+        producer: RingProducer,
+        output: Arc<OutputShared>,
+        timeline: Arc<RwLock<TrackTimeline>>,
+        state: Arc<RwLock<PlaybackState>>,
+    ) -> Self {
+        let (command_tx, command_rx) = mpsc::channel();
+
+        let spec = source.spec();
+        let replaygain = source.replaygain();
+        let gain = track_gain(&state, &spec, &replaygain);
+        let entry = ChainEntry::new(path.to_string(), byte_limit, spec.duration());
+
+        // Register the track before the thread starts so the state is never without it
+        timeline.write().reset(TimelineEntry {
+            start_frame: 0,
+            playthrough: 0,
+            fade_frames: 0,
+            path: path.to_string(),
+            duration: spec.duration().unwrap_or(0.0),
+            bit_depth: spec.bit_depth,
+            gain,
+            native: is_native(&spec, output_sample_rate, output_channels),
+            dsd_rate: spec.dsd_rate,
+            channels: spec.channels,
+        });
+
+        let track_album = source.album().map(|a| a.to_string());
+        let speed = speed_processor(&spec, output_sample_rate, output_channels, &state);
+        output.set_speed(speed.as_ref().map_or(1.0, |s| s.speed()));
+
+        let worker = DecodeWorker {
+            chain: vec![entry],
+            current: 0,
+            source: Some(source),
+            resampler: None,
+            mixer: None,
+            speed,
+            track_spec: spec,
+            track_album,
+            track_replaygain: replaygain,
+            gain,
+            output_sample_rate,
+            output_channels,
+            producer,
+            output,
+            timeline,
+            state,
+            command_rx,
+            pending: Vec::new(),
+            pending_pos: 0,
+            pending_flush: None,
+            frames_produced: 0,
+            track_start_frame: 0,
+            playthroughs: 0,
+            track_playthrough: 0,
+            next: None,
+            crossfade: None,
+            previous: None,
+        };
+
+        let thread = thread::Builder::new()
+            .name("hiflac-decoder".to_string())
+            .spawn(move || worker.run())
+            .ok();
+
+        Self { command_tx, thread }
+    }
+
+    pub fn send(&self, command: DecoderCommand) {
+        let _ = self.command_tx.send(command);
+    }
+}
+
+impl Drop for DecoderHandle {
+    fn drop(&mut self) {
+        let _ = self.command_tx.send(DecoderCommand::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct DecodeWorker {
+    chain: Vec<ChainEntry>,
+    current: usize,
+    source: Option<TrackSource>,
+    resampler: Option<StreamResampler>,
+    /// None when the source already has the output's channel layout
+    mixer: Option<ChannelMixer>,
+    /// Tempo and pitch change of the output, None at normal speed
+    speed: Option<SpeedProcessor>,
+    /// Format of the first source of the current track
+    track_spec: SourceSpec,
+    track_album: Option<String>,
+    track_replaygain: ReplayGainInfo,
+    /// Linear ReplayGain of the current track
+    gain: f32,
+    output_sample_rate: u32,
+    output_channels: u16,
+    producer: RingProducer,
+    output: Arc<OutputShared>,
+    timeline: Arc<RwLock<TrackTimeline>>,
+    state: Arc<RwLock<PlaybackState>>,
+    command_rx: mpsc::Receiver<DecoderCommand>,
+    /// Converted output samples not yet accepted by the ring
+    pending: Vec<f32>,
+    pending_pos: usize,
+    /// Flush requested from the output callback after a seek, not yet acknowledged
+    pending_flush: Option<u64>,
+    /// Output frames produced into `pending`, counted the same way as the callback's frames played
+    frames_produced: u64,
+    /// Output frame at which the current track started
+    track_start_frame: u64,
+    /// Tracks started in this stream after the first, and the number of the current one
+    playthroughs: u64,
+    track_playthrough: u64,
+    next: Option<PreparedTrack>,
+    crossfade: Option<Crossfade>,
+    /// The track before the last switch, kept while it is still audible so a seek can go back
+    previous: Option<PreviousTrack>,
+}
+
+impl DecodeWorker {
+    fn run(mut self) {
+        self.prepare_current();
+        self.update_duration();
+
+        loop {
+            // Handle commands without blocking while there is decoding to do
+            loop {
+                match self.command_rx.try_recv() {
+                    Ok(command) => {
+                        if !self.handle_command(command) {
+                            return;
+                        }
+                    }
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                }
+            }
+
+            if self.pending_pos >= self.pending.len() {
+                self.pending.clear();
+                self.pending_pos = 0;
+
+                if !self.decode_more() && !self.flush_speed() {
+                    // Nothing left to decode: wait for an append, seek or stop
+                    self.output.set_end_of_stream(true);
+                    match self.command_rx.recv() {
+                        Ok(command) => {
+                            if !self.handle_command(command) {
+                                return;
+                            }
+                        }
+                        Err(_) => return,
+                    }
+                    continue;
+                }
+            }
+
+            // After a seek, nothing new may enter the ring until the callback dropped the old audio
+            let flushing = match self.pending_flush {
+                Some(epoch) if self.output.flush_done(epoch) => {
+                    self.pending_flush = None;
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            };
+
+            let accepted = if flushing {
+                0
+            } else {
+                // Only hand over whole frames so frame counting stays exact
+                let channels = self.output_channels as usize;
+                let free = self.producer.free_len() / channels * channels;
+                let end = (self.pending_pos + free).min(self.pending.len());
+                self.producer.push(&self.pending[self.pending_pos..end])
+            };
+            self.pending_pos += accepted;
+
+            if accepted == 0 {
+                // Ring is full - wait for the output to drain while staying responsive
+                match self.command_rx.recv_timeout(Duration::from_millis(10)) {
+                    Ok(command) => {
+                        if !self.handle_command(command) {
+                            return;
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        }
+    }
+
+    /// Returns false when the worker should exit
+    fn handle_command(&mut self, command: DecoderCommand) -> bool {
+        match command {
+            DecoderCommand::Stop => return false,
+            DecoderCommand::Seek(position) => self.seek(position),
+            DecoderCommand::Append(path) => {
+                let start_time = self.chain_end_time();
+                let mut entry = ChainEntry::new(path, None, None);
+                entry.start_time = start_time;
+                self.chain.push(entry);
+                self.output.set_end_of_stream(false);
+            }
+            DecoderCommand::ExtendLimit(path, total_limit) => {
+                if let Some(index) = self.chain.iter().rposition(|e| e.path == path) {
+                    let entry = &mut self.chain[index];
+                    if let Some(limit) = &entry.byte_limit {
+                        limit.fetch_max(total_limit, Ordering::AcqRel);
+                    }
+                    // A source that already hit its old limit is reopened where it stopped
+                    if entry.exhausted {
+                        entry.exhausted = false;
+                        self.output.set_end_of_stream(false);
+                    } else if index < self.current {
+                        log::warn!("Byte limit extended for a source that was already played");
+                    }
+                } else {
+                    log::warn!("Byte limit extended for unknown source: {}", path);
+                }
+            }
+            DecoderCommand::SetNext(path, crossfade) => {
+                // Too late to chain into this stream: let the audio thread start it
+                if self.output.is_finished() {
+                    self.timeline.write().pending_handoff = path;
+                    return true;
+                }
+                // Open the next track right away so the switch costs nothing at the boundary
+                self.next = path.map(|path| {
+                    let source = match TrackSource::open(&path, None, self.dsd_output(true)) {
+                        Ok(source) => Some(source),
+                        Err(e) => {
+                            log::warn!("Failed to prepare next track {}: {}", path, e);
+                            None
+                        }
+                    };
+                    PreparedTrack {
+                        path,
+                        source,
+                        crossfade,
+                    }
+                });
+                if self.next.is_some() {
+                    self.output.set_end_of_stream(false);
+                }
+            }
+            DecoderCommand::RefreshGain => {
+                self.gain = track_gain(&self.state, &self.track_spec, &self.track_replaygain);
+                let mut timeline = self.timeline.write();
+                timeline.set_gain(&self.chain[0].path, self.gain);
+                if let Some(fade) = self.crossfade.as_mut() {
+                    fade.gain = track_gain(&self.state, &fade.spec, &fade.replaygain);
+                    timeline.set_gain(&fade.path, fade.gain);
+                }
+            }
+            // DoP never changes speed
+            DecoderCommand::RefreshSpeed if self.track_spec.dop => {}
+            DecoderCommand::RefreshSpeed => {
+                self.speed = speed_processor(
+                    &self.track_spec,
+                    self.output_sample_rate,
+                    self.output_channels,
+                    &self.state,
+                );
+                // Audio buffered at the old speed is dropped so the change is heard right away
+                let frames = self.output.frames_played();
+                let start = self
+                    .timeline
+                    .read()
+                    .current(frames)
+                    .map_or(0, |e| e.start_frame);
+                let position = frames.saturating_sub(start) as f64 / self.output_sample_rate as f64;
+                self.seek(position);
+            }
+            DecoderCommand::RefreshDownmix => {
+                if let Some(source) = &self.source {
+                    self.mixer = channel_mixer(&source.spec(), self.output_channels, &self.state);
+                }
+                if let Some(fade) = self.crossfade.as_mut() {
+                    fade.mixer = channel_mixer(&fade.spec, self.output_channels, &self.state);
+                }
+            }
+        }
+        true
+    }
+
+    /// Decode the next packet into `pending`, moving along the chain as sources end.
+    /// Returns false when every source has been decoded.
+    fn decode_more(&mut self) -> bool {
+        loop {
+            if self.source.is_none() {
+                if self.current >= self.chain.len() {
+                    // The track is done: continue with the next one if it fits this stream
+                    if self.start_next_track() {
+                        continue;
+                    }
+                    return false;
+                }
+                if self.chain[self.current].exhausted {
+                    if self.current + 1 < self.chain.len() {
+                        self.current += 1;
+                        continue;
+                    }
+                    return false;
+                }
+                if !self.open_current() {
+                    // Skip sources that cannot be opened
+                    self.current += 1;
+                    continue;
+                }
+            }
+
+            let mut decoded = Vec::new();
+            let more = match self.source.as_mut() {
+                Some(source) => source.decode_next(&mut decoded).unwrap_or(false),
+                None => false,
+            };
+
+            if let Some(source) = &self.source {
+                self.chain[self.current].frames_decoded = source.frame_position();
+            }
+
+            if more {
+                self.convert(&decoded);
+                if self.pending.is_empty() {
+                    continue;
+                }
+                return true;
+            }
+
+            // End of this source: flush the resampler and move on
+            if let Some(resampler) = self.resampler.as_mut() {
+                let mut tail = Vec::new();
+                resampler.flush(&mut tail);
+                self.convert_channels_into_pending(&tail);
+            }
+            self.finish_current();
+            if !self.pending.is_empty() {
+                return true;
+            }
+        }
+    }
+
+    /// Switch to the prepared next track. Tracks whose format matches the current stream
+    /// continue in the same ring, so the transition is sample-exact. Anything else is handed
+    /// back to the audio thread, which reopens the device once this track has played out.
+    fn start_next_track(&mut self) -> bool {
+        if let Some(crossfade) = self.crossfade.take() {
+            self.finish_crossfade(crossfade);
+            return true;
+        }
+
+        let Some(mut next) = self.next.take() else {
+            return false;
+        };
+
+        let source = match next.source.take() {
+            Some(source) => source,
+            None => match TrackSource::open(&next.path, None, self.dsd_output(true)) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("Failed to open next track {}: {}", next.path, e);
+                    return false;
+                }
+            },
+        };
+
+        let spec = source.spec();
+        let native = is_native(&spec, self.output_sample_rate, self.output_channels);
+        let same_as_current = spec.sample_rate == self.track_spec.sample_rate
+            && spec.channels == self.track_spec.channels;
+
+        // DoP and PCM never share a stream: the callback treats every frame of a DoP stream as DSD
+        if (!native && !same_as_current) || spec.dop != self.track_spec.dop {
+            println!(
+                "[Audio] Next track needs a different output format ({}Hz/{}ch) - reopening stream",
+                spec.sample_rate, spec.channels
+            );
+            self.timeline.write().pending_handoff = Some(next.path);
+            return false;
+        }
+
+        println!(
+            "[Audio] Gapless transition to {} at frame {}",
+            next.path, self.frames_produced
+        );
+
+        let previous_chain = std::mem::replace(
+            &mut self.chain,
+            vec![ChainEntry::new(next.path.clone(), None, spec.duration())],
+        );
+        let album = source.album().map(|a| a.to_string());
+        self.previous = Some(PreviousTrack {
+            chain: previous_chain,
+            switch_frame: self.frames_produced,
+            playthrough: self.track_playthrough,
+            crossfade: next.crossfade,
+            spec: self.track_spec,
+            album: std::mem::replace(&mut self.track_album, album),
+            replaygain: self.track_replaygain,
+        });
+        self.track_replaygain = source.replaygain();
+        self.gain = track_gain(&self.state, &spec, &self.track_replaygain);
+        self.current = 0;
+        self.source = Some(source);
+        self.track_spec = spec;
+        self.track_start_frame = self.frames_produced;
+        self.playthroughs += 1;
+        self.track_playthrough = self.playthroughs;
+        self.prepare_current();
+
+        let mut timeline = self.timeline.write();
+        timeline.push(TimelineEntry {
+            start_frame: self.frames_produced,
+            playthrough: self.track_playthrough,
+            fade_frames: 0,
+            path: next.path,
+            duration: spec.duration().unwrap_or(0.0),
+            bit_depth: spec.bit_depth,
+            gain: self.gain,
+            native,
+            dsd_rate: spec.dsd_rate,
+            channels: spec.channels,
+        });
+        self.output.limit_untouched(self.frames_produced);
+        true
+    }
+
+    /// Start mixing in the next track once production reaches the last `duration` seconds
+    /// of the current one. Needs the full length of the current track to be known.
+    fn begin_crossfade(&mut self, produced_end: u64) {
+        let Some(settings) = self.next.as_ref().and_then(|n| n.crossfade) else {
+            return;
+        };
+        if self
+            .chain
+            .iter()
+            .any(|e| e.duration.is_none() || e.byte_limit.is_some())
+        {
+            return;
+        }
+
+        let rate = self.output_sample_rate as f64;
+        let track_frames = (self.chain_end_time() * rate) as u64;
+        let length = ((settings.duration * rate) as u64).min(track_frames / 2);
+        let fade_start = self.track_start_frame + track_frames - length;
+        if length == 0 || produced_end <= fade_start {
+            return;
+        }
+
+        let Some(mut next) = self.next.take() else {
+            return;
+        };
+        let source = match next.source.take() {
+            Some(source) => source,
+            None => match TrackSource::open(&next.path, None, self.dsd_output(true)) {
+                Ok(source) => source,
+                Err(e) => {
+                    log::error!("Failed to open next track {}: {}", next.path, e);
+                    return;
+                }
+            },
+        };
+
+        // Consecutive tracks of one album are meant to flow into each other, and DoP has to
+        // reach the DAC untouched
+        let album = source.album().map(|a| a.to_string());
+        let spec = source.spec();
+        let same_album = album.is_some() && album == self.track_album;
+        if same_album || spec.dop || self.track_spec.dop {
+            println!(
+                "[Audio] {} - gapless instead of crossfade",
+                if same_album { "Same album" } else { "DoP" }
+            );
+            next.source = Some(source);
+            next.crossfade = None;
+            self.next = Some(next);
+            return;
+        }
+
+        let length = match spec.duration() {
+            Some(duration) => length.min((duration * rate) as u64 / 2),
+            None => length,
+        };
+        let start_frame = self.track_start_frame + track_frames - length;
+
+        let resampler = if spec.sample_rate != self.output_sample_rate {
+            match StreamResampler::new(
+                spec.sample_rate,
+                self.output_sample_rate,
+                spec.channels as usize,
+                self.state.read().resampler_quality,
+            ) {
+                Ok(resampler) => Some(resampler),
+                Err(e) => {
+                    log::error!("{}", e);
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        println!(
+            "[Audio] Crossfading into {} over {:.1}s ({:?})",
+            next.path,
+            length as f64 / rate,
+            settings.curve
+        );
+
+        let replaygain = source.replaygain();
+        let gain = track_gain(&self.state, &spec, &replaygain);
+
+        self.playthroughs += 1;
+        let mut timeline = self.timeline.write();
+        timeline.push(TimelineEntry {
+            start_frame,
+            playthrough: self.playthroughs,
+            fade_frames: length,
+            path: next.path.clone(),
+            duration: spec.duration().unwrap_or(0.0),
+            bit_depth: spec.bit_depth,
+            gain,
+            native: is_native(&spec, self.output_sample_rate, self.output_channels),
+            dsd_rate: spec.dsd_rate,
+            channels: spec.channels,
+        });
+        // The outgoing track is mixed from here on
+        self.output.limit_untouched(start_frame);
+        drop(timeline);
+
+        self.crossfade = Some(Crossfade {
+            settings,
+            start_frame,
+            length,
+            path: next.path,
+            playthrough: self.playthroughs,
+            spec,
+            album,
+            replaygain,
+            gain,
+            mixer: channel_mixer(&spec, self.output_channels, &self.state),
+            source: Some(source),
+            resampler,
+            frames_decoded: 0,
+            buffer: Vec::new(),
+            buffer_pos: 0,
+        });
+    }
+
+    /// Mix the incoming track into the newly produced samples of `pending[start..]`
+    fn mix_crossfade(&mut self, start: usize) {
+        let channels = self.output_channels as usize;
+        let first = self.frames_produced;
+        let end = first + ((self.pending.len() - start) / channels) as u64;
+        self.frames_produced = end;
+
+        if self.crossfade.is_none() {
+            self.begin_crossfade(end);
+        }
+        let Some(fade) = self.crossfade.as_mut() else {
+            return;
+        };
+        let from = fade.start_frame.max(first);
+        if from >= end {
+            return;
+        }
+
+        fade.fill((end - from) as usize * channels);
+        for frame in from..end {
+            let t = (frame - fade.start_frame) as f32 / fade.length as f32;
+            let (gain_out, gain_in) = fade.settings.curve.gains(t);
+            let base = start + (frame - first) as usize * channels;
+            for sample in self.pending[base..base + channels].iter_mut() {
+                *sample = *sample * gain_out + fade.next_sample() * gain_in;
+            }
+        }
+        fade.buffer.drain(..fade.buffer_pos);
+        fade.buffer_pos = 0;
+    }
+
+    /// The outgoing track is done: the incoming one becomes the current track
+    fn finish_crossfade(&mut self, fade: Crossfade) {
+        let mut entry = ChainEntry::new(fade.path, None, fade.spec.duration());
+        entry.frames_decoded = fade.frames_decoded;
+        let previous_chain = std::mem::replace(&mut self.chain, vec![entry]);
+        self.previous = Some(PreviousTrack {
+            chain: previous_chain,
+            switch_frame: fade.start_frame,
+            playthrough: self.track_playthrough,
+            crossfade: Some(fade.settings),
+            spec: self.track_spec,
+            album: self.track_album.take(),
+            replaygain: self.track_replaygain,
+        });
+        self.current = 0;
+        self.track_replaygain = fade.replaygain;
+        self.gain = fade.gain;
+        self.source = fade.source;
+        self.resampler = fade.resampler;
+        self.mixer = fade.mixer;
+        self.track_spec = fade.spec;
+        self.track_album = fade.album;
+        self.track_start_frame = fade.start_frame;
+        self.track_playthrough = fade.playthrough;
+
+        // Whatever was decoded beyond the overlap plays as is
+        let start = self.pending.len();
+        let rest = &fade.buffer[fade.buffer_pos..];
+        self.pending.extend_from_slice(rest);
+        self.frames_produced += (rest.len() / self.output_channels as usize) as u64;
+        self.stretch_pending(start);
+        self.update_duration();
+    }
+
+    /// Mark the current source as fully decoded
+    fn finish_current(&mut self) {
+        let sample_rate = self
+            .source
+            .as_ref()
+            .map(|s| s.spec().sample_rate)
+            .unwrap_or(self.output_sample_rate);
+        let is_last = self.current + 1 == self.chain.len();
+        let entry = &mut self.chain[self.current];
+        let measured = entry.frames_decoded as f64 / sample_rate as f64;
+        if entry.duration.is_none() {
+            entry.duration = Some(measured);
+        }
+        self.source = None;
+        self.resampler = None;
+        self.mixer = None;
+
+        // A byte-limited source may still grow, so stay on it until it is extended
+        if entry.byte_limit.is_some() && is_last {
+            entry.exhausted = true;
+        } else {
+            self.current += 1;
+        }
+        self.recompute_start_times();
+        self.update_duration();
+    }
+
+    /// Open the current chain entry, resuming where a byte-limited decode stopped
+    fn open_current(&mut self) -> bool {
+        let entry = &self.chain[self.current];
+        let resume_frame = entry.frames_decoded;
+        match TrackSource::open(
+            &entry.path,
+            entry.byte_limit.clone(),
+            self.dsd_output(false),
+        ) {
+            Ok(mut source) => {
+                if resume_frame > 0 {
+                    if let Some(total) = source.spec().n_frames {
+                        if resume_frame >= total {
+                            return false;
+                        }
+                    }
+                    if let Err(e) = source.seek(resume_frame) {
+                        log::warn!("Failed to resume source: {}", e);
+                        return false;
+                    }
+                }
+                if self.chain[self.current].duration.is_none() {
+                    self.chain[self.current].duration = source.spec().duration();
+                }
+                self.source = Some(source);
+                self.prepare_current();
+                self.recompute_start_times();
+                self.update_duration();
+                true
+            }
+            Err(e) => {
+                log::error!(
+                    "Failed to open source {}: {}",
+                    self.chain[self.current].path,
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Set up the resampler and channel mixer for the current source
+    fn prepare_current(&mut self) {
+        self.resampler = None;
+        self.mixer = None;
+        if let Some(source) = &self.source {
+            let spec = source.spec();
+            self.mixer = channel_mixer(&spec, self.output_channels, &self.state);
+            if spec.sample_rate != self.output_sample_rate {
+                println!(
+                    "[Audio] ⚡ RESAMPLING: {}Hz -> {}Hz",
+                    spec.sample_rate, self.output_sample_rate
+                );
+                match StreamResampler::new(
+                    spec.sample_rate,
+                    self.output_sample_rate,
+                    spec.channels as usize,
+                    self.state.read().resampler_quality,
+                ) {
+                    Ok(resampler) => self.resampler = Some(resampler),
+                    Err(e) => log::error!("{}", e),
+                }
+            }
+        }
+    }
+
+    /// Resample and channel-convert decoded source samples into `pending`
+    fn convert(&mut self, decoded: &[f32]) {
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let mut resampled = Vec::new();
+                resampler.process(decoded, &mut resampled);
+                self.convert_channels_into_pending(&resampled);
+            }
+            None => self.convert_channels_into_pending(decoded),
+        }
+    }
+
+    fn convert_channels_into_pending(&mut self, samples: &[f32]) {
+        if samples.is_empty() {
+            return;
+        }
+        let start = self.pending.len();
+        match &self.mixer {
+            Some(mixer) => mixer.process(samples, &mut self.pending),
+            None => self.pending.extend_from_slice(samples),
+        }
+        apply_gain(&mut self.pending[start..], self.gain);
+        self.mix_crossfade(start);
+        self.stretch_pending(start);
+    }
+
+    /// Change the speed of `pending[start..]`. Frames are counted before this, so frame
+    /// positions stay in source time.
+    fn stretch_pending(&mut self, start: usize) {
+        if let Some(speed) = self.speed.as_mut() {
+            let mut stretched = Vec::new();
+            speed.process(&self.pending[start..], &mut stretched);
+            self.pending.truncate(start);
+            self.pending.extend_from_slice(&stretched);
+        }
+    }
+
+    /// Move what the speed processor still holds into `pending` once decoding is done.
+    /// Returns false when there was nothing.
+    fn flush_speed(&mut self) -> bool {
+        if let Some(speed) = self.speed.as_mut() {
+            speed.flush(&mut self.pending);
+        }
+        !self.pending.is_empty()
+    }
+
+    fn seek(&mut self, position: f64) {
+        let position = position.max(0.0);
+
+        // If a switch was decoded but not heard yet, the seek is meant for the old track
+        if let Some(previous) = self.previous.take() {
+            if self.output.frames_played() < previous.switch_frame {
+                let upcoming = std::mem::replace(&mut self.chain, previous.chain);
+                if let Some(first) = upcoming.into_iter().next() {
+                    self.next = Some(PreparedTrack {
+                        path: first.path,
+                        source: None,
+                        crossfade: previous.crossfade,
+                    });
+                }
+                self.track_spec = previous.spec;
+                self.track_album = previous.album;
+                self.track_replaygain = previous.replaygain;
+                self.track_playthrough = previous.playthrough;
+                self.gain = track_gain(&self.state, &self.track_spec, &self.track_replaygain);
+                self.source = None;
+                self.current = 0;
+            }
+        }
+
+        // A crossfade in progress starts over when the end of the track is reached again
+        if let Some(fade) = self.crossfade.take() {
+            self.next = Some(PreparedTrack {
+                path: fade.path,
+                source: None,
+                crossfade: Some(fade.settings),
+            });
+        }
+        self.timeline.write().pending_handoff = None;
+
+        // Find the chain entry that contains the requested position
+        let index = self
+            .chain
+            .iter()
+            .position(|e| match e.duration {
+                Some(duration) => position < e.start_time + duration,
+                None => true,
+            })
+            .unwrap_or(self.chain.len().saturating_sub(1));
+
+        if index != self.current || self.source.is_none() {
+            self.current = index;
+            self.source = None;
+            self.chain[index].frames_decoded = 0;
+            self.chain[index].exhausted = false;
+            if !self.open_current() {
+                return;
+            }
+        }
+
+        let offset = position - self.chain[index].start_time;
+        if let Some(source) = self.source.as_mut() {
+            let frame = (offset.max(0.0) * source.spec().sample_rate as f64) as u64;
+            if let Err(e) = source.seek(frame) {
+                log::warn!("{}", e);
+                return;
+            }
+            self.chain[index].frames_decoded = source.frame_position();
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.reset();
+        }
+        if let Some(speed) = self.speed.as_mut() {
+            speed.reset();
+        }
+
+        // Drop everything decoded for the old position; the callback empties the ring
+        self.pending.clear();
+        self.pending_pos = 0;
+        let frame = (position * self.output_sample_rate as f64) as u64;
+        self.output
+            .set_speed(self.speed.as_ref().map_or(1.0, |s| s.speed()));
+        // Hold the timeline so the audio thread cannot republish the old track's range
+        // between the flush and the reset below
+        let mut timeline = self.timeline.write();
+        self.output.set_untouched_until(0);
+        self.pending_flush = Some(self.output.request_flush(frame));
+        self.frames_produced = frame;
+        self.track_start_frame = 0;
+
+        // The track restarts its timeline at frame 0, so `frame` maps straight to `position`.
+        // It is the same playthrough, so no track change is reported.
+        let path = self.chain[0].path.clone();
+        let duration = self.chain_end_time();
+        timeline.reset(TimelineEntry {
+            start_frame: 0,
+            playthrough: self.track_playthrough,
+            fade_frames: 0,
+            path,
+            duration,
+            bit_depth: self.track_spec.bit_depth,
+            gain: self.gain,
+            native: is_native(
+                &self.track_spec,
+                self.output_sample_rate,
+                self.output_channels,
+            ),
+            dsd_rate: self.track_spec.dsd_rate,
+            channels: self.track_spec.channels,
+        });
+        self.output.set_end_of_stream(false);
+    }
+
+    fn recompute_start_times(&mut self) {
+        let mut start = 0.0;
+        for entry in self.chain.iter_mut() {
+            entry.start_time = start;
+            start += entry.duration.unwrap_or(0.0);
+        }
+    }
+
+    fn chain_end_time(&self) -> f64 {
+        self.chain
+            .last()
+            .map(|e| e.start_time + e.duration.unwrap_or(0.0))
+            .unwrap_or(0.0)
+    }
+
+    /// What DSD sources are decoded to. Sources of the current track match it. A next track
+    /// is tried as DoP unless this stream already plays DSD as PCM; if it then needs a new
+    /// stream, the audio thread checks whether the device takes DoP at its rate.
+    fn dsd_output(&self, next_track: bool) -> DsdOutput {
+        let settings = self.state.read().dsd;
+        if self.track_spec.dop || (next_track && settings.dop && self.track_spec.dsd_rate.is_none())
+        {
+            DsdOutput::Dop
+        } else {
+            DsdOutput::Pcm(settings.pcm_rate)
+        }
+    }
+
+    fn update_duration(&self) {
+        // While crossfading the latest timeline entry is the incoming track
+        if self.crossfade.is_some() {
+            return;
+        }
+        let duration = self.chain_end_time();
+        self.timeline.write().set_latest_duration(duration);
+    }
+}
+
+/// Whether a source plays without resampling or channel conversion
+fn is_native(spec: &SourceSpec, output_sample_rate: u32, output_channels: u16) -> bool {
+    spec.sample_rate == output_sample_rate && spec.channels == output_channels
+}
+
+/// Mixer from a source's channel layout to the output's, None when nothing needs mixing
+fn channel_mixer(
+    spec: &SourceSpec,
+    output_channels: u16,
+    state: &RwLock<PlaybackState>,
+) -> Option<ChannelMixer> {
+    let settings = state.read().downmix;
+    let mixer = ChannelMixer::new(spec.layout, spec.channels, output_channels, &settings);
+    if mixer.is_passthrough() {
+        None
+    } else {
+        Some(mixer)
+    }
+}
+
+/// Speed processor for a stream under the current settings. DoP always plays as is.
+fn speed_processor(
+    spec: &SourceSpec,
+    output_sample_rate: u32,
+    output_channels: u16,
+    state: &RwLock<PlaybackState>,
+) -> Option<SpeedProcessor> {
+    if spec.dop {
+        return None;
+    }
+    let settings = state.read().speed;
+    SpeedProcessor::new(&settings, output_sample_rate, output_channels as usize).unwrap_or_else(
+        |e| {
+            log::error!("{}", e);
+            None
+        },
+    )
+}
+
+/// ReplayGain for a track under the current settings. DoP is never scaled.
+fn track_gain(state: &RwLock<PlaybackState>, spec: &SourceSpec, info: &ReplayGainInfo) -> f32 {
+    if spec.dop {
+        return 1.0;
+    }
+    let state = state.read();
+    state.replaygain.gain(info, state.shuffle)
+}
+
+fn apply_gain(samples: &mut [f32], gain: f32) {
+    if gain != 1.0 {
+        for sample in samples.iter_mut() {
+            *sample *= gain;
+        }
+    }
+}
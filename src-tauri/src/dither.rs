@@ -0,0 +1,99 @@
+//! Dither Module
+//! TPDF and noise-shaped dither for the final conversion to integer output formats.
+//! Runs in the output callback, so nothing here allocates after construction.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMode {
+    Off,
+    /// TPDF on 16-bit output while digital volume or DSP alter the signal
+    #[default]
+    Auto,
+    Tpdf,
+    NoiseShaped,
+}
+
+impl DitherMode {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            DitherMode::Off => 0,
+            DitherMode::Auto => 1,
+            DitherMode::Tpdf => 2,
+            DitherMode::NoiseShaped => 3,
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            0 => DitherMode::Off,
+            2 => DitherMode::Tpdf,
+            3 => DitherMode::NoiseShaped,
+            _ => DitherMode::Auto,
+        }
+    }
+}
+
+// Error feedback filters. Wannamaker's 3-tap F-weighted filter moves the noise where the
+// ear is least sensitive at 44.1/48kHz; at higher rates a first-order filter pushes it
+// above the audio band.
+const SHAPING_BASE_RATE: [f32; 3] = [1.623, -0.982, 0.109];
+const SHAPING_HIGH_RATE: [f32; 3] = [1.0, 0.0, 0.0];
+
+pub struct Ditherer {
+    channels: usize,
+    shaping: [f32; 3],
+    /// Last three quantization errors per channel
+    errors: Vec<[f32; 3]>,
+    rng: u32,
+}
+
+impl Ditherer {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        Self {
+            channels: channels.max(1) as usize,
+            shaping: if sample_rate <= 48000 {
+                SHAPING_BASE_RATE
+            } else {
+                SHAPING_HIGH_RATE
+            },
+            errors: vec![[0.0; 3]; channels.max(1) as usize],
+            rng: 0x9E37_79B9,
+        }
+    }
+
+    /// Uniform in [0, 1)
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    /// Quantize interleaved samples to `bits` with triangular dither, optionally shaping
+    /// the requantization noise. The results are exact multiples of the output step.
+    pub fn process(&mut self, samples: &mut [f32], bits: u32, noise_shaped: bool) {
+        let scale = (1u64 << (bits - 1)) as f32;
+        let max = (scale - 1.0) / scale;
+        for frame in samples.chunks_exact_mut(self.channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let shaped = if noise_shaped {
+                    let e = self.errors[channel];
+                    *sample
+                        - (self.shaping[0] * e[0] + self.shaping[1] * e[1] + self.shaping[2] * e[2])
+                } else {
+                    *sample
+                };
+                let noise = self.random() - self.random(); // TPDF, +-1 LSB
+                let quantized = ((shaped * scale + noise).round() / scale).clamp(-1.0, max);
+                if noise_shaped {
+                    // Bounded so a clipped sample cannot feed back a huge error
+                    let error = (quantized - shaped).clamp(-2.0 / scale, 2.0 / scale);
+                    let e = &mut self.errors[channel];
+                    *e = [error, e[0], e[1]];
+                }
+                *sample = quantized;
+            }
+        }
+    }
+}
@@ -0,0 +1,748 @@
+//! DSD Module
+//! Native DSF and DSDIFF (DFF) reading. DSD reaches the DAC either packed into PCM frames
+//! (DoP) or converted to PCM by a two-stage decimating low-pass filter.
+
+use crate::audio::AudioError;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use symphonia::core::audio::Channels;
+
+/// Idle pattern: as many ones as zeros, so it filters to silence
+const DSD_SILENCE: u8 = 0x69;
+/// DoP frames alternate between these markers in their top byte
+const DOP_MARKERS: [u32; 2] = [0x05, 0xFA];
+/// DSD bytes per channel read from the file at a time
+const READ_BYTES: u64 = 4096;
+/// Largest metadata chunk we load (ID3 tags with artwork)
+const MAX_TAG_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Audio band the PCM conversion keeps flat
+const PASS_HZ: f64 = 24_000.0;
+/// DSD pushes its quantization noise above the audio band; the conversion removes
+/// everything past this, or past the output's Nyquist frequency if that is lower
+const STOP_HZ: f64 = 48_000.0;
+const STOPBAND_DB: f64 = 120.0;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct DsdSettings {
+    pub dop: bool,     // Send DSD as DoP to devices that accept the DoP rate
+    pub pcm_rate: u32, // Target rate when converting to PCM
+}
+
+impl Default for DsdSettings {
+    fn default() -> Self {
+        Self {
+            dop: true,
+            pcm_rate: 176_400,
+        }
+    }
+}
+
+/// What a DSD source is decoded to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DsdOutput {
+    /// 16 DSD bits per channel in each 24-bit frame, at a sixteenth of the DSD rate
+    Dop,
+    /// PCM at the rate closest to this one that divides the DSD rate
+    Pcm(u32),
+}
+
+/// Format and tags of a DSD file
+pub struct DsdInfo {
+    /// DSD sample rate per channel (2822400 for DSD64)
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub layout: Option<Channels>,
+    /// DSD samples (bits) per channel
+    pub sample_count: u64,
+    pub id3: Option<id3::Tag>,
+    /// From a DFF's edited master information, for files without ID3
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    data_offset: u64,
+    /// DSF stores each channel in blocks of this many bytes; DFF interleaves single bytes
+    block_size: u64,
+    lsb_first: bool,
+}
+
+impl DsdInfo {
+    pub fn duration(&self) -> f64 {
+        self.sample_count as f64 / self.sample_rate as f64
+    }
+
+    /// Bytes per channel holding samples
+    fn data_bytes(&self) -> u64 {
+        self.sample_count.div_ceil(8)
+    }
+}
+
+pub fn is_dsd_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.eq_ignore_ascii_case("dsf") || e.eq_ignore_ascii_case("dff"))
+        .unwrap_or(false)
+}
+
+/// Parse the headers and tags of a DSF or DFF file
+pub fn read_info(path: &Path) -> Result<DsdInfo, AudioError> {
+    let mut file = File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic).map_err(io_error)?;
+    file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    match &magic {
+        b"DSD " => parse_dsf(&mut file),
+        b"FRM8" => parse_dff(&mut file),
+        _ => Err(AudioError::UnsupportedFormat),
+    }
+}
+
+fn parse_dsf(file: &mut File) -> Result<DsdInfo, AudioError> {
+    let mut header = [0u8; 28];
+    file.read_exact(&mut header).map_err(io_error)?;
+    let header_size = le_u64(&header[4..12]);
+    let metadata_offset = le_u64(&header[20..28]);
+
+    file.seek(SeekFrom::Start(header_size)).map_err(io_error)?;
+    let mut fmt = [0u8; 52];
+    file.read_exact(&mut fmt).map_err(io_error)?;
+    if &fmt[0..4] != b"fmt " {
+        return Err(invalid("DSF file without fmt chunk"));
+    }
+    if le_u32(&fmt[16..20]) != 0 {
+        return Err(invalid("DSF file is not raw DSD"));
+    }
+    let channel_type = le_u32(&fmt[20..24]);
+    let channels = le_u32(&fmt[24..28]) as u16;
+    let sample_rate = le_u32(&fmt[28..32]);
+    let bits_per_sample = le_u32(&fmt[32..36]);
+    let sample_count = le_u64(&fmt[36..44]);
+    let block_size = le_u32(&fmt[44..48]) as u64;
+    if channels == 0 || sample_rate == 0 || block_size == 0 {
+        return Err(invalid("DSF file with an empty format"));
+    }
+
+    let data_chunk = header_size + le_u64(&fmt[4..12]);
+    file.seek(SeekFrom::Start(data_chunk)).map_err(io_error)?;
+    let mut data = [0u8; 12];
+    file.read_exact(&mut data).map_err(io_error)?;
+    if &data[0..4] != b"data" {
+        return Err(invalid("DSF file without data chunk"));
+    }
+
+    // The ID3v2 tag sits at the end of the file, where the header points
+    let id3 = if metadata_offset > 0 {
+        file.seek(SeekFrom::Start(metadata_offset))
+            .map_err(io_error)?;
+        id3::Tag::read_from2(&mut *file).ok()
+    } else {
+        None
+    };
+
+    use Channels as C;
+    let layout = match channel_type {
+        2 => Some(C::FRONT_LEFT | C::FRONT_RIGHT),
+        3 => Some(C::FRONT_LEFT | C::FRONT_RIGHT | C::FRONT_CENTRE),
+        4 => Some(C::FRONT_LEFT | C::FRONT_RIGHT | C::REAR_LEFT | C::REAR_RIGHT),
+        5 => Some(C::FRONT_LEFT | C::FRONT_RIGHT | C::FRONT_CENTRE | C::LFE1),
+        6 => Some(C::FRONT_LEFT | C::FRONT_RIGHT | C::FRONT_CENTRE | C::REAR_LEFT | C::REAR_RIGHT),
+        7 => Some(
+            C::FRONT_LEFT
+                | C::FRONT_RIGHT
+                | C::FRONT_CENTRE
+                | C::LFE1
+                | C::REAR_LEFT
+                | C::REAR_RIGHT,
+        ),
+        _ => None,
+    };
+
+    Ok(DsdInfo {
+        sample_rate,
+        channels,
+        layout: layout.filter(|l| l.count() == channels as usize),
+        sample_count,
+        id3,
+        title: None,
+        artist: None,
+        data_offset: data_chunk + 12,
+        block_size,
+        lsb_first: bits_per_sample == 1,
+    })
+}
+
+fn parse_dff(file: &mut File) -> Result<DsdInfo, AudioError> {
+    let file_len = file.metadata().map_err(io_error)?.len();
+    let mut form = [0u8; 16];
+    file.read_exact(&mut form).map_err(io_error)?;
+    if &form[12..16] != b"DSD " {
+        return Err(invalid("DSDIFF file is not a DSD form"));
+    }
+
+    let mut sample_rate = 0;
+    let mut speakers: Vec<Channels> = Vec::new();
+    let mut channels = 0u16;
+    let mut data: Option<(u64, u64)> = None;
+    let mut id3 = None;
+    let mut title = None;
+    let mut artist = None;
+
+    // Tag chunks are sometimes appended after the form, so walk up to the end of the file
+    let mut pos = 16;
+    while pos + 12 <= file_len {
+        file.seek(SeekFrom::Start(pos)).map_err(io_error)?;
+        let (id, size) = read_dff_chunk_header(file)?;
+        let body = pos + 12;
+        match &id {
+            b"PROP" => {
+                let prop = read_body(file, size)?;
+                parse_dff_prop(&prop, &mut sample_rate, &mut channels, &mut speakers)?;
+            }
+            b"DSD " => data = Some((body, size)),
+            b"DST " => return Err(invalid("DST compressed DSDIFF is not supported")),
+            b"DIIN" => {
+                let diin = read_body(file, size)?;
+                for (id, text) in dff_sub_chunks(&diin) {
+                    let text = dff_text(text);
+                    match &id {
+                        b"DITI" => title = text,
+                        b"DIAR" => artist = text,
+                        _ => {}
+                    }
+                }
+            }
+            b"ID3 " | b"id3 " => {
+                id3 = id3::Tag::read_from2(Cursor::new(read_body(file, size)?)).ok();
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos = body + size + (size & 1);
+    }
+
+    let (data_offset, data_len) = data.ok_or_else(|| invalid("DSDIFF file without DSD data"))?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("DSDIFF file without sound properties"));
+    }
+    let layout = speakers
+        .iter()
+        .try_fold(Channels::empty(), |layout, speaker| {
+            // Symphonia orders channels by their flag, so the file has to agree
+            (layout.bits() < speaker.bits()).then(|| layout | *speaker)
+        })
+        .filter(|l| l.count() == channels as usize && channels > 1);
+
+    Ok(DsdInfo {
+        sample_rate,
+        channels,
+        layout,
+        sample_count: data_len / channels as u64 * 8,
+        id3,
+        title,
+        artist,
+        data_offset,
+        block_size: 1,
+        lsb_first: false,
+    })
+}
+
+fn parse_dff_prop(
+    prop: &[u8],
+    sample_rate: &mut u32,
+    channels: &mut u16,
+    speakers: &mut Vec<Channels>,
+) -> Result<(), AudioError> {
+    if prop.len() < 4 || &prop[0..4] != b"SND " {
+        return Ok(());
+    }
+    for (id, body) in dff_sub_chunks(&prop[4..]) {
+        match &id {
+            b"FS  " if body.len() >= 4 => *sample_rate = be_u32(&body[0..4]),
+            b"CHNL" if body.len() >= 2 => {
+                *channels = u16::from_be_bytes([body[0], body[1]]);
+                for id in body[2..].chunks_exact(4) {
+                    speakers.push(match id {
+                        b"SLFT" | b"MLFT" => Channels::FRONT_LEFT,
+                        b"SRGT" | b"MRGT" => Channels::FRONT_RIGHT,
+                        b"C   " => Channels::FRONT_CENTRE,
+                        b"LFE " => Channels::LFE1,
+                        b"LS  " => Channels::REAR_LEFT,
+                        b"RS  " => Channels::REAR_RIGHT,
+                        _ => Channels::empty(),
+                    });
+                }
+            }
+            b"CMPR" if body.len() >= 4 && &body[0..4] != b"DSD " => {
+                return Err(invalid("DST compressed DSDIFF is not supported"));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_dff_chunk_header(file: &mut File) -> Result<([u8; 4], u64), AudioError> {
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).map_err(io_error)?;
+    let mut id = [0u8; 4];
+    id.copy_from_slice(&header[0..4]);
+    Ok((id, be_u64(&header[4..12])))
+}
+
+/// Local chunks inside a DSDIFF container chunk
+fn dff_sub_chunks(mut body: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    while body.len() >= 12 {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&body[0..4]);
+        let size = be_u64(&body[4..12]).min((body.len() - 12) as u64) as usize;
+        chunks.push((id, &body[12..12 + size]));
+        body = &body[(12 + size + (size & 1)).min(body.len())..];
+    }
+    chunks
+}
+
+/// DITI/DIAR text: a byte count followed by the characters
+fn dff_text(body: &[u8]) -> Option<String> {
+    let len = be_u32(body.get(0..4)?) as usize;
+    let text = body.get(4..4 + len.min(body.len() - 4))?;
+    let text = String::from_utf8_lossy(text).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Value of an ID3v2 TXXX frame
+pub fn tag_text<'a>(tag: &'a id3::Tag, description: &str) -> Option<&'a str> {
+    tag.extended_texts()
+        .find(|t| t.description.eq_ignore_ascii_case(description))
+        .map(|t| t.value.as_str())
+}
+
+fn read_body(file: &mut File, size: u64) -> Result<Vec<u8>, AudioError> {
+    if size > MAX_TAG_BYTES {
+        return Err(invalid("DSD metadata chunk too large"));
+    }
+    let mut body = vec![0u8; size as usize];
+    file.read_exact(&mut body).map_err(io_error)?;
+    Ok(body)
+}
+
+/// Reads a DSD file as DoP frames or converted PCM, interleaved f32
+pub struct DsdReader {
+    file: File,
+    info: DsdInfo,
+    output: DsdOutput,
+    converter: Option<DsdConverter>,
+    sample_rate: u32,
+    /// DSD bytes per channel that make up one output frame
+    frame_bytes: u64,
+    /// Bytes per channel read from the file so far
+    position: u64,
+    frames_out: u64,
+    frames_total: u64,
+    /// Per-channel bytes read but not yet turned into frames, oldest bit first
+    pending: Vec<Vec<u8>>,
+}
+
+impl DsdReader {
+    pub fn open(path: &Path, output: DsdOutput) -> Result<Self, AudioError> {
+        let info = read_info(path)?;
+        let file = File::open(path).map_err(|e| AudioError::FileNotFound(e.to_string()))?;
+        let channels = info.channels as usize;
+
+        let (sample_rate, frame_bytes, converter) = match output {
+            DsdOutput::Dop => (info.sample_rate / 16, 2, None),
+            DsdOutput::Pcm(target) => {
+                // Every output frame stands for a whole number of DSD bytes
+                let byte_rate = info.sample_rate / 8;
+                let decimation = ((byte_rate + target / 2) / target.max(1)).max(1);
+                let rate = byte_rate / decimation;
+                let converter = DsdConverter::new(info.sample_rate, rate, decimation, channels);
+                (rate, decimation as u64, Some(converter))
+            }
+        };
+
+        Ok(Self {
+            frames_total: info.sample_count / (frame_bytes * 8),
+            file,
+            output,
+            converter,
+            sample_rate,
+            frame_bytes,
+            position: 0,
+            frames_out: 0,
+            pending: vec![Vec::new(); channels],
+            info,
+        })
+    }
+
+    pub fn info(&self) -> &DsdInfo {
+        &self.info
+    }
+
+    pub fn is_dop(&self) -> bool {
+        self.output == DsdOutput::Dop
+    }
+
+    /// Rate of the frames handed out
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn n_frames(&self) -> u64 {
+        self.frames_total
+    }
+
+    /// Append the next frames to `out`. Returns the number of frames, 0 at the end.
+    pub fn read(&mut self, out: &mut Vec<f32>) -> Result<u64, AudioError> {
+        let start = out.len();
+        loop {
+            if self.frames_out >= self.frames_total {
+                return Ok(0);
+            }
+            let read = self.read_bytes()?;
+            if read == 0 {
+                // Past the data: idle bytes carry the filter's tail out
+                if self.converter.is_none() {
+                    return Ok(0);
+                }
+                for pending in self.pending.iter_mut() {
+                    pending.resize(self.frame_bytes as usize, DSD_SILENCE);
+                }
+            }
+
+            match self.converter.as_mut() {
+                Some(converter) => {
+                    converter.process(&self.pending, out);
+                    for pending in self.pending.iter_mut() {
+                        pending.clear();
+                    }
+                }
+                None => self.pack_dop(out),
+            }
+
+            let channels = self.info.channels as usize;
+            let frames = ((out.len() - start) / channels) as u64;
+            let frames = frames.min(self.frames_total - self.frames_out);
+            out.truncate(start + frames as usize * channels);
+            if frames > 0 {
+                self.frames_out += frames;
+                return Ok(frames);
+            }
+        }
+    }
+
+    /// Continue from output frame `frame`
+    pub fn seek(&mut self, frame: u64) -> Result<(), AudioError> {
+        let frame = frame.min(self.frames_total);
+        // Start early enough to fill the filters with the audio before `frame`
+        let preroll = match &self.converter {
+            Some(converter) => converter.history_frames().min(frame),
+            None => 0,
+        };
+        self.position = (frame - preroll) * self.frame_bytes;
+        self.frames_out = frame;
+        for pending in self.pending.iter_mut() {
+            pending.clear();
+        }
+        if let Some(converter) = self.converter.as_mut() {
+            converter.reset();
+            converter.skip_frames += preroll as usize;
+        }
+        Ok(())
+    }
+
+    /// Read up to one block of bytes per channel into `pending`. Returns the count per channel.
+    fn read_bytes(&mut self) -> Result<usize, AudioError> {
+        let info = &self.info;
+        let channels = info.channels as u64;
+        let remaining = info.data_bytes().saturating_sub(self.position);
+        // DSF stores each channel in blocks, DFF interleaves them byte by byte.
+        // Byte `i` of a channel is at `channel * channel_step + i * byte_step`.
+        let (offset, count, channel_step, byte_step) = if info.block_size > 1 {
+            let block = self.position / info.block_size;
+            let within = self.position % info.block_size;
+            let offset = info.data_offset + block * info.block_size * channels + within;
+            let count = (info.block_size - within).min(remaining);
+            (offset, count, info.block_size, 1)
+        } else {
+            let offset = info.data_offset + self.position * channels;
+            (offset, READ_BYTES.min(remaining), 1, channels)
+        };
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let len = (channels - 1) * channel_step + (count - 1) * byte_step + 1;
+        let mut raw = vec![0u8; len as usize];
+        self.file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+        self.file.read_exact(&mut raw).map_err(io_error)?;
+
+        for (channel, pending) in self.pending.iter_mut().enumerate() {
+            let start = channel as u64 * channel_step;
+            let bytes = (0..count).map(|i| raw[(start + i * byte_step) as usize]);
+            if info.lsb_first {
+                pending.extend(bytes.map(u8::reverse_bits));
+            } else {
+                pending.extend(bytes);
+            }
+        }
+        self.position += count;
+        Ok(count as usize)
+    }
+
+    /// Pack pairs of DSD bytes into DoP frames, keeping an odd byte for the next call
+    fn pack_dop(&mut self, out: &mut Vec<f32>) {
+        let frames = self.pending[0].len() / 2;
+        out.reserve(frames * self.pending.len());
+        for frame in 0..frames {
+            let marker = DOP_MARKERS[((self.frames_out + frame as u64) & 1) as usize];
+            for pending in &self.pending {
+                let bits = (pending[frame * 2] as u32) << 8 | pending[frame * 2 + 1] as u32;
+                out.push(dop_sample(marker, bits));
+            }
+        }
+        for pending in self.pending.iter_mut() {
+            pending.drain(..frames * 2);
+        }
+    }
+}
+
+/// A DoP word in the top 24 bits of a full-scale f32, exact for any marker and DSD bits
+fn dop_sample(marker: u32, bits: u32) -> f32 {
+    (((marker << 16 | bits) << 8) as i32) as f32 / 2_147_483_648.0
+}
+
+/// Give every frame of a DoP stream the next marker, so they keep alternating across track
+/// changes and seeks, and fill everything from `valid` on with DSD silence
+pub fn restamp_dop(samples: &mut [f32], channels: usize, valid: usize, next_marker: &mut usize) {
+    let silence = (DSD_SILENCE as u32) << 8 | DSD_SILENCE as u32;
+    for (index, frame) in samples.chunks_mut(channels).enumerate() {
+        let marker = DOP_MARKERS[*next_marker & 1];
+        *next_marker ^= 1;
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let bits = if index * channels + channel < valid {
+                ((*sample as f64 * 2_147_483_648.0) as i32 >> 8) as u32 & 0xFFFF
+            } else {
+                silence
+            };
+            *sample = dop_sample(marker, bits);
+        }
+    }
+}
+
+/// DSD to PCM in two stages. The first low-pass runs at the DSD rate and keeps one sample
+/// per DSD byte: each group of eight taps becomes a table indexed by the byte. The second
+/// runs at that eighth of the DSD rate, removes the noise above the audio band and keeps
+/// every `decimation`th sample. Both are linear phase, and their delay is dropped so output
+/// frame `n` lines up with DSD sample `n * 8 * decimation`. DC gain is unity: SACD's 0 dB
+/// reference (50% modulation) lands at -6 dBFS, leaving room for DSD's overshoots.
+struct DsdConverter {
+    tables: Vec<[f32; 256]>,
+    taps: Vec<f32>,
+    decimation: usize,
+    /// Per channel: the last `tables.len() - 1` bytes
+    bytes: Vec<Vec<u8>>,
+    /// Per channel: first-stage samples, the next output's window starting at 0
+    samples: Vec<Vec<f32>>,
+    /// First-stage samples and output frames still to drop for the filters' delay
+    skip_samples: usize,
+    skip_frames: usize,
+    scratch: Vec<Vec<f32>>,
+}
+
+impl DsdConverter {
+    fn new(dsd_rate: u32, output_rate: u32, decimation: u32, channels: usize) -> Self {
+        let dsd_rate = dsd_rate as f64;
+        let byte_rate = dsd_rate / 8.0;
+        let stop = STOP_HZ.min(output_rate as f64 / 2.0);
+
+        // Stage 1 only has to keep what would alias into the final band out of it
+        let stop1 = byte_rate - stop;
+        let len1 = kaiser_len((stop1 - PASS_HZ) / dsd_rate).div_ceil(16) * 16;
+        let coeffs = kaiser_lowpass(len1, (PASS_HZ + stop1) / 2.0 / dsd_rate);
+        let tables = coeffs
+            .chunks_exact(8)
+            .map(|group| {
+                let mut table = [0f32; 256];
+                for (byte, value) in table.iter_mut().enumerate() {
+                    *value = group
+                        .iter()
+                        .enumerate()
+                        .map(|(bit, c)| if byte & (0x80 >> bit) != 0 { *c } else { -*c })
+                        .sum::<f64>() as f32;
+                }
+                table
+            })
+            .collect::<Vec<_>>();
+
+        // Stage 2: odd length with its centre on an output frame
+        let decimation = decimation as usize;
+        let span = 2 * decimation;
+        let len2 = kaiser_len((stop - PASS_HZ) / byte_rate).div_ceil(span) * span + 1;
+        let taps = kaiser_lowpass(len2, (PASS_HZ + stop) / 2.0 / byte_rate)
+            .into_iter()
+            .map(|c| c as f32)
+            .collect();
+
+        let mut converter = Self {
+            tables,
+            taps,
+            decimation,
+            bytes: vec![Vec::new(); channels],
+            samples: vec![Vec::new(); channels],
+            skip_samples: 0,
+            skip_frames: 0,
+            scratch: vec![Vec::new(); channels],
+        };
+        converter.reset();
+        converter
+    }
+
+    /// Forget the history, as at the start of the file
+    fn reset(&mut self) {
+        let window = self.tables.len();
+        for bytes in self.bytes.iter_mut() {
+            bytes.clear();
+            bytes.resize(window - 1, DSD_SILENCE);
+        }
+        for samples in self.samples.iter_mut() {
+            samples.clear();
+            samples.resize(self.taps.len() - 1, 0.0);
+        }
+        // Stage 1 is centred between bytes half its window back, stage 2 on its middle tap
+        self.skip_samples = window / 2 - 1;
+        self.skip_frames = (self.taps.len() - 1) / 2 / self.decimation;
+    }
+
+    /// Output frames whose input fills both filters' windows
+    fn history_frames(&self) -> u64 {
+        ((self.taps.len() + self.tables.len()) / self.decimation + 1) as u64
+    }
+
+    /// Convert equally long runs of DSD bytes per channel, appending interleaved frames
+    fn process(&mut self, input: &[Vec<u8>], out: &mut Vec<f32>) {
+        let window = self.tables.len();
+        let skip = self.skip_samples;
+        let mut skipped = 0;
+        for (channel, bytes) in input.iter().enumerate() {
+            let history = &mut self.bytes[channel];
+            history.extend_from_slice(bytes);
+            let samples = &mut self.samples[channel];
+            for start in 0..bytes.len() {
+                let value: f32 = self
+                    .tables
+                    .iter()
+                    .zip(&history[start..start + window])
+                    .map(|(table, byte)| table[*byte as usize])
+                    .sum();
+                if start >= skip {
+                    samples.push(value);
+                }
+            }
+            skipped = skip.min(bytes.len());
+            history.drain(..bytes.len());
+
+            let scratch = &mut self.scratch[channel];
+            scratch.clear();
+            let mut offset = 0;
+            while offset + self.taps.len() <= samples.len() {
+                scratch.push(dot(&self.taps, &samples[offset..offset + self.taps.len()]));
+                offset += self.decimation;
+            }
+            samples.drain(..offset);
+        }
+        self.skip_samples -= skipped;
+
+        let frames = self.scratch[0].len();
+        let skip = self.skip_frames.min(frames);
+        self.skip_frames -= skip;
+        out.reserve((frames - skip) * self.scratch.len());
+        for frame in skip..frames {
+            for scratch in &self.scratch {
+                out.push(scratch[frame]);
+            }
+        }
+    }
+}
+
+/// Dot product with independent partial sums, so it vectorizes
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let mut sums = [0f32; 8];
+    let chunks = a.len() / 8 * 8;
+    for (x, y) in a[..chunks].chunks_exact(8).zip(b[..chunks].chunks_exact(8)) {
+        for lane in 0..8 {
+            sums[lane] += x[lane] * y[lane];
+        }
+    }
+    let tail: f32 = a[chunks..]
+        .iter()
+        .zip(&b[chunks..])
+        .map(|(x, y)| x * y)
+        .sum();
+    sums.iter().sum::<f32>() + tail
+}
+
+/// Taps needed for `STOPBAND_DB` over a transition `width` wide (a fraction of the rate)
+fn kaiser_len(width: f64) -> usize {
+    ((STOPBAND_DB - 7.95) / (14.36 * width)).ceil() as usize + 1
+}
+
+/// Kaiser-windowed sinc low-pass with unity DC gain, `cutoff` a fraction of the rate
+fn kaiser_lowpass(len: usize, cutoff: f64) -> Vec<f64> {
+    let beta = 0.1102 * (STOPBAND_DB - 8.7);
+    let centre = (len - 1) as f64 / 2.0;
+    let mut coeffs: Vec<f64> = (0..len)
+        .map(|n| {
+            let t = n as f64 - centre;
+            let x = 2.0 * std::f64::consts::PI * cutoff * t;
+            let sinc = if t == 0.0 { 1.0 } else { x.sin() / x };
+            let r = t / centre;
+            sinc * bessel_i0(beta * (1.0 - r * r).max(0.0).sqrt())
+        })
+        .collect();
+    let sum: f64 = coeffs.iter().sum();
+    for c in coeffs.iter_mut() {
+        *c /= sum;
+    }
+    coeffs
+}
+
+/// Zeroth-order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..64 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+        if term < sum * 1e-16 {
+            break;
+        }
+    }
+    sum
+}
+
+fn invalid(message: &str) -> AudioError {
+    AudioError::Decode(message.to_string())
+}
+
+fn io_error(e: std::io::Error) -> AudioError {
+    AudioError::Decode(e.to_string())
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap_or_default())
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap_or_default())
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap_or_default())
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes.try_into().unwrap_or_default())
+}
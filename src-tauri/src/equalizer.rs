@@ -0,0 +1,277 @@
+//! Equalizer Module
+//! Multi-band parametric EQ built from RBJ cookbook biquads, plus AutoEQ preset import.
+//! Coefficients are designed for the sample rate of the output stream.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct EqBand {
+    pub filter_type: FilterType,
+    pub frequency: f32, // Hz
+    pub gain_db: f32,   // Ignored by the pass filters
+    pub q: f32,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EqSettings {
+    pub enabled: bool,
+    pub preamp_db: f32,
+    pub bands: Vec<EqBand>,
+}
+
+impl EqSettings {
+    /// Whether the EQ changes the signal at all
+    pub fn is_active(&self) -> bool {
+        self.enabled && (self.preamp_db != 0.0 || self.bands.iter().any(|b| b.enabled))
+    }
+
+    /// Keep every parameter in a range the filters stay stable in
+    pub fn clamped(mut self) -> Self {
+        self.preamp_db = self.preamp_db.clamp(-30.0, 30.0);
+        for band in self.bands.iter_mut() {
+            band.frequency = band.frequency.clamp(10.0, 40000.0);
+            band.gain_db = band.gain_db.clamp(-30.0, 30.0);
+            band.q = band.q.clamp(0.05, 30.0);
+        }
+        self
+    }
+}
+
+/// Normalized biquad coefficients (a0 = 1)
+#[derive(Clone, Copy, Debug)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Biquad {
+    fn design(band: &EqBand, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f64;
+        // Keep the center just below Nyquist so high bands still work at 44.1kHz
+        let frequency = (band.frequency as f64).min(sample_rate * 0.49);
+        let a = 10f64.powf(band.gain_db as f64 / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q as f64);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+/// Runs the enabled bands over interleaved samples. Lives in the output callback,
+/// so `process` never allocates.
+pub struct Equalizer {
+    preamp: f32,
+    filters: Vec<Biquad>,
+    /// Transposed direct form II memory, `channels` entries per filter
+    state: Vec<[f64; 2]>,
+    channels: usize,
+}
+
+impl Equalizer {
+    /// Design the filters for the output stream. None when the EQ would do nothing.
+    pub fn new(settings: &EqSettings, sample_rate: u32, channels: u16) -> Option<Self> {
+        if !settings.is_active() || sample_rate == 0 || channels == 0 {
+            return None;
+        }
+        let filters: Vec<Biquad> = settings
+            .bands
+            .iter()
+            .filter(|b| b.enabled)
+            .map(|b| Biquad::design(b, sample_rate))
+            .collect();
+        let channels = channels as usize;
+        Some(Self {
+            preamp: 10f32.powf(settings.preamp_db / 20.0),
+            state: vec![[0.0; 2]; filters.len() * channels],
+            filters,
+            channels,
+        })
+    }
+
+    /// Continue from the filter memory of the equalizer this one replaces, so
+    /// adjusting a band while playing does not click
+    pub fn carry_state(&mut self, previous: &Equalizer) {
+        if previous.state.len() == self.state.len() && previous.channels == self.channels {
+            self.state.copy_from_slice(&previous.state);
+        }
+    }
+
+    /// Forget the filter memory (after a seek)
+    pub fn reset(&mut self) {
+        self.state.fill([0.0; 2]);
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let channels = self.channels;
+        for frame in samples.chunks_exact_mut(channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut x = (*sample * self.preamp) as f64;
+                for (index, filter) in self.filters.iter().enumerate() {
+                    let state = &mut self.state[index * channels + channel];
+                    let y = filter.b0 * x + state[0];
+                    state[0] = filter.b1 * x - filter.a1 * y + state[1];
+                    state[1] = filter.b2 * x - filter.a2 * y;
+                    x = y;
+                }
+                *sample = x as f32;
+            }
+        }
+    }
+}
+
+/// Parse an AutoEQ / Equalizer APO `ParametricEQ.txt`:
+///
+/// ```text
+/// Preamp: -6.2 dB
+/// Filter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70
+/// Filter 2: ON PK Fc 2200 Hz Gain -3.1 dB Q 1.41
+/// ```
+pub fn parse_autoeq(text: &str) -> Result<EqSettings, String> {
+    let mut settings = EqSettings {
+        enabled: true,
+        ..Default::default()
+    };
+    let mut found = false;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim();
+
+        if key.eq_ignore_ascii_case("preamp") {
+            let value = value.trim().trim_end_matches("dB").trim();
+            settings.preamp_db = value
+                .parse()
+                .map_err(|_| format!("Invalid preamp: {}", line))?;
+            found = true;
+        } else if key.starts_with("Filter") {
+            if let Some(band) = parse_filter(value).map_err(|e| format!("{}: {}", e, line))? {
+                settings.bands.push(band);
+                found = true;
+            }
+        }
+    }
+
+    if !found {
+        return Err("No preamp or filters found".to_string());
+    }
+    Ok(settings.clamped())
+}
+
+/// One filter definition, None for filter types the EQ does not have
+fn parse_filter(definition: &str) -> Result<Option<EqBand>, String> {
+    let mut tokens = definition.split_whitespace();
+    let enabled = match tokens.next() {
+        Some("ON") => true,
+        Some("OFF") => false,
+        _ => return Err("Expected ON or OFF".to_string()),
+    };
+    let filter_type = match tokens.next() {
+        Some("PK") | Some("PEQ") => FilterType::Peaking,
+        Some("LS") | Some("LSC") | Some("LSQ") => FilterType::LowShelf,
+        Some("HS") | Some("HSC") | Some("HSQ") => FilterType::HighShelf,
+        Some("LP") | Some("LPQ") => FilterType::LowPass,
+        Some("HP") | Some("HPQ") => FilterType::HighPass,
+        Some(other) => {
+            log::warn!("Skipping unsupported AutoEQ filter type {}", other);
+            return Ok(None);
+        }
+        None => return Err("Missing filter type".to_string()),
+    };
+
+    let mut frequency = None;
+    let mut gain_db = 0.0;
+    let mut q = std::f32::consts::FRAC_1_SQRT_2;
+    while let Some(token) = tokens.next() {
+        let mut number = || -> Result<f32, String> {
+            tokens
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| format!("Invalid value for {}", token))
+        };
+        match token {
+            "Fc" => frequency = Some(number()?),
+            "Gain" => gain_db = number()?,
+            "Q" => q = number()?,
+            _ => {} // Units
+        }
+    }
+
+    Ok(Some(EqBand {
+        filter_type,
+        frequency: frequency.ok_or("Missing Fc")?,
+        gain_db,
+        q,
+        enabled,
+    }))
+}
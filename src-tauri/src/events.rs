@@ -0,0 +1,217 @@
+//! Playback Events Module
+//! State changes the audio engine pushes to the frontend, so it only has to call
+//! get_playback_state for the initial sync.
+
+use crate::audio::{PlaybackState, RepeatMode};
+use crate::queue::QueueSnapshot;
+use crate::visualizer::VisualizerFrame;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Minimum time between position ticks while playing
+const POSITION_INTERVAL: Duration = Duration::from_millis(250);
+/// A position further than this from where playback should be was a seek
+const POSITION_JUMP_SECONDS: f64 = 1.0;
+
+/// Delivers events to the frontend; called from the audio thread and stream callbacks
+pub type EventSink = Arc<dyn Fn(PlaybackEvent) + Send + Sync>;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum PlaybackEvent {
+    State(PlaybackStatus),
+    Position {
+        position: f64,
+        duration: f64,
+    },
+    TrackStarted {
+        path: String,
+        duration: f64,
+    },
+    TrackFinished {
+        path: String,
+    },
+    DeviceChanged {
+        device: Option<String>, // Device playback goes to
+        devices: Vec<String>,
+        fallback: bool, // The preferred device is gone and the default stands in
+    },
+    QueueChanged(QueueSnapshot),
+    QueuePosition {
+        position: Option<usize>,
+    },
+    Error {
+        message: String,
+    },
+    Visualization(VisualizerFrame),
+}
+
+impl PlaybackEvent {
+    /// Tauri event name
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlaybackEvent::State(_) => "playback-state",
+            PlaybackEvent::Position { .. } => "playback-position",
+            PlaybackEvent::TrackStarted { .. } => "track-started",
+            PlaybackEvent::TrackFinished { .. } => "track-finished",
+            PlaybackEvent::DeviceChanged { .. } => "audio-device-changed",
+            PlaybackEvent::QueueChanged(_) => "queue-changed",
+            PlaybackEvent::QueuePosition { .. } => "queue-position",
+            PlaybackEvent::Error { .. } => "playback-error",
+            PlaybackEvent::Visualization(_) => "visualizer-frame",
+        }
+    }
+}
+
+/// Everything in the playback state except the position, which has its own throttled event
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct PlaybackStatus {
+    pub is_playing: bool,
+    pub current_track: Option<String>, // File path of the audible track
+    pub duration: f64,
+    pub volume: f32,
+    pub sample_rate: u32,
+    pub bit_depth: u16,
+    pub channels: u16,
+    pub shuffle: bool,
+    pub repeat_mode: RepeatMode,
+    pub track_finished: bool,
+    pub output_sample_format: String,
+    pub bit_perfect: bool,
+    pub dsd_rate: Option<u32>,
+    pub dop: bool,
+    pub output_device: Option<String>,
+}
+
+impl From<&PlaybackState> for PlaybackStatus {
+    fn from(state: &PlaybackState) -> Self {
+        Self {
+            is_playing: state.is_playing,
+            current_track: state.current_track.clone(),
+            duration: state.duration,
+            volume: state.volume,
+            sample_rate: state.sample_rate,
+            bit_depth: state.bit_depth,
+            channels: state.channels,
+            shuffle: state.shuffle,
+            repeat_mode: state.repeat_mode.clone(),
+            track_finished: state.track_finished,
+            output_sample_format: state.output_sample_format.clone(),
+            bit_perfect: state.bit_perfect,
+            dsd_rate: state.dsd_rate,
+            dop: state.dop,
+            output_device: state.output_device.clone(),
+        }
+    }
+}
+
+/// Turns successive state snapshots into events, sending only what changed
+pub struct EventPublisher {
+    sink: EventSink,
+    status: Option<PlaybackStatus>,
+    track: Option<TrackKey>,
+    finished: bool, // TrackFinished was sent for the current track
+    position: f64,
+    position_sent: Instant,
+}
+
+/// Identifies one playthrough of a track: the stream it plays in and its number there
+#[derive(Clone, PartialEq)]
+struct TrackKey {
+    stream: u64,
+    playthrough: u64,
+    path: String,
+}
+
+impl EventPublisher {
+    pub fn new(sink: EventSink) -> Self {
+        Self {
+            sink,
+            status: None,
+            track: None,
+            finished: false,
+            position: 0.0,
+            position_sent: Instant::now(),
+        }
+    }
+
+    pub fn sink(&self) -> EventSink {
+        Arc::clone(&self.sink)
+    }
+
+    pub fn emit(&self, event: PlaybackEvent) {
+        (self.sink)(event);
+    }
+
+    /// Playback of the current track moved to output stream number `stream`
+    pub fn stream_restarted(&mut self, stream: u64) {
+        if let Some(track) = self.track.as_mut() {
+            track.stream = stream;
+            track.playthrough = 0;
+        }
+    }
+
+    /// The current track played to its end and the next one gets a new stream
+    pub fn finish_track(&mut self) {
+        if let (Some(track), false) = (&self.track, self.finished) {
+            self.emit(PlaybackEvent::TrackFinished {
+                path: track.path.clone(),
+            });
+        }
+        self.finished = true;
+    }
+
+    /// Publish the changes in `state`, a snapshot of output stream number `stream`.
+    /// Returns true when playback moved on to the next track in the same stream.
+    pub fn update(&mut self, state: &PlaybackState, stream: u64) -> bool {
+        let track = state.current_track.as_ref().map(|path| TrackKey {
+            stream,
+            playthrough: state.track_playthrough,
+            path: path.clone(),
+        });
+        let track_changed = track != self.track;
+        let mut moved_on = false;
+        if track_changed {
+            // Moving on within the same stream is a gapless transition, so the previous track ended
+            if let (Some(previous), Some(next)) = (&self.track, &track) {
+                if previous.stream == next.stream {
+                    self.finish_track();
+                    moved_on = true;
+                }
+            }
+            if let Some(next) = &track {
+                self.emit(PlaybackEvent::TrackStarted {
+                    path: next.path.clone(),
+                    duration: state.duration,
+                });
+            }
+            self.track = track;
+            self.finished = false;
+        }
+        if state.track_finished {
+            self.finish_track();
+        }
+
+        let status = PlaybackStatus::from(state);
+        if self.status.as_ref() != Some(&status) {
+            self.status = Some(status.clone());
+            self.emit(PlaybackEvent::State(status));
+        }
+
+        if state.current_track.is_some() && state.position != self.position {
+            let elapsed = self.position_sent.elapsed();
+            let jumped = (state.position - self.position - elapsed.as_secs_f64()).abs()
+                > POSITION_JUMP_SECONDS;
+            if track_changed || jumped || !state.is_playing || elapsed >= POSITION_INTERVAL {
+                self.position = state.position;
+                self.position_sent = Instant::now();
+                self.emit(PlaybackEvent::Position {
+                    position: state.position,
+                    duration: state.duration,
+                });
+            }
+        }
+        moved_on
+    }
+}
@@ -1,18 +1,38 @@
 mod audio;
+mod channel_mixer;
 mod commands;
+mod convolution;
+mod crossfeed;
+mod cue;
 mod database;
+mod decoder;
+mod dither;
+mod dsd;
+mod equalizer;
+mod events;
 mod ffmpeg;
 mod library;
+mod loudness;
+mod queue;
+mod render;
+mod resampler;
+mod ring_buffer;
+mod sample_format;
+mod sink;
 mod stream_cache;
 mod streaming;
+mod time_stretch;
+mod visualizer;
 
 use parking_lot::Mutex;
 use std::sync::Arc;
-use tauri::Manager;
+use tauri::{Emitter, Manager};
 
 use audio::AudioEngine;
 use database::Database;
+use events::PlaybackEvent;
 use library::LibraryScanner;
+use loudness::LoudnessAnalyzer;
 use streaming::StreamingService;
 
 pub struct AppState {
@@ -20,6 +40,7 @@ pub struct AppState {
     pub database: Arc<Mutex<Database>>,
     pub library_scanner: Arc<Mutex<LibraryScanner>>,
     pub streaming_service: Arc<Mutex<StreamingService>>,
+    pub loudness_analyzer: Arc<LoudnessAnalyzer>,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -40,15 +61,43 @@ pub fn run() {
 
             let db_path = app_dir.join("hiflac.db");
             let database = Database::new(&db_path).expect("Failed to initialize database");
-            let audio_engine = AudioEngine::new().expect("Failed to initialize audio engine");
+            let database = Arc::new(Mutex::new(database));
+            // Playback changes are pushed to the frontend instead of being polled
+            let app_handle = app.handle().clone();
+            // Tracks the queue moves on to by itself count as played, like those the user starts
+            let play_database = Arc::clone(&database);
+            let mut audio_engine = AudioEngine::new(
+                Arc::new(move |event: PlaybackEvent| {
+                    app_handle.emit(event.name(), &event).ok();
+                }),
+                Arc::new(move |track_id| {
+                    play_database.lock().record_play(track_id).ok();
+                }),
+            )
+            .expect("Failed to initialize audio engine");
+            {
+                let db = database.lock();
+                match db.get_device_profiles() {
+                    Ok(profiles) => {
+                        for (device_name, profile) in profiles {
+                            let profile = commands::with_eq_preset(&db, profile);
+                            audio_engine.set_device_profile(&device_name, Some(profile));
+                        }
+                    }
+                    Err(e) => log::error!("Failed to load device profiles: {}", e),
+                }
+            }
             let library_scanner = LibraryScanner::new();
             let streaming_service = StreamingService::new();
 
+            let loudness_analyzer = LoudnessAnalyzer::start(Arc::clone(&database));
+
             let state = AppState {
                 audio_engine: Arc::new(Mutex::new(audio_engine)),
-                database: Arc::new(Mutex::new(database)),
+                database,
                 library_scanner: Arc::new(Mutex::new(library_scanner)),
                 streaming_service: Arc::new(Mutex::new(streaming_service)),
+                loudness_analyzer,
             };
 
             app.manage(state);
@@ -71,12 +120,63 @@ pub fn run() {
             commands::seek,
             commands::set_volume,
             commands::get_playback_state,
+            commands::get_queue,
+            commands::play_queue,
+            commands::add_to_queue,
+            commands::play_next,
+            commands::remove_from_queue,
+            commands::move_in_queue,
+            commands::clear_queue,
+            commands::play_queue_index,
             commands::next_track,
             commands::previous_track,
+            commands::get_crossfade,
+            commands::set_crossfade,
+            commands::get_replaygain,
+            commands::set_replaygain,
+            commands::set_clipping_prevention,
+            commands::get_downmix,
+            commands::set_downmix,
+            commands::get_resampler_quality,
+            commands::set_resampler_quality,
+            commands::get_dither,
+            commands::set_dither,
+            commands::get_dsd,
+            commands::set_dsd,
+            commands::get_equalizer,
+            commands::set_equalizer,
+            commands::get_eq_presets,
+            commands::save_eq_preset,
+            commands::delete_eq_preset,
+            commands::apply_eq_preset,
+            commands::import_autoeq,
+            commands::get_loudness_analysis_status,
+            commands::set_loudness_analysis_enabled,
+            commands::get_track_loudness,
+            commands::write_replaygain_tags,
             commands::set_shuffle,
             commands::set_repeat_mode,
             commands::get_audio_devices,
             commands::set_audio_device,
+            commands::get_device_settings,
+            commands::set_device_settings,
+            commands::get_buffer_size,
+            commands::set_buffer_size,
+            commands::get_audio_diagnostics,
+            commands::get_output_sink,
+            commands::set_output_sink,
+            commands::get_visualizer,
+            commands::set_visualizer,
+            commands::get_speed,
+            commands::set_speed,
+            commands::get_crossfeed,
+            commands::set_crossfeed,
+            commands::get_convolution,
+            commands::set_convolution,
+            commands::render_track,
+            commands::get_device_profiles,
+            commands::save_device_profile,
+            commands::delete_device_profile,
             commands::get_track_artwork,
             commands::search,
             commands::get_statistics,
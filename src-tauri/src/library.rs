@@ -1,15 +1,19 @@
 //! Library Scanner Module
 //! Scans folders for audio files and extracts metadata
 
+use crate::cue::{self, CueRange, CueSheet};
 use crate::database::Track;
-use lofty::{Accessor, AudioFile, Probe, TaggedFileExt};
+use crate::dsd::{is_dsd_path, read_info, tag_text};
+use id3::TagLike;
+use lofty::{Accessor, AudioFile, ItemKey, Probe, TaggedFile, TaggedFileExt};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 use blake3::Hasher;
 use std::fs::File;
 use std::io::Read;
 
-const SUPPORTED_EXTENSIONS: &[&str] = &["flac", "wav", "alac", "m4a", "aiff", "aif", "mp3", "ogg", "opus"];
+const SUPPORTED_EXTENSIONS: &[&str] = &["flac", "wav", "alac", "m4a", "aiff", "aif", "mp3", "ogg", "opus", "dsf", "dff"];
 
 pub struct LibraryScanner {
     scanning: bool,
@@ -26,7 +30,8 @@ impl LibraryScanner {
 
     pub fn scan_folder(&mut self, folder_path: &Path) -> Vec<Track> {
         self.scanning = true;
-        let mut tracks = Vec::new();
+        let mut audio_files = Vec::new();
+        let mut cue_files = Vec::new();
 
         for entry in WalkDir::new(folder_path)
             .follow_links(true)
@@ -46,18 +51,99 @@ impl LibraryScanner {
 
             if let Some(ext) = extension {
                 if SUPPORTED_EXTENSIONS.contains(&ext.as_str()) {
-                    if let Some(track) = self.extract_metadata(path) {
-                        tracks.push(track);
-                    }
+                    audio_files.push(path.to_path_buf());
+                } else if ext == "cue" {
+                    cue_files.push(path.to_path_buf());
                 }
             }
         }
 
+        // Album images are split into the tracks of their cue sheet
+        let mut sheets: HashMap<PathBuf, CueSheet> = cue_files
+            .iter()
+            .flat_map(|cue_path| CueSheet::read_file(cue_path, &audio_files))
+            .collect();
+
+        let mut tracks = Vec::new();
+        for path in &audio_files {
+            let sheet = sheets
+                .remove(path)
+                .or_else(|| CueSheet::read_embedded(path));
+            if let Some(sheet) = sheet {
+                let cue_tracks = self.extract_cue_tracks(path, &sheet);
+                if !cue_tracks.is_empty() {
+                    tracks.extend(cue_tracks);
+                    continue;
+                }
+            }
+            if let Some(track) = self.extract_metadata(path) {
+                tracks.push(track);
+            }
+        }
+
         self.scanning = false;
         tracks
     }
 
+    /// The tracks of an album image, cut at the cue sheet's INDEX 01 points. The image's
+    /// tags fill in what the sheet leaves out.
+    fn extract_cue_tracks(&self, path: &Path, sheet: &CueSheet) -> Vec<Track> {
+        let Some(image) = self.extract_metadata(path) else {
+            return Vec::new();
+        };
+        let sample_rate = image.sample_rate.max(1) as u32;
+        let total_frames = (image.duration * sample_rate as f64).round() as u64;
+        let starts: Vec<u64> = sheet.tracks.iter().map(|t| t.start.frame(sample_rate)).collect();
+        let in_order = starts.windows(2).all(|pair| pair[0] < pair[1]);
+        if !in_order || starts.last().is_some_and(|&last| last >= total_frames) {
+            log::warn!("Cue sheet does not match {}", path.display());
+            return Vec::new();
+        }
+
+        // The album's gain from the sheet, else from the image's own tags
+        let (album_gain, album_peak) = match (sheet.replaygain_gain, image.replaygain_album_gain) {
+            (Some(gain), _) => (Some(gain), sheet.replaygain_peak),
+            (None, Some(gain)) => (Some(gain), image.replaygain_album_peak),
+            (None, None) => (image.replaygain_track_gain, image.replaygain_track_peak),
+        };
+
+        sheet.tracks.iter().enumerate().map(|(i, cue_track)| {
+            let range = CueRange {
+                start: starts[i],
+                end: starts.get(i + 1).copied(),
+            };
+            let frames = range.frames(Some(total_frames)).unwrap_or(0);
+            Track {
+                file_path: cue::locator(&image.file_path, range),
+                file_hash: format!("{}#{}", image.file_hash, cue_track.number),
+                title: cue_track.title.clone()
+                    .unwrap_or_else(|| format!("Track {}", cue_track.number)),
+                artist: cue_track.performer.clone()
+                    .or_else(|| sheet.performer.clone())
+                    .unwrap_or_else(|| image.artist.clone()),
+                album: sheet.title.clone().unwrap_or_else(|| image.album.clone()),
+                album_artist: sheet.performer.clone().or_else(|| image.album_artist.clone()),
+                track_number: Some(cue_track.number as i32),
+                year: sheet.year.or(image.year),
+                genre: sheet.genre.clone().or_else(|| image.genre.clone()),
+                duration: frames as f64 / sample_rate as f64,
+                file_size: (image.file_size as f64 * frames as f64 / total_frames as f64) as i64,
+                replaygain_track_gain: cue_track.replaygain_gain,
+                replaygain_track_peak: cue_track.replaygain_peak,
+                replaygain_album_gain: album_gain,
+                replaygain_album_peak: album_peak,
+                start_offset: Some(range.start as i64),
+                end_offset: range.end.map(|end| end as i64),
+                ..image.clone()
+            }
+        }).collect()
+    }
+
     fn extract_metadata(&self, path: &Path) -> Option<Track> {
+        if is_dsd_path(path) {
+            return self.extract_dsd_metadata(path);
+        }
+
         let tagged_file = Probe::open(path).ok()?.read().ok()?;
         
         let properties = tagged_file.properties();
@@ -123,6 +209,8 @@ impl LibraryScanner {
             .map(|t| !t.pictures().is_empty())
             .unwrap_or(false);
 
+        let replaygain = |key: ItemKey| replaygain_value(&tagged_file, &key);
+
         Some(Track {
             id: 0,
             file_path,
@@ -146,10 +234,83 @@ impl LibraryScanner {
             last_played: None,
             date_added: chrono_now(),
             is_favorite: false,
+            replaygain_track_gain: replaygain(ItemKey::ReplayGainTrackGain),
+            replaygain_track_peak: replaygain(ItemKey::ReplayGainTrackPeak),
+            replaygain_album_gain: replaygain(ItemKey::ReplayGainAlbumGain),
+            replaygain_album_peak: replaygain(ItemKey::ReplayGainAlbumPeak),
+            start_offset: None,
+            end_offset: None,
+        })
+    }
+
+    /// Lofty reads neither DSF nor DFF: their tags are ID3v2 (DSF, some DFF) or the DFF's
+    /// own edited master information
+    fn extract_dsd_metadata(&self, path: &Path) -> Option<Track> {
+        let info = read_info(path).ok()?;
+        let tag = info.id3.as_ref();
+        let text = |value: Option<&str>| value.map(|s| s.to_string());
+
+        let file_path = path.to_string_lossy().to_string();
+        let file_hash = self.compute_file_hash(path).unwrap_or_default();
+        let file_size = std::fs::metadata(path).ok()?.len() as i64;
+        let format = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_uppercase())
+            .unwrap_or_else(|| "DSD".to_string());
+
+        let title = text(tag.and_then(|t| t.title()))
+            .or_else(|| info.title.clone())
+            .unwrap_or_else(|| {
+                path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("Unknown")
+                    .to_string()
+            });
+        let artist = text(tag.and_then(|t| t.artist()))
+            .or_else(|| info.artist.clone())
+            .unwrap_or_else(|| "Unknown Artist".to_string());
+        let album = text(tag.and_then(|t| t.album())).unwrap_or_else(|| "Unknown Album".to_string());
+
+        let replaygain = |description: &str| {
+            tag.and_then(|t| tag_text(t, description))
+                .and_then(parse_replaygain)
+        };
+
+        Some(Track {
+            id: 0,
+            file_path,
+            file_hash,
+            title,
+            artist,
+            album,
+            album_artist: text(tag.and_then(|t| t.album_artist())),
+            track_number: tag.and_then(|t| t.track()).map(|n| n as i32),
+            disc_number: tag.and_then(|t| t.disc()).map(|n| n as i32),
+            year: tag.and_then(|t| t.year().or_else(|| t.date_recorded().map(|d| d.year))),
+            genre: tag.and_then(|t| t.genre_parsed()).map(|g| g.into_owned()),
+            duration: info.duration(),
+            // The DSD rate and its one bit per sample
+            sample_rate: info.sample_rate as i32,
+            bit_depth: 1,
+            channels: info.channels as i32,
+            file_size,
+            format,
+            has_artwork: tag.map(|t| t.pictures().next().is_some()).unwrap_or(false),
+            play_count: 0,
+            last_played: None,
+            date_added: chrono_now(),
+            is_favorite: false,
+            replaygain_track_gain: replaygain("REPLAYGAIN_TRACK_GAIN"),
+            replaygain_track_peak: replaygain("REPLAYGAIN_TRACK_PEAK"),
+            replaygain_album_gain: replaygain("REPLAYGAIN_ALBUM_GAIN"),
+            replaygain_album_peak: replaygain("REPLAYGAIN_ALBUM_PEAK"),
+            start_offset: None,
+            end_offset: None,
         })
     }
 
-    fn compute_file_hash(&self, path: &Path) -> Option<String> {
+    pub fn compute_file_hash(&self, path: &Path) -> Option<String> {
         let mut file = File::open(path).ok()?;
         let mut hasher = Hasher::new();
         
@@ -162,6 +323,12 @@ impl LibraryScanner {
     }
 
     pub fn extract_artwork(&self, path: &Path) -> Option<Vec<u8>> {
+        if is_dsd_path(path) {
+            let tag = read_info(path).ok()?.id3?;
+            let picture = tag.pictures().next()?;
+            return Some(picture.data.clone());
+        }
+
         let tagged_file = Probe::open(path).ok()?.read().ok()?;
         let tag = tagged_file.primary_tag()
             .or_else(|| tagged_file.first_tag())?;
@@ -194,6 +361,25 @@ impl LibraryScanner {
     }
 }
 
+/// Read a REPLAYGAIN_* value from any tag in the file ("-6.52 dB" or "0.988123")
+fn replaygain_value(tagged_file: &TaggedFile, key: &ItemKey) -> Option<f64> {
+    tagged_file
+        .tags()
+        .iter()
+        .find_map(|tag| tag.get_string(key))
+        .and_then(parse_replaygain)
+}
+
+pub(crate) fn parse_replaygain(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value)
+        .trim();
+    number.parse::<f64>().ok().filter(|v| v.is_finite())
+}
+
 fn chrono_now() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let duration = SystemTime::now()
@@ -0,0 +1,651 @@
+//! Loudness Analysis Module
+//! EBU R128 (ITU-R BS.1770-4) measurement of library tracks on a background thread.
+//! Results are stored in the database and feed ReplayGain normalization of untagged files.
+
+use crate::audio::ReplayGainInfo;
+use crate::database::{AlbumLoudnessInput, Database, TrackLoudness};
+use crate::decoder::TrackSource;
+use crate::dsd::{DsdOutput, DsdSettings};
+use lofty::{ItemKey, Probe, Tag, TagExt, TaggedFileExt};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// ReplayGain 2.0 reference level
+pub const REFERENCE_LUFS: f64 = -18.0;
+
+// Histogram of block loudness from -70 to +30 LUFS in 0.1 LU steps (libebur128's histogram mode).
+// Histograms of several tracks can be merged, which gives exact album gating without keeping
+// every block around.
+const HISTOGRAM_MIN: f64 = -70.0;
+const HISTOGRAM_STEP: f64 = 0.1;
+const HISTOGRAM_BINS: usize = 1000;
+
+lazy_static::lazy_static! {
+    /// Analysis results by file path, consulted when a file has no ReplayGain tags
+    static ref LOUDNESS_CACHE: RwLock<HashMap<String, ReplayGainInfo>> = RwLock::new(HashMap::new());
+}
+
+/// ReplayGain values derived from the analysis of `path`, if it has been analyzed
+pub fn analyzed_replaygain(path: &str) -> Option<ReplayGainInfo> {
+    LOUDNESS_CACHE.read().get(path).copied()
+}
+
+/// ReplayGain values for an analysis result, relative to the -18 LUFS reference
+pub fn replaygain_from(loudness: &TrackLoudness) -> ReplayGainInfo {
+    ReplayGainInfo {
+        track_gain: loudness
+            .integrated_lufs
+            .map(|l| (REFERENCE_LUFS - l) as f32),
+        track_peak: loudness.true_peak.map(|p| p as f32),
+        album_gain: loudness
+            .album_integrated_lufs
+            .map(|l| (REFERENCE_LUFS - l) as f32),
+        album_peak: loudness.album_true_peak.map(|p| p as f32),
+    }
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[derive(Clone)]
+pub struct LoudnessHistogram {
+    bins: Vec<u32>,
+}
+
+impl LoudnessHistogram {
+    pub fn new() -> Self {
+        Self {
+            bins: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    fn add(&mut self, loudness: f64) {
+        // Blocks below the absolute gate (-70 LUFS) never count
+        if loudness < HISTOGRAM_MIN {
+            return;
+        }
+        let index = ((loudness - HISTOGRAM_MIN) / HISTOGRAM_STEP) as usize;
+        self.bins[index.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &LoudnessHistogram) {
+        for (bin, count) in self.bins.iter_mut().zip(other.bins.iter()) {
+            *bin += count;
+        }
+    }
+
+    fn bin_loudness(index: usize) -> f64 {
+        HISTOGRAM_MIN + (index as f64 + 0.5) * HISTOGRAM_STEP
+    }
+
+    fn bin_energy(index: usize) -> f64 {
+        10f64.powf((Self::bin_loudness(index) + 0.691) / 10.0)
+    }
+
+    /// First bin whose loudness is at or above `loudness`
+    fn first_bin_from(loudness: f64) -> usize {
+        let index = ((loudness - HISTOGRAM_MIN) / HISTOGRAM_STEP - 0.5).ceil();
+        (index.max(0.0) as usize).min(HISTOGRAM_BINS)
+    }
+
+    /// Mean loudness of the bins from `start` on, with their block count
+    fn mean_from(&self, start: usize) -> Option<(f64, u64)> {
+        let mut energy = 0.0;
+        let mut count = 0u64;
+        for (index, &blocks) in self.bins.iter().enumerate().skip(start) {
+            energy += Self::bin_energy(index) * blocks as f64;
+            count += blocks as u64;
+        }
+        (count > 0).then(|| (energy_to_lufs(energy / count as f64), count))
+    }
+
+    /// Gated integrated loudness: relative gate 10 LU below the absolute-gated mean
+    pub fn integrated(&self) -> Option<f64> {
+        let (ungated, _) = self.mean_from(0)?;
+        let (gated, _) = self.mean_from(Self::first_bin_from(ungated - 10.0))?;
+        Some(gated)
+    }
+
+    /// Loudness range (EBU Tech 3342) of short-term values: relative gate 20 LU below the
+    /// mean, then the spread between the 10th and 95th percentile
+    pub fn range(&self) -> Option<f64> {
+        let (ungated, _) = self.mean_from(0)?;
+        let start = Self::first_bin_from(ungated - 20.0);
+        let total: u64 = self.bins[start..].iter().map(|&c| c as u64).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let percentile = |fraction: f64| {
+            let target = (total as f64 * fraction).floor() as u64;
+            let mut seen = 0u64;
+            for (index, &count) in self.bins.iter().enumerate().skip(start) {
+                seen += count as u64;
+                if seen > target {
+                    return Self::bin_loudness(index);
+                }
+            }
+            Self::bin_loudness(HISTOGRAM_BINS - 1)
+        };
+        Some((percentile(0.95) - percentile(0.10)).max(0.0))
+    }
+
+    /// Sparse encoding for storage: (bin u16, count u32) pairs, little endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (index, &count) in self.bins.iter().enumerate() {
+            if count > 0 {
+                bytes.extend_from_slice(&(index as u16).to_le_bytes());
+                bytes.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut histogram = Self::new();
+        for pair in bytes.chunks_exact(6) {
+            let index = u16::from_le_bytes([pair[0], pair[1]]) as usize;
+            let count = u32::from_le_bytes([pair[2], pair[3], pair[4], pair[5]]);
+            if index < HISTOGRAM_BINS {
+                histogram.bins[index] += count;
+            }
+        }
+        histogram
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// BS.1770 K-weighting (high shelf + high-pass) designed for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // Pre-filter: high shelf modelling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    // RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+        ..Default::default()
+    };
+
+    [shelf, highpass]
+}
+
+/// BS.1770 channel weights. LFE is excluded and surround channels count +1.5 dB.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    match channels {
+        5 => vec![1.0, 1.0, 1.0, 1.41, 1.41],
+        6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+        _ => vec![1.0; channels],
+    }
+}
+
+/// Inter-sample peak detection by polyphase oversampling (BS.1770-4 Annex 2)
+struct TruePeakDetector {
+    factor: usize,
+    taps: usize,
+    /// Interpolation filter, phase `p` uses coefficients p, p + factor, p + 2 * factor, ...
+    coefficients: Vec<f64>,
+    history: Vec<Vec<f64>>,
+    position: usize,
+    peak: f64,
+}
+
+impl TruePeakDetector {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        let factor = match sample_rate {
+            0..=95_999 => 4,
+            96_000..=191_999 => 2,
+            _ => 1,
+        };
+        let taps = 12;
+        let length = factor * taps;
+        let centre = (length - 1) as f64 / 2.0;
+        let coefficients = (0..length)
+            .map(|n| {
+                let x = (n as f64 - centre) / factor as f64;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                };
+                // Blackman window
+                let w = 2.0 * std::f64::consts::PI * n as f64 / (length - 1) as f64;
+                sinc * (0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos())
+            })
+            .collect();
+
+        Self {
+            factor,
+            taps,
+            coefficients,
+            history: vec![vec![0.0; taps]; channels],
+            position: 0,
+            peak: 0.0,
+        }
+    }
+
+    fn process_frame(&mut self, frame: &[f32]) {
+        for (ch, &sample) in frame.iter().enumerate() {
+            let history = &mut self.history[ch];
+            history[self.position] = sample as f64;
+            self.peak = self.peak.max(sample.abs() as f64);
+
+            if self.factor > 1 {
+                for phase in 0..self.factor {
+                    let mut sum = 0.0;
+                    for k in 0..self.taps {
+                        let index = (self.position + self.taps - k) % self.taps;
+                        sum += self.coefficients[phase + k * self.factor] * history[index];
+                    }
+                    self.peak = self.peak.max(sum.abs());
+                }
+            }
+        }
+        self.position = (self.position + 1) % self.taps;
+    }
+}
+
+/// Result of measuring one track
+pub struct TrackAnalysis {
+    pub integrated: Option<f64>,
+    pub range: Option<f64>,
+    pub true_peak: f64, // Linear
+    pub blocks: LoudnessHistogram,
+    pub short_term: LoudnessHistogram,
+}
+
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    /// 100 ms steps: gating blocks (400 ms) and short-term windows (3 s) are built from these
+    step_frames: usize,
+    step_position: usize,
+    step_energy: Vec<f64>,
+    sub_blocks: VecDeque<f64>,
+    blocks: LoudnessHistogram,
+    short_term: LoudnessHistogram,
+    true_peak: TruePeakDetector,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, channels: usize) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            weights: channel_weights(channels),
+            filters: vec![k_weighting(sample_rate); channels],
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_position: 0,
+            step_energy: vec![0.0; channels],
+            sub_blocks: VecDeque::with_capacity(30),
+            blocks: LoudnessHistogram::new(),
+            short_term: LoudnessHistogram::new(),
+            true_peak: TruePeakDetector::new(sample_rate, channels),
+        }
+    }
+
+    /// Feed interleaved samples
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.true_peak.process_frame(frame);
+
+            for (ch, &sample) in frame.iter().enumerate() {
+                let [shelf, highpass] = &mut self.filters[ch];
+                let y = highpass.process(shelf.process(sample as f64));
+                self.step_energy[ch] += y * y;
+            }
+
+            self.step_position += 1;
+            if self.step_position == self.step_frames {
+                self.finish_step();
+            }
+        }
+    }
+
+    fn finish_step(&mut self) {
+        let energy: f64 = self
+            .step_energy
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(e, w)| w * e / self.step_frames as f64)
+            .sum();
+        self.step_energy.iter_mut().for_each(|e| *e = 0.0);
+        self.step_position = 0;
+
+        if self.sub_blocks.len() == 30 {
+            self.sub_blocks.pop_front();
+        }
+        self.sub_blocks.push_back(energy);
+
+        // 400 ms gating blocks with 75% overlap
+        if self.sub_blocks.len() >= 4 {
+            let block: f64 = self.sub_blocks.iter().rev().take(4).sum::<f64>() / 4.0;
+            self.blocks.add(energy_to_lufs(block));
+        }
+        // 3 s short-term loudness for the loudness range
+        if self.sub_blocks.len() == 30 {
+            let window: f64 = self.sub_blocks.iter().sum::<f64>() / 30.0;
+            self.short_term.add(energy_to_lufs(window));
+        }
+    }
+
+    pub fn finish(self) -> TrackAnalysis {
+        TrackAnalysis {
+            integrated: self.blocks.integrated(),
+            range: self.short_term.range(),
+            true_peak: self.true_peak.peak,
+            blocks: self.blocks,
+            short_term: self.short_term,
+        }
+    }
+}
+
+/// Decode a whole file and measure it
+pub fn analyze_file(path: &str) -> Result<TrackAnalysis, String> {
+    let dsd = DsdOutput::Pcm(DsdSettings::default().pcm_rate);
+    let mut source = TrackSource::open(path, None, dsd).map_err(|e| e.to_string())?;
+    let spec = source.spec();
+    let mut meter = LoudnessMeter::new(spec.sample_rate, spec.channels as usize);
+
+    let mut samples = Vec::new();
+    while source
+        .decode_next(&mut samples)
+        .map_err(|e| e.to_string())?
+    {
+        meter.process(&samples);
+        samples.clear();
+    }
+    Ok(meter.finish())
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct AnalysisStatus {
+    pub enabled: bool,
+    pub analyzed: i64,
+    pub total: i64,
+    pub current_track: Option<String>,
+}
+
+/// Background job measuring every track without a stored analysis, one at a time.
+/// Progress lives in the database, so the job simply continues after a restart.
+pub struct LoudnessAnalyzer {
+    enabled: AtomicBool,
+    wake_tx: Mutex<mpsc::Sender<()>>,
+    status: RwLock<AnalysisStatus>,
+}
+
+impl LoudnessAnalyzer {
+    pub fn start(database: Arc<Mutex<Database>>) -> Arc<Self> {
+        let (wake_tx, wake_rx) = mpsc::channel();
+        let analyzer = Arc::new(Self {
+            enabled: AtomicBool::new(true),
+            wake_tx: Mutex::new(wake_tx),
+            status: RwLock::new(AnalysisStatus {
+                enabled: true,
+                ..Default::default()
+            }),
+        });
+
+        let worker = Arc::clone(&analyzer);
+        thread::Builder::new()
+            .name("hiflac-loudness".to_string())
+            .spawn(move || worker.run(database, wake_rx))
+            .ok();
+
+        analyzer
+    }
+
+    /// Check for new tracks right away (after a library scan)
+    pub fn wake(&self) {
+        let _ = self.wake_tx.lock().send(());
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+        self.status.write().enabled = enabled;
+        self.wake();
+    }
+
+    pub fn status(&self) -> AnalysisStatus {
+        self.status.read().clone()
+    }
+
+    fn run(&self, database: Arc<Mutex<Database>>, wake_rx: mpsc::Receiver<()>) {
+        // Results of earlier runs feed normalization straight away
+        match database.lock().get_all_track_loudness() {
+            Ok(results) => {
+                let mut cache = LOUDNESS_CACHE.write();
+                for (path, loudness) in results {
+                    cache.insert(path, replaygain_from(&loudness));
+                }
+            }
+            Err(e) => log::error!("Failed to load loudness analysis: {}", e),
+        }
+
+        loop {
+            if !self.enabled.load(Ordering::Acquire) {
+                self.status.write().current_track = None;
+                if let Err(mpsc::RecvTimeoutError::Disconnected) =
+                    wake_rx.recv_timeout(Duration::from_secs(60))
+                {
+                    return;
+                }
+                continue;
+            }
+
+            let (next, progress) = {
+                let db = database.lock();
+                (db.next_unanalyzed_track(), db.get_loudness_progress())
+            };
+            if let Ok((analyzed, total)) = progress {
+                let mut status = self.status.write();
+                status.analyzed = analyzed;
+                status.total = total;
+            }
+
+            let (track_id, path) = match next {
+                Ok(Some(next)) => next,
+                Ok(None) => {
+                    // Everything is analyzed: wait for a scan to add tracks
+                    self.status.write().current_track = None;
+                    if let Err(mpsc::RecvTimeoutError::Disconnected) =
+                        wake_rx.recv_timeout(Duration::from_secs(60))
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Loudness analysis query failed: {}", e);
+                    thread::sleep(Duration::from_secs(60));
+                    continue;
+                }
+            };
+
+            self.status.write().current_track = Some(path.clone());
+            match analyze_file(&path) {
+                Ok(analysis) => {
+                    log::info!(
+                        "Loudness of {}: {:?} LUFS, LRA {:?} LU, true peak {:.3}",
+                        path,
+                        analysis.integrated,
+                        analysis.range,
+                        analysis.true_peak
+                    );
+                    self.store(&database, track_id, &path, &analysis);
+                }
+                Err(e) => {
+                    // Remember the failure so the file is not retried on every pass
+                    log::warn!("Loudness analysis failed for {}: {}", path, e);
+                    database.lock().mark_loudness_failed(track_id).ok();
+                }
+            }
+
+            // Leave room for playback decoding
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn store(
+        &self,
+        database: &Mutex<Database>,
+        track_id: i64,
+        path: &str,
+        analysis: &TrackAnalysis,
+    ) {
+        let db = database.lock();
+        let loudness = TrackLoudness {
+            track_id,
+            integrated_lufs: analysis.integrated,
+            loudness_range: analysis.range,
+            true_peak: Some(analysis.true_peak),
+            album_integrated_lufs: None,
+            album_loudness_range: None,
+            album_true_peak: None,
+        };
+        if let Err(e) = db.save_track_loudness(
+            &loudness,
+            &analysis.blocks.to_bytes(),
+            &analysis.short_term.to_bytes(),
+        ) {
+            log::error!("Failed to store loudness of {}: {}", path, e);
+            return;
+        }
+        LOUDNESS_CACHE
+            .write()
+            .insert(path.to_string(), replaygain_from(&loudness));
+
+        // Once the whole album is measured, gate all its blocks together
+        match db.get_album_loudness_inputs(track_id) {
+            Ok(inputs) => {
+                if inputs.iter().all(|t| t.analyzed) {
+                    store_album_loudness(&db, &inputs);
+                }
+            }
+            Err(e) => log::error!("Failed to load album loudness: {}", e),
+        }
+    }
+}
+
+fn store_album_loudness(db: &Database, inputs: &[AlbumLoudnessInput]) {
+    let mut blocks = LoudnessHistogram::new();
+    let mut short_term = LoudnessHistogram::new();
+    let mut peak: Option<f64> = None;
+    for input in inputs {
+        if let Some(bytes) = &input.block_histogram {
+            blocks.merge(&LoudnessHistogram::from_bytes(bytes));
+        }
+        if let Some(bytes) = &input.short_term_histogram {
+            short_term.merge(&LoudnessHistogram::from_bytes(bytes));
+        }
+        if let Some(track_peak) = input.true_peak {
+            peak = Some(peak.map_or(track_peak, |p| p.max(track_peak)));
+        }
+    }
+
+    let integrated = blocks.integrated();
+    let range = short_term.range();
+    let track_ids: Vec<i64> = inputs.iter().map(|t| t.track_id).collect();
+    if let Err(e) = db.save_album_loudness(&track_ids, integrated, range, peak) {
+        log::error!("Failed to store album loudness: {}", e);
+        return;
+    }
+
+    let mut cache = LOUDNESS_CACHE.write();
+    for input in inputs {
+        if let Some(info) = cache.get_mut(&input.file_path) {
+            info.album_gain = integrated.map(|l| (REFERENCE_LUFS - l) as f32);
+            info.album_peak = peak.map(|p| p as f32);
+        }
+    }
+}
+
+/// Write REPLAYGAIN_* tags computed from the analysis into the file
+pub fn write_replaygain_tags(path: &str, info: &ReplayGainInfo) -> Result<(), String> {
+    let mut tagged_file = Probe::open(path)
+        .and_then(|p| p.read())
+        .map_err(|e| e.to_string())?;
+
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        tagged_file.insert_tag(Tag::new(tag_type));
+    }
+    let tag = tagged_file
+        .primary_tag_mut()
+        .ok_or_else(|| "File does not support tags".to_string())?;
+
+    let values = [
+        (
+            ItemKey::ReplayGainTrackGain,
+            info.track_gain.map(|g| format!("{:.2} dB", g)),
+        ),
+        (
+            ItemKey::ReplayGainTrackPeak,
+            info.track_peak.map(|p| format!("{:.6}", p)),
+        ),
+        (
+            ItemKey::ReplayGainAlbumGain,
+            info.album_gain.map(|g| format!("{:.2} dB", g)),
+        ),
+        (
+            ItemKey::ReplayGainAlbumPeak,
+            info.album_peak.map(|p| format!("{:.6}", p)),
+        ),
+    ];
+    for (key, value) in values {
+        if let Some(value) = value {
+            tag.insert_text(key, value);
+        }
+    }
+
+    tag.save_to_path(path).map_err(|e| e.to_string())
+}
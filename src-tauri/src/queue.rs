@@ -0,0 +1,437 @@
+//! Play Queue Module
+//! The tracks to play and the order to play them in. Shuffle plays a permutation of the
+//! queue and turning it off returns to the queued order; previous walks back the history.
+
+use crate::audio::RepeatMode;
+use crate::database::Track;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Tracks remembered for previous
+const HISTORY_LIMIT: usize = 500;
+
+/// The queue as the frontend shows it
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct QueueSnapshot {
+    pub tracks: Vec<Track>,      // In play order
+    pub position: Option<usize>, // Index of the current track in `tracks`
+    pub shuffle: bool,
+    pub repeat_mode: RepeatMode,
+}
+
+pub struct PlayQueue {
+    tracks: HashMap<u64, Track>, // Queued tracks by entry id
+    queued: Vec<u64>,            // Entries in the order they were queued
+    order: Vec<u64>,             // Play order: `queued`, or a permutation of it while shuffled
+    position: Option<usize>,     // Index into `order` of the current track, None before the first
+    removed: bool,               // The current track was removed, `position` is the one before it
+    history: Vec<u64>,           // Entries played before the current one, most recent last
+    next_id: u64,
+    shuffle: bool,
+    repeat_mode: RepeatMode,
+    rng: u32,
+}
+
+impl Default for PlayQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        Self {
+            tracks: HashMap::new(),
+            queued: Vec::new(),
+            order: Vec::new(),
+            position: None,
+            removed: false,
+            history: Vec::new(),
+            next_id: 0,
+            shuffle: false,
+            repeat_mode: RepeatMode::Off,
+            rng: seed | 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            tracks: self
+                .order
+                .iter()
+                .map(|id| self.tracks[id].clone())
+                .collect(),
+            position: self.position(),
+            shuffle: self.shuffle,
+            repeat_mode: self.repeat_mode.clone(),
+        }
+    }
+
+    pub fn position(&self) -> Option<usize> {
+        self.position.filter(|_| !self.removed)
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.current_id().map(|id| &self.tracks[&id])
+    }
+
+    /// The track that follows the current one when it plays to its end
+    pub fn upcoming(&self) -> Option<&Track> {
+        self.upcoming_position()
+            .map(|p| &self.tracks[&self.order[p]])
+    }
+
+    fn upcoming_position(&self) -> Option<usize> {
+        if self.repeat_mode == RepeatMode::One && self.current_id().is_some() {
+            return self.position;
+        }
+        self.following_position()
+    }
+
+    /// The position after the current one, wrapping around unless repeat is off
+    fn following_position(&self) -> Option<usize> {
+        let next = self.position.map_or(0, |p| p + 1);
+        if next < self.order.len() {
+            Some(next)
+        } else if self.repeat_mode != RepeatMode::Off && !self.order.is_empty() {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn current_id(&self) -> Option<u64> {
+        self.position
+            .filter(|_| !self.removed)
+            .map(|p| self.order[p])
+    }
+
+    /// Give each track an entry id
+    fn new_entries(&mut self, tracks: Vec<Track>) -> Vec<u64> {
+        tracks
+            .into_iter()
+            .map(|track| {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.tracks.insert(id, track);
+                id
+            })
+            .collect()
+    }
+
+    /// Replace the queue and make the track at `start` (in the given order) current
+    pub fn set(&mut self, tracks: Vec<Track>, start: usize) {
+        self.clear();
+        self.queued = self.new_entries(tracks);
+        self.order = self.queued.clone();
+        self.position = (start < self.order.len()).then_some(start);
+        if self.shuffle {
+            self.reshuffle();
+        }
+    }
+
+    /// Append tracks to the end of the queue
+    pub fn add(&mut self, tracks: Vec<Track>) {
+        let ids = self.new_entries(tracks);
+        self.queued.extend(&ids);
+        for id in ids {
+            if self.shuffle {
+                // Anywhere after the current track, so it is still ahead
+                let start = self.position.map_or(0, |p| p + 1);
+                let at = start + self.random(self.order.len() - start + 1);
+                self.order.insert(at, id);
+            } else {
+                self.order.push(id);
+            }
+        }
+    }
+
+    /// Queue tracks to play right after the current one, in the given order
+    pub fn insert_next(&mut self, tracks: Vec<Track>) {
+        let ids = self.new_entries(tracks);
+        let queued_at = self
+            .position
+            .map(|p| self.order[p])
+            .and_then(|c| self.queued.iter().position(|&id| id == c))
+            .map_or(0, |i| i + 1);
+        self.queued
+            .splice(queued_at..queued_at, ids.iter().copied());
+        let at = self.position.map_or(0, |p| p + 1);
+        self.order.splice(at..at, ids);
+    }
+
+    /// Remove the track at `index` in play order. Removing the current track leaves
+    /// nothing current, with the track that followed it up next.
+    pub fn remove(&mut self, index: usize) -> bool {
+        if index >= self.order.len() {
+            return false;
+        }
+        let id = self.order.remove(index);
+        self.queued.retain(|&i| i != id);
+        self.history.retain(|&i| i != id);
+        self.tracks.remove(&id);
+        self.position = match self.position {
+            Some(p) if p > index => Some(p - 1),
+            Some(p) if p == index => {
+                self.removed = true;
+                p.checked_sub(1)
+            }
+            p => p,
+        };
+        true
+    }
+
+    /// Move the track at `from` to `to`, both in play order
+    pub fn move_track(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.order.len() || to >= self.order.len() {
+            return false;
+        }
+        let current = self.position.map(|p| self.order[p]);
+        let id = self.order.remove(from);
+        self.order.insert(to, id);
+        if !self.shuffle {
+            self.queued = self.order.clone();
+        }
+        self.position = current.and_then(|c| self.order.iter().position(|&i| i == c));
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.queued.clear();
+        self.order.clear();
+        self.history.clear();
+        self.position = None;
+        self.removed = false;
+    }
+
+    pub fn set_repeat_mode(&mut self, mode: RepeatMode) {
+        self.repeat_mode = mode;
+    }
+
+    /// Shuffling keeps the current track and plays the rest in random order.
+    /// Turning it off goes back to the queued order from the current track.
+    pub fn set_shuffle(&mut self, enabled: bool) {
+        if enabled == self.shuffle {
+            return;
+        }
+        self.shuffle = enabled;
+        if enabled {
+            self.reshuffle();
+        } else {
+            let current = self.position.map(|p| self.order[p]);
+            self.order = self.queued.clone();
+            self.position = current.and_then(|c| self.order.iter().position(|&i| i == c));
+        }
+    }
+
+    fn reshuffle(&mut self) {
+        let current = self.current_id();
+        let mut order: Vec<u64> = self
+            .queued
+            .iter()
+            .copied()
+            .filter(|&id| Some(id) != current)
+            .collect();
+        // Fisher-Yates
+        for i in (1..order.len()).rev() {
+            let j = self.random(i + 1);
+            order.swap(i, j);
+        }
+        if let Some(current) = current {
+            order.insert(0, current);
+        }
+        self.order = order;
+        self.position = current.map(|_| 0);
+    }
+
+    /// Playback moved on to the upcoming track by itself
+    pub fn advance(&mut self) -> Option<&Track> {
+        let next = self.upcoming_position()?;
+        self.go_to(next);
+        self.current()
+    }
+
+    /// Skip to the following track, leaving a repeated track behind
+    pub fn skip(&mut self) -> Option<&Track> {
+        let next = self.following_position()?;
+        self.go_to(next);
+        self.current()
+    }
+
+    /// Go back to the track played before the current one
+    pub fn previous(&mut self) -> Option<&Track> {
+        let previous = match self.history.pop() {
+            Some(id) => self.order.iter().position(|&i| i == id)?,
+            None => self.position?.checked_sub(usize::from(!self.removed))?,
+        };
+        self.position = Some(previous);
+        self.removed = false;
+        self.current()
+    }
+
+    /// Make the track at `index` in play order current
+    pub fn jump(&mut self, index: usize) -> Option<&Track> {
+        if index >= self.order.len() {
+            return None;
+        }
+        self.go_to(index);
+        self.current()
+    }
+
+    fn go_to(&mut self, position: usize) {
+        if let Some(id) = self.current_id() {
+            self.history.push(id);
+            if self.history.len() > HISTORY_LIMIT {
+                self.history.remove(0);
+            }
+        }
+        self.position = Some(position);
+        self.removed = false;
+    }
+
+    /// Uniform in 0..n
+    fn random(&mut self, n: usize) -> usize {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as usize % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(id: i64) -> Track {
+        Track {
+            id,
+            file_path: format!("/music/{}.flac", id),
+            file_hash: String::new(),
+            title: id.to_string(),
+            artist: String::new(),
+            album: String::new(),
+            album_artist: None,
+            track_number: None,
+            disc_number: None,
+            year: None,
+            genre: None,
+            duration: 0.0,
+            sample_rate: 44100,
+            bit_depth: 16,
+            channels: 2,
+            file_size: 0,
+            format: "FLAC".to_string(),
+            has_artwork: false,
+            play_count: 0,
+            last_played: None,
+            date_added: String::new(),
+            is_favorite: false,
+            replaygain_track_gain: None,
+            replaygain_track_peak: None,
+            replaygain_album_gain: None,
+            replaygain_album_peak: None,
+            start_offset: None,
+            end_offset: None,
+        }
+    }
+
+    /// Tracks 1 to `count`, with the one at `start` current
+    fn numbered(count: i64, start: usize) -> PlayQueue {
+        let mut queue = PlayQueue::new();
+        queue.set((1..=count).map(track).collect(), start);
+        queue
+    }
+
+    fn id(track: Option<&Track>) -> Option<i64> {
+        track.map(|t| t.id)
+    }
+
+    fn order(queue: &PlayQueue) -> Vec<i64> {
+        queue.snapshot().tracks.iter().map(|t| t.id).collect()
+    }
+
+    #[test]
+    fn removing_the_current_track_leaves_the_following_up_next() {
+        let mut queue = numbered(4, 1);
+        assert!(queue.remove(1));
+        assert_eq!(id(queue.current()), None);
+        assert_eq!(queue.position(), None);
+        assert_eq!(id(queue.upcoming()), Some(3));
+        assert_eq!(id(queue.advance()), Some(3));
+        assert_eq!(queue.position(), Some(1));
+        // The removed track was never left behind, so previous goes to the one before it
+        assert_eq!(id(queue.previous()), Some(1));
+    }
+
+    #[test]
+    fn previous_after_removing_the_current_track() {
+        let mut queue = numbered(4, 0);
+        queue.advance();
+        queue.advance();
+        assert!(queue.remove(2));
+        assert_eq!(id(queue.previous()), Some(2));
+        assert_eq!(id(queue.previous()), Some(1));
+        assert_eq!(id(queue.previous()), None);
+
+        // Without history, previous steps back from where the removed track was
+        let mut queue = numbered(4, 2);
+        assert!(queue.remove(2));
+        assert_eq!(id(queue.previous()), Some(2));
+    }
+
+    #[test]
+    fn insert_next_after_removing_the_current_track() {
+        let mut queue = numbered(4, 1);
+        assert!(queue.remove(1));
+        queue.insert_next(vec![track(10)]);
+        assert_eq!(order(&queue), vec![1, 10, 3, 4]);
+        assert_eq!(id(queue.advance()), Some(10));
+        assert_eq!(id(queue.advance()), Some(3));
+
+        // With the first track removed nothing comes before the inserted one
+        let mut queue = numbered(3, 0);
+        assert!(queue.remove(0));
+        queue.insert_next(vec![track(10)]);
+        assert_eq!(order(&queue), vec![10, 2, 3]);
+        assert_eq!(id(queue.advance()), Some(10));
+    }
+
+    #[test]
+    fn turning_shuffle_off_restores_the_queued_order() {
+        let mut queue = numbered(6, 2);
+        queue.set_shuffle(true);
+        assert_eq!(id(queue.current()), Some(3));
+        assert_eq!(queue.position(), Some(0));
+        queue.insert_next(vec![track(10)]);
+        assert_eq!(id(queue.upcoming()), Some(10));
+        queue.add(vec![track(11)]);
+
+        queue.set_shuffle(false);
+        assert_eq!(order(&queue), vec![1, 2, 3, 10, 4, 5, 6, 11]);
+        assert_eq!(id(queue.current()), Some(3));
+        assert_eq!(queue.position(), Some(2));
+    }
+
+    #[test]
+    fn repeat_one_repeats_on_advance_but_not_on_skip() {
+        let mut queue = numbered(3, 0);
+        queue.set_repeat_mode(RepeatMode::One);
+        assert_eq!(id(queue.upcoming()), Some(1));
+        assert_eq!(id(queue.advance()), Some(1));
+        assert_eq!(id(queue.skip()), Some(2));
+        assert_eq!(id(queue.skip()), Some(3));
+        // Skipping past the end wraps around while repeating
+        assert_eq!(id(queue.skip()), Some(1));
+
+        queue.set_repeat_mode(RepeatMode::Off);
+        queue.jump(2);
+        assert_eq!(id(queue.advance()), None);
+        assert_eq!(id(queue.current()), Some(3));
+    }
+}
@@ -0,0 +1,476 @@
+//! Render Module
+//! Offline rendering: a track goes through the same decoder and output renderer as
+//! playback, and the result is written to a WAV or FLAC file instead of a device. Used to
+//! compare DSP settings in other tools, and as a device-free test of the signal path. The
+//! writer also serves the file output sink.
+
+use crate::audio::AudioError;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::process::Command;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderFormat {
+    Wav,
+    /// Written as WAV and encoded with FFmpeg
+    Flac,
+    /// Interleaved little-endian samples without a header
+    Raw,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenderOptions {
+    pub format: RenderFormat,
+    pub bit_depth: u16,           // 16 or 24, or 32 for float WAV
+    pub sample_rate: Option<u32>, // None keeps the source rate
+    pub channels: Option<u16>,    // None keeps the source layout
+}
+
+impl RenderOptions {
+    pub fn validate(&self) -> Result<(), AudioError> {
+        let supported = match self.format {
+            RenderFormat::Wav | RenderFormat::Raw => matches!(self.bit_depth, 16 | 24 | 32),
+            RenderFormat::Flac => matches!(self.bit_depth, 16 | 24),
+        };
+        if !supported {
+            return Err(AudioError::Write(format!(
+                "{}-bit {:?} is not supported",
+                self.bit_depth, self.format
+            )));
+        }
+        if let Some(rate) = self.sample_rate.filter(|r| !(8_000..=768_000).contains(r)) {
+            return Err(AudioError::Write(format!(
+                "Unsupported sample rate {}Hz",
+                rate
+            )));
+        }
+        if let Some(channels) = self.channels.filter(|c| !(1..=8).contains(c)) {
+            return Err(AudioError::Write(format!(
+                "Unsupported channel count {}",
+                channels
+            )));
+        }
+        Ok(())
+    }
+
+    /// Width to dither to, None for float
+    pub fn dither_bits(&self) -> Option<u32> {
+        match self.bit_depth {
+            16 | 24 => Some(self.bit_depth as u32),
+            _ => None,
+        }
+    }
+}
+
+/// What was written
+#[derive(Clone, Debug, Serialize)]
+pub struct RenderSummary {
+    pub path: String,
+    pub frames: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bit_depth: u16,
+    pub peak_db: f32, // Highest sample level, dBFS
+}
+
+/// Header sizes of a WAV file whose length is not known yet, as streaming writers use them
+const STREAMING_DATA_LEN: u32 = u32::MAX - 36;
+
+/// Writes interleaved f32 samples to a WAV or raw file, fixing up the header sizes at the end
+pub struct RenderWriter {
+    file: BufWriter<File>,
+    path: String,
+    wav_path: String, // `path`, or a temporary file for FLAC
+    options: RenderOptions,
+    sample_rate: u32,
+    channels: u16,
+    samples: u64,
+    peak: f32,
+}
+
+impl RenderWriter {
+    pub fn create(
+        path: &str,
+        options: &RenderOptions,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, AudioError> {
+        options.validate()?;
+        let wav_path = match options.format {
+            RenderFormat::Wav | RenderFormat::Raw => path.to_string(),
+            RenderFormat::Flac => format!("{}.render.wav", path),
+        };
+        let file = File::create(&wav_path).map_err(|e| AudioError::Write(e.to_string()))?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            path: path.to_string(),
+            wav_path,
+            options: *options,
+            sample_rate,
+            channels,
+            samples: 0,
+            peak: 0.0,
+        };
+        // Readers of a pipe see this header; a file gets the real sizes when finished
+        if options.format != RenderFormat::Raw {
+            writer.write_header(STREAMING_DATA_LEN)?;
+        }
+        Ok(writer)
+    }
+
+    /// RIFF header with `data_len` bytes of samples
+    fn write_header(&mut self, data_len: u32) -> Result<(), AudioError> {
+        let bytes_per_sample = self.options.bit_depth as u32 / 8;
+        let format_tag: u16 = if self.options.bit_depth == 32 { 3 } else { 1 }; // Float or PCM
+        let block_align = self.channels as u32 * bytes_per_sample;
+
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(36 + data_len).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&self.channels.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.sample_rate * block_align).to_le_bytes());
+        header.extend_from_slice(&(block_align as u16).to_le_bytes());
+        header.extend_from_slice(&self.options.bit_depth.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+        self.file
+            .write_all(&header)
+            .map_err(|e| AudioError::Write(e.to_string()))
+    }
+
+    /// Append interleaved samples, whole frames only
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), AudioError> {
+        let mut bytes = Vec::with_capacity(samples.len() * 4);
+        for &sample in samples {
+            self.peak = self.peak.max(sample.abs());
+            match self.options.bit_depth {
+                16 => {
+                    let value = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+                24 => {
+                    let value = (sample as f64 * 8388608.0)
+                        .round()
+                        .clamp(-8388608.0, 8388607.0) as i32;
+                    bytes.extend_from_slice(&value.to_le_bytes()[..3]);
+                }
+                _ => bytes.extend_from_slice(&sample.to_le_bytes()),
+            }
+        }
+        self.samples += samples.len() as u64;
+        self.file
+            .write_all(&bytes)
+            .map_err(|e| AudioError::Write(e.to_string()))
+    }
+
+    /// Hand what was written so far to the file, for a reader on the other end of a pipe
+    pub fn flush(&mut self) -> Result<(), AudioError> {
+        self.file
+            .flush()
+            .map_err(|e| AudioError::Write(e.to_string()))
+    }
+
+    /// Complete the file: fix up the header, and encode to FLAC if asked for. A pipe
+    /// cannot seek back, so its header keeps the streaming sizes.
+    pub fn finish(mut self) -> Result<RenderSummary, AudioError> {
+        if self.options.format != RenderFormat::Raw && self.file.seek(SeekFrom::Start(0)).is_ok() {
+            let bytes_per_sample = self.options.bit_depth as u64 / 8;
+            let data_len = (self.samples * bytes_per_sample).min(STREAMING_DATA_LEN as u64);
+            self.write_header(data_len as u32)?;
+        }
+        self.flush()?;
+
+        if self.options.format == RenderFormat::Flac {
+            let encoded = encode_flac(&self.wav_path, &self.path);
+            let _ = fs::remove_file(&self.wav_path);
+            encoded?;
+        }
+
+        Ok(RenderSummary {
+            path: self.path,
+            frames: self.samples / self.channels.max(1) as u64,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            bit_depth: self.options.bit_depth,
+            peak_db: 20.0 * self.peak.max(1e-10).log10(),
+        })
+    }
+}
+
+fn encode_flac(input: &str, output: &str) -> Result<(), AudioError> {
+    let ffmpeg = crate::ffmpeg::get_ffmpeg_path().map_err(AudioError::Write)?;
+    let status = Command::new(&ffmpeg)
+        .args(["-y", "-i", input, "-c:a", "flac", output])
+        .output()
+        .map_err(|e| AudioError::Write(format!("Failed to run ffmpeg: {}", e)))?;
+
+    if !status.status.success() {
+        let stderr = String::from_utf8_lossy(&status.stderr);
+        return Err(AudioError::Write(format!(
+            "ffmpeg FLAC encoding failed: {}",
+            stderr
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{render_offline, PlaybackState, RenderContext};
+    use crate::convolution::{ConvolutionSettings, ImpulseResponse};
+    use crate::decoder::TrackSource;
+    use crate::dither::DitherMode;
+    use crate::dsd::DsdOutput;
+    use crate::equalizer::{EqBand, EqSettings, FilterType};
+    use std::f32::consts::TAU;
+    use std::sync::Arc;
+
+    const RATE: u32 = 44100;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("hiflac-render-{}-{}", std::process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn float_wav() -> RenderOptions {
+        RenderOptions {
+            format: RenderFormat::Wav,
+            bit_depth: 32,
+            sample_rate: None,
+            channels: None,
+        }
+    }
+
+    fn write_source(name: &str, rate: u32, channels: u16, samples: &[f32]) -> String {
+        write_wav(name, &float_wav(), rate, channels, samples)
+    }
+
+    fn write_wav(
+        name: &str,
+        options: &RenderOptions,
+        rate: u32,
+        channels: u16,
+        samples: &[f32],
+    ) -> String {
+        let path = temp_path(name);
+        let mut writer = RenderWriter::create(&path, options, rate, channels).unwrap();
+        writer.write(samples).unwrap();
+        writer.finish().unwrap();
+        path
+    }
+
+    fn read(path: &str) -> Vec<f32> {
+        let mut source = TrackSource::open(path, None, DsdOutput::Pcm(88200)).unwrap();
+        let mut samples = Vec::new();
+        while source.decode_next(&mut samples).unwrap() {}
+        samples
+    }
+
+    /// Interleaved sine of `hz` at half scale, the same on every channel
+    fn sine(hz: f32, rate: u32, channels: u16, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let value = 0.5 * (TAU * hz * i as f32 / rate as f32).sin();
+                std::iter::repeat_n(value, channels as usize)
+            })
+            .collect()
+    }
+
+    /// RMS of the middle half, away from filter onsets and the end
+    fn rms_db(samples: &[f32]) -> f32 {
+        let middle = &samples[samples.len() / 4..samples.len() * 3 / 4];
+        let power = middle.iter().map(|s| s * s).sum::<f32>() / middle.len() as f32;
+        10.0 * power.log10()
+    }
+
+    fn context() -> RenderContext {
+        RenderContext {
+            state: PlaybackState::default(),
+            equalizer: EqSettings::default(),
+            impulse: None,
+            dither: DitherMode::Auto,
+        }
+    }
+
+    fn render(
+        input: &str,
+        name: &str,
+        options: &RenderOptions,
+        context: RenderContext,
+    ) -> (RenderSummary, Vec<f32>) {
+        let output = temp_path(name);
+        let summary = render_offline(input, &output, options, context).unwrap();
+        let samples = read(&output);
+        let _ = fs::remove_file(&output);
+        (summary, samples)
+    }
+
+    #[test]
+    fn untouched_path_is_bit_exact() {
+        // A 24-bit source survives a 24-bit render unchanged
+        let mut seed = 1u32;
+        let samples: Vec<f32> = (0..RATE as usize * 2)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((seed >> 8) as i32 - (1 << 23)) as f32 / 8_388_608.0
+            })
+            .collect();
+        let options = RenderOptions {
+            bit_depth: 24,
+            ..float_wav()
+        };
+        let input = write_wav("exact.wav", &options, RATE, 2, &samples);
+        let (summary, output) = render(&input, "exact-out.wav", &options, context());
+        let _ = fs::remove_file(&input);
+
+        assert_eq!(summary.frames, RATE as u64);
+        assert!(output == samples, "samples changed");
+    }
+
+    #[test]
+    fn resampling_keeps_duration_and_level() {
+        let input = write_source("rate.wav", RATE, 2, &sine(1000.0, RATE, 2, RATE as usize));
+        let options = RenderOptions {
+            sample_rate: Some(48000),
+            ..float_wav()
+        };
+        let (summary, output) = render(&input, "rate-out.wav", &options, context());
+        let _ = fs::remove_file(&input);
+
+        assert_eq!(summary.frames, 48000);
+        assert_eq!(output.len(), 48000 * 2);
+        assert!((rms_db(&output) - 20.0 * (0.5f32 / 2f32.sqrt()).log10()).abs() < 0.05);
+    }
+
+    #[test]
+    fn equalizer_is_applied() {
+        let tone = sine(1000.0, RATE, 2, RATE as usize);
+        let input = write_source("eq.wav", RATE, 2, &tone);
+        let mut context = context();
+        context.equalizer = EqSettings {
+            enabled: true,
+            preamp_db: 0.0,
+            bands: vec![EqBand {
+                filter_type: FilterType::Peaking,
+                frequency: 1000.0,
+                gain_db: 6.0,
+                q: 1.0,
+                enabled: true,
+            }],
+        };
+        let (_, output) = render(&input, "eq-out.wav", &float_wav(), context);
+        let _ = fs::remove_file(&input);
+
+        let gain = rms_db(&output) - rms_db(&tone);
+        assert!((gain - 6.0).abs() < 0.1, "gain {}", gain);
+    }
+
+    #[test]
+    fn dithered_renders_are_deterministic() {
+        let input = write_source(
+            "dither.wav",
+            RATE,
+            2,
+            &sine(440.0, RATE, 2, RATE as usize / 2),
+        );
+        let options = RenderOptions {
+            bit_depth: 16,
+            ..float_wav()
+        };
+        let mut context_a = context();
+        context_a.dither = DitherMode::Tpdf;
+        let mut context_b = context();
+        context_b.dither = DitherMode::Tpdf;
+        let (_, first) = render(&input, "dither-a.wav", &options, context_a);
+        let (_, second) = render(&input, "dither-b.wav", &options, context_b);
+        let _ = fs::remove_file(&input);
+
+        assert!(first == second, "renders differ");
+        // Dither moved samples off the values plain rounding gives
+        let rounded = sine(440.0, RATE, 2, RATE as usize / 2)
+            .iter()
+            .map(|s| (s * 32768.0).round() / 32768.0)
+            .collect::<Vec<_>>();
+        assert!(first != rounded, "not dithered");
+    }
+
+    #[test]
+    fn last_chunk_is_dithered() {
+        // A float source is narrowed to 16 bits, so Auto dithers all of it
+        let frames = RATE as usize / 2;
+        let source = sine(440.0, RATE, 2, frames);
+        let input = write_source("last.wav", RATE, 2, &source);
+        let options = RenderOptions {
+            bit_depth: 16,
+            ..float_wav()
+        };
+        let (_, output) = render(&input, "last-out.wav", &options, context());
+        let _ = fs::remove_file(&input);
+
+        // Renders go in chunks of 1024 frames, so the last one is only partly audio
+        let tail = 2 * 200;
+        let rounded = source[source.len() - tail..]
+            .iter()
+            .map(|s| (s * 32768.0).round() / 32768.0)
+            .collect::<Vec<_>>();
+        assert_eq!(output.len(), source.len());
+        assert!(output[output.len() - tail..] != rounded[..], "not dithered");
+    }
+
+    #[test]
+    fn channel_conversion_to_mono() {
+        let input = write_source(
+            "mono.wav",
+            RATE,
+            2,
+            &sine(440.0, RATE, 2, RATE as usize / 2),
+        );
+        let options = RenderOptions {
+            channels: Some(1),
+            ..float_wav()
+        };
+        let (summary, output) = render(&input, "mono-out.wav", &options, context());
+        let _ = fs::remove_file(&input);
+
+        assert_eq!(summary.channels, 1);
+        assert_eq!(output.len(), RATE as usize / 2);
+    }
+
+    #[test]
+    fn convolution_tail_is_rendered() {
+        // A unit impulse 100 frames in: the output is the input, delayed
+        let mut impulse = vec![0.0; 1000];
+        impulse[100] = 1.0;
+        let ir_path = write_source("ir.wav", RATE, 1, &impulse);
+        let tone = sine(1000.0, RATE, 2, RATE as usize / 2);
+        let input = write_source("conv.wav", RATE, 2, &tone);
+        let mut context = context();
+        context.state.convolution = ConvolutionSettings {
+            enabled: true,
+            ir_path: Some(ir_path.clone()),
+        };
+        context.impulse = Some(Arc::new(ImpulseResponse::load(&ir_path).unwrap()));
+        let (summary, output) = render(&input, "conv-out.wav", &float_wav(), context);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&ir_path);
+
+        // One 512-frame partition of latency, then the impulse response's own length
+        assert_eq!(summary.frames, RATE as u64 / 2 + 512 + 1000);
+        let delay = (512 + 100) * 2;
+        let error = tone
+            .iter()
+            .zip(&output[delay..])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 1e-5, "error {}", error);
+    }
+}
@@ -0,0 +1,393 @@
+//! Resampler Module
+//! Streaming sample rate conversion for the decode path. Audio goes in and comes out in
+//! pieces of any size; the filter delay is removed, so a source of `n` frames becomes exactly
+//! `n * to / from` frames that line up with the input.
+
+use crate::audio::AudioError;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use rubato::sinc_interpolator::SincInterpolator;
+use rubato::{
+    calculate_cutoff, FftFixedIn, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    WindowFunction,
+};
+use rubato::{ResampleResult, Resampler};
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResamplerQuality {
+    /// Synchronous FFT resampler, lowest CPU use
+    #[default]
+    Fast,
+    /// Long linear-phase sinc filter
+    HighQuality,
+    /// The same sinc filter made minimum phase: no pre-ringing, at the cost of a slight
+    /// phase shift near the cutoff
+    MinimumPhase,
+}
+
+const CHUNK_FRAMES: usize = 1024;
+const SINC_LEN: usize = 256;
+const OVERSAMPLING: usize = 256;
+
+lazy_static! {
+    /// Minimum-phase filter banks by cutoff; designing one takes a few large FFTs
+    static ref MINIMUM_PHASE_FILTERS: Mutex<HashMap<u32, Arc<MinimumPhaseFilter>>> =
+        Mutex::new(HashMap::new());
+}
+
+enum Engine {
+    Fft(FftFixedIn<f32>),
+    Sinc(SincFixedIn<f32>),
+}
+
+impl Engine {
+    fn input_frames_next(&self) -> usize {
+        match self {
+            Engine::Fft(r) => r.input_frames_next(),
+            Engine::Sinc(r) => r.input_frames_next(),
+        }
+    }
+
+    fn process(&mut self, input: &[Vec<f32>]) -> ResampleResult<Vec<Vec<f32>>> {
+        match self {
+            Engine::Fft(r) => r.process(input, None),
+            Engine::Sinc(r) => r.process(input, None),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Engine::Fft(r) => r.reset(),
+            Engine::Sinc(r) => r.reset(),
+        }
+    }
+}
+
+/// Resampler that accepts audio in arbitrarily sized pieces and keeps its state between them
+pub struct StreamResampler {
+    resampler: Engine,
+    channels: usize,
+    from_rate: u32,
+    to_rate: u32,
+    input: Vec<Vec<f32>>,
+    /// Silent frames fed ahead of the audio
+    preroll: usize,
+    /// Output frames the filter lags behind the input
+    delay: usize,
+    /// Delay frames still to be dropped
+    skip: usize,
+    /// Frames fed and emitted since creation or the last reset
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl StreamResampler {
+    pub fn new(
+        from_rate: u32,
+        to_rate: u32,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Result<Self, AudioError> {
+        let channels = channels.max(1);
+        let ratio = to_rate as f64 / from_rate as f64;
+        let error = |e: String| AudioError::Decode(format!("Failed to create resampler: {}", e));
+
+        // How many output frames each resampler lags behind the input. Output frame `n` of
+        // SincFixedIn lines up with input `(n + 1) / ratio - 1`.
+        let (resampler, lag) = match quality {
+            ResamplerQuality::Fast => {
+                let resampler = FftFixedIn::<f32>::new(
+                    from_rate as usize,
+                    to_rate as usize,
+                    CHUNK_FRAMES,
+                    2, // sub chunks
+                    channels,
+                )
+                .map_err(|e| error(e.to_string()))?;
+                let lag = resampler.output_delay() as f64;
+                (Engine::Fft(resampler), lag)
+            }
+            ResamplerQuality::HighQuality => {
+                let parameters = SincInterpolationParameters {
+                    sinc_len: SINC_LEN,
+                    f_cutoff: calculate_cutoff(SINC_LEN, WindowFunction::BlackmanHarris2),
+                    oversampling_factor: OVERSAMPLING,
+                    interpolation: SincInterpolationType::Cubic,
+                    window: WindowFunction::BlackmanHarris2,
+                };
+                let resampler =
+                    SincFixedIn::<f32>::new(ratio, 1.0, parameters, CHUNK_FRAMES, channels)
+                        .map_err(|e| error(e.to_string()))?;
+                (Engine::Sinc(resampler), ratio - 1.0)
+            }
+            ResamplerQuality::MinimumPhase => {
+                let interpolator = MinimumPhaseInterpolator::new(ratio);
+                // The filter peaks near its start rather than at its center
+                let offset = interpolator.filter.peak - (SINC_LEN / 2) as f64;
+                let resampler = SincFixedIn::<f32>::new_with_interpolator(
+                    ratio,
+                    1.0,
+                    SincInterpolationType::Cubic,
+                    Box::new(interpolator),
+                    CHUNK_FRAMES,
+                    channels,
+                )
+                .map_err(|e| error(e.to_string()))?;
+                (Engine::Sinc(resampler), ratio - 1.0 + offset * ratio)
+            }
+        };
+        let (preroll, delay) = alignment(from_rate, to_rate, lag);
+
+        Ok(Self {
+            resampler,
+            channels,
+            from_rate,
+            to_rate,
+            input: vec![vec![0.0; preroll]; channels],
+            preroll,
+            delay,
+            skip: delay,
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Feed interleaved samples and append whatever output is ready to `out`
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        for (i, sample) in samples.iter().enumerate() {
+            self.input[i % self.channels].push(*sample);
+        }
+        self.frames_in += (samples.len() / self.channels) as u64;
+
+        while self.input[0].len() >= self.resampler.input_frames_next() {
+            self.process_chunk(out, u64::MAX);
+        }
+    }
+
+    /// Push out the rest of the source at the end, padding with silence until the filter
+    /// has produced the output for every input frame
+    pub fn flush(&mut self, out: &mut Vec<f32>) {
+        let expected = self.frames_in * self.to_rate as u64 / self.from_rate as u64;
+        while self.frames_out < expected {
+            let needed = self.resampler.input_frames_next();
+            for ch in self.input.iter_mut() {
+                ch.resize(needed.max(ch.len()), 0.0);
+            }
+            if self.process_chunk(out, expected) == 0 {
+                break;
+            }
+        }
+        for ch in self.input.iter_mut() {
+            ch.clear();
+        }
+    }
+
+    /// Discard buffered input and filter state (after a seek)
+    pub fn reset(&mut self) {
+        self.resampler.reset();
+        for ch in self.input.iter_mut() {
+            ch.clear();
+            ch.resize(self.preroll, 0.0);
+        }
+        self.skip = self.delay;
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    /// Run one input chunk, emitting at most up to `limit` total output frames.
+    /// Returns the frames the resampler produced.
+    fn process_chunk(&mut self, out: &mut Vec<f32>, limit: u64) -> usize {
+        let needed = self.resampler.input_frames_next();
+        let chunk: Vec<Vec<f32>> = self
+            .input
+            .iter_mut()
+            .map(|ch| ch.drain(..needed).collect())
+            .collect();
+        let output = match self.resampler.process(&chunk) {
+            Ok(output) => output,
+            Err(e) => {
+                log::warn!("Resampling error: {}", e);
+                return 0;
+            }
+        };
+
+        let frames = output.first().map(|c| c.len()).unwrap_or(0);
+        let skip = self.skip.min(frames);
+        self.skip -= skip;
+        let take = ((frames - skip) as u64).min(limit.saturating_sub(self.frames_out)) as usize;
+        out.reserve(take * self.channels);
+        for frame in skip..skip + take {
+            for ch in &output {
+                out.push(ch[frame]);
+            }
+        }
+        self.frames_out += take as u64;
+        frames
+    }
+}
+
+struct MinimumPhaseFilter {
+    /// Sub-filters, one per oversampling step
+    sincs: Vec<Vec<f32>>,
+    /// Position of the impulse response's peak, in input frames
+    peak: f64,
+}
+
+/// Silent input frames to feed first and output frames to drop so that a resampler lagging
+/// `lag` output frames (negative: running ahead) lines up with its input. Rates with a common
+/// divisor allow an exact fit; otherwise the closest one within a few thousand frames is used.
+fn alignment(from_rate: u32, to_rate: u32, lag: f64) -> (usize, usize) {
+    let ratio = to_rate as f64 / from_rate as f64;
+    let period = (from_rate / gcd(from_rate, to_rate)).min(4096) as usize;
+    let mut best = (0, 0, f64::MAX);
+    for preroll in 0..period + (-lag / ratio).max(0.0).ceil() as usize {
+        let shift = preroll as f64 * ratio + lag;
+        if shift < -0.5 {
+            continue;
+        }
+        let error = (shift - shift.round()).abs();
+        if error < best.2 - 1e-9 {
+            best = (preroll, shift.round().max(0.0) as usize, error);
+        }
+    }
+    (best.0, best.1)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Sinc interpolator with a minimum-phase filter bank
+struct MinimumPhaseInterpolator {
+    filter: Arc<MinimumPhaseFilter>,
+}
+
+impl MinimumPhaseInterpolator {
+    fn new(ratio: f64) -> Self {
+        // Lowered the same way rubato does when downsampling
+        let mut f_cutoff: f32 = calculate_cutoff(SINC_LEN, WindowFunction::BlackmanHarris2);
+        if ratio < 1.0 {
+            f_cutoff *= ratio as f32;
+        }
+        let filter = MINIMUM_PHASE_FILTERS
+            .lock()
+            .entry(f_cutoff.to_bits())
+            .or_insert_with(|| Arc::new(minimum_phase_filter(f_cutoff)))
+            .clone();
+        Self { filter }
+    }
+}
+
+impl SincInterpolator<f32> for MinimumPhaseInterpolator {
+    fn get_sinc_interpolated(&self, wave: &[f32], index: usize, subindex: usize) -> f32 {
+        let sinc = &self.filter.sincs[subindex];
+        let wave = &wave[index..index + sinc.len()];
+        // Independent accumulators so the loop vectorizes
+        let mut acc = [0.0f32; 8];
+        for (w, s) in wave.chunks_exact(8).zip(sinc.chunks_exact(8)) {
+            for i in 0..8 {
+                acc[i] += w[i] * s[i];
+            }
+        }
+        acc.iter().sum()
+    }
+
+    fn len(&self) -> usize {
+        SINC_LEN
+    }
+
+    fn nbr_sincs(&self) -> usize {
+        OVERSAMPLING
+    }
+}
+
+/// Design the windowed sinc rubato would use, convert it to minimum phase by folding its
+/// real cepstrum, and split it into `OVERSAMPLING` sub-filters of `SINC_LEN` taps
+fn minimum_phase_filter(f_cutoff: f32) -> MinimumPhaseFilter {
+    let total = SINC_LEN * OVERSAMPLING;
+    let prototype = (0..total).map(|x| {
+        // Squared Blackman-Harris window
+        let t = 2.0 * PI * x as f64 / total as f64;
+        let window =
+            0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos();
+        let arg = (x as f64 - (total / 2) as f64) * f_cutoff as f64 / OVERSAMPLING as f64;
+        let sinc = if arg == 0.0 {
+            1.0
+        } else {
+            (PI * arg).sin() / (PI * arg)
+        };
+        window * window * sinc
+    });
+
+    // Generous zero padding keeps cepstral aliasing well below the stopband
+    let size = (total * 8).next_power_of_two();
+    let mut planner = FftPlanner::<f64>::new();
+    let forward = planner.plan_fft_forward(size);
+    let inverse = planner.plan_fft_inverse(size);
+    let mut spectrum: Vec<Complex<f64>> = prototype
+        .map(|v| Complex::new(v, 0.0))
+        .chain(std::iter::repeat(Complex::new(0.0, 0.0)))
+        .take(size)
+        .collect();
+
+    forward.process(&mut spectrum);
+    let peak = spectrum.iter().map(|c| c.norm()).fold(0.0, f64::max);
+    let floor = peak * 1e-10; // -200 dB, keeps the logarithm finite in the stopband nulls
+    for c in spectrum.iter_mut() {
+        *c = Complex::new(c.norm().max(floor).ln(), 0.0);
+    }
+    inverse.process(&mut spectrum);
+    for (n, c) in spectrum.iter_mut().enumerate() {
+        let fold = if n == 0 || n == size / 2 {
+            1.0
+        } else if n < size / 2 {
+            2.0
+        } else {
+            0.0
+        };
+        *c = Complex::new(c.re * fold / size as f64, 0.0);
+    }
+    forward.process(&mut spectrum);
+    for c in spectrum.iter_mut() {
+        *c = c.exp();
+    }
+    inverse.process(&mut spectrum);
+    let minimum: Vec<f64> = spectrum[..total]
+        .iter()
+        .map(|c| c.re / size as f64)
+        .collect();
+
+    let peak = minimum
+        .iter()
+        .enumerate()
+        .fold(
+            (0, 0.0),
+            |best, (i, v)| if v.abs() > best.1 { (i, v.abs()) } else { best },
+        )
+        .0;
+
+    // Unity gain at DC for every sub-filter, laid out (time-reversed) as rubato expects
+    let sum = minimum.iter().sum::<f64>() / OVERSAMPLING as f64;
+    let mut sincs = vec![vec![0.0f32; SINC_LEN]; OVERSAMPLING];
+    for p in 0..SINC_LEN {
+        for n in 0..OVERSAMPLING {
+            let tap = minimum[total - 1 - (OVERSAMPLING * p + n)];
+            sincs[OVERSAMPLING - n - 1][p] = (tap / sum) as f32;
+        }
+    }
+    MinimumPhaseFilter {
+        sincs,
+        peak: peak as f64 / OVERSAMPLING as f64,
+    }
+}
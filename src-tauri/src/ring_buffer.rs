@@ -0,0 +1,121 @@
+//! Lock-Free Ring Buffer Module
+//! Single-producer single-consumer sample ring between the decoder thread and the
+//! real-time output callback. Neither side ever blocks or allocates after creation.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct RingShared {
+    buffer: Box<[UnsafeCell<f32>]>,
+    /// Total samples ever written (only advanced by the producer)
+    write: AtomicUsize,
+    /// Total samples ever read (only advanced by the consumer)
+    read: AtomicUsize,
+}
+
+// The producer only writes slots between `write` and `read + capacity`, the consumer only
+// reads slots between `read` and `write`, so the two never touch the same slot concurrently.
+unsafe impl Sync for RingShared {}
+unsafe impl Send for RingShared {}
+
+impl RingShared {
+    fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+/// Create a ring holding up to `capacity` samples
+pub fn sample_ring(capacity: usize) -> (RingProducer, RingConsumer) {
+    let capacity = capacity.max(1);
+    let buffer: Box<[UnsafeCell<f32>]> = (0..capacity).map(|_| UnsafeCell::new(0.0)).collect();
+    let shared = Arc::new(RingShared {
+        buffer,
+        write: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (
+        RingProducer {
+            shared: Arc::clone(&shared),
+        },
+        RingConsumer { shared },
+    )
+}
+
+/// Writing half of the ring, owned by the decoder thread
+pub struct RingProducer {
+    shared: Arc<RingShared>,
+}
+
+impl RingProducer {
+    /// Free space in samples
+    pub fn free_len(&self) -> usize {
+        let write = self.shared.write.load(Ordering::Relaxed);
+        let read = self.shared.read.load(Ordering::Acquire);
+        self.shared.capacity() - write.wrapping_sub(read)
+    }
+
+    /// Push as many samples as fit, returning how many were accepted
+    pub fn push(&mut self, samples: &[f32]) -> usize {
+        let count = samples.len().min(self.free_len());
+        let capacity = self.shared.capacity();
+        let write = self.shared.write.load(Ordering::Relaxed);
+
+        for (i, sample) in samples[..count].iter().enumerate() {
+            let slot = &self.shared.buffer[(write.wrapping_add(i)) % capacity];
+            // SAFETY: slots in [write, read + capacity) belong to the producer
+            unsafe { *slot.get() = *sample };
+        }
+
+        self.shared
+            .write
+            .store(write.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+/// Reading half of the ring, owned by the output callback
+pub struct RingConsumer {
+    shared: Arc<RingShared>,
+}
+
+impl RingConsumer {
+    /// Samples ready to be read
+    pub fn available(&self) -> usize {
+        let write = self.shared.write.load(Ordering::Acquire);
+        let read = self.shared.read.load(Ordering::Relaxed);
+        write.wrapping_sub(read)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.available() == 0
+    }
+
+    /// Fill `out` from the ring, returning how many samples were written
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.available());
+        let capacity = self.shared.capacity();
+        let read = self.shared.read.load(Ordering::Relaxed);
+
+        for (i, dst) in out[..count].iter_mut().enumerate() {
+            let slot = &self.shared.buffer[(read.wrapping_add(i)) % capacity];
+            // SAFETY: slots in [read, write) belong to the consumer
+            *dst = unsafe { *slot.get() };
+        }
+
+        self.shared
+            .read
+            .store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Discard everything currently readable, returning how many samples were dropped
+    pub fn clear(&mut self) -> usize {
+        let count = self.available();
+        let read = self.shared.read.load(Ordering::Relaxed);
+        self.shared
+            .read
+            .store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
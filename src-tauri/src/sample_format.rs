@@ -0,0 +1,175 @@
+//! Sample Format Module
+//! Conversion of every sample format symphonia decodes to the engine's interleaved f32.
+//! Integer formats are scaled by a power of two, so sources of up to 24 bits convert exactly.
+
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::sample::{Sample, SampleFormat};
+
+/// Append a decoded buffer to `out` as interleaved f32 samples
+pub fn append_interleaved(decoded: &AudioBufferRef, out: &mut Vec<f32>) {
+    match decoded {
+        AudioBufferRef::U8(buf) => interleave(buf, out),
+        AudioBufferRef::U16(buf) => interleave(buf, out),
+        AudioBufferRef::U24(buf) => interleave(buf, out),
+        AudioBufferRef::U32(buf) => interleave(buf, out),
+        AudioBufferRef::S8(buf) => interleave(buf, out),
+        AudioBufferRef::S16(buf) => interleave(buf, out),
+        AudioBufferRef::S24(buf) => interleave(buf, out),
+        AudioBufferRef::S32(buf) => interleave(buf, out),
+        AudioBufferRef::F32(buf) => interleave(buf, out),
+        AudioBufferRef::F64(buf) => interleave(buf, out),
+    }
+}
+
+fn interleave<S: Sample + IntoSample<f32>>(buf: &AudioBuffer<S>, out: &mut Vec<f32>) {
+    let channels = buf.spec().channels.count();
+    out.reserve(buf.frames() * channels);
+    for frame in 0..buf.frames() {
+        for ch in 0..channels {
+            out.push(buf.chan(ch)[frame].into_sample());
+        }
+    }
+}
+
+/// Width in bits of a sample format
+pub fn format_bits(format: SampleFormat) -> u16 {
+    match format {
+        SampleFormat::U8 | SampleFormat::S8 => 8,
+        SampleFormat::U16 | SampleFormat::S16 => 16,
+        SampleFormat::U24 | SampleFormat::S24 => 24,
+        SampleFormat::U32 | SampleFormat::S32 | SampleFormat::F32 => 32,
+        SampleFormat::F64 => 64,
+    }
+}
+
+/// Bit depth of a source: what the codec declares, else the width of the samples it
+/// decodes to. Decoders that declare neither (the lossy ones) produce f32.
+pub fn source_bit_depth(bits_per_sample: Option<u32>, sample_format: Option<SampleFormat>) -> u16 {
+    bits_per_sample
+        .map(|bits| bits as u16)
+        .or(sample_format.map(format_bits))
+        .unwrap_or(32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use symphonia::core::audio::{Channels, SignalSpec};
+    use symphonia::core::sample::{i24, u24};
+
+    /// A stereo buffer holding `left` and `right`
+    fn stereo<S: Sample>(left: &[S], right: &[S]) -> Cow<'static, AudioBuffer<S>> {
+        let spec = SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buf = AudioBuffer::<S>::new(left.len() as u64, spec);
+        buf.render_reserved(Some(left.len()));
+        buf.chan_mut(0).copy_from_slice(left);
+        buf.chan_mut(1).copy_from_slice(right);
+        Cow::Owned(buf)
+    }
+
+    fn convert(decoded: AudioBufferRef) -> Vec<f32> {
+        let mut out = Vec::new();
+        append_interleaved(&decoded, &mut out);
+        out
+    }
+
+    #[test]
+    fn u8_is_offset_binary() {
+        let out = convert(AudioBufferRef::U8(stereo(
+            &[0u8, 128, 255],
+            &[64u8, 192, 128],
+        )));
+        assert_eq!(out, vec![-1.0, -0.5, 0.0, 0.5, 127.0 / 128.0, 0.0]);
+    }
+
+    #[test]
+    fn u16_is_offset_binary() {
+        let out = convert(AudioBufferRef::U16(stereo(
+            &[0u16, 32768],
+            &[65535u16, 16384],
+        )));
+        assert_eq!(out, vec![-1.0, 32767.0 / 32768.0, 0.0, -0.5]);
+    }
+
+    #[test]
+    fn u24_is_offset_binary() {
+        let out = convert(AudioBufferRef::U24(stereo(
+            &[u24(0), u24(0x80_0000)],
+            &[u24(0xFF_FFFF), u24(0x40_0000)],
+        )));
+        assert_eq!(out, vec![-1.0, 8_388_607.0 / 8_388_608.0, 0.0, -0.5]);
+    }
+
+    #[test]
+    fn u32_is_offset_binary() {
+        let out = convert(AudioBufferRef::U32(stereo(
+            &[0u32, 0x8000_0000],
+            &[0xC000_0000u32, 0],
+        )));
+        assert_eq!(out, vec![-1.0, 0.5, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn s8_scales_by_128() {
+        let out = convert(AudioBufferRef::S8(stereo(&[i8::MIN, 0], &[64i8, -32])));
+        assert_eq!(out, vec![-1.0, 0.5, 0.0, -0.25]);
+    }
+
+    #[test]
+    fn s16_is_exact() {
+        let samples: Vec<i16> = vec![i16::MIN, -1, 0, 1, i16::MAX];
+        let out = convert(AudioBufferRef::S16(stereo(&samples, &samples)));
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(out[i * 2], *sample as f32 / 32768.0);
+            assert_eq!((out[i * 2 + 1] * 32768.0) as i16, *sample);
+        }
+    }
+
+    #[test]
+    fn s24_is_exact() {
+        let samples = [-8_388_608, -1, 0, 1, 8_388_607];
+        let buf: Vec<i24> = samples.iter().map(|s| i24(*s)).collect();
+        let out = convert(AudioBufferRef::S24(stereo(&buf, &buf)));
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!((out[i * 2] * 8_388_608.0) as i32, *sample);
+        }
+    }
+
+    #[test]
+    fn s32_keeps_24_significant_bits() {
+        // FLAC and ALAC deliver 24-bit audio left-justified in S32
+        let samples = [i32::MIN, -256, 0, 256, 8_388_607 << 8];
+        let out = convert(AudioBufferRef::S32(stereo(&samples, &samples)));
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!((out[i * 2] as f64 * 2_147_483_648.0) as i32, *sample);
+        }
+    }
+
+    #[test]
+    fn f32_passes_through() {
+        let out = convert(AudioBufferRef::F32(stereo(
+            &[0.25f32, -1.0],
+            &[0.5f32, 1.0],
+        )));
+        assert_eq!(out, vec![0.25, 0.5, -1.0, 1.0]);
+    }
+
+    #[test]
+    fn f64_is_narrowed() {
+        let out = convert(AudioBufferRef::F64(stereo(
+            &[0.25f64, -0.125],
+            &[0.1f64, 1.0],
+        )));
+        assert_eq!(out, vec![0.25, 0.1f32, -0.125, 1.0]);
+    }
+
+    #[test]
+    fn bit_depth_falls_back_to_sample_format() {
+        assert_eq!(source_bit_depth(Some(24), Some(SampleFormat::S32)), 24);
+        assert_eq!(source_bit_depth(None, Some(SampleFormat::U8)), 8);
+        assert_eq!(source_bit_depth(None, Some(SampleFormat::F64)), 64);
+        assert_eq!(source_bit_depth(None, None), 32);
+    }
+}
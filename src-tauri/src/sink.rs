@@ -0,0 +1,314 @@
+//! Sink Module
+//! Outputs that need no sound card: a null sink that discards the audio, and a file sink
+//! that writes raw PCM or WAV to a file or a named pipe, such as the FIFO a Snapcast server
+//! reads from. A thread pulls from the output renderer at real-time pace, so position,
+//! track ends and queue advance behave as they do on a device.
+
+use crate::audio::AudioError;
+use crate::events::{EventSink, PlaybackEvent};
+use crate::render::{RenderFormat, RenderOptions, RenderWriter};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Frames rendered per period when no buffer size is set
+pub const SINK_PERIOD_FRAMES: u32 = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputBackend {
+    /// The sound card chosen in the device settings
+    #[default]
+    Device,
+    /// Plays in real time and discards the audio
+    Null,
+    /// Plays in real time into a file or FIFO
+    File,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkContainer {
+    /// Headerless interleaved samples, as Snapcast's pipe source takes them
+    #[default]
+    Raw,
+    Wav,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutputSinkSettings {
+    pub backend: OutputBackend,
+    pub path: Option<String>, // File or FIFO the file sink writes to
+    pub container: SinkContainer,
+    pub bit_depth: u16,           // 16 or 24, or 32 for float
+    pub sample_rate: Option<u32>, // None follows the source
+    pub channels: Option<u16>,    // None follows the source
+}
+
+impl Default for OutputSinkSettings {
+    fn default() -> Self {
+        Self {
+            backend: OutputBackend::Device,
+            path: None,
+            container: SinkContainer::Raw,
+            bit_depth: 16,
+            sample_rate: None,
+            channels: None,
+        }
+    }
+}
+
+impl OutputSinkSettings {
+    pub fn clamped(self) -> Self {
+        Self {
+            bit_depth: match self.bit_depth {
+                24 => 24,
+                32 => 32,
+                _ => 16,
+            },
+            sample_rate: self.sample_rate.map(|r| r.clamp(8_000, 768_000)),
+            channels: self.channels.map(|c| c.clamp(1, 8)),
+            ..self
+        }
+    }
+
+    /// Playback goes to a sink rather than a device
+    pub fn is_virtual(&self) -> bool {
+        self.backend != OutputBackend::Device
+    }
+
+    /// Shown as the output device
+    pub fn label(&self) -> String {
+        match (self.backend, &self.path) {
+            (OutputBackend::File, Some(path)) => format!("File: {}", path),
+            _ => "Null output".to_string(),
+        }
+    }
+
+    /// Sample rate and channel count of the output for a source
+    pub fn format(&self, sample_rate: u32, channels: u16) -> (u32, u16) {
+        (
+            self.sample_rate.unwrap_or(sample_rate),
+            self.channels.unwrap_or(channels),
+        )
+    }
+
+    /// Sample format as the playback state names it. The null sink keeps the
+    /// processing format.
+    pub fn sample_format(&self) -> &'static str {
+        match (self.backend, self.bit_depth) {
+            (OutputBackend::File, 16) => "i16",
+            (OutputBackend::File, 24) => "i24",
+            _ => "f32",
+        }
+    }
+
+    /// Width of an integer output format, 0 for float
+    pub fn integer_bits(&self) -> u16 {
+        match self.sample_format() {
+            "f32" => 0,
+            _ => self.bit_depth,
+        }
+    }
+
+    /// Width to dither to, None for float
+    pub fn dither_bits(&self) -> Option<u32> {
+        Some(self.integer_bits() as u32).filter(|bits| *bits > 0)
+    }
+
+    fn file_target(&self, sample_rate: u32, channels: u16) -> Option<FileTarget> {
+        let path = self
+            .path
+            .clone()
+            .filter(|_| self.backend == OutputBackend::File)?;
+        Some(FileTarget {
+            path,
+            options: RenderOptions {
+                format: match self.container {
+                    SinkContainer::Raw => RenderFormat::Raw,
+                    SinkContainer::Wav => RenderFormat::Wav,
+                },
+                bit_depth: self.bit_depth,
+                sample_rate: None,
+                channels: None,
+            },
+            sample_rate,
+            channels,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FileTarget {
+    path: String,
+    options: RenderOptions,
+    sample_rate: u32,
+    channels: u16,
+}
+
+/// The file sink's open file. It outlives the stream writing to it, so consecutive tracks
+/// go into one file for as long as the format stays the same.
+pub struct SinkFile {
+    writer: RenderWriter,
+    target: FileTarget,
+}
+
+impl SinkFile {
+    /// `previous` if it is the file asked for, else a new one. Opening a FIFO blocks until
+    /// something reads from it.
+    fn open(previous: Option<SinkFile>, target: FileTarget) -> Result<Self, AudioError> {
+        if let Some(file) = previous {
+            if file.target == target {
+                return Ok(file);
+            }
+            file.finish();
+        }
+        println!(
+            "[Audio] Writing {}Hz/{}ch/{}-bit to {}",
+            target.sample_rate, target.channels, target.options.bit_depth, target.path
+        );
+        let writer = RenderWriter::create(
+            &target.path,
+            &target.options,
+            target.sample_rate,
+            target.channels,
+        )?;
+        Ok(Self { writer, target })
+    }
+
+    /// Fix up the header of a WAV file
+    pub fn finish(self) {
+        match self.writer.finish() {
+            Ok(summary) => println!(
+                "[Audio] Wrote {} frames to {}",
+                summary.frames, summary.path
+            ),
+            Err(e) => log::error!("Failed to complete {}: {}", self.target.path, e),
+        }
+    }
+}
+
+/// Output stream of a sink. The thread stops when this is dropped.
+pub struct SinkStream {
+    stop: Arc<AtomicBool>,
+    /// The file is open, so the thread is rendering or about to
+    opened: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Option<SinkFile>>>,
+}
+
+impl SinkStream {
+    /// Call `render` with periods of `period_frames` at real-time pace. It is told whether
+    /// the period comes late, and returns whether the period is to be written: a paused
+    /// stream plays silence but leaves nothing in the file.
+    pub fn start<F>(
+        settings: &OutputSinkSettings,
+        previous: Option<SinkFile>,
+        sample_rate: u32,
+        channels: u16,
+        period_frames: u32,
+        events: EventSink,
+        mut render: F,
+    ) -> Result<Self, AudioError>
+    where
+        F: FnMut(&mut [f32], bool) -> bool + Send + 'static,
+    {
+        let target = settings.file_target(sample_rate, channels);
+        if settings.backend == OutputBackend::File && target.is_none() {
+            return Err(AudioError::Write("No file to write to".to_string()));
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let opened = Arc::new(AtomicBool::new(false));
+        let period = Duration::from_secs_f64(period_frames as f64 / sample_rate.max(1) as f64);
+        let mut buffer = vec![0.0f32; period_frames as usize * channels.max(1) as usize];
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let opened = Arc::clone(&opened);
+            thread::Builder::new()
+                .name("output-sink".to_string())
+                .spawn(move || {
+                    let mut file = match target {
+                        Some(target) => match SinkFile::open(previous, target) {
+                            Ok(file) => Some(file),
+                            Err(e) => {
+                                log::error!("Output file error: {}", e);
+                                events(PlaybackEvent::Error {
+                                    message: e.to_string(),
+                                });
+                                None
+                            }
+                        },
+                        None => {
+                            if let Some(previous) = previous {
+                                previous.finish();
+                            }
+                            None
+                        }
+                    };
+                    // Stopped while waiting for a FIFO reader: the stream was not waited for,
+                    // so nothing may be rendered any more
+                    opened.store(true, Ordering::SeqCst);
+                    if stop.load(Ordering::SeqCst) {
+                        return file;
+                    }
+
+                    let mut deadline = Instant::now();
+                    while !stop.load(Ordering::SeqCst) {
+                        // More than a period behind is what a device would have run dry on
+                        let late = Instant::now() > deadline + period;
+                        if late {
+                            deadline = Instant::now();
+                        }
+                        if render(&mut buffer, late) {
+                            let written = file
+                                .as_mut()
+                                .map(|f| f.writer.write(&buffer).and_then(|_| f.writer.flush()));
+                            if let Some(Err(e)) = written {
+                                // Playback carries on as on the null sink
+                                log::error!("Output file error: {}", e);
+                                events(PlaybackEvent::Error {
+                                    message: e.to_string(),
+                                });
+                                file = None;
+                            }
+                        }
+                        deadline += period;
+                        if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                            thread::sleep(wait);
+                        }
+                    }
+                    file
+                })
+                .map_err(|e| AudioError::StreamBuild(e.to_string()))?
+        };
+
+        Ok(Self {
+            stop,
+            opened,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop rendering, returning the file to write the next stream into
+    pub fn stop(mut self) -> Option<SinkFile> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> Option<SinkFile> {
+        self.stop.store(true, Ordering::SeqCst);
+        let thread = self.thread.take()?;
+        // A thread still waiting for a FIFO reader is left to notice the stop by itself
+        if !self.opened.load(Ordering::SeqCst) {
+            return None;
+        }
+        thread.join().ok().flatten()
+    }
+}
+
+impl Drop for SinkStream {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
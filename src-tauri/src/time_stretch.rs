@@ -0,0 +1,335 @@
+//! Time Stretch Module
+//! Playback speed and pitch for the decode path. Tempo changes that keep the pitch use
+//! WSOLA: overlapping segments of the source are spliced together at the offset where they
+//! match best, so waveforms line up across each splice. Pitch moves by resampling.
+
+use crate::audio::AudioError;
+use crate::resampler::{ResamplerQuality, StreamResampler};
+use serde::{Deserialize, Serialize};
+
+/// Length of a WSOLA segment; consecutive segments overlap by half
+const SEGMENT_SECONDS: f64 = 0.04;
+/// How far a segment may move from its nominal position to match the previous one
+const TOLERANCE_SECONDS: f64 = 0.01;
+/// Rate the similarity search looks at; higher rates are searched on every n-th frame
+const SEARCH_RATE: u32 = 11025;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedMode {
+    /// Tempo changes, pitch stays
+    #[default]
+    PreservePitch,
+    /// Pitch moves with tempo, like a turntable
+    Varispeed,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SpeedSettings {
+    pub speed: f64, // Tempo factor, 0.5-2.0
+    pub mode: SpeedMode,
+    pub pitch: f64, // Pitch shift in semitones on top of the mode, -12 to 12
+}
+
+impl Default for SpeedSettings {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            mode: SpeedMode::PreservePitch,
+            pitch: 0.0,
+        }
+    }
+}
+
+impl SpeedSettings {
+    pub fn clamped(self) -> Self {
+        Self {
+            speed: self.speed.clamp(0.5, 2.0),
+            pitch: self.pitch.clamp(-12.0, 12.0),
+            ..self
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.speed != 1.0 || self.pitch != 0.0
+    }
+
+    /// Factor the pitch is raised by
+    fn pitch_factor(&self) -> f64 {
+        let varispeed = match self.mode {
+            SpeedMode::PreservePitch => 1.0,
+            SpeedMode::Varispeed => self.speed,
+        };
+        varispeed * 2f64.powf(self.pitch / 12.0)
+    }
+}
+
+/// Speed and pitch change of interleaved audio: a WSOLA time stretch followed by a
+/// resampler that plays the stretched audio faster or slower
+pub struct SpeedProcessor {
+    stretch: Option<Wsola>,
+    resampler: Option<StreamResampler>,
+    speed: f64,
+}
+
+impl SpeedProcessor {
+    /// None when the settings leave the audio untouched
+    pub fn new(
+        settings: &SpeedSettings,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Option<Self>, AudioError> {
+        if !settings.is_active() {
+            return Ok(None);
+        }
+        // Declaring the audio to be at a higher rate than it plays at raises its pitch
+        let from_rate = (sample_rate as f64 * settings.pitch_factor()).round() as u32;
+        let resampler = if from_rate != sample_rate {
+            // Arbitrary ratios need the sinc filter, the FFT resampler wants common divisors
+            Some(StreamResampler::new(
+                from_rate,
+                sample_rate,
+                channels,
+                ResamplerQuality::HighQuality,
+            )?)
+        } else {
+            None
+        };
+        let tempo = settings.speed * sample_rate as f64 / from_rate as f64;
+        let stretch = if (tempo - 1.0).abs() > 1e-6 {
+            Some(Wsola::new(tempo, sample_rate, channels))
+        } else {
+            None
+        };
+        Ok(Some(Self {
+            stretch,
+            resampler,
+            speed: settings.speed,
+        }))
+    }
+
+    /// Source frames each output frame stands for
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        match (self.stretch.as_mut(), self.resampler.as_mut()) {
+            (Some(stretch), Some(resampler)) => {
+                let mut stretched = Vec::new();
+                stretch.process(samples, &mut stretched);
+                resampler.process(&stretched, out);
+            }
+            (Some(stretch), None) => stretch.process(samples, out),
+            (None, Some(resampler)) => resampler.process(samples, out),
+            (None, None) => out.extend_from_slice(samples),
+        }
+    }
+
+    /// Push out everything still buffered at the end of the stream and start over
+    pub fn flush(&mut self, out: &mut Vec<f32>) {
+        let mut stretched = Vec::new();
+        if let Some(stretch) = self.stretch.as_mut() {
+            stretch.flush(&mut stretched);
+        }
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                resampler.process(&stretched, out);
+                resampler.flush(out);
+                resampler.reset();
+            }
+            None => out.extend_from_slice(&stretched),
+        }
+    }
+
+    /// Discard buffered audio (after a seek)
+    pub fn reset(&mut self) {
+        if let Some(stretch) = self.stretch.as_mut() {
+            stretch.reset();
+        }
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.reset();
+        }
+    }
+}
+
+/// Waveform-similarity overlap-add. Every step outputs `hop` frames: the second half of the
+/// previous segment faded out over the first half of a new one faded in. The new segment
+/// starts near where the tempo says it should, at the offset whose audio continues the
+/// previous segment most closely.
+struct Wsola {
+    channels: usize,
+    tempo: f64,
+    segment: usize,
+    hop: usize,
+    tolerance: usize,
+    stride: usize,
+    window: Vec<f32>, // Periodic Hann, its two halves sum to one
+    input: Vec<f32>,
+    input_start: u64, // Frame of the stream `input` starts at
+    position: f64,    // Nominal start frame of the next segment
+    /// Frame where the audio following the previous segment's first half starts, which
+    /// the next segment should resemble. None before the first segment.
+    template: Option<u64>,
+    overlap: Vec<f32>, // Faded out second half of the previous segment
+    frames_in: u64,
+    frames_out: u64,
+}
+
+impl Wsola {
+    fn new(tempo: f64, sample_rate: u32, channels: usize) -> Self {
+        let hop = ((sample_rate as f64 * SEGMENT_SECONDS) as usize / 2).max(1);
+        let segment = hop * 2;
+        let step = std::f64::consts::TAU / segment as f64;
+        Self {
+            channels,
+            tempo,
+            segment,
+            hop,
+            tolerance: (sample_rate as f64 * TOLERANCE_SECONDS) as usize,
+            stride: (sample_rate / SEARCH_RATE).max(1) as usize,
+            window: (0..segment)
+                .map(|i| (0.5 - 0.5 * (step * i as f64).cos()) as f32)
+                .collect(),
+            input: Vec::new(),
+            input_start: 0,
+            position: 0.0,
+            template: None,
+            overlap: vec![0.0; hop * channels],
+            frames_in: 0,
+            frames_out: 0,
+        }
+    }
+
+    fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        self.input.extend_from_slice(samples);
+        self.frames_in += (samples.len() / self.channels) as u64;
+        while self.step(out) {}
+    }
+
+    /// Run the remaining input through, padded with silence, until the output is as long
+    /// as the input at this tempo
+    fn flush(&mut self, out: &mut Vec<f32>) {
+        let expected = (self.frames_in as f64 / self.tempo).round() as u64;
+        let padding = (self.segment + 2 * self.tolerance) * self.channels;
+        while self.frames_out < expected {
+            self.input.resize(self.input.len() + padding, 0.0);
+            while self.frames_out < expected && self.step(out) {}
+        }
+        let excess = (self.frames_out - expected) as usize * self.channels;
+        out.truncate(out.len().saturating_sub(excess));
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.input_start = 0;
+        self.position = 0.0;
+        self.template = None;
+        self.overlap.fill(0.0);
+        self.frames_in = 0;
+        self.frames_out = 0;
+    }
+
+    fn input_end(&self) -> u64 {
+        self.input_start + (self.input.len() / self.channels) as u64
+    }
+
+    /// Output one hop if enough input is buffered
+    fn step(&mut self, out: &mut Vec<f32>) -> bool {
+        let nominal = self.position.round() as u64;
+        let (low, high) = match self.template {
+            Some(_) => (
+                nominal
+                    .saturating_sub(self.tolerance as u64)
+                    .max(self.input_start),
+                nominal + self.tolerance as u64,
+            ),
+            None => (nominal, nominal),
+        };
+        if self.input_end() < high + self.segment as u64 {
+            return false;
+        }
+
+        let start = match self.template {
+            Some(template) => self.best_start(template, low, high),
+            None => {
+                // Fade the first segment in over itself, so output starts with the input as is
+                let first = self.offset(nominal);
+                for (i, sample) in self.overlap.iter_mut().enumerate() {
+                    *sample = self.input[first + i] * self.window[self.hop + i / self.channels];
+                }
+                nominal
+            }
+        };
+
+        let first = self.offset(start);
+        out.reserve(self.hop * self.channels);
+        for i in 0..self.hop * self.channels {
+            let fade_in = self.input[first + i] * self.window[i / self.channels];
+            out.push(self.overlap[i] + fade_in);
+        }
+        let second = first + self.hop * self.channels;
+        for (i, sample) in self.overlap.iter_mut().enumerate() {
+            *sample = self.input[second + i] * self.window[self.hop + i / self.channels];
+        }
+        self.frames_out += self.hop as u64;
+
+        self.template = Some(start + self.hop as u64);
+        self.position += self.hop as f64 * self.tempo;
+
+        // Keep what the next search and its template can still reach
+        let keep = (start + self.hop as u64)
+            .min((self.position as u64).saturating_sub(self.tolerance as u64))
+            .max(self.input_start);
+        self.input.drain(..self.offset(keep));
+        self.input_start = keep;
+        true
+    }
+
+    /// Index in `input` of the first sample of `frame`
+    fn offset(&self, frame: u64) -> usize {
+        (frame - self.input_start) as usize * self.channels
+    }
+
+    /// The segment start in `low..=high` whose first half best matches the audio at
+    /// `template`: a coarse search on every `stride`-th offset, then a fine one around it
+    fn best_start(&self, template: u64, low: u64, high: u64) -> u64 {
+        let stride = self.stride as u64;
+        let mut best = (low, f32::MIN);
+        let mut candidate = low;
+        while candidate <= high {
+            let score = self.similarity(candidate, template);
+            if score > best.1 {
+                best = (candidate, score);
+            }
+            candidate += stride;
+        }
+        let coarse = best.0;
+        for candidate in
+            coarse.saturating_sub(stride - 1).max(low)..=(coarse + stride - 1).min(high)
+        {
+            let score = self.similarity(candidate, template);
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        best.0
+    }
+
+    /// Cross-correlation of the channel mix at `candidate` with that at `template` over half
+    /// a segment, normalized by the candidate's energy
+    fn similarity(&self, candidate: u64, template: u64) -> f32 {
+        let a = self.offset(candidate);
+        let b = self.offset(template);
+        let (mut cross, mut energy) = (0.0f32, 0.0f32);
+        for frame in (0..self.hop).step_by(self.stride) {
+            let i = frame * self.channels;
+            let x: f32 = self.input[a + i..a + i + self.channels].iter().sum();
+            let y: f32 = self.input[b + i..b + i + self.channels].iter().sum();
+            cross += x * y;
+            energy += x * x;
+        }
+        cross / (energy.sqrt() + 1e-9)
+    }
+}
@@ -0,0 +1,292 @@
+//! Visualization Module
+//! Spectrum and level meter data of the audio as it goes to the device, after resampling
+//! and DSP. The output callback copies what it renders into a tap ring and the analyzer
+//! thread here reads it at a fixed frame rate, so the real-time thread only does a copy.
+
+use crate::events::{EventSink, PlaybackEvent};
+use crate::ring_buffer::{sample_ring, RingConsumer, RingProducer};
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Seconds of output the tap ring holds; the analyzer empties it every frame
+const TAP_SECONDS: f64 = 0.5;
+/// Floor of the reported magnitudes and levels
+const SILENCE_DB: f32 = -120.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowFunction {
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl WindowFunction {
+    fn coefficients(self, size: usize) -> Vec<f32> {
+        let step = 2.0 * std::f64::consts::PI / size as f64;
+        (0..size)
+            .map(|i| {
+                let x = step * i as f64;
+                let w = match self {
+                    WindowFunction::Rectangular => 1.0,
+                    WindowFunction::Hann => 0.5 - 0.5 * x.cos(),
+                    WindowFunction::Hamming => 0.54 - 0.46 * x.cos(),
+                    WindowFunction::BlackmanHarris => {
+                        0.35875 - 0.48829 * x.cos() + 0.14128 * (2.0 * x).cos()
+                            - 0.01168 * (3.0 * x).cos()
+                    }
+                };
+                w as f32
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct VisualizerSettings {
+    pub enabled: bool,   // Only analyzed while a view shows the data
+    pub fft_size: usize, // Power of two, 256-16384
+    pub window: WindowFunction,
+    pub frame_rate: u32, // Frames per second, 10-60
+}
+
+impl Default for VisualizerSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fft_size: 2048,
+            window: WindowFunction::Hann,
+            frame_rate: 30,
+        }
+    }
+}
+
+impl VisualizerSettings {
+    pub fn clamped(self) -> Self {
+        Self {
+            fft_size: self.fft_size.clamp(256, 16384).next_power_of_two(),
+            frame_rate: self.frame_rate.clamp(10, 60),
+            ..self
+        }
+    }
+}
+
+/// Level of one output channel since the previous frame
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelLevel {
+    pub peak_db: f32,
+    pub rms_db: f32,
+    pub clipped: bool, // A sample reached full scale
+}
+
+/// Payload of the "visualizer-frame" event
+#[derive(Clone, Debug, Serialize)]
+pub struct VisualizerFrame {
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    pub bins: Vec<f32>, // dBFS of each FFT bin from 0Hz to Nyquist, channels mixed
+    pub levels: Vec<ChannelLevel>,
+    pub clipped: bool, // Any channel clipped
+}
+
+/// Reading end of the tap on one output stream
+pub struct Tap {
+    consumer: RingConsumer,
+    sample_rate: u32,
+    channels: usize,
+}
+
+/// Create the tap of a new output stream. The producer goes to the output callback,
+/// the tap to `Visualizer::attach`.
+pub fn tap(sample_rate: u32, channels: u16) -> (RingProducer, Tap) {
+    let channels = channels.max(1) as usize;
+    let (producer, consumer) = sample_ring((sample_rate as f64 * TAP_SECONDS) as usize * channels);
+    (
+        producer,
+        Tap {
+            consumer,
+            sample_rate,
+            channels,
+        },
+    )
+}
+
+enum VisualizerCommand {
+    Attach(Tap),
+    Settings(VisualizerSettings),
+}
+
+/// Handle to the analyzer thread, which stops once every handle is dropped
+#[derive(Clone)]
+pub struct Visualizer {
+    tx: mpsc::Sender<VisualizerCommand>,
+}
+
+impl Visualizer {
+    pub fn spawn(events: EventSink) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || Analyzer::new(events).run(rx));
+        Self { tx }
+    }
+
+    /// Analyze the stream `tap` belongs to from now on
+    pub fn attach(&self, tap: Tap) {
+        let _ = self.tx.send(VisualizerCommand::Attach(tap));
+    }
+
+    pub fn set_settings(&self, settings: VisualizerSettings) {
+        let _ = self.tx.send(VisualizerCommand::Settings(settings));
+    }
+}
+
+struct Analyzer {
+    settings: VisualizerSettings,
+    tap: Option<Tap>,
+    events: EventSink,
+    samples: Vec<f32>, // Interleaved samples read from the tap this frame
+    history: Vec<f32>, // Channel mix of the latest `fft_size` frames, oldest first
+    window: Vec<f32>,
+    window_sum: f32,
+    planner: FftPlanner<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    spectrum: Vec<Complex<f32>>,
+}
+
+impl Analyzer {
+    fn new(events: EventSink) -> Self {
+        let settings = VisualizerSettings::default();
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(settings.fft_size);
+        let window = settings.window.coefficients(settings.fft_size);
+        Self {
+            settings,
+            tap: None,
+            events,
+            samples: Vec::new(),
+            history: Vec::new(),
+            window_sum: window.iter().sum(),
+            window,
+            planner,
+            fft,
+            spectrum: Vec::new(),
+        }
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<VisualizerCommand>) {
+        let mut next_frame = Instant::now();
+        loop {
+            let timeout = next_frame.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(timeout) {
+                Ok(VisualizerCommand::Attach(tap)) => {
+                    self.tap = Some(tap);
+                    self.history.clear();
+                }
+                Ok(VisualizerCommand::Settings(settings)) => self.set_settings(settings),
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    next_frame += Duration::from_secs(1) / self.settings.frame_rate;
+                    // Do not try to catch up after falling behind
+                    next_frame = next_frame.max(Instant::now());
+                    if self.settings.enabled {
+                        self.frame();
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    fn set_settings(&mut self, settings: VisualizerSettings) {
+        if settings.fft_size != self.settings.fft_size || settings.window != self.settings.window {
+            self.fft = self.planner.plan_fft_forward(settings.fft_size);
+            self.window = settings.window.coefficients(settings.fft_size);
+            self.window_sum = self.window.iter().sum();
+        }
+        self.settings = settings;
+    }
+
+    /// Analyze what the device was given since the last frame
+    fn frame(&mut self) {
+        let Some(tap) = self.tap.as_mut() else {
+            return;
+        };
+        let available = tap.consumer.available();
+        let available = available - available % tap.channels;
+        if available == 0 {
+            return;
+        }
+        self.samples.resize(available, 0.0);
+        tap.consumer.pop(&mut self.samples);
+
+        let channels = tap.channels;
+        let levels: Vec<ChannelLevel> = (0..channels)
+            .map(|ch| {
+                let (peak, sum) = self
+                    .samples
+                    .iter()
+                    .skip(ch)
+                    .step_by(channels)
+                    .fold((0.0f32, 0.0f64), |(peak, sum), s| {
+                        (peak.max(s.abs()), sum + (*s as f64) * (*s as f64))
+                    });
+                let frames = available / channels;
+                ChannelLevel {
+                    peak_db: to_db(peak),
+                    rms_db: to_db((sum / frames as f64).sqrt() as f32),
+                    clipped: peak >= 1.0,
+                }
+            })
+            .collect();
+
+        let size = self.settings.fft_size;
+        self.history.extend(
+            self.samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        if self.history.len() > size {
+            self.history.drain(..self.history.len() - size);
+        }
+
+        // Too little history yet: the missing oldest frames count as silence
+        let padding = size - self.history.len();
+        self.spectrum.clear();
+        self.spectrum.resize(padding, Complex::default());
+        self.spectrum.extend(
+            self.history
+                .iter()
+                .zip(&self.window[padding..])
+                .map(|(s, w)| Complex::new(s * w, 0.0)),
+        );
+        self.fft.process(&mut self.spectrum);
+
+        // A full-scale sine at a bin's frequency reads 0dB whatever the window
+        let scale = 2.0 / self.window_sum;
+        let bins = self.spectrum[..=size / 2]
+            .iter()
+            .map(|c| to_db(c.norm() * scale))
+            .collect();
+
+        (self.events)(PlaybackEvent::Visualization(VisualizerFrame {
+            sample_rate: tap.sample_rate,
+            fft_size: size,
+            bins,
+            clipped: levels.iter().any(|l| l.clipped),
+            levels,
+        }));
+    }
+}
+
+fn to_db(linear: f32) -> f32 {
+    if linear > 0.0 {
+        (20.0 * linear.log10()).max(SILENCE_DB)
+    } else {
+        SILENCE_DB
+    }
+}
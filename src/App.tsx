@@ -11,6 +11,7 @@ import AlbumDetailPage from "./pages/AlbumDetailPage";
 import ArtistDetailPage from "./pages/ArtistDetailPage";
 import SearchPage from "./pages/SearchPage";
 import SettingsPage from "./pages/SettingsPage";
+import AnalyzerPage from "./pages/AnalyzerPage";
 import StatisticsPage from "./pages/StatisticsPage";
 import SmartPlaylistPage from "./pages/SmartPlaylistPage";
 import AuthPage from "./pages/AuthPage";
@@ -78,6 +79,7 @@ function App() {
               <Route path="library" element={<LibraryPage />} />
               <Route path="search" element={<SearchPage />} />
               <Route path="settings" element={<SettingsPage />} />
+              <Route path="analyzer" element={<AnalyzerPage />} />
               <Route path="profile" element={<ProfileSettingsPage />} />
               <Route path="statistics" element={<StatisticsPage />} />
               <Route
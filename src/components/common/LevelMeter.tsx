@@ -0,0 +1,51 @@
+import { useEffect } from "react";
+import { clsx } from "clsx";
+import { useVisualizerStore } from "../../stores/visualizerStore";
+
+// Lowest level shown, in dBFS
+const FLOOR_DB = -60;
+
+const toPercent = (db: number) =>
+  Math.max(0, Math.min(100, ((db - FLOOR_DB) / -FLOOR_DB) * 100));
+
+interface LevelMeterProps {
+  className?: string;
+}
+
+// Peak and RMS of each output channel, with a clip indicator
+export default function LevelMeter({ className }: LevelMeterProps) {
+  const { frame, subscribe } = useVisualizerStore();
+
+  useEffect(() => subscribe(), [subscribe]);
+
+  const levels = frame?.levels ?? [];
+
+  return (
+    <div className={clsx("flex items-center gap-1.5", className)}>
+      <div className="flex flex-col gap-0.5 w-20">
+        {levels.map((level, channel) => (
+          <div
+            key={channel}
+            className="h-1 bg-amoled-hover rounded-full overflow-hidden relative"
+          >
+            <div
+              className="absolute inset-y-0 left-0 bg-[#1DB954]/50"
+              style={{ width: `${toPercent(level.peak_db)}%` }}
+            />
+            <div
+              className="absolute inset-y-0 left-0 bg-[#1DB954]"
+              style={{ width: `${toPercent(level.rms_db)}%` }}
+            />
+          </div>
+        ))}
+      </div>
+      <span
+        title="Clipping"
+        className={clsx(
+          "w-1.5 h-1.5 rounded-full",
+          frame?.clipped ? "bg-red-500" : "bg-amoled-hover",
+        )}
+      />
+    </div>
+  );
+}
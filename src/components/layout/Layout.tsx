@@ -28,8 +28,8 @@ export default function Layout() {
   const loadRecentlyPlayed = useLibraryStore(
     (state) => state.loadRecentlyPlayed,
   );
-  const updatePlaybackState = usePlayerStore(
-    (state) => state.updatePlaybackState,
+  const subscribeToPlaybackEvents = usePlayerStore(
+    (state) => state.subscribeToPlaybackEvents,
   );
   const playbackState = usePlayerStore((state) => state.playbackState);
   const {
@@ -52,11 +52,13 @@ export default function Layout() {
     loadRecentlyPlayed();
   }, [loadLibrary, loadStatistics, loadSmartPlaylists, loadRecentlyPlayed]);
 
-  // Update playback state periodically
+  // Follow the playback events pushed by the engine
   useEffect(() => {
-    const interval = setInterval(updatePlaybackState, 1000);
-    return () => clearInterval(interval);
-  }, [updatePlaybackState]);
+    const unlisten = subscribeToPlaybackEvents();
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, [subscribeToPlaybackEvents]);
 
   // Load artwork when track changes
   useEffect(() => {
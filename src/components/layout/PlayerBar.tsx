@@ -7,6 +7,7 @@ import { useLibraryStore } from "../../stores/libraryStore";
 import { useStreamingStore } from "../../stores/streamingStore";
 import { useGradient } from "../../contexts/GradientContext";
 import AlbumArt from "../common/AlbumArt";
+import LevelMeter from "../common/LevelMeter";
 import {
   PlayIcon,
   PauseIcon,
@@ -21,6 +22,7 @@ import {
   HeartFilledIcon,
   QueueIcon,
   DevicesIcon,
+  AudioWaveIcon,
   ExpandIcon,
   GlobeIcon,
 } from "../icons";
@@ -63,6 +65,7 @@ export default function PlayerBar() {
   const [seekPosition, setSeekPosition] = useState(0);
   const [artworkUrl, setArtworkUrl] = useState<string | null>(null);
   const [isVolumeHovered, setIsVolumeHovered] = useState(false);
+  const [showMeter, setShowMeter] = useState(false);
   const progressRef = useRef<HTMLDivElement>(null);
   const volumeRef = useRef<HTMLDivElement>(null);
 
@@ -420,6 +423,20 @@ export default function PlayerBar() {
             </div>
           )}
 
+          {showMeter && <LevelMeter className="mr-1" />}
+          <button
+            onClick={() => setShowMeter(!showMeter)}
+            title="Level meter"
+            className={clsx(
+              "p-2.5 transition-colors",
+              showMeter
+                ? "text-[#1DB954]"
+                : "text-text-secondary hover:text-text-primary",
+            )}
+          >
+            <AudioWaveIcon className="w-5 h-5" />
+          </button>
+
           <button className="p-2.5 text-text-secondary hover:text-text-primary transition-colors">
             <QueueIcon className="w-5 h-5" />
           </button>
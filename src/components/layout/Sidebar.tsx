@@ -13,6 +13,7 @@ import {
   PlusIcon,
   HeartFilledIcon,
   PinIcon,
+  AudioWaveIcon,
 } from "../icons";
 import type { Track } from "../../types";
 
@@ -115,6 +116,21 @@ export default function Sidebar() {
             <SearchIcon className="w-5 h-5" />
             <span>Search</span>
           </NavLink>
+
+          <NavLink
+            to="/analyzer"
+            className={({ isActive }) =>
+              clsx(
+                "flex items-center gap-3 px-3 py-2 rounded-lg font-medium transition-colors text-sm",
+                isActive
+                  ? "text-text-primary bg-amoled-hover/50"
+                  : "text-text-secondary hover:text-text-primary hover:bg-amoled-hover/30",
+              )
+            }
+          >
+            <AudioWaveIcon className="w-5 h-5" />
+            <span>Analyzer</span>
+          </NavLink>
         </nav>
       </div>
 
@@ -0,0 +1,147 @@
+import { useEffect, useRef } from "react";
+import { useVisualizerStore } from "../stores/visualizerStore";
+import LevelMeter from "../components/common/LevelMeter";
+import type { VisualizerFrame, WindowFunction } from "../types";
+
+// Shown range of the spectrum
+const MIN_HZ = 20;
+const MIN_DB = -100;
+
+const FFT_SIZES = [256, 512, 1024, 2048, 4096, 8192, 16384];
+const WINDOWS: { value: WindowFunction; label: string }[] = [
+  { value: "hann", label: "Hann" },
+  { value: "hamming", label: "Hamming" },
+  { value: "blackman_harris", label: "Blackman-Harris" },
+  { value: "rectangular", label: "Rectangular" },
+];
+
+// Spectrum on a logarithmic frequency axis, from MIN_HZ to Nyquist
+function drawSpectrum(canvas: HTMLCanvasElement, frame: VisualizerFrame) {
+  const context = canvas.getContext("2d");
+  if (!context) return;
+  const { width, height } = canvas;
+  context.clearRect(0, 0, width, height);
+
+  const nyquist = frame.sample_rate / 2;
+  const binHz = frame.sample_rate / frame.fft_size;
+  const span = Math.log(nyquist / MIN_HZ);
+  const x = (hz: number) => (Math.log(hz / MIN_HZ) / span) * width;
+  const y = (db: number) => (Math.max(db, MIN_DB) / MIN_DB) * height;
+
+  // Grid every 10dB and at each decade
+  context.strokeStyle = "rgba(255, 255, 255, 0.06)";
+  context.lineWidth = 1;
+  for (let db = 0; db >= MIN_DB; db -= 10) {
+    context.beginPath();
+    context.moveTo(0, y(db));
+    context.lineTo(width, y(db));
+    context.stroke();
+  }
+  for (let hz = 100; hz < nyquist; hz *= 10) {
+    context.beginPath();
+    context.moveTo(x(hz), 0);
+    context.lineTo(x(hz), height);
+    context.stroke();
+  }
+
+  context.beginPath();
+  context.moveTo(0, height);
+  frame.bins.forEach((db, bin) => {
+    const hz = bin * binHz;
+    if (hz >= MIN_HZ) context.lineTo(x(hz), y(db));
+  });
+  context.lineTo(width, height);
+  context.closePath();
+  context.fillStyle = "rgba(29, 185, 84, 0.35)";
+  context.fill();
+  context.strokeStyle = "#1DB954";
+  context.stroke();
+}
+
+export default function AnalyzerPage() {
+  const { frame, settings, subscribe, updateSettings } = useVisualizerStore();
+  const canvasRef = useRef<HTMLCanvasElement>(null);
+
+  useEffect(() => subscribe(), [subscribe]);
+
+  useEffect(() => {
+    if (canvasRef.current && frame) drawSpectrum(canvasRef.current, frame);
+  }, [frame]);
+
+  const selectClass =
+    "px-3 py-2 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm";
+
+  return (
+    <div className="p-8 pb-32">
+      <h1 className="text-3xl font-bold text-text-primary mb-2">Analyzer</h1>
+      <p className="text-text-muted mb-8">
+        The output as it goes to the device, after resampling and DSP
+      </p>
+
+      <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border mb-6">
+        <canvas ref={canvasRef} width={1200} height={400} className="w-full" />
+        {!frame && (
+          <p className="text-sm text-text-muted mt-3">
+            Start playback to see the spectrum
+          </p>
+        )}
+      </div>
+
+      <div className="flex flex-wrap items-center gap-6">
+        <LevelMeter className="scale-150 origin-left" />
+        <label className="text-sm text-text-muted flex items-center gap-2">
+          FFT size
+          <select
+            value={settings.fft_size}
+            onChange={(e) => updateSettings({ fft_size: Number(e.target.value) })}
+            className={selectClass}
+          >
+            {FFT_SIZES.map((size) => (
+              <option key={size} value={size}>
+                {size}
+              </option>
+            ))}
+          </select>
+        </label>
+        <label className="text-sm text-text-muted flex items-center gap-2">
+          Window
+          <select
+            value={settings.window}
+            onChange={(e) =>
+              updateSettings({ window: e.target.value as WindowFunction })
+            }
+            className={selectClass}
+          >
+            {WINDOWS.map((window) => (
+              <option key={window.value} value={window.value}>
+                {window.label}
+              </option>
+            ))}
+          </select>
+        </label>
+        <label className="text-sm text-text-muted flex items-center gap-2">
+          Frame rate
+          <select
+            value={settings.frame_rate}
+            onChange={(e) =>
+              updateSettings({ frame_rate: Number(e.target.value) })
+            }
+            className={selectClass}
+          >
+            {[15, 30, 60].map((rate) => (
+              <option key={rate} value={rate}>
+                {rate} fps
+              </option>
+            ))}
+          </select>
+        </label>
+        {frame && (
+          <span className="text-sm text-text-muted tabular-nums">
+            {(frame.sample_rate / 1000).toFixed(1)}kHz ·{" "}
+            {(frame.sample_rate / frame.fft_size).toFixed(1)}Hz per bin
+          </span>
+        )}
+      </div>
+    </div>
+  );
+}
@@ -1,10 +1,22 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
+import { open } from "@tauri-apps/plugin-dialog";
 import { clsx } from "clsx";
 import { useGradient } from "../contexts/GradientContext";
 import { useStreamingStore } from "../stores/streamingStore";
 import type { SpotifyCredentials } from "../types/streaming";
+import type {
+  AudioDeviceChangedEvent,
+  AudioDiagnostics,
+  ConvolutionSettings,
+  CrossfeedSettings,
+  DeviceProfile,
+  DeviceSettings,
+  EqPreset,
+  OutputSinkSettings,
+  SpeedSettings,
+} from "../types";
 
 interface FFmpegStatus {
   installed: boolean;
@@ -21,8 +33,42 @@ interface FFmpegProgress {
 export default function SettingsPage() {
   const [audioDevices, setAudioDevices] = useState<string[]>([]);
   const [selectedDevice, setSelectedDevice] = useState<string>("");
+  const [activeDevice, setActiveDevice] = useState<string | null>(null);
+  const [deviceFallback, setDeviceFallback] = useState(false);
+  const [returnToPreferred, setReturnToPreferred] = useState(true);
+  const [bufferSize, setBufferSize] = useState<number | null>(null);
+  const [diagnostics, setDiagnostics] = useState<AudioDiagnostics | null>(
+    null,
+  );
   const [exclusiveMode, setExclusiveMode] = useState(false);
   const [replayGain, setReplayGain] = useState(false);
+  const [speed, setSpeed] = useState<SpeedSettings>({
+    speed: 1,
+    mode: "preserve_pitch",
+    pitch: 0,
+  });
+  const [crossfeed, setCrossfeed] = useState<CrossfeedSettings>({
+    enabled: false,
+    preset: "default",
+    cutoff: 700,
+    level: 4.5,
+  });
+  const [convolution, setConvolution] = useState<ConvolutionSettings>({
+    enabled: false,
+    ir_path: null,
+  });
+  const [convolutionError, setConvolutionError] = useState<string | null>(
+    null,
+  );
+  const [outputSink, setOutputSink] = useState<OutputSinkSettings>({
+    backend: "device",
+    path: null,
+    container: "raw",
+    bit_depth: 16,
+    sample_rate: null,
+    channels: null,
+  });
+  const [outputSinkError, setOutputSinkError] = useState<string | null>(null);
 
   // Spotify credentials
   const [spotifyClientId, setSpotifyClientId] = useState("");
@@ -68,8 +114,28 @@ export default function SettingsPage() {
       },
     );
 
+    // Devices coming and going, and fallbacks to the default device
+    const unlistenDevices = listen<AudioDeviceChangedEvent>(
+      "audio-device-changed",
+      (event) => {
+        setAudioDevices(event.payload.devices);
+        setActiveDevice(event.payload.device);
+        setDeviceFallback(event.payload.fallback);
+      },
+    );
+
+    // Latency and dropout counters change while playing
+    const loadDiagnostics = () =>
+      invoke<AudioDiagnostics>("get_audio_diagnostics")
+        .then(setDiagnostics)
+        .catch((error) => console.error("Failed to get diagnostics:", error));
+    loadDiagnostics();
+    const diagnosticsInterval = setInterval(loadDiagnostics, 1000);
+
     return () => {
       unlisten.then((fn) => fn());
+      unlistenDevices.then((fn) => fn());
+      clearInterval(diagnosticsInterval);
     };
   }, []);
 
@@ -124,9 +190,33 @@ export default function SettingsPage() {
 
   const loadAudioDevices = async () => {
     try {
-      const devices = await invoke<string[]>("get_audio_devices");
+      const [
+        devices,
+        settings,
+        frames,
+        speedSettings,
+        crossfeedSettings,
+        convolutionSettings,
+        sinkSettings,
+      ] = await Promise.all([
+        invoke<string[]>("get_audio_devices"),
+        invoke<DeviceSettings>("get_device_settings"),
+        invoke<number | null>("get_buffer_size"),
+        invoke<SpeedSettings>("get_speed"),
+        invoke<CrossfeedSettings>("get_crossfeed"),
+        invoke<ConvolutionSettings>("get_convolution"),
+        invoke<OutputSinkSettings>("get_output_sink"),
+      ]);
       setAudioDevices(devices);
-      if (devices.length > 0 && !selectedDevice) {
+      setBufferSize(frames);
+      setSpeed(speedSettings);
+      setCrossfeed(crossfeedSettings);
+      setConvolution(convolutionSettings);
+      setOutputSink(sinkSettings);
+      setReturnToPreferred(settings.return_to_preferred);
+      if (settings.preferred) {
+        setSelectedDevice(settings.preferred);
+      } else if (devices.length > 0 && !selectedDevice) {
         setSelectedDevice(devices[0]);
       }
     } catch (error) {
@@ -224,6 +314,103 @@ export default function SettingsPage() {
     }
   };
 
+  const handleBufferSizeChange = async (frames: number | null) => {
+    setBufferSize(frames);
+    try {
+      await invoke("set_buffer_size", { frames });
+    } catch (error) {
+      console.error("Failed to set buffer size:", error);
+    }
+  };
+
+  const handleSpeedChange = async (changes: Partial<SpeedSettings>) => {
+    const settings = { ...speed, ...changes };
+    setSpeed(settings);
+    try {
+      await invoke("set_speed", { ...settings });
+    } catch (error) {
+      console.error("Failed to set speed:", error);
+    }
+  };
+
+  const handleCrossfeedChange = async (
+    changes: Partial<CrossfeedSettings>,
+  ) => {
+    const settings = { ...crossfeed, ...changes };
+    setCrossfeed(settings);
+    try {
+      await invoke("set_crossfeed", { ...settings });
+    } catch (error) {
+      console.error("Failed to set crossfeed:", error);
+    }
+  };
+
+  const handleConvolutionChange = async (
+    changes: Partial<ConvolutionSettings>,
+  ) => {
+    const settings = { ...convolution, ...changes };
+    try {
+      await invoke("set_convolution", {
+        enabled: settings.enabled,
+        irPath: settings.ir_path,
+      });
+      setConvolution(settings);
+      setConvolutionError(null);
+    } catch (error) {
+      console.error("Failed to set convolution:", error);
+      setConvolutionError(String(error));
+    }
+  };
+
+  const handleOutputSinkChange = async (
+    changes: Partial<OutputSinkSettings>,
+  ) => {
+    const settings = { ...outputSink, ...changes };
+    setOutputSink(settings);
+    // A file sink waits for its path before it is applied
+    if (settings.backend === "file" && !settings.path) {
+      return;
+    }
+    try {
+      await invoke("set_output_sink", {
+        backend: settings.backend,
+        path: settings.path,
+        container: settings.container,
+        bitDepth: settings.bit_depth,
+        sampleRate: settings.sample_rate,
+        channels: settings.channels,
+      });
+      setOutputSinkError(null);
+    } catch (error) {
+      console.error("Failed to set output:", error);
+      setOutputSinkError(String(error));
+    }
+  };
+
+  const handleChooseImpulseResponse = async () => {
+    try {
+      const selected = await open({
+        multiple: false,
+        title: "Select Impulse Response",
+        filters: [{ name: "Impulse response", extensions: ["wav"] }],
+      });
+      if (selected && typeof selected === "string") {
+        await handleConvolutionChange({ ir_path: selected, enabled: true });
+      }
+    } catch (error) {
+      console.error("Failed to choose impulse response:", error);
+    }
+  };
+
+  const handleReturnToPreferredChange = async (enabled: boolean) => {
+    setReturnToPreferred(enabled);
+    try {
+      await invoke("set_device_settings", { returnToPreferred: enabled });
+    } catch (error) {
+      console.error("Failed to save device settings:", error);
+    }
+  };
+
   return (
     <div className="p-8 pb-32 max-w-3xl">
       <h1 className="text-3xl font-bold text-text-primary mb-10">Settings</h1>
@@ -235,6 +422,94 @@ export default function SettingsPage() {
           Audio
         </h2>
         <div className="space-y-5">
+          {/* Output */}
+          <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border space-y-4">
+            <div>
+              <label className="block text-base font-medium text-text-primary mb-3">
+                Output
+              </label>
+              <select
+                value={outputSink.backend}
+                onChange={(e) =>
+                  handleOutputSinkChange({
+                    backend: e.target.value as OutputSinkSettings["backend"],
+                  })
+                }
+                className="w-full px-4 py-3 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-base"
+              >
+                <option value="device">Output device</option>
+                <option value="null">Null (discard audio)</option>
+                <option value="file">File or FIFO</option>
+              </select>
+              <p className="mt-3 text-sm text-text-muted">
+                Null and file outputs play in real time without a sound card.
+                Point a file output at a FIFO to feed Snapcast.
+              </p>
+            </div>
+            {outputSink.backend === "file" && (
+              <>
+                <input
+                  type="text"
+                  defaultValue={outputSink.path ?? ""}
+                  placeholder="/tmp/snapfifo"
+                  onBlur={(e) =>
+                    handleOutputSinkChange({ path: e.target.value || null })
+                  }
+                  className="w-full px-4 py-3 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-base"
+                />
+                <div className="grid grid-cols-3 gap-3">
+                  <select
+                    value={outputSink.container}
+                    onChange={(e) =>
+                      handleOutputSinkChange({
+                        container: e.target
+                          .value as OutputSinkSettings["container"],
+                      })
+                    }
+                    className="px-3 py-2 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm"
+                  >
+                    <option value="raw">Raw PCM</option>
+                    <option value="wav">WAV</option>
+                  </select>
+                  <select
+                    value={outputSink.bit_depth}
+                    onChange={(e) =>
+                      handleOutputSinkChange({
+                        bit_depth: Number(e.target.value),
+                      })
+                    }
+                    className="px-3 py-2 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm"
+                  >
+                    <option value={16}>16-bit</option>
+                    <option value={24}>24-bit</option>
+                    <option value={32}>32-bit float</option>
+                  </select>
+                  <select
+                    value={outputSink.sample_rate ?? ""}
+                    onChange={(e) =>
+                      handleOutputSinkChange({
+                        sample_rate: e.target.value
+                          ? Number(e.target.value)
+                          : null,
+                      })
+                    }
+                    className="px-3 py-2 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm"
+                  >
+                    <option value="">Source rate</option>
+                    {[44100, 48000, 88200, 96000, 192000].map((rate) => (
+                      <option key={rate} value={rate}>
+                        {rate / 1000} kHz
+                      </option>
+                    ))}
+                  </select>
+                </div>
+              </>
+            )}
+            {outputSinkError && (
+              <p className="text-sm text-red-400">{outputSinkError}</p>
+            )}
+          </div>
+
           {/* Output Device */}
           <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border">
             <label className="block text-base font-medium text-text-primary mb-3">
@@ -245,6 +520,11 @@ export default function SettingsPage() {
               onChange={(e) => handleDeviceChange(e.target.value)}
               className="w-full px-4 py-3 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-base"
             >
+              {selectedDevice && !audioDevices.includes(selectedDevice) && (
+                <option value={selectedDevice}>
+                  {selectedDevice} (disconnected)
+                </option>
+              )}
               {audioDevices.map((device) => (
                 <option key={device} value={device}>
                   {device}
@@ -252,8 +532,64 @@ export default function SettingsPage() {
               ))}
             </select>
             <p className="mt-3 text-sm text-text-muted">
-              Select the audio output device for playback
+              {deviceFallback
+                ? `${selectedDevice} is disconnected, playing on ${activeDevice ?? "the default device"}`
+                : "Select the audio output device for playback"}
+            </p>
+            <div className="flex items-center justify-between gap-4 mt-5">
+              <div>
+                <label className="block text-base font-medium text-text-primary">
+                  Return to Device on Reconnect
+                </label>
+                <p className="text-sm text-text-muted mt-1.5">
+                  Switch back from the default device when this one is plugged in again
+                </p>
+              </div>
+              <Toggle
+                checked={returnToPreferred}
+                onChange={handleReturnToPreferredChange}
+              />
+            </div>
+            {selectedDevice && <DeviceProfileEditor device={selectedDevice} />}
+          </div>
+
+          {/* Buffer Size */}
+          <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border">
+            <label className="block text-base font-medium text-text-primary mb-3">
+              Buffer Size
+            </label>
+            <select
+              value={bufferSize ?? ""}
+              onChange={(e) =>
+                handleBufferSizeChange(
+                  e.target.value ? Number(e.target.value) : null,
+                )
+              }
+              className="w-full px-4 py-3 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-base"
+            >
+              <option value="">Device default</option>
+              {[64, 128, 256, 512, 1024, 2048, 4096, 8192].map((frames) => (
+                <option key={frames} value={frames}>
+                  {frames} frames
+                </option>
+              ))}
+            </select>
+            <p className="mt-3 text-sm text-text-muted">
+              Smaller buffers lower latency, larger ones survive a busy system
+              without dropouts. Device profiles can override this.
             </p>
+            {diagnostics && (
+              <p className="mt-3 text-sm text-text-muted">
+                Latency {diagnostics.latency_ms.toFixed(1)} ms ·{" "}
+                {diagnostics.callback_frames} frames per callback
+                {diagnostics.buffer_range &&
+                  ` (device takes ${diagnostics.buffer_range[0]}–${diagnostics.buffer_range[1]})`}{" "}
+                · {diagnostics.underruns} underruns · {diagnostics.xruns} xruns
+                {convolution.enabled &&
+                  convolution.ir_path &&
+                  ` · Convolution ${diagnostics.convolution_latency_ms.toFixed(1)} ms, ${(diagnostics.convolution_load * 100).toFixed(1)}% CPU`}
+              </p>
+            )}
           </div>
 
           {/* Exclusive Mode */}
@@ -285,6 +621,185 @@ export default function SettingsPage() {
               <Toggle checked={replayGain} onChange={setReplayGain} />
             </div>
           </div>
+
+          {/* Crossfeed */}
+          <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border space-y-4">
+            <div className="flex items-center justify-between gap-4">
+              <div>
+                <label className="block text-base font-medium text-text-primary">
+                  Headphone Crossfeed
+                </label>
+                <p className="text-sm text-text-muted mt-1.5">
+                  Blends some of each channel into the other for less fatiguing
+                  hard-panned stereo. Mono and multichannel play as they are.
+                </p>
+              </div>
+              <Toggle
+                checked={crossfeed.enabled}
+                onChange={(enabled) => handleCrossfeedChange({ enabled })}
+              />
+            </div>
+            {crossfeed.enabled && (
+              <>
+                <select
+                  value={crossfeed.preset}
+                  onChange={(e) =>
+                    handleCrossfeedChange({
+                      preset: e.target.value as CrossfeedSettings["preset"],
+                    })
+                  }
+                  className="w-full px-4 py-2.5 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm"
+                >
+                  <option value="default">Default (700Hz, 4.5dB)</option>
+                  <option value="chu_moy">Chu Moy (700Hz, 6dB)</option>
+                  <option value="jan_meier">Jan Meier (650Hz, 9.5dB)</option>
+                  <option value="custom">Custom</option>
+                </select>
+                {crossfeed.preset === "custom" && (
+                  <>
+                    <label className="block text-sm text-text-muted">
+                      Cutoff: {Math.round(crossfeed.cutoff)}Hz
+                      <input
+                        type="range"
+                        min={300}
+                        max={2000}
+                        step={10}
+                        value={crossfeed.cutoff}
+                        onChange={(e) =>
+                          handleCrossfeedChange({
+                            cutoff: Number(e.target.value),
+                          })
+                        }
+                        className="w-full mt-1.5 accent-accent-primary"
+                      />
+                    </label>
+                    <label className="block text-sm text-text-muted">
+                      Level: {crossfeed.level.toFixed(1)}dB
+                      <input
+                        type="range"
+                        min={1}
+                        max={15}
+                        step={0.5}
+                        value={crossfeed.level}
+                        onChange={(e) =>
+                          handleCrossfeedChange({
+                            level: Number(e.target.value),
+                          })
+                        }
+                        className="w-full mt-1.5 accent-accent-primary"
+                      />
+                    </label>
+                  </>
+                )}
+              </>
+            )}
+          </div>
+
+          {/* Convolution */}
+          <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border space-y-4">
+            <div className="flex items-center justify-between gap-4">
+              <div>
+                <label className="block text-base font-medium text-text-primary">
+                  Room Correction
+                </label>
+                <p className="text-sm text-text-muted mt-1.5">
+                  Convolves the output with a mono or stereo WAV impulse
+                  response, such as a filter exported from REW
+                </p>
+              </div>
+              <Toggle
+                checked={convolution.enabled}
+                onChange={(enabled) => handleConvolutionChange({ enabled })}
+              />
+            </div>
+            <div className="flex items-center gap-3">
+              <p className="flex-1 text-sm text-text-secondary truncate">
+                {convolution.ir_path ?? "No impulse response"}
+              </p>
+              <button
+                onClick={handleChooseImpulseResponse}
+                className="px-4 py-2 bg-amoled-elevated hover:bg-amoled-hover text-text-primary rounded-lg border border-amoled-border text-sm transition-colors"
+              >
+                Choose…
+              </button>
+              {convolution.ir_path && (
+                <button
+                  onClick={() =>
+                    handleConvolutionChange({ ir_path: null, enabled: false })
+                  }
+                  className="px-4 py-2 text-text-muted hover:text-text-primary text-sm transition-colors"
+                >
+                  Clear
+                </button>
+              )}
+            </div>
+            {convolutionError && (
+              <p className="text-sm text-red-400">{convolutionError}</p>
+            )}
+          </div>
+
+          {/* Playback Speed */}
+          <div className="bg-amoled-card rounded-xl p-5 border border-amoled-border space-y-4">
+            <div className="flex items-center justify-between gap-4">
+              <div>
+                <label className="block text-base font-medium text-text-primary">
+                  Playback Speed
+                </label>
+                <p className="text-sm text-text-muted mt-1.5">
+                  Varispeed lets the pitch follow the speed, like a turntable
+                </p>
+              </div>
+              <select
+                value={speed.mode}
+                onChange={(e) =>
+                  handleSpeedChange({
+                    mode: e.target.value as SpeedSettings["mode"],
+                  })
+                }
+                className="px-4 py-2.5 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm"
+              >
+                <option value="preserve_pitch">Keep Pitch</option>
+                <option value="varispeed">Varispeed</option>
+              </select>
+            </div>
+            <label className="block text-sm text-text-muted">
+              Speed: {speed.speed.toFixed(2)}x
+              <input
+                type="range"
+                min={0.5}
+                max={2}
+                step={0.05}
+                value={speed.speed}
+                onChange={(e) =>
+                  handleSpeedChange({ speed: Number(e.target.value) })
+                }
+                className="w-full mt-1.5 accent-accent-primary"
+              />
+            </label>
+            <label className="block text-sm text-text-muted">
+              Pitch: {speed.pitch > 0 ? "+" : ""}
+              {speed.pitch} semitones
+              <input
+                type="range"
+                min={-12}
+                max={12}
+                step={1}
+                value={speed.pitch}
+                onChange={(e) =>
+                  handleSpeedChange({ pitch: Number(e.target.value) })
+                }
+                className="w-full mt-1.5 accent-accent-primary"
+              />
+            </label>
+            {(speed.speed !== 1 || speed.pitch !== 0) && (
+              <button
+                onClick={() => handleSpeedChange({ speed: 1, pitch: 0 })}
+                className="text-sm text-accent-primary hover:underline"
+              >
+                Reset
+              </button>
+            )}
+          </div>
         </div>
       </section>
 
@@ -870,3 +1385,182 @@ function Toggle({ checked, onChange }: ToggleProps) {
     </button>
   );
 }
+
+const DEFAULT_PROFILE: DeviceProfile = {
+  max_sample_rate: null,
+  sample_format: null,
+  buffer_size: null,
+  volume_limit: 1,
+  eq_preset: null,
+  resample: false,
+};
+
+const SAMPLE_RATES = [44100, 48000, 88200, 96000, 176400, 192000, 352800, 384000];
+
+// Output settings of one device, applied whenever playback goes to it
+function DeviceProfileEditor({ device }: { device: string }) {
+  const [profile, setProfile] = useState<DeviceProfile>(DEFAULT_PROFILE);
+  const [hasProfile, setHasProfile] = useState(false);
+  const [eqPresets, setEqPresets] = useState<EqPreset[]>([]);
+
+  useEffect(() => {
+    Promise.all([
+      invoke<Record<string, DeviceProfile>>("get_device_profiles"),
+      invoke<EqPreset[]>("get_eq_presets"),
+    ])
+      .then(([profiles, presets]) => {
+        setProfile(profiles[device] ?? DEFAULT_PROFILE);
+        setHasProfile(device in profiles);
+        setEqPresets(presets);
+      })
+      .catch((error) => console.error("Failed to load device profile:", error));
+  }, [device]);
+
+  const update = (changes: Partial<DeviceProfile>) =>
+    setProfile((current) => ({ ...current, ...changes }));
+
+  const save = async () => {
+    try {
+      await invoke("save_device_profile", { deviceName: device, profile });
+      setHasProfile(true);
+    } catch (error) {
+      console.error("Failed to save device profile:", error);
+    }
+  };
+
+  const reset = async () => {
+    try {
+      await invoke("delete_device_profile", { deviceName: device });
+      setProfile(DEFAULT_PROFILE);
+      setHasProfile(false);
+    } catch (error) {
+      console.error("Failed to delete device profile:", error);
+    }
+  };
+
+  const selectClass =
+    "w-full px-3 py-2 bg-amoled-elevated text-text-primary rounded-lg border border-amoled-border focus:border-accent-primary focus:outline-none text-sm";
+
+  return (
+    <div className="mt-5 pt-5 border-t border-amoled-border space-y-4">
+      <p className="text-base font-medium text-text-primary">Device Profile</p>
+      <div className="grid grid-cols-2 gap-4">
+        <label className="text-sm text-text-muted">
+          Max sample rate
+          <select
+            value={profile.max_sample_rate ?? ""}
+            onChange={(e) =>
+              update({
+                max_sample_rate: e.target.value ? Number(e.target.value) : null,
+              })
+            }
+            className={clsx(selectClass, "mt-1.5")}
+          >
+            <option value="">No limit</option>
+            {SAMPLE_RATES.map((rate) => (
+              <option key={rate} value={rate}>
+                {rate / 1000} kHz
+              </option>
+            ))}
+          </select>
+        </label>
+        <label className="text-sm text-text-muted">
+          Sample format
+          <select
+            value={profile.sample_format ?? ""}
+            onChange={(e) =>
+              update({
+                sample_format: (e.target.value ||
+                  null) as DeviceProfile["sample_format"],
+              })
+            }
+            className={clsx(selectClass, "mt-1.5")}
+          >
+            <option value="">Automatic</option>
+            <option value="i16">16-bit integer</option>
+            <option value="i32">32-bit integer</option>
+            <option value="f32">32-bit float</option>
+          </select>
+        </label>
+        <label className="text-sm text-text-muted">
+          Buffer size (frames)
+          <input
+            type="number"
+            min={16}
+            max={65536}
+            placeholder="Device default"
+            value={profile.buffer_size ?? ""}
+            onChange={(e) =>
+              update({
+                buffer_size: e.target.value ? Number(e.target.value) : null,
+              })
+            }
+            className={clsx(selectClass, "mt-1.5")}
+          />
+        </label>
+        <label className="text-sm text-text-muted">
+          EQ preset
+          <select
+            value={profile.eq_preset ?? ""}
+            onChange={(e) =>
+              update({
+                eq_preset: e.target.value ? Number(e.target.value) : null,
+              })
+            }
+            className={clsx(selectClass, "mt-1.5")}
+          >
+            <option value="">Keep current EQ</option>
+            {eqPresets.map((preset) => (
+              <option key={preset.id} value={preset.id}>
+                {preset.name}
+              </option>
+            ))}
+          </select>
+        </label>
+      </div>
+      <label className="block text-sm text-text-muted">
+        Volume limit: {Math.round(profile.volume_limit * 100)}%
+        <input
+          type="range"
+          min={0}
+          max={1}
+          step={0.01}
+          value={profile.volume_limit}
+          onChange={(e) => update({ volume_limit: Number(e.target.value) })}
+          className="w-full mt-1.5 accent-accent-primary"
+        />
+      </label>
+      <div className="flex items-center justify-between gap-4">
+        <div>
+          <p className="text-sm font-medium text-text-primary">
+            Resample to a Fixed Rate
+          </p>
+          <p className="text-sm text-text-muted mt-1">
+            Keep the device at its max (or default) rate instead of switching
+            rate per track
+          </p>
+        </div>
+        <Toggle
+          checked={profile.resample}
+          onChange={(resample) => update({ resample })}
+        />
+      </div>
+      <div className="flex gap-3">
+        <button
+          onClick={save}
+          className="px-4 py-2 bg-accent-primary text-white rounded-lg text-sm font-medium hover:opacity-90 transition-opacity"
+        >
+          Save Profile
+        </button>
+        {hasProfile && (
+          <button
+            onClick={reset}
+            className="px-4 py-2 bg-amoled-elevated text-text-secondary rounded-lg text-sm font-medium hover:bg-amoled-hover transition-colors"
+          >
+            Reset
+          </button>
+        )}
+      </div>
+    </div>
+  );
+}
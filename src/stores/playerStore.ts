@@ -1,7 +1,14 @@
 import { create } from "zustand";
 import { persist } from "zustand/middleware";
 import { invoke } from "@tauri-apps/api/core";
-import type { Track, PlaybackState } from "../types";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import type {
+  Track,
+  PlaybackState,
+  PlaybackStatusEvent,
+  PlaybackPositionEvent,
+  QueueSnapshot,
+} from "../types";
 
 interface PlayerStore {
   // State
@@ -19,10 +26,21 @@ interface PlayerStore {
   previousTrack: () => Promise<void>;
   toggleShuffle: () => Promise<void>;
   cycleRepeatMode: () => Promise<void>;
-  setQueue: (tracks: Track[], startIndex?: number) => void;
+  playQueueIndex: (index: number) => Promise<void>;
+  addToQueue: (tracks: Track[]) => Promise<void>;
+  playNext: (tracks: Track[]) => Promise<void>;
+  removeFromQueue: (index: number) => Promise<void>;
+  moveInQueue: (from: number, to: number) => Promise<void>;
+  clearQueue: () => Promise<void>;
   updatePlaybackState: () => Promise<void>;
+  updateQueue: () => Promise<void>;
+  subscribeToPlaybackEvents: () => Promise<UnlistenFn>;
+  trackStarted: (filePath: string) => Promise<void>;
 }
 
+// The queue lives in the backend, which advances it even while the webview is suspended.
+// The store mirrors it from the queue events.
+
 const defaultPlaybackState: PlaybackState = {
   is_playing: false,
   current_track: null,
@@ -45,12 +63,12 @@ export const usePlayerStore = create<PlayerStore>()(
 
       playTrack: async (track: Track, queue?: Track[]) => {
         try {
-          if (queue) {
-            const index = queue.findIndex((t) => t.id === track.id);
-            set({ queue, queueIndex: index >= 0 ? index : 0 });
-          }
-
-          await invoke("play_track", { filePath: track.file_path });
+          const tracks = queue ?? [track];
+          const index = tracks.findIndex((t) => t.id === track.id);
+          await invoke("play_queue", {
+            tracks,
+            startIndex: index >= 0 ? index : 0,
+          });
 
           set((state) => ({
             playbackState: {
@@ -132,50 +150,19 @@ export const usePlayerStore = create<PlayerStore>()(
       },
 
       nextTrack: async () => {
-        const { queue, queueIndex, playbackState } = get();
-        if (queue.length === 0) return;
-
-        let nextIndex: number;
-
-        if (playbackState.shuffle) {
-          nextIndex = Math.floor(Math.random() * queue.length);
-        } else {
-          nextIndex = queueIndex + 1;
-          if (nextIndex >= queue.length) {
-            if (playbackState.repeat_mode === "all") {
-              nextIndex = 0;
-            } else {
-              return; // End of queue
-            }
-          }
-        }
-
-        const nextTrack = queue[nextIndex];
-        if (nextTrack) {
-          set({ queueIndex: nextIndex });
-          await get().playTrack(nextTrack);
+        try {
+          await invoke("next_track");
+        } catch (error) {
+          console.error("Failed to skip to next track:", error);
         }
       },
 
+      // Restarts the track, or goes back through the play history near its start
       previousTrack: async () => {
-        const { queue, queueIndex, playbackState } = get();
-
-        // If more than 3 seconds in, restart current track
-        if (playbackState.position > 3) {
-          await get().seekTo(0);
-          return;
-        }
-
-        if (queue.length === 0 || queueIndex <= 0) {
-          await get().seekTo(0);
-          return;
-        }
-
-        const prevIndex = queueIndex - 1;
-        const prevTrack = queue[prevIndex];
-        if (prevTrack) {
-          set({ queueIndex: prevIndex });
-          await get().playTrack(prevTrack);
+        try {
+          await invoke("previous_track");
+        } catch (error) {
+          console.error("Failed to go to previous track:", error);
         }
       },
 
@@ -214,8 +201,52 @@ export const usePlayerStore = create<PlayerStore>()(
         }
       },
 
-      setQueue: (tracks: Track[], startIndex = 0) => {
-        set({ queue: tracks, queueIndex: startIndex });
+      playQueueIndex: async (index: number) => {
+        try {
+          await invoke("play_queue_index", { index });
+        } catch (error) {
+          console.error("Failed to play queued track:", error);
+        }
+      },
+
+      addToQueue: async (tracks: Track[]) => {
+        try {
+          await invoke("add_to_queue", { tracks });
+        } catch (error) {
+          console.error("Failed to add to queue:", error);
+        }
+      },
+
+      playNext: async (tracks: Track[]) => {
+        try {
+          await invoke("play_next", { tracks });
+        } catch (error) {
+          console.error("Failed to queue next:", error);
+        }
+      },
+
+      removeFromQueue: async (index: number) => {
+        try {
+          await invoke("remove_from_queue", { index });
+        } catch (error) {
+          console.error("Failed to remove from queue:", error);
+        }
+      },
+
+      moveInQueue: async (from: number, to: number) => {
+        try {
+          await invoke("move_in_queue", { from, to });
+        } catch (error) {
+          console.error("Failed to move in queue:", error);
+        }
+      },
+
+      clearQueue: async () => {
+        try {
+          await invoke("clear_queue");
+        } catch (error) {
+          console.error("Failed to clear queue:", error);
+        }
       },
 
       updatePlaybackState: async () => {
@@ -226,6 +257,72 @@ export const usePlayerStore = create<PlayerStore>()(
           console.error("Failed to update playback state:", error);
         }
       },
+
+      updateQueue: async () => {
+        try {
+          const queue = await invoke<QueueSnapshot>("get_queue");
+          set({ queue: queue.tracks, queueIndex: queue.position ?? -1 });
+        } catch (error) {
+          console.error("Failed to update queue:", error);
+        }
+      },
+
+      // Sync once, then follow the events the engine pushes
+      subscribeToPlaybackEvents: async () => {
+        const unlisteners = await Promise.all([
+          listen<PlaybackStatusEvent>("playback-state", (event) => {
+            const { current_track, ...status } = event.payload;
+            set((state) => ({
+              playbackState: {
+                ...state.playbackState,
+                ...status,
+                current_track:
+                  current_track === null
+                    ? null
+                    : state.playbackState.current_track,
+              },
+            }));
+          }),
+          listen<PlaybackPositionEvent>("playback-position", (event) => {
+            set((state) => ({
+              playbackState: { ...state.playbackState, ...event.payload },
+            }));
+          }),
+          listen<{ path: string }>("track-started", (event) => {
+            get().trackStarted(event.payload.path);
+          }),
+          listen<QueueSnapshot>("queue-changed", (event) => {
+            set({
+              queue: event.payload.tracks,
+              queueIndex: event.payload.position ?? -1,
+            });
+          }),
+          listen<{ position: number | null }>("queue-position", (event) => {
+            set({ queueIndex: event.payload.position ?? -1 });
+          }),
+          listen<{ message: string }>("playback-error", (event) => {
+            console.error("Playback error:", event.payload.message);
+          }),
+        ]);
+        await Promise.all([get().updatePlaybackState(), get().updateQueue()]);
+        return () => unlisteners.forEach((unlisten) => unlisten());
+      },
+
+      trackStarted: async (filePath: string) => {
+        const track = get().queue.find((t) => t.file_path === filePath);
+        if (track) {
+          set((state) => ({
+            playbackState: {
+              ...state.playbackState,
+              current_track: track,
+              position: 0,
+            },
+          }));
+        } else {
+          // Not from the queue, so look the track up once
+          await get().updatePlaybackState();
+        }
+      },
     }),
     {
       name: "hiflac-player",
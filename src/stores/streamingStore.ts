@@ -1,6 +1,7 @@
 import { create } from "zustand";
 import { invoke } from "@tauri-apps/api/core";
 import { supabase } from "../lib/supabase";
+import { usePlayerStore } from "./playerStore";
 import type {
   SpotifyTrack,
   SpotifyAlbum,
@@ -755,12 +756,8 @@ export const useStreamingStore = create<StreamingState>((set, get) => ({
         if (isTransitioning) return;
 
         try {
-          const playbackState = await invoke<{
-            is_playing: boolean;
-            position: number;
-            duration: number;
-            track_finished: boolean;
-          }>("get_playback_state");
+          // Kept current by the engine's playback events
+          const { playbackState } = usePlayerStore.getState();
 
           const timeRemaining = playbackState.duration - playbackState.position;
           const nextChunkToAppend = lastAppendedChunk + 1;
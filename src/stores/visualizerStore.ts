@@ -0,0 +1,69 @@
+import { create } from "zustand";
+import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import type { VisualizerFrame, VisualizerSettings } from "../types";
+
+interface VisualizerStore {
+  frame: VisualizerFrame | null;
+  settings: VisualizerSettings;
+  subscribe: () => () => void;
+  updateSettings: (changes: Partial<VisualizerSettings>) => Promise<void>;
+}
+
+// The backend only analyzes the output while some view shows the data, so views
+// subscribe while mounted and the last one to leave turns the analysis off again.
+let subscribers = 0;
+let unlisten: Promise<UnlistenFn> | null = null;
+
+const sendSettings = (settings: VisualizerSettings) =>
+  invoke("set_visualizer", {
+    enabled: settings.enabled,
+    fftSize: settings.fft_size,
+    window: settings.window,
+    frameRate: settings.frame_rate,
+  }).catch((error) => console.error("Failed to set visualizer:", error));
+
+export const useVisualizerStore = create<VisualizerStore>()((set, get) => ({
+  frame: null,
+  settings: {
+    enabled: false,
+    fft_size: 2048,
+    window: "hann",
+    frame_rate: 30,
+  },
+
+  subscribe: () => {
+    subscribers += 1;
+    if (subscribers === 1) {
+      unlisten = listen<VisualizerFrame>("visualizer-frame", (event) =>
+        set({ frame: event.payload }),
+      );
+      invoke<VisualizerSettings>("get_visualizer")
+        .then((settings) => {
+          // Unsubscribed again before the settings arrived
+          if (subscribers === 0) return;
+          const enabled = { ...settings, enabled: true };
+          set({ settings: enabled });
+          sendSettings(enabled);
+        })
+        .catch((error) => console.error("Failed to get visualizer:", error));
+    }
+
+    return () => {
+      subscribers -= 1;
+      if (subscribers === 0) {
+        unlisten?.then((fn) => fn());
+        unlisten = null;
+        const disabled = { ...get().settings, enabled: false };
+        set({ settings: disabled, frame: null });
+        sendSettings(disabled);
+      }
+    };
+  },
+
+  updateSettings: async (changes) => {
+    const settings = { ...get().settings, ...changes };
+    set({ settings });
+    await sendSettings(settings);
+  },
+}));
@@ -22,6 +22,12 @@ export interface Track {
   last_played: string | null;
   date_added: string;
   is_favorite: boolean;
+  replaygain_track_gain: number | null; // dB
+  replaygain_track_peak: number | null;
+  replaygain_album_gain: number | null;
+  replaygain_album_peak: number | null;
+  start_offset: number | null; // Frames into the file of a cue sheet track
+  end_offset: number | null;
 }
 
 // Album type
@@ -64,6 +70,208 @@ export interface PlaybackState {
   shuffle: boolean;
   repeat_mode: "off" | "one" | "all";
   track_finished?: boolean; // True when current track has finished playing
+  output_sample_format?: "i16" | "i24" | "i32" | "f32"; // i24 only from a file output
+  bit_perfect?: boolean; // Decoded samples reach the device unaltered
+  dsd_rate?: number | null; // DSD sample rate of the audible track
+  dop?: boolean; // DSD reaches the device packed as DoP
+  output_device?: string | null; // Device playback goes to
+  speed?: SpeedSettings;
+}
+
+// Payload of the "playback-state" event: the playback state without the position,
+// with the audible track as a file path
+export interface PlaybackStatusEvent
+  extends Omit<PlaybackState, "current_track" | "position"> {
+  current_track: string | null;
+}
+
+// Payload of the "playback-position" event, throttled while playing
+export interface PlaybackPositionEvent {
+  position: number;
+  duration: number;
+}
+
+// Playback speed and pitch; position and duration stay in track time
+export type SpeedMode = "preserve_pitch" | "varispeed";
+
+export interface SpeedSettings {
+  speed: number; // 0.5-2.0
+  mode: SpeedMode; // "varispeed" lets the pitch follow the speed
+  pitch: number; // Semitones, -12 to 12
+}
+
+// Headphone crossfeed, applied to stereo sources only
+export type CrossfeedPreset = "default" | "chu_moy" | "jan_meier" | "custom";
+
+export interface CrossfeedSettings {
+  enabled: boolean;
+  preset: CrossfeedPreset;
+  cutoff: number; // Hz, 300-2000, custom preset
+  level: number; // dB, 1-15, custom preset
+}
+
+// Room correction by convolution with an impulse response
+export interface ConvolutionSettings {
+  enabled: boolean;
+  ir_path: string | null; // Mono or stereo WAV impulse response
+}
+
+// Where playback goes: the output device, or a sink that needs no sound card
+export interface OutputSinkSettings {
+  backend: "device" | "null" | "file";
+  path: string | null; // File or FIFO the file output writes to
+  container: "raw" | "wav";
+  bit_depth: number; // 16 or 24, or 32 for float
+  sample_rate: number | null; // null follows the source
+  channels: number | null; // null follows the source
+}
+
+// Output device choice
+export interface DeviceSettings {
+  preferred: string | null; // null follows the system default
+  return_to_preferred: boolean; // Switch back when the preferred device reappears
+  profiles: Record<string, DeviceProfile>; // By device name
+}
+
+// Output settings for one device, applied whenever playback goes to it
+export interface DeviceProfile {
+  max_sample_rate: number | null; // Highest rate to open the device at
+  sample_format: "i16" | "i32" | "f32" | null; // null picks by source bit depth
+  buffer_size: number | null; // Frames per callback, null for the device default
+  volume_limit: number; // Highest volume, 0-1
+  eq_preset: number | null; // EQ preset switched to along with the device
+  resample: boolean; // Resample to one rate instead of switching the device rate
+}
+
+// How the output stream keeps up with the device
+export interface AudioDiagnostics {
+  buffer_size: number | null; // Frames per callback asked for, null for the default
+  buffer_range: [number, number] | null; // Sizes the device takes, if it says
+  callback_frames: number; // Frames the device asked for in the latest callback
+  latency_ms: number; // From handing a frame to the device to hearing it
+  underruns: number; // Decoding fell behind and silence was played
+  xruns: number; // A callback came too late and the device ran dry
+  convolution_latency_ms: number; // Added by the convolver, counting the IR's own delay
+  convolution_load: number; // Convolver time relative to the audio's duration
+}
+
+// Spectrum analyzer and level meter data of the output
+export type WindowFunction =
+  | "rectangular"
+  | "hann"
+  | "hamming"
+  | "blackman_harris";
+
+export interface VisualizerSettings {
+  enabled: boolean; // Only analyzed while a view shows the data
+  fft_size: number; // Power of two, 256-16384
+  window: WindowFunction;
+  frame_rate: number; // Frames per second, 10-60
+}
+
+export interface ChannelLevel {
+  peak_db: number;
+  rms_db: number;
+  clipped: boolean; // A sample reached full scale
+}
+
+// Payload of the "visualizer-frame" event
+export interface VisualizerFrame {
+  sample_rate: number;
+  fft_size: number;
+  bins: number[]; // dBFS of each FFT bin from 0Hz to Nyquist, channels mixed
+  levels: ChannelLevel[];
+  clipped: boolean;
+}
+
+// Payload of the "audio-device-changed" event
+export interface AudioDeviceChangedEvent {
+  device: string | null; // Device playback goes to
+  devices: string[];
+  fallback: boolean; // The preferred device is gone and the default stands in
+}
+
+// The backend play queue, in play order (shuffled while shuffle is on)
+export interface QueueSnapshot {
+  tracks: Track[];
+  position: number | null; // Index of the current track in tracks
+  shuffle: boolean;
+  repeat_mode: "off" | "one" | "all";
+}
+
+// Loudness normalization
+export interface ReplayGainSettings {
+  mode: "off" | "track" | "album" | "auto";
+  preamp_db: number;
+  fallback_gain_db: number;
+  prevent_clipping: boolean;
+  current_gain_db: number;
+}
+
+// Sample rate conversion filter
+export type ResamplerQuality = "fast" | "high_quality" | "minimum_phase";
+
+// Multichannel to output channel mixing
+export interface DownmixSettings {
+  include_lfe: boolean;
+  normalize: boolean;
+}
+
+// DSF/DFF playback: DoP where the device takes it, else PCM conversion
+export interface DsdSettings {
+  dop: boolean;
+  pcm_rate: number;
+}
+
+// Dither for 16-bit output
+export type DitherMode = 'off' | 'auto' | 'tpdf' | 'noise_shaped';
+
+// Parametric equalizer
+export type EqFilterType = 'peaking' | 'low_shelf' | 'high_shelf' | 'low_pass' | 'high_pass';
+
+export interface EqBand {
+  filter_type: EqFilterType;
+  frequency: number;
+  gain_db: number;
+  q: number;
+  enabled: boolean;
+}
+
+export interface EqSettings {
+  enabled: boolean;
+  preamp_db: number;
+  bands: EqBand[];
+}
+
+export interface EqPreset {
+  id: number;
+  name: string;
+  preamp_db: number;
+  bands: EqBand[];
+}
+
+// EBU R128 analysis (LUFS / LU, peaks linear)
+export interface TrackLoudness {
+  track_id: number;
+  integrated_lufs: number | null;
+  loudness_range: number | null;
+  true_peak: number | null;
+  album_integrated_lufs: number | null;
+  album_loudness_range: number | null;
+  album_true_peak: number | null;
+}
+
+export interface LoudnessAnalysisStatus {
+  enabled: boolean;
+  analyzed: number;
+  total: number;
+  current_track: string | null;
+}
+
+// Crossfade between consecutive tracks (duration 0 = off)
+export interface CrossfadeSettings {
+  duration: number;
+  curve: "linear" | "equal_power" | "logarithmic";
 }
 
 // Statistics